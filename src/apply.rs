@@ -0,0 +1,256 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use filetime::FileTime;
+
+use crate::placeholder;
+use crate::sparse;
+use crate::staging::{self, StagedFile};
+use crate::suppression::ExpectedChanges;
+use crate::sync_engine;
+use crate::trace;
+use crate::verify;
+use crate::xattrs::{self, Xattrs};
+
+/// A file version received from a peer, as it should appear locally.
+#[derive(Debug, Clone, Copy)]
+pub struct IncomingFile<'a> {
+    pub relative_path: &'a Path,
+    pub hash: &'a str,
+    /// The source file's modification time.
+    pub modified: SystemTime,
+    /// Extended attributes to set, or `None` when the folder does not sync them.
+    pub xattrs: Option<&'a Xattrs>,
+}
+
+/// The location of `relative_path` inside `folder_root`. Paths come from
+/// peers, so one that is empty, absolute or has `..` or `.` components is an
+/// `InvalidData` error rather than a path outside the folder.
+pub fn folder_path(folder_root: &Path, relative_path: &Path) -> io::Result<PathBuf> {
+    let mut components = relative_path.components().peekable();
+    if components.peek().is_none()
+        || !components.all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{:?} is not a path inside the folder", relative_path),
+        ));
+    }
+    Ok(folder_root.join(relative_path))
+}
+
+/// Writes a file received from a peer into a synced folder.
+///
+/// The data is staged first and the change is registered as expected before
+/// it becomes visible, so the resulting watcher events are not re-announced.
+/// Staged data that does not match the announced hash is discarded with an
+/// `InvalidData` error (see [`verify::verify_file`]), so a corrupted transfer
+/// never replaces the local file or gets indexed.
+/// The file gets the source's modification time and extended attributes
+/// before it is moved into place; since its hash is unchanged, indexing it
+/// later does not bump its version. Blocks of zeros are written as holes, so
+/// sparse files such as VM images keep their allocated size. A placeholder of
+/// the file is removed.
+pub fn write_remote_file<R: Read>(
+    folder_root: &Path,
+    file: IncomingFile,
+    data: R,
+    expected_changes: &ExpectedChanges,
+) -> io::Result<()> {
+    let target = folder_path(folder_root, file.relative_path)?;
+
+    let mut staged = StagedFile::create(folder_root)?;
+    staged.write_sparse(data)?;
+    verify::verify_file(staged.path(), file.hash)?;
+    if let Some(attrs) = file.xattrs {
+        xattrs::write(staged.path(), attrs)?;
+    }
+    staged.set_modified(file.modified)?;
+
+    expected_changes.expect(target.clone(), Some(file.hash.to_string()));
+    staged.persist(&target)?;
+    placeholder::remove_for(&target)
+}
+
+/// Materializes a file whose content already exists locally at `source`
+/// (found through [`crate::database::Database::find_file_by_hash`]) instead of
+/// transferring it from a peer, e.g. when a peer renamed or copied a file.
+///
+/// The copy is a reflink where the filesystem supports it (Btrfs, XFS, APFS,
+/// ReFS), which is near-instant regardless of size, and otherwise a copy of
+/// the source's data regions that keeps its holes. The staged copy is verified against `hash`, so a source that
+/// changed since it was indexed yields `InvalidData` and the caller should
+/// fall back to a transfer. Like [`write_remote_file`], the copy gets the
+/// remote file's modification time and attributes rather than the source's,
+/// and replaces the file's placeholder if it has one.
+pub fn copy_local_file(
+    folder_root: &Path,
+    file: IncomingFile,
+    source: &Path,
+    expected_changes: &ExpectedChanges,
+) -> io::Result<()> {
+    let target = folder_path(folder_root, file.relative_path)?;
+    let hash = file.hash;
+    let staged = staging::staging_path(folder_root)?;
+
+    let result = sparse::reflink_or_copy(source, &staged).and_then(|copied| {
+        File::open(&staged)?.sync_all()?;
+        verify::verify_file(&staged, hash)?;
+        if let Some(attrs) = file.xattrs {
+            xattrs::write(&staged, attrs)?;
+        }
+        filetime::set_file_mtime(&staged, FileTime::from_system_time(file.modified))?;
+        Ok(copied)
+    });
+    let copied = match result {
+        Ok(copied) => copied,
+        Err(e) => {
+            let _ = fs::remove_file(&staged);
+            return Err(e);
+        }
+    };
+
+    expected_changes.expect(target.clone(), Some(hash.to_string()));
+    staging::move_into_place(&staged, &target).inspect_err(|_| {
+        let _ = fs::remove_file(&staged);
+    })?;
+    placeholder::remove_for(&target)?;
+
+    match copied {
+        None => println!(
+            "[APPLY] {}Reflinked {:?} from {:?}",
+            trace::prefix(),
+            target,
+            source
+        ),
+        Some(bytes) => println!(
+            "[APPLY] {}Copied {} bytes to {:?} from {:?}",
+            trace::prefix(),
+            bytes,
+            target,
+            source
+        ),
+    }
+    Ok(())
+}
+
+/// Creates a file as a hard link of `existing`, another file in the same
+/// folder, when the peer's index shows both as links of one inode (see
+/// [`crate::database::Database::get_link_group`]). Nothing is copied, so the
+/// content is stored once, as on the peer. `existing` is verified against
+/// `hash` first; on `InvalidData` the caller should fall back to a transfer.
+///
+/// The link shares the inode's modification time and attributes, which
+/// already match the peer's since all links of a file have the same ones.
+pub fn link_local_file(
+    folder_root: &Path,
+    file: IncomingFile,
+    existing: &Path,
+    expected_changes: &ExpectedChanges,
+) -> io::Result<()> {
+    let source = folder_path(folder_root, existing)?;
+    let target = folder_path(folder_root, file.relative_path)?;
+    verify::verify_file(&source, file.hash)?;
+
+    let staged = staging::staging_path(folder_root)?;
+    fs::hard_link(&source, &staged)?;
+    expected_changes.expect(target.clone(), Some(file.hash.to_string()));
+    staging::move_into_place(&staged, &target).inspect_err(|_| {
+        let _ = fs::remove_file(&staged);
+    })?;
+    placeholder::remove_for(&target)?;
+    println!(
+        "[APPLY] {}Linked {:?} to {:?}",
+        trace::prefix(),
+        target,
+        source
+    );
+    Ok(())
+}
+
+/// Moves a file that a peer moved between two synced folders. Within one
+/// filesystem this is a rename; across filesystems the file is copied with
+/// [`copy_local_file`], which verifies its content, and the source removed.
+pub fn move_local_file(
+    from_root: &Path,
+    from: &Path,
+    to_root: &Path,
+    file: IncomingFile,
+    expected_changes: &ExpectedChanges,
+) -> io::Result<()> {
+    let source = folder_path(from_root, from)?;
+    let target = folder_path(to_root, file.relative_path)?;
+
+    expected_changes.expect(source.clone(), None);
+    expected_changes.expect(target.clone(), Some(file.hash.to_string()));
+    match staging::move_into_place(&source, &target) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            copy_local_file(to_root, file, &source, expected_changes)?;
+            remove_local_file(from_root, from, expected_changes)
+        }
+        result => {
+            result?;
+            placeholder::remove_for(&target)?;
+            println!(
+                "[APPLY] {}Moved {:?} to {:?}",
+                trace::prefix(),
+                source,
+                target
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Removes a file that was deleted on a peer.
+pub fn remove_local_file(
+    folder_root: &Path,
+    relative_path: &Path,
+    expected_changes: &ExpectedChanges,
+) -> io::Result<()> {
+    let target = folder_path(folder_root, relative_path)?;
+
+    expected_changes.expect(target.clone(), None);
+    match fs::remove_file(&target) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Renames a file that was renamed on a peer, keeping its content in place.
+///
+/// A rename that only changes letter case goes through a staging name, so it
+/// is applied on case-insensitive filesystems (NTFS, APFS, exFAT) as well,
+/// where renaming `photo.JPG` straight to `photo.jpg` may be a no-op or be
+/// mistaken for replacing an existing file.
+pub fn rename_local_file(
+    folder_root: &Path,
+    from: &Path,
+    to: &Path,
+    hash: &str,
+    expected_changes: &ExpectedChanges,
+) -> io::Result<()> {
+    let source = folder_path(folder_root, from)?;
+    let target = folder_path(folder_root, to)?;
+
+    expected_changes.expect(source.clone(), None);
+    expected_changes.expect(target.clone(), Some(hash.to_string()));
+    if !sync_engine::is_case_only_rename(from, to) {
+        return staging::move_into_place(&source, &target);
+    }
+
+    let staged = staging::staging_path(folder_root)?;
+    fs::rename(&source, &staged)?;
+    staging::move_into_place(&staged, &target).inspect_err(|_| {
+        let _ = fs::rename(&staged, &source);
+    })?;
+    println!(
+        "[APPLY] {}Renamed {:?} to {:?} (case only)",
+        trace::prefix(),
+        source,
+        target
+    );
+    Ok(())
+}
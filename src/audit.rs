@@ -0,0 +1,177 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::LiveConfig;
+use crate::control::SyncControl;
+use crate::database::IndexedFile;
+use crate::db_pool::DbPool;
+use crate::event_queue::{EventQueue, QueueEvent};
+use crate::health::FolderHealth;
+use crate::removable;
+use crate::sync_engine::{FsEventKind, calculate_hash};
+use crate::trace::TraceId;
+
+/// How often a turned off audit looks at the configuration again.
+const DISABLED_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Files modified on disk more recently than this are left to the watcher,
+/// whose events for them may still be debounced or settling.
+const RECENT_CHANGE: Duration = Duration::from_secs(5 * 60);
+
+/// What the audit found out about a sampled file.
+#[derive(Debug)]
+enum Finding {
+    Matches,
+    /// Gone from disk without the watcher reporting it.
+    Missing,
+    /// Size or modification time differ: edited while nobody was watching,
+    /// or the event was lost.
+    EditedOutOfBand,
+    /// Same size and modification time but different content: bit rot, a
+    /// failing disk, or a tool that restored the modification time.
+    Corrupted {
+        actual_hash: String,
+    },
+    /// Not checked this time, e.g. the drive is unplugged or the file is
+    /// being written.
+    Skipped,
+}
+
+/// Re-verifies the hash of a random indexed file every so often while the
+/// event loop is idle, at the rate of the `[audit]` section of
+/// sync_rs.toml. A file whose content no longer matches the index is counted
+/// in the folder's health. Edits and removals the watcher missed are queued
+/// like watcher events, so the change is indexed and announced as usual;
+/// corrupted content is never indexed, its indexed version is fetched from
+/// peers again instead.
+pub async fn run(
+    db: DbPool,
+    queue: EventQueue,
+    control: Arc<SyncControl>,
+    config: Arc<LiveConfig>,
+    health: Arc<FolderHealth>,
+) {
+    loop {
+        // Read every time, as a reload may change or turn it off.
+        let Some(interval) = config.get().audit.interval() else {
+            tokio::time::sleep(DISABLED_INTERVAL).await;
+            continue;
+        };
+        tokio::time::sleep(interval).await;
+        if control.is_stopped() || queue.depth() > 0 || queue.current().is_some() {
+            continue;
+        }
+
+        let sample = match db.read().await.random_indexed_file() {
+            Ok(Some(sample)) => sample,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("[AUDIT] Failed to pick a file: {}", e);
+                continue;
+            }
+        };
+        let folder = match db.read().await.get_folder_by_id(sample.folder_id) {
+            Ok(Some(folder)) => folder,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("[AUDIT] Failed to read folder {}: {}", sample.folder_id, e);
+                continue;
+            }
+        };
+        if folder.is_unsynced(&sample.relative_path) || !removable::is_mounted(&folder) {
+            continue;
+        }
+
+        let path = folder.local_path.join(&sample.relative_path);
+        let relative_path = sample.relative_path.clone();
+        let check_path = path.clone();
+        let finding = match tokio::task::spawn_blocking(move || check(&check_path, &sample)).await {
+            Ok(finding) => finding,
+            Err(e) => {
+                eprintln!("[AUDIT] Checking {:?} failed: {}", path, e);
+                continue;
+            }
+        };
+        let (message, kind) = match finding {
+            Finding::Matches => {
+                health.record_audit(folder.id, None);
+                continue;
+            }
+            Finding::Skipped => continue,
+            Finding::Missing => (
+                format!("{:?} is missing, but the watcher never reported it", path),
+                Some(FsEventKind::Remove),
+            ),
+            Finding::EditedOutOfBand => (
+                format!(
+                    "{:?} changed on disk, but the watcher never reported it",
+                    path
+                ),
+                Some(FsEventKind::Modify),
+            ),
+            // Indexing it would announce the damage to peers as a new
+            // version, so the index keeps the good hash.
+            Finding::Corrupted { actual_hash } => (
+                format!(
+                    "{:?} has hash {} although its size and modification time are unchanged; \
+                     the disk may be corrupting data",
+                    path,
+                    actual_hash.get(..12).unwrap_or(&actual_hash)
+                ),
+                None,
+            ),
+        };
+        eprintln!("[AUDIT] {}", message);
+        health.record_audit(folder.id, Some(message));
+        match kind {
+            Some(kind) => {
+                queue
+                    .send(QueueEvent::FileChanged {
+                        path,
+                        kind,
+                        trace: TraceId::new(),
+                    })
+                    .await
+            }
+            None => match db.write().await.request_fetch(folder.id, &relative_path) {
+                Ok(()) => println!("[AUDIT] Queued {:?} to be fetched from peers again", path),
+                Err(e) => eprintln!("[AUDIT] Failed to queue a fetch of {:?}: {}", path, e),
+            },
+        }
+    }
+}
+
+/// Compares the file on disk with its index entry, hashing it only when its
+/// size and modification time still match.
+fn check(path: &std::path::Path, indexed: &IndexedFile) -> Finding {
+    let metadata = match path.symlink_metadata() {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Finding::Missing,
+        Err(_) => return Finding::Skipped,
+    };
+    if !metadata.is_file() {
+        return Finding::Skipped;
+    }
+    let Ok(modified) = metadata.modified() else {
+        return Finding::Skipped;
+    };
+    if SystemTime::now()
+        .duration_since(modified)
+        .is_ok_and(|age| age < RECENT_CHANGE)
+    {
+        return Finding::Skipped;
+    }
+    let modified_secs = modified
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if metadata.len() != indexed.size_bytes || modified_secs != indexed.modified_secs {
+        return Finding::EditedOutOfBand;
+    }
+    match calculate_hash(path) {
+        Ok(actual_hash) if actual_hash == indexed.hash => Finding::Matches,
+        Ok(actual_hash) => Finding::Corrupted { actual_hash },
+        // Locked or unreadable for now; the next sample tries another file.
+        Err(_) => Finding::Skipped,
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{NaiveDateTime, Utc};
+use filetime::FileTime;
+
+use crate::database::{Database, SyncedFolder};
+use crate::error::{Result, SyncError};
+use crate::snapshot::{self, MANIFEST_NAME, Manifest, ManifestEntry, Skipped};
+use crate::sync_engine::calculate_hash;
+
+/// Name format of the dated snapshot directories, in UTC.
+const STAMP_FORMAT: &str = "%Y-%m-%dT%H%M%SZ";
+
+/// Suffix of a snapshot directory that is still being written.
+const PARTIAL_SUFFIX: &str = ".partial";
+
+#[derive(Debug)]
+pub struct BackupSummary {
+    pub snapshot: PathBuf,
+    /// Files hardlinked from the previous snapshot.
+    pub linked: usize,
+    /// Files copied because their content is new.
+    pub copied: usize,
+    pub copied_bytes: u64,
+    pub skipped: Skipped,
+    /// Old snapshots removed to honour `keep`.
+    pub pruned: Vec<PathBuf>,
+}
+
+/// Writes a point-in-time snapshot of `folder` into a new dated directory
+/// under `target`, rsnapshot style: files whose hash already appears in the
+/// previous snapshot are hardlinked from it, so only new content takes space.
+/// With `keep`, only that many snapshots are kept, oldest removed first.
+pub fn run(
+    db: &Database,
+    folder: &SyncedFolder,
+    target: &Path,
+    keep: Option<usize>,
+) -> Result<BackupSummary> {
+    fs::create_dir_all(target)
+        .map_err(|e| SyncError::io("failed to create backup target", target, e))?;
+    remove_partial_snapshots(target)?;
+
+    let previous = list_snapshots(target)?.pop();
+    let previous_files = match &previous {
+        Some(dir) => read_manifest(dir)
+            .map(|manifest| by_hash(dir, manifest))
+            .unwrap_or_default(),
+        None => HashMap::new(),
+    };
+
+    let stamp = Utc::now().format(STAMP_FORMAT).to_string();
+    let snapshot_dir = target.join(&stamp);
+    if snapshot_dir.exists() {
+        return Err(SyncError::io(
+            "snapshot already exists",
+            &snapshot_dir,
+            io::ErrorKind::AlreadyExists.into(),
+        ));
+    }
+    let partial_dir = target.join(format!("{}{}", stamp, PARTIAL_SUFFIX));
+
+    let (files, mut skipped) = snapshot::verified_files(db, folder)?;
+    let mut summary = BackupSummary {
+        snapshot: snapshot_dir.clone(),
+        linked: 0,
+        copied: 0,
+        copied_bytes: 0,
+        skipped: Vec::new(),
+        pruned: Vec::new(),
+    };
+    let mut stored = Vec::with_capacity(files.len());
+    for entry in files {
+        let destination = partial_dir.join(&entry.path);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| SyncError::io("failed to create snapshot directory", parent, e))?;
+        }
+
+        let linked = previous_files
+            .get(&entry.hash)
+            .is_some_and(|source| fs::hard_link(source, &destination).is_ok());
+        if linked {
+            summary.linked += 1;
+            stored.push(entry);
+            continue;
+        }
+
+        let source = folder.local_path.join(&entry.path);
+        fs::copy(&source, &destination)
+            .and_then(|_| copy_mtime(&source, &destination))
+            .map_err(|e| SyncError::io("failed to copy into snapshot", &source, e))?;
+        // The file may have changed since it was verified.
+        if calculate_hash(&destination).is_ok_and(|hash| hash == entry.hash) {
+            summary.copied += 1;
+            summary.copied_bytes += entry.size;
+            stored.push(entry);
+        } else {
+            let _ = fs::remove_file(&destination);
+            skipped.push((entry.path, "changed while it was copied".to_string()));
+        }
+    }
+
+    let manifest = Manifest::new(db, folder, stored)?;
+    let manifest_path = partial_dir.join(MANIFEST_NAME);
+    fs::create_dir_all(&partial_dir)
+        .and_then(|()| fs::write(&manifest_path, serde_json::to_vec_pretty(&manifest)?))
+        .map_err(|e| SyncError::io("failed to write snapshot manifest", &manifest_path, e))?;
+    fs::rename(&partial_dir, &snapshot_dir)
+        .map_err(|e| SyncError::io("failed to finish snapshot", &snapshot_dir, e))?;
+    summary.skipped = skipped;
+
+    if let Some(keep) = keep {
+        let snapshots = list_snapshots(target)?;
+        let excess = snapshots.len().saturating_sub(keep.max(1));
+        for old in snapshots.into_iter().take(excess) {
+            fs::remove_dir_all(&old)
+                .map_err(|e| SyncError::io("failed to remove old snapshot", &old, e))?;
+            summary.pruned.push(old);
+        }
+    }
+    Ok(summary)
+}
+
+fn copy_mtime(source: &Path, destination: &Path) -> io::Result<()> {
+    let modified = FileTime::from_last_modification_time(&fs::metadata(source)?);
+    filetime::set_file_mtime(destination, modified)
+}
+
+/// Completed snapshot directories under `target`, oldest first.
+pub fn list_snapshots(target: &Path) -> Result<Vec<PathBuf>> {
+    let entries =
+        fs::read_dir(target).map_err(|e| SyncError::io("failed to list backups", target, e))?;
+    let mut snapshots: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| NaiveDateTime::parse_from_str(name, STAMP_FORMAT).is_ok())
+        })
+        .map(|entry| entry.path())
+        .collect();
+    // The stamp format sorts chronologically.
+    snapshots.sort();
+    Ok(snapshots)
+}
+
+/// Removes snapshots left half-written by an interrupted run.
+fn remove_partial_snapshots(target: &Path) -> Result<()> {
+    let entries =
+        fs::read_dir(target).map_err(|e| SyncError::io("failed to list backups", target, e))?;
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let is_partial = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.ends_with(PARTIAL_SUFFIX));
+        if is_partial {
+            let path = entry.path();
+            println!("[BACKUP] Removing incomplete snapshot {:?}", path);
+            fs::remove_dir_all(&path)
+                .map_err(|e| SyncError::io("failed to remove incomplete snapshot", &path, e))?;
+        }
+    }
+    Ok(())
+}
+
+fn read_manifest(snapshot_dir: &Path) -> Option<Manifest> {
+    let data = fs::read(snapshot_dir.join(MANIFEST_NAME)).ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+/// Maps each content hash in a snapshot to one file holding it.
+fn by_hash(snapshot_dir: &Path, manifest: Manifest) -> HashMap<String, PathBuf> {
+    manifest
+        .files
+        .into_iter()
+        .map(|ManifestEntry { path, hash, .. }| (hash, snapshot_dir.join(path)))
+        .collect()
+}
@@ -0,0 +1,147 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use crate::config::Config;
+use crate::database::Database;
+use crate::db_pool::DbPool;
+use crate::error::{self, SyncError};
+use crate::replay::{self, RecordedEvent, RecordedKind};
+use crate::sync_engine::calculate_hash;
+use crate::verify;
+
+/// Block sizes whose hashing is measured, from the smallest block peers
+/// exchange to the largest.
+pub const BLOCK_SIZES: [u64; 3] = [128 * 1024, 1024 * 1024, 16 * 1024 * 1024];
+
+/// Size of each file of the end-to-end measurement, like a small document.
+const EVENT_FILE_SIZE: usize = 4096;
+
+/// Throughput of one way of hashing a file.
+#[derive(Debug, Clone)]
+pub struct HashRate {
+    /// E.g. `sha256` or `sha256, 1 MiB blocks`.
+    pub algorithm: String,
+    pub bytes_per_sec: f64,
+}
+
+/// Writes `size` bytes of incompressible data to `path`, so neither the
+/// filesystem nor the disk can shortcut reading it.
+pub fn write_sample(path: &Path, size: u64) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    // xorshift64: fast, and random enough for filesystem compression.
+    let mut state = 0x9e37_79b9_7f4a_7c15u64;
+    for _ in 0..size.div_ceil(8) {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        writer.write_all(&state.to_le_bytes())?;
+    }
+    let file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.set_len(size)?;
+    file.sync_all()
+}
+
+/// Hashes the file at `path` whole and in each of [`BLOCK_SIZES`].
+pub fn hash_rates(path: &Path) -> io::Result<Vec<HashRate>> {
+    let size = path.metadata()?.len();
+    let mut rates = Vec::new();
+    let started = Instant::now();
+    calculate_hash(path)?;
+    rates.push(HashRate {
+        algorithm: "sha256".to_string(),
+        bytes_per_sec: per_sec(size, started),
+    });
+    for block_size in BLOCK_SIZES {
+        let started = Instant::now();
+        verify::block_hashes_of_size(path, block_size)?;
+        rates.push(HashRate {
+            algorithm: if block_size >= 1024 * 1024 {
+                format!("sha256, {} MiB blocks", block_size / (1024 * 1024))
+            } else {
+                format!("sha256, {} KiB blocks", block_size / 1024)
+            },
+            bytes_per_sec: per_sec(size, started),
+        });
+    }
+    Ok(rates)
+}
+
+/// Bytes per second `threads` threads hash `files` at, each taking the
+/// next file not yet hashed, as a folder scan does.
+pub fn parallel_hash_rate(files: &[PathBuf], threads: usize) -> io::Result<f64> {
+    let total = files
+        .iter()
+        .map(|file| file.metadata().map(|metadata| metadata.len()))
+        .sum::<io::Result<u64>>()?;
+    let next = AtomicUsize::new(0);
+    let started = Instant::now();
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    while let Some(file) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        calculate_hash(file)?;
+                    }
+                    Ok::<_, io::Error>(())
+                })
+            })
+            .collect();
+        workers.into_iter().try_for_each(|worker| {
+            worker
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("hashing thread panicked")))
+        })
+    })?;
+    Ok(per_sec(total, started))
+}
+
+/// Index entries written per second, one transaction each, as the event
+/// loop writes them.
+pub fn upsert_rate(db: &Database, folder_id: i64, count: usize) -> Result<f64, rusqlite::Error> {
+    let started = Instant::now();
+    for index in 0..count {
+        let relative_path = PathBuf::from(format!("upserts/{}.bin", index));
+        let hash = format!("{:064x}", index);
+        db.upsert_file_record(folder_id, &relative_path, 4096, &hash, 0)?;
+    }
+    Ok(per_sec(count as u64, started))
+}
+
+/// Creates `count` small files in `folder`, registers it in the database of
+/// the current directory and feeds their creation through an event loop,
+/// returning the events it handled per second. Each is hashed and indexed
+/// as if a file watcher had reported it.
+pub async fn event_rate(db: DbPool, folder: &Path, count: usize) -> error::Result<f64> {
+    fs::create_dir_all(folder).map_err(|e| SyncError::io("failed to create", folder, e))?;
+    let mut recorded = Vec::with_capacity(count);
+    let mut content = vec![0u8; EVENT_FILE_SIZE];
+    for index in 0..count {
+        let path = folder.join(format!("{:06}.txt", index));
+        content[..8].copy_from_slice(&(index as u64).to_le_bytes());
+        fs::write(&path, &content).map_err(|e| SyncError::io("failed to write", &path, e))?;
+        recorded.push(RecordedEvent {
+            recorded_ms: 0,
+            path,
+            kind: RecordedKind::Create,
+        });
+    }
+    db.write()
+        .await
+        .add_folder("bench", error::path_str(folder)?)?;
+
+    let started = Instant::now();
+    replay::replay(db, Config::default(), &recorded).await?;
+    // The replay only returns once the event loop has been idle a while.
+    let elapsed = started
+        .elapsed()
+        .saturating_sub(replay::IDLE_TIME)
+        .as_secs_f64();
+    Ok(count as f64 / elapsed.max(f64::EPSILON))
+}
+
+fn per_sec(amount: u64, started: Instant) -> f64 {
+    amount as f64 / started.elapsed().as_secs_f64().max(f64::EPSILON)
+}
@@ -0,0 +1,674 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use prost::Message;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::database::SyncedFolder;
+use crate::protocol::IndexEntry;
+use crate::verify;
+use crate::version_vector::VersionVector;
+
+pub mod proto {
+    tonic::include_proto!("bep");
+}
+
+use proto::{
+    BlockInfo, ClusterConfig, Compression, Counter, FileInfo, FileInfoType, Header, Hello,
+    MessageCompression, MessageType,
+};
+
+/// Precedes the [`Hello`] on a new connection.
+pub const HELLO_MAGIC: u32 = 0x2EA7_D90B;
+
+/// Protocol name negotiated with ALPN in the TLS handshake.
+pub const ALPN_PROTOCOL: &str = "bep/1.0";
+
+pub const CLIENT_NAME: &str = "sync_rs";
+
+/// Smallest block size, the same as [`verify::BLOCK_SIZE`], so for all but
+/// large files BEP blocks are the blocks we verify transfers with.
+pub const MIN_BLOCK_SIZE: u64 = 128 * 1024;
+
+pub const MAX_BLOCK_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Files are split into about this many blocks at most, up to
+/// [`MAX_BLOCK_SIZE`].
+const DESIRED_BLOCKS_PER_FILE: u64 = 2000;
+
+/// Upper bound for a message after decompression. Block responses are at
+/// most [`MAX_BLOCK_SIZE`]; index messages are sent in batches well below.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Smaller messages are not worth compressing.
+const COMPRESSION_THRESHOLD: usize = 128;
+
+/// A device's identity: the SHA-256 of its TLS certificate. Shown and
+/// configured as 56 base32 characters, including a check character per 13,
+/// in groups of 7 separated by dashes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DeviceId([u8; 32]);
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+impl DeviceId {
+    /// ID of the device presenting the DER-encoded `certificate`.
+    pub fn from_certificate(certificate: &[u8]) -> Self {
+        Self(Sha256::digest(certificate).into())
+    }
+
+    /// From the raw form sent in a [`proto::Device`].
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        bytes.try_into().ok().map(Self)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// First 8 bytes, identifying the device in version vectors.
+    pub fn short_id(&self) -> u64 {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&self.0[..8]);
+        u64::from_be_bytes(bytes)
+    }
+
+    /// Parses the text form, with or without dashes and check characters.
+    /// Lowercase and the digits 0, 1 and 8, which people type for O, I and
+    /// B, are accepted as Syncthing does.
+    pub fn parse(text: &str) -> Option<Self> {
+        let chars: Vec<u8> = text
+            .bytes()
+            .filter(|c| *c != b'-' && *c != b' ')
+            .map(|c| match c.to_ascii_uppercase() {
+                b'0' => b'O',
+                b'1' => b'I',
+                b'8' => b'B',
+                c => c,
+            })
+            .collect();
+        let encoded = match chars.len() {
+            52 => chars,
+            56 => {
+                let mut encoded = Vec::with_capacity(52);
+                for group in chars.chunks(14) {
+                    let (data, check) = group.split_at(13);
+                    if luhn_base32(data)? != check[0] {
+                        return None;
+                    }
+                    encoded.extend_from_slice(data);
+                }
+                encoded
+            }
+            _ => return None,
+        };
+        Self::from_bytes(&decode_base32(&encoded)?)
+    }
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let encoded = encode_base32(&self.0);
+        let mut checked = Vec::with_capacity(56);
+        for group in encoded.chunks(13) {
+            checked.extend_from_slice(group);
+            checked.push(luhn_base32(group).unwrap_or(b'A'));
+        }
+        let groups: Vec<&str> = checked
+            .chunks(7)
+            .map(|group| std::str::from_utf8(group).unwrap_or_default())
+            .collect();
+        f.write_str(&groups.join("-"))
+    }
+}
+
+/// Unpadded RFC 4648 base32.
+fn encode_base32(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(bytes.len() * 8 / 5 + 1);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = (buffer << 8) | u32::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize]);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize]);
+    }
+    encoded
+}
+
+fn decode_base32(encoded: &[u8]) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(encoded.len() * 5 / 8);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &c in encoded {
+        let value = BASE32_ALPHABET.iter().position(|&a| a == c)? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+/// Luhn mod 32 check character of a group of base32 characters, computed
+/// the way Syncthing does.
+fn luhn_base32(group: &[u8]) -> Option<u8> {
+    let mut factor = 1;
+    let mut sum = 0;
+    for &c in group {
+        let addend = factor * BASE32_ALPHABET.iter().position(|&a| a == c)?;
+        factor = if factor == 2 { 1 } else { 2 };
+        sum += addend / 32 + addend % 32;
+    }
+    Some(BASE32_ALPHABET[(32 - sum % 32) % 32])
+}
+
+/// Short ID for a device ID of our version vectors: its Syncthing short ID
+/// when it is a Syncthing device ID, otherwise derived from its hash, so
+/// sync_rs devices keep distinct, stable counters.
+pub fn short_id(device_id: &str) -> u64 {
+    DeviceId::parse(device_id)
+        .unwrap_or_else(|| DeviceId(Sha256::digest(device_id.as_bytes()).into()))
+        .short_id()
+}
+
+/// Our [`Hello`], sent before anything else.
+pub fn local_hello(device_name: Option<String>) -> Hello {
+    Hello {
+        device_name: device_name.unwrap_or_default(),
+        client_name: CLIENT_NAME.to_string(),
+        client_version: env!("CARGO_PKG_VERSION").to_string(),
+    }
+}
+
+/// Sends `local` and waits for the peer's [`Hello`]; both sides send theirs
+/// at once. Like [`crate::protocol`], this works on any stream; Syncthing
+/// expects it inside TLS, after checking the peer's [`DeviceId`].
+pub async fn exchange_hello<S>(stream: &mut S, local: &Hello) -> io::Result<Hello>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let payload = local.encode_to_vec();
+    let len = u16::try_from(payload.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "hello too large"))?;
+    stream.write_u32(HELLO_MAGIC).await?;
+    stream.write_u16(len).await?;
+    stream.write_all(&payload).await?;
+    stream.flush().await?;
+
+    let magic = stream.read_u32().await?;
+    if magic != HELLO_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("not a BEP peer (magic {:#010x})", magic),
+        ));
+    }
+    let len = stream.read_u16().await?;
+    let mut payload = vec![0; len as usize];
+    stream.read_exact(&mut payload).await?;
+    Hello::decode(payload.as_slice()).map_err(invalid_data)
+}
+
+/// A message exchanged after the hellos. The first one each side sends is
+/// its [`ClusterConfig`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum BepMessage {
+    ClusterConfig(ClusterConfig),
+    Index(proto::Index),
+    IndexUpdate(proto::IndexUpdate),
+    Request(proto::Request),
+    Response(proto::Response),
+    /// Progress of a peer's downloads, which we do not use; peers only
+    /// send it for folders that allow temporary indexes.
+    DownloadProgress,
+    Ping,
+    Close(proto::Close),
+}
+
+impl BepMessage {
+    fn message_type(&self) -> MessageType {
+        match self {
+            Self::ClusterConfig(_) => MessageType::ClusterConfig,
+            Self::Index(_) => MessageType::Index,
+            Self::IndexUpdate(_) => MessageType::IndexUpdate,
+            Self::Request(_) => MessageType::Request,
+            Self::Response(_) => MessageType::Response,
+            Self::DownloadProgress => MessageType::DownloadProgress,
+            Self::Ping => MessageType::Ping,
+            Self::Close(_) => MessageType::Close,
+        }
+    }
+
+    fn encode_body(&self) -> Vec<u8> {
+        match self {
+            Self::ClusterConfig(message) => message.encode_to_vec(),
+            Self::Index(message) => message.encode_to_vec(),
+            Self::IndexUpdate(message) => message.encode_to_vec(),
+            Self::Request(message) => message.encode_to_vec(),
+            Self::Response(message) => message.encode_to_vec(),
+            Self::DownloadProgress | Self::Ping => Vec::new(),
+            Self::Close(message) => message.encode_to_vec(),
+        }
+    }
+
+    fn decode_body(message_type: MessageType, body: &[u8]) -> io::Result<Self> {
+        Ok(match message_type {
+            MessageType::ClusterConfig => {
+                Self::ClusterConfig(ClusterConfig::decode(body).map_err(invalid_data)?)
+            }
+            MessageType::Index => Self::Index(proto::Index::decode(body).map_err(invalid_data)?),
+            MessageType::IndexUpdate => {
+                Self::IndexUpdate(proto::IndexUpdate::decode(body).map_err(invalid_data)?)
+            }
+            MessageType::Request => {
+                Self::Request(proto::Request::decode(body).map_err(invalid_data)?)
+            }
+            MessageType::Response => {
+                Self::Response(proto::Response::decode(body).map_err(invalid_data)?)
+            }
+            MessageType::DownloadProgress => Self::DownloadProgress,
+            MessageType::Ping => Self::Ping,
+            MessageType::Close => Self::Close(proto::Close::decode(body).map_err(invalid_data)?),
+        })
+    }
+
+    /// Whether `compression`, as the peer asked for it in its
+    /// [`ClusterConfig`], applies to this message.
+    fn should_compress(&self, compression: Compression) -> bool {
+        match compression {
+            Compression::Always => true,
+            Compression::Metadata => !matches!(self, Self::Response(_)),
+            Compression::Never => false,
+        }
+    }
+}
+
+fn invalid_data(error: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Writes `message` as a 2-byte header length, the [`Header`], a 4-byte
+/// message length and the message, LZ4 compressed when `compression`
+/// applies to it and makes it smaller.
+pub async fn write_message<W>(
+    writer: &mut W,
+    message: &BepMessage,
+    compression: Compression,
+) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut body = message.encode_body();
+    let mut header = Header {
+        r#type: message.message_type() as i32,
+        compression: MessageCompression::None as i32,
+    };
+    if body.len() >= COMPRESSION_THRESHOLD && message.should_compress(compression) {
+        let mut compressed = (body.len() as u32).to_be_bytes().to_vec();
+        compressed.extend_from_slice(&lz4_flex::block::compress(&body));
+        if compressed.len() < body.len() {
+            body = compressed;
+            header.compression = MessageCompression::Lz4 as i32;
+        }
+    }
+    let len = u32::try_from(body.len())
+        .ok()
+        .filter(|len| *len as usize <= MAX_MESSAGE_SIZE)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "message too large"))?;
+
+    let header = header.encode_to_vec();
+    writer.write_u16(header.len() as u16).await?;
+    writer.write_all(&header).await?;
+    writer.write_u32(len).await?;
+    writer.write_all(&body).await?;
+    writer.flush().await
+}
+
+/// Reads a message written by [`write_message`] or by Syncthing.
+pub async fn read_message<R>(reader: &mut R) -> io::Result<BepMessage>
+where
+    R: AsyncRead + Unpin,
+{
+    let header_len = reader.read_u16().await?;
+    let mut header = vec![0; header_len as usize];
+    reader.read_exact(&mut header).await?;
+    let header = Header::decode(header.as_slice()).map_err(invalid_data)?;
+
+    let len = reader.read_u32().await? as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message too large",
+        ));
+    }
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).await?;
+
+    match MessageCompression::try_from(header.compression) {
+        Ok(MessageCompression::None) => {}
+        Ok(MessageCompression::Lz4) => body = decompress(&body)?,
+        Err(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown message compression {}", header.compression),
+            ));
+        }
+    }
+    let message_type = MessageType::try_from(header.r#type).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown message type {}", header.r#type),
+        )
+    })?;
+    BepMessage::decode_body(message_type, &body)
+}
+
+/// LZ4 block preceded by its decompressed size as a 4-byte big-endian
+/// integer.
+fn decompress(body: &[u8]) -> io::Result<Vec<u8>> {
+    let Some((size, block)) = body.split_first_chunk::<4>() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "compressed message too short",
+        ));
+    };
+    let size = u32::from_be_bytes(*size) as usize;
+    if size > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message too large",
+        ));
+    }
+    lz4_flex::block::decompress(block, size).map_err(invalid_data)
+}
+
+/// Describes one of our folders in the [`ClusterConfig`], shared between
+/// `devices`. Temporary indexes are turned off, as we neither send nor use
+/// download progress.
+pub fn cluster_folder(folder: &SyncedFolder, devices: Vec<proto::Device>) -> proto::Folder {
+    proto::Folder {
+        id: folder.folder_uuid.clone(),
+        label: folder.name.clone(),
+        // We do not track permissions.
+        ignore_permissions: true,
+        disable_temp_indexes: true,
+        devices,
+        ..Default::default()
+    }
+}
+
+/// Block size Syncthing uses for a file of `size` bytes: the smallest power
+/// of two from [`MIN_BLOCK_SIZE`] that keeps the file within
+/// [`DESIRED_BLOCKS_PER_FILE`] blocks, at most [`MAX_BLOCK_SIZE`].
+pub fn block_size(size: u64) -> u64 {
+    let mut block_size = MIN_BLOCK_SIZE;
+    while block_size < MAX_BLOCK_SIZE && size >= DESIRED_BLOCKS_PER_FILE * block_size {
+        block_size *= 2;
+    }
+    block_size
+}
+
+/// The blocks of the file at `path` of `size` bytes, as listed in its
+/// [`FileInfo`]. Hashed with [`verify::block_hashes_of_size`], so they are
+/// the blocks a transfer is verified and repaired with.
+pub fn file_blocks(path: &Path, size: u64) -> io::Result<Vec<BlockInfo>> {
+    let block_size = block_size(size);
+    let hashes = verify::block_hashes_of_size(path, block_size)?;
+    let mut blocks = Vec::with_capacity(hashes.len());
+    for (index, hash) in hashes.iter().enumerate() {
+        let offset = index as u64 * block_size;
+        blocks.push(BlockInfo {
+            offset: offset as i64,
+            size: block_size.min(size.saturating_sub(offset)) as i32,
+            hash: decode_hex(hash).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "malformed block hash")
+            })?,
+            weak_hash: 0,
+        });
+    }
+    Ok(blocks)
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Converts an entry of our index into a BEP file record with its
+/// `blocks` (see [`file_blocks`]). Removed files have none.
+pub fn file_info(entry: &IndexEntry, blocks: Vec<BlockInfo>) -> FileInfo {
+    let mut counters: Vec<Counter> = entry
+        .version_vector
+        .iter()
+        .map(|(device, value)| Counter {
+            id: short_id(device),
+            value,
+        })
+        .collect();
+    // Syncthing expects the counters ordered by device.
+    counters.sort_by_key(|counter| counter.id);
+    FileInfo {
+        name: entry.path.clone(),
+        r#type: FileInfoType::File as i32,
+        size: entry.size_bytes as i64,
+        permissions: 0o644,
+        modified_s: entry.modified_secs as i64,
+        deleted: entry.deleted,
+        no_permissions: true,
+        version: Some(proto::Vector { counters }),
+        sequence: entry.sequence as i64,
+        block_size: if entry.deleted {
+            0
+        } else {
+            block_size(entry.size_bytes) as i32
+        },
+        blocks: if entry.deleted { Vec::new() } else { blocks },
+        ..Default::default()
+    }
+}
+
+/// Converts a BEP file record into an entry of our index, naming the devices
+/// in its version vector after `devices`, which maps short IDs to device IDs;
+/// unknown devices keep their short ID. Directories, symlinks and files the
+/// peer marked invalid have no entry, since we only sync regular files.
+///
+/// BEP has no hash of the whole content, so it is only known for files of at
+/// most one block; for the others it is computed once the file is in place.
+pub fn index_entry(file: &FileInfo, devices: &HashMap<u64, String>) -> Option<IndexEntry> {
+    if file.r#type != FileInfoType::File as i32 || file.invalid {
+        return None;
+    }
+    let version_vector: VersionVector = file
+        .version
+        .iter()
+        .flat_map(|vector| &vector.counters)
+        .map(|counter| {
+            let device = devices
+                .get(&counter.id)
+                .cloned()
+                .unwrap_or_else(|| format!("{:016X}", counter.id));
+            (device, counter.value)
+        })
+        .collect();
+    let sha256_hash = match file.blocks.as_slice() {
+        _ if file.deleted => None,
+        [] if file.size == 0 => Some(format!("{:x}", Sha256::digest([]))),
+        [block] if i64::from(block.size) == file.size => Some(
+            block
+                .hash
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+        ),
+        _ => None,
+    };
+    Some(IndexEntry {
+        path: file.name.clone(),
+        size_bytes: file.size.max(0) as u64,
+        modified_secs: file.modified_s.max(0) as u64,
+        sha256_hash,
+        version_vector,
+        sequence: file.sequence.max(0) as u64,
+        deleted: file.deleted,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A device ID from Syncthing's own tests, in its canonical form.
+    const SYNCTHING_ID: &str = "P56IOI7-MZJNU2Y-IQGDREY-DM2MGTI-MGL3BXN-PQ6W5BM-TBBZ4TJ-XZWICQ2";
+
+    #[test]
+    fn device_id_parses_every_syncthing_form() {
+        let id = DeviceId::parse(SYNCTHING_ID).unwrap();
+        assert_eq!(id.to_string(), SYNCTHING_ID);
+        for text in [
+            "P56IOI7MZJNU2IQGDREYDM2MGTMGL3BXNPQ6W5BTBBZ4TJXZWICQ",
+            "P56IOI7MZJNU2YIQGDREYDM2MGTIMGL3BXNPQ6W5BMTBBZ4TJXZWICQ2",
+            "p56ioi7mzjnu2iqgdreydm2mgtmgl3bxnpq6w5btbbz4tjxzwicq",
+            "P56IOI7 MZJNU2Y IQGDREY DM2MGTI MGL3BXN PQ6W5BM TBBZ4TJ XZWICQ2",
+        ] {
+            assert_eq!(DeviceId::parse(text), Some(id), "{}", text);
+        }
+    }
+
+    #[test]
+    fn device_id_rejects_bad_check_characters_and_lengths() {
+        let mut wrong_check = SYNCTHING_ID.to_string();
+        wrong_check.replace_range(62.., "3");
+        assert_eq!(DeviceId::parse(&wrong_check), None);
+        assert_eq!(DeviceId::parse(&SYNCTHING_ID[..60]), None);
+        assert_eq!(DeviceId::parse(""), None);
+    }
+
+    #[test]
+    fn device_id_display_round_trips() {
+        let id = DeviceId::from_certificate(b"certificate");
+        let text = id.to_string();
+        assert_eq!(text.len(), 63);
+        assert_eq!(DeviceId::parse(&text), Some(id));
+        assert_eq!(DeviceId::from_bytes(id.as_bytes()), Some(id));
+    }
+
+    fn entry(deleted: bool) -> IndexEntry {
+        let content = b"hello";
+        IndexEntry {
+            path: "docs/hello.txt".to_string(),
+            size_bytes: if deleted { 0 } else { content.len() as u64 },
+            modified_secs: 1_700_000_000,
+            sha256_hash: (!deleted).then(|| format!("{:x}", Sha256::digest(content))),
+            version_vector: [
+                (SYNCTHING_ID.to_string(), 3),
+                ("5f0c3bb6-6c52-4a0e-9b1c-3c1f4b2a7e10".to_string(), 1),
+            ]
+            .into_iter()
+            .collect(),
+            sequence: 42,
+            deleted,
+        }
+    }
+
+    fn round_trip(entry: &IndexEntry) -> IndexEntry {
+        let blocks = match &entry.sha256_hash {
+            Some(hash) => vec![BlockInfo {
+                offset: 0,
+                size: entry.size_bytes as i32,
+                hash: decode_hex(hash).unwrap(),
+                weak_hash: 0,
+            }],
+            None => Vec::new(),
+        };
+        let devices: HashMap<u64, String> = entry
+            .version_vector
+            .iter()
+            .map(|(device, _)| (short_id(device), device.to_string()))
+            .collect();
+        index_entry(&file_info(entry, blocks), &devices).unwrap()
+    }
+
+    fn assert_same(actual: &IndexEntry, expected: &IndexEntry) {
+        assert_eq!(actual.path, expected.path);
+        assert_eq!(actual.size_bytes, expected.size_bytes);
+        assert_eq!(actual.modified_secs, expected.modified_secs);
+        assert_eq!(actual.sha256_hash, expected.sha256_hash);
+        assert_eq!(actual.version_vector, expected.version_vector);
+        assert_eq!(actual.sequence, expected.sequence);
+        assert_eq!(actual.deleted, expected.deleted);
+    }
+
+    #[test]
+    fn file_info_round_trips_through_index_entry() {
+        let expected = entry(false);
+        assert_same(&round_trip(&expected), &expected);
+    }
+
+    #[test]
+    fn deleted_file_info_round_trips_without_blocks() {
+        let expected = entry(true);
+        let file = file_info(&expected, Vec::new());
+        assert!(file.blocks.is_empty());
+        assert_eq!(file.block_size, 0);
+        assert_same(&round_trip(&expected), &expected);
+    }
+
+    #[test]
+    fn unknown_devices_keep_their_short_id() {
+        let file = file_info(&entry(false), Vec::new());
+        let converted = index_entry(&file, &HashMap::new()).unwrap();
+        let expected = format!("{:016X}", short_id(SYNCTHING_ID));
+        assert!(
+            converted
+                .version_vector
+                .iter()
+                .any(|(device, value)| device == expected && value == 3)
+        );
+    }
+
+    #[test]
+    fn directories_have_no_index_entry() {
+        let file = FileInfo {
+            name: "docs".to_string(),
+            r#type: FileInfoType::Directory as i32,
+            ..Default::default()
+        };
+        assert!(index_entry(&file, &HashMap::new()).is_none());
+    }
+
+    #[tokio::test]
+    async fn messages_round_trip_compressed() {
+        let files = (0..50)
+            .map(|index| {
+                let mut entry = entry(false);
+                entry.path = format!("docs/{}.txt", index);
+                file_info(&entry, Vec::new())
+            })
+            .collect();
+        let message = BepMessage::Index(proto::Index {
+            folder: "folder".to_string(),
+            files,
+        });
+        let (mut writer, mut reader) = tokio::io::duplex(1024 * 1024);
+        write_message(&mut writer, &message, Compression::Always)
+            .await
+            .unwrap();
+        write_message(&mut writer, &BepMessage::Ping, Compression::Always)
+            .await
+            .unwrap();
+        assert_eq!(read_message(&mut reader).await.unwrap(), message);
+        assert_eq!(read_message(&mut reader).await.unwrap(), BepMessage::Ping);
+    }
+}
@@ -0,0 +1,695 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use chrono::{Local, NaiveDate, NaiveDateTime};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use sync_rs::{conflicts, database, dedup, relocate, webhooks};
+
+pub const DEFAULT_GRPC_LISTEN: &str = "127.0.0.1:50051";
+pub const DEFAULT_HTTP_LISTEN: &str = "127.0.0.1:8080";
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "sync_rs",
+    version,
+    about = "Peer-to-peer folder synchronisation"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the sync daemon (default when no command is given).
+    Run(RunArgs),
+    /// Read or change persistent settings.
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Show this device and its synced folders.
+    Status {
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+    /// Manage HTTP webhooks notified about sync events.
+    Webhooks {
+        #[command(subcommand)]
+        action: WebhookAction,
+    },
+    /// Change per-folder options.
+    Folders {
+        #[command(subcommand)]
+        action: FolderAction,
+    },
+    /// List files with identical content across all synced folders.
+    DedupReport {
+        /// Replace duplicates with links to one copy. Hardlinked copies
+        /// share edits; reflinks need a copy-on-write filesystem.
+        #[arg(long, value_enum)]
+        consolidate: Option<ConsolidateMode>,
+    },
+    /// Point a folder at the directory its data was copied or moved to,
+    /// e.g. a bigger disk, keeping its index instead of rescanning and
+    /// resyncing everything. The daemon must be stopped.
+    MoveFolder(MoveFolderArgs),
+    /// Archive a folder's indexed files, or restore such an archive.
+    Snapshot(SnapshotArgs),
+    /// Write a dated, hardlinked point-in-time copy of a folder under a
+    /// backup directory; unchanged content is linked from the last snapshot.
+    Backup {
+        /// Folder to back up, by ID, name or UUID.
+        folder: String,
+        /// Directory holding the dated snapshots.
+        target: PathBuf,
+        /// Number of snapshots to keep; older ones are removed.
+        #[arg(long)]
+        keep: Option<usize>,
+    },
+    /// Manage the previous versions and deleted files kept in each folder.
+    Versions {
+        #[command(subcommand)]
+        action: VersionsAction,
+    },
+    /// Show statistics recorded by the daemon.
+    Stats {
+        #[command(subcommand)]
+        action: StatsAction,
+    },
+    /// Review and resolve files changed on this device and a peer at once.
+    Conflicts {
+        #[command(subcommand)]
+        action: ConflictAction,
+    },
+    /// Search the index of all folders by glob or substring, e.g.
+    /// `sync_rs find '*.pdf' --min-size 1M`.
+    Find(FindArgs),
+    /// Read or change the key/value tags of indexed files, e.g.
+    /// `sync_rs tags set ~/Photos/a.jpg status=reviewed`.
+    Tags {
+        #[command(subcommand)]
+        action: TagAction,
+    },
+    /// Download an unsynced file or directory of a synced folder once,
+    /// e.g. `sync_rs fetch ~/Photos/2019`.
+    Fetch { path: PathBuf },
+    /// Approve the local changes a manual-push folder holds back, so they
+    /// are announced to peers, e.g. `sync_rs push Documents`.
+    Push(PushArgs),
+    /// Stop indexing local changes in every folder until `sync_rs resume
+    /// --all`. Persisted across restarts.
+    Pause(PauseArgs),
+    /// Resume indexing in every folder and rescan them.
+    Resume(PauseArgs),
+    /// Stop file watchers, indexing and transfers for a large offline
+    /// reorganization while the daemon keeps answering its APIs, e.g.
+    /// `sync_rs maintenance on`. Persisted across restarts; every folder is
+    /// rescanned when it is turned off.
+    Maintenance(MaintenanceArgs),
+    /// Make the running daemon re-read sync_rs.toml and watch folders
+    /// registered since it started, like sending it SIGHUP.
+    Reload(ReloadArgs),
+    /// Start the daemon at login with this data directory: a systemd user
+    /// unit on Linux, a launchd agent on macOS, or a service started at boot
+    /// on Windows.
+    Service {
+        #[command(subcommand)]
+        action: ServiceAction,
+    },
+    /// Feed the file watcher events recorded since a time through the event
+    /// loop again, to reproduce indexing bugs, e.g. `sync_rs replay --since
+    /// 30m`. The daemon must be stopped.
+    Replay(ReplayArgs),
+    /// Measure hashing, database and event loop throughput on this machine
+    /// and suggest settings for sync_rs.toml, e.g. `sync_rs bench
+    /// ~/Photos`. Include the output in performance issue reports.
+    Bench(BenchArgs),
+    /// Check the installation and print actionable findings.
+    Doctor(DoctorArgs),
+    /// Add the folders and devices of a Syncthing installation, e.g.
+    /// `sync_rs import-syncthing ~/.local/state/syncthing/config.xml`.
+    ImportSyncthing(ImportSyncthingArgs),
+    /// Print a shell completion script to stdout.
+    Completions { shell: Shell },
+    /// Generate man pages for sync_rs and its subcommands into a directory.
+    Manpages { dir: PathBuf },
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// Stop an instance already running in this data directory and take over.
+    #[arg(long)]
+    pub takeover: bool,
+
+    /// Address the gRPC management API listens on.
+    #[arg(long, default_value = DEFAULT_GRPC_LISTEN)]
+    pub grpc_listen: SocketAddr,
+
+    /// Address the web UI and its JSON API listen on.
+    #[arg(long, default_value = DEFAULT_HTTP_LISTEN)]
+    pub http_listen: SocketAddr,
+
+    /// Run as the Windows service registered by `sync_rs service install`,
+    /// in this data directory.
+    #[cfg(windows)]
+    #[arg(long, hide = true)]
+    pub service_dir: Option<PathBuf>,
+}
+
+impl Default for RunArgs {
+    fn default() -> Self {
+        Self {
+            takeover: false,
+            grpc_listen: DEFAULT_GRPC_LISTEN.parse().unwrap(),
+            http_listen: DEFAULT_HTTP_LISTEN.parse().unwrap(),
+            #[cfg(windows)]
+            service_dir: None,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct DoctorArgs {
+    /// gRPC address the daemon is expected to listen on.
+    #[arg(long, default_value = DEFAULT_GRPC_LISTEN)]
+    pub grpc_listen: SocketAddr,
+
+    /// Web UI address the daemon is expected to listen on.
+    #[arg(long, default_value = DEFAULT_HTTP_LISTEN)]
+    pub http_listen: SocketAddr,
+}
+
+#[derive(Debug, Args)]
+pub struct ImportSyncthingArgs {
+    /// Syncthing's config.xml.
+    pub config: PathBuf,
+
+    /// Syncthing device ID of this machine (`syncthing --device-id`), so
+    /// it is not added as a peer.
+    #[arg(long, value_name = "ID")]
+    pub this_device: Option<String>,
+
+    /// Print what would be imported without changing anything.
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct FindArgs {
+    /// Glob such as `*.jpg` or `2019/*`, matched at any depth, or a
+    /// case-insensitive part of the path when it has no wildcards.
+    pub pattern: String,
+
+    /// Only search this folder, by ID, name or UUID.
+    #[arg(long)]
+    pub folder: Option<String>,
+
+    /// Only files of at least this size, e.g. `10M`.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub min_size: Option<u64>,
+
+    /// Only files modified at or after this local time, like `2024-05-01`
+    /// or `2024-05-01 12:00`, an RFC 3339 time, or how long ago, e.g. `2h`.
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    pub modified_since: Option<SystemTime>,
+}
+
+#[derive(Debug, Args)]
+pub struct MoveFolderArgs {
+    /// Folder to move, by ID, name or UUID.
+    pub folder: String,
+    /// Directory now holding the folder's files.
+    pub new_path: PathBuf,
+    /// Unchanged files whose content is hashed to check the copy; files
+    /// whose modification time changed are always hashed.
+    #[arg(long, value_name = "N", default_value_t = relocate::DEFAULT_SPOT_CHECKS)]
+    pub spot_checks: usize,
+    /// Move even if files are missing or differ at the new path. Missing
+    /// files are then deleted on peers, and differing ones synced to them.
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ReplayArgs {
+    /// Local time like `2026-01-31 14:05`, or how long ago, e.g. `30m`,
+    /// `2h` or `1d`. Events are kept for a day.
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    pub since: SystemTime,
+    /// Last event to replay, in the same format; the latest by default.
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    pub until: Option<SystemTime>,
+    /// Only events inside this folder, by ID, name or UUID.
+    #[arg(long)]
+    pub folder: Option<String>,
+    /// Print the events without replaying them.
+    #[arg(long)]
+    pub list: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct BenchArgs {
+    /// Directory on the disk to measure, e.g. a synced folder; the system
+    /// temporary directory by default. Its files are left alone.
+    pub path: Option<PathBuf>,
+    /// Data hashed per measurement, e.g. `1G`.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "256M")]
+    pub size: u64,
+    /// Files indexed by the database and event loop measurements.
+    #[arg(long, value_name = "N", default_value_t = 2000)]
+    pub files: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct PauseArgs {
+    /// Every folder; pausing single folders is not supported yet.
+    #[arg(long, required = true)]
+    pub all: bool,
+
+    /// gRPC address of the running daemon.
+    #[arg(long, default_value = DEFAULT_GRPC_LISTEN)]
+    pub grpc_listen: SocketAddr,
+}
+
+#[derive(Debug, Args)]
+pub struct MaintenanceArgs {
+    pub state: Switch,
+
+    /// gRPC address of the running daemon.
+    #[arg(long, default_value = DEFAULT_GRPC_LISTEN)]
+    pub grpc_listen: SocketAddr,
+}
+
+#[derive(Debug, Args)]
+pub struct ReloadArgs {
+    /// gRPC address of the running daemon.
+    #[arg(long, default_value = DEFAULT_GRPC_LISTEN)]
+    pub grpc_listen: SocketAddr,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ServiceAction {
+    /// Register the daemon to run in the current directory, replacing an
+    /// earlier registration.
+    Install {
+        /// gRPC listen address of the daemon.
+        #[arg(long, default_value = DEFAULT_GRPC_LISTEN)]
+        grpc_listen: SocketAddr,
+        /// HTTP listen address of the daemon's web UI and API.
+        #[arg(long, default_value = DEFAULT_HTTP_LISTEN)]
+        http_listen: SocketAddr,
+        /// Print what would be registered without changing anything.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Stop the daemon and remove its registration.
+    Uninstall,
+    /// Start the registered daemon now instead of at the next login or boot.
+    Start,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Switch {
+    On,
+    Off,
+}
+
+#[derive(Debug, Args)]
+pub struct PushArgs {
+    /// Folder by ID, name or UUID.
+    pub folder: String,
+
+    /// Only approve changes at or below these paths, relative to the
+    /// folder root; all pending changes when omitted.
+    pub paths: Vec<PathBuf>,
+
+    /// List the pending changes without approving them.
+    #[arg(long)]
+    pub list: bool,
+}
+
+#[derive(Debug, Args)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct SnapshotArgs {
+    #[command(subcommand)]
+    pub action: Option<SnapshotAction>,
+
+    /// Folder to archive, by ID, name or UUID. Only indexed files whose
+    /// content still matches the index are included.
+    #[arg(required = true)]
+    pub folder: Option<String>,
+
+    /// Archive to write, e.g. `snap.tar.zst`.
+    #[arg(long, short, required = true)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SnapshotAction {
+    /// Extract a snapshot into an empty or new directory and verify it.
+    Restore { archive: PathBuf, target: PathBuf },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigAction {
+    /// Set a setting, e.g. `config set device-name laptop`.
+    Set { key: ConfigKey, value: String },
+    /// Print the current value of a setting.
+    Get { key: ConfigKey },
+    /// Clear a setting so its default applies again.
+    Unset { key: ConfigKey },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum WebhookAction {
+    /// Register a URL that receives JSON event payloads.
+    Add {
+        url: String,
+        /// Sign payloads with HMAC-SHA256 using this secret.
+        #[arg(long)]
+        secret: Option<String>,
+        /// Only send these events (repeatable); all events when omitted.
+        #[arg(long = "event", value_parser = clap::builder::PossibleValuesParser::new(webhooks::EVENT_NAMES))]
+        events: Vec<String>,
+    },
+    /// List registered webhooks.
+    List,
+    /// Remove a webhook by ID.
+    Remove { id: i64 },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum FolderAction {
+    /// List synced folders with their size.
+    List {
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+    /// Register a directory to sync. A running daemon starts watching it on
+    /// `sync_rs reload`.
+    Add {
+        path: PathBuf,
+        /// Display name; the directory name when omitted.
+        #[arg(long)]
+        name: Option<String>,
+        /// Profile from sync_rs.toml to copy options from; the default
+        /// profile, if any, when omitted.
+        #[arg(long)]
+        profile: Option<String>,
+        /// UUID of a folder shared by a peer, to sync with it.
+        #[arg(long, value_name = "UUID")]
+        folder_uuid: Option<String>,
+    },
+    /// Update options of a folder, given by ID, name or UUID.
+    Set {
+        folder: String,
+        /// Skip hidden files and directories (dotfiles on Unix, the hidden
+        /// attribute on Windows). Files already indexed are kept.
+        #[arg(long)]
+        ignore_hidden: Option<bool>,
+        /// Sync extended attributes (user.* on Linux, com.apple.* including
+        /// resource forks on macOS). Needs filesystem support on every device.
+        #[arg(long)]
+        sync_xattrs: Option<bool>,
+        /// Record which files are hard links of each other, so peers
+        /// recreate the links instead of storing the content twice.
+        #[arg(long)]
+        sync_hardlinks: Option<bool>,
+        /// Copy the options of a profile from sync_rs.toml onto the folder,
+        /// replacing its ignore patterns, receive-only, debounce and settle
+        /// settings.
+        #[arg(long)]
+        profile: Option<String>,
+        /// Maximum total size, e.g. `500M` or `20G`; `0` removes the limit.
+        /// Receiving new data pauses while the folder is over it.
+        #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+        quota: Option<u64>,
+        /// Keep a small placeholder stub (`<name>.syncrs-placeholder`) for
+        /// each unsynced file that is not on this device.
+        #[arg(long)]
+        placeholders: Option<bool>,
+        /// Only keep the index peers announce, for monitoring: no content is
+        /// downloaded and local files are not indexed.
+        #[arg(long)]
+        observer: Option<bool>,
+        /// Hold local changes back until they are approved with
+        /// `sync_rs push`; turning this off approves all pending changes.
+        #[arg(long)]
+        manual_push: Option<bool>,
+        /// Files a scan reads and hashes at once, e.g. 1 on a spinning disk
+        /// or 8 on NVMe; 0 picks a number from the CPU count.
+        #[arg(long, value_name = "N")]
+        hash_threads: Option<usize>,
+        /// Only hash and announce a file once it has gone unmodified this
+        /// many seconds, for video captures or dumps written over minutes;
+        /// 0 indexes changes right away.
+        #[arg(long, value_name = "SECS")]
+        settle_secs: Option<u64>,
+        /// Keep this folder a one-way copy of another folder on this
+        /// machine, e.g. on an external drive. Changes made here are
+        /// overwritten with the source's.
+        #[arg(long, value_name = "SOURCE")]
+        mirror_of: Option<String>,
+        /// Stop mirroring; the folder keeps its files and syncs normally.
+        #[arg(long, conflicts_with = "mirror_of")]
+        no_mirror: bool,
+        /// The folder is on a drive that gets unplugged. A marker file is
+        /// written to its root; while it is missing, the folder is paused
+        /// instead of its files being treated as deleted.
+        #[arg(long)]
+        removable: Option<bool>,
+    },
+    /// Stop keeping the content of subpaths on this device. Their files stay
+    /// known from peers and can be downloaded with `sync_rs fetch`; local
+    /// copies are kept until removed, and removing them is not synced.
+    Unsync {
+        folder: String,
+        /// Paths relative to the folder root.
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Sync the content of subpaths marked with `unsync` again.
+    Resync {
+        folder: String,
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum VersionsAction {
+    /// Remove versions beyond the limits in the `[versions]` section of
+    /// sync_rs.toml, as the daemon does every hour. Options override it.
+    Prune {
+        /// Only prune this folder, by ID, name or UUID.
+        #[arg(long)]
+        folder: Option<String>,
+        /// Versions to keep of each file.
+        #[arg(long, value_name = "N")]
+        keep: Option<usize>,
+        /// Total size of a folder's versions, e.g. `10G`.
+        #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+        max_size: Option<u64>,
+        /// Remove versions kept longer ago than this.
+        #[arg(long, value_name = "DAYS")]
+        max_age_days: Option<u64>,
+        /// List what would be removed without removing it.
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum StatsAction {
+    /// Bytes sent to and received from each peer per folder and day, and
+    /// this month's totals against the caps in the `[transfers]` section of
+    /// sync_rs.toml.
+    Transfers {
+        /// Days to list, including today.
+        #[arg(long, value_name = "N", default_value_t = 30)]
+        days: u32,
+        /// Only this peer, by device ID or name.
+        #[arg(long)]
+        peer: Option<String>,
+        /// Only this folder, by ID, name or UUID.
+        #[arg(long)]
+        folder: Option<String>,
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+    /// A folder's size and transfers per day, as recorded by the daemon.
+    History {
+        /// Folder, by ID, name or UUID.
+        folder: String,
+        /// Days to list, including today.
+        #[arg(long, value_name = "N", default_value_t = 30)]
+        days: u32,
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConflictAction {
+    /// List unresolved conflicts.
+    List {
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+    /// Resolve a conflict by ID; the result is synced to peers as a new version.
+    Resolve {
+        id: i64,
+        #[arg(long, value_enum)]
+        keep: KeepSide,
+    },
+}
+
+/// How commands that list things print them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable text.
+    #[default]
+    Text,
+    /// JSON for scripts; fields are only ever added.
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum KeepSide {
+    /// Keep this device's version and delete the conflict copy.
+    Local,
+    /// Replace this device's version with the conflict copy.
+    Remote,
+    /// Keep the conflict copy as a separate file.
+    Both,
+}
+
+impl From<KeepSide> for conflicts::Resolution {
+    fn from(side: KeepSide) -> Self {
+        match side {
+            KeepSide::Local => conflicts::Resolution::KeepLocal,
+            KeepSide::Remote => conflicts::Resolution::KeepRemote,
+            KeepSide::Both => conflicts::Resolution::KeepBoth,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TagAction {
+    /// Print the tags of a file.
+    List { path: PathBuf },
+    /// Add or overwrite tags; they are synced to peers with the file.
+    Set {
+        path: PathBuf,
+        #[arg(required = true, value_name = "KEY=VALUE", value_parser = parse_tag)]
+        tags: Vec<(String, String)>,
+    },
+    /// Remove tags by key.
+    Remove {
+        path: PathBuf,
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+}
+
+fn parse_tag(value: &str) -> Result<(String, String), String> {
+    let (key, value) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, got {:?}", value))?;
+    if !database::is_valid_tag_key(key) {
+        return Err(format!("invalid tag key {:?}", key));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Parses a byte count with an optional binary K, M, G or T suffix.
+fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (digits, shift) = match value.char_indices().last() {
+        Some((index, unit)) if unit.is_ascii_alphabetic() => {
+            let shift = match unit.to_ascii_uppercase() {
+                'K' => 10,
+                'M' => 20,
+                'G' => 30,
+                'T' => 40,
+                _ => return Err(format!("unknown size unit {:?}", unit)),
+            };
+            (&value[..index], shift)
+        }
+        _ => (value, 0),
+    };
+    let count: u64 = digits
+        .parse()
+        .map_err(|_| format!("invalid size {:?}", value))?;
+    count
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("size {:?} is too large", value))
+}
+
+/// Parses a local date and time, with optional seconds or only a date, an
+/// RFC 3339 time, or a duration before now with an s, m, h or d suffix.
+fn parse_time(value: &str) -> Result<SystemTime, String> {
+    let value = value.trim();
+    if let Some((index, unit)) = value.char_indices().last()
+        && unit.is_ascii_alphabetic()
+        && let Ok(count) = value[..index].parse::<u64>()
+    {
+        let secs = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(format!("unknown time unit {:?}", unit)),
+        };
+        return count
+            .checked_mul(secs)
+            .and_then(|secs| SystemTime::now().checked_sub(Duration::from_secs(secs)))
+            .ok_or_else(|| format!("{:?} is too long ago", value));
+    }
+
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Ok(time.into());
+    }
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .ok_or_else(|| format!("invalid time {:?}", value))?;
+    naive
+        .and_local_timezone(Local)
+        .earliest()
+        .map(SystemTime::from)
+        .ok_or_else(|| format!("{:?} does not exist in the local time zone", value))
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ConsolidateMode {
+    Hardlink,
+    Reflink,
+}
+
+impl From<ConsolidateMode> for dedup::LinkMode {
+    fn from(mode: ConsolidateMode) -> Self {
+        match mode {
+            ConsolidateMode::Hardlink => dedup::LinkMode::Hardlink,
+            ConsolidateMode::Reflink => dedup::LinkMode::Reflink,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ConfigKey {
+    /// Human-readable name shown to peers instead of the device ID.
+    DeviceName,
+    /// MQTT broker to publish events to, e.g. `mqtt://host:1883`.
+    MqttUrl,
+    /// First topic level for MQTT messages (default `sync_rs`).
+    MqttTopicPrefix,
+}
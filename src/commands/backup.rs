@@ -0,0 +1,24 @@
+use std::path::Path;
+
+use sync_rs::backup;
+use sync_rs::database::Database;
+use sync_rs::error::{self, SyncError};
+
+pub fn run(db: &Database, folder: &str, target: &Path, keep: Option<usize>) -> error::Result<()> {
+    let Some(found) = db.find_folder(folder)? else {
+        return Err(SyncError::UnknownFolder(folder.to_string()));
+    };
+
+    let summary = backup::run(db, &found, target, keep)?;
+    for (path, reason) in &summary.skipped {
+        println!("[BACKUP] Skipped {:?}: {}", path, reason);
+    }
+    println!(
+        "[BACKUP] Wrote {:?}: {} file(s) linked, {} copied ({} bytes)",
+        summary.snapshot, summary.linked, summary.copied, summary.copied_bytes
+    );
+    for old in &summary.pruned {
+        println!("[BACKUP] Removed old snapshot {:?}", old);
+    }
+    Ok(())
+}
@@ -0,0 +1,184 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use sync_rs::bench::{self, HashRate};
+use sync_rs::config::QueueSizes;
+use sync_rs::database::MAX_AUTO_HASH_THREADS;
+use sync_rs::db_pool::DbPool;
+use sync_rs::error::{self, SyncError};
+
+use super::status::format_bytes;
+use crate::cli::BenchArgs;
+
+/// What the measurements came to.
+struct Results {
+    hashing: Vec<HashRate>,
+    /// Bytes per second by number of threads.
+    parallel: Vec<(usize, f64)>,
+    upserts_per_sec: f64,
+    events_per_sec: f64,
+}
+
+pub async fn run(args: BenchArgs) -> error::Result<()> {
+    let base = args.path.clone().unwrap_or_else(std::env::temp_dir);
+    if !base.is_dir() {
+        return Err(SyncError::io(
+            "cannot benchmark",
+            base,
+            io::Error::new(io::ErrorKind::NotADirectory, "not a directory"),
+        ));
+    }
+    let work = base.join(format!(".sync_rs-bench-{}", std::process::id()));
+    std::fs::create_dir(&work).map_err(|e| SyncError::io("failed to create", &work, e))?;
+    let data_dir =
+        std::env::current_dir().map_err(|e| SyncError::io("failed to read", Path::new("."), e))?;
+    // The database lives in the working directory; measure a scratch one
+    // on the disk under test instead of the daemon's.
+    std::env::set_current_dir(&work).map_err(|e| SyncError::io("failed to enter", &work, e))?;
+    let results = measure(&args, &work).await;
+    let restored = std::env::set_current_dir(&data_dir);
+    if let Err(e) = std::fs::remove_dir_all(&work) {
+        eprintln!("[BENCH] Failed to remove {:?}: {}", work, e);
+    }
+    restored.map_err(|e| SyncError::io("failed to return to", &data_dir, e))?;
+    print_results(&results?, &args, &base);
+    Ok(())
+}
+
+async fn measure(args: &BenchArgs, work: &Path) -> error::Result<Results> {
+    println!("[BENCH] Hashing a {} file...", format_bytes(args.size));
+    let sample = work.join("sample.bin");
+    bench::write_sample(&sample, args.size)
+        .map_err(|e| SyncError::io("failed to write", &sample, e))?;
+    let hashing =
+        bench::hash_rates(&sample).map_err(|e| SyncError::io("failed to hash", &sample, e))?;
+    std::fs::remove_file(&sample).map_err(|e| SyncError::io("failed to remove", &sample, e))?;
+
+    let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get());
+    let mut thread_counts: Vec<usize> = std::iter::successors(Some(1), |threads| Some(threads * 2))
+        .take_while(|threads| *threads < cpus)
+        .collect();
+    thread_counts.push(cpus);
+    let file_count = (cpus * 2).max(8);
+    println!(
+        "[BENCH] Hashing {} files with 1 to {} thread(s)...",
+        file_count, cpus
+    );
+    let files: Vec<PathBuf> = (0..file_count)
+        .map(|index| work.join(format!("parallel-{}.bin", index)))
+        .collect();
+    for file in &files {
+        bench::write_sample(file, args.size / file_count as u64)
+            .map_err(|e| SyncError::io("failed to write", file, e))?;
+    }
+    let mut parallel = Vec::new();
+    for threads in thread_counts {
+        let rate = bench::parallel_hash_rate(&files, threads)
+            .map_err(|e| SyncError::io("failed to hash", work, e))?;
+        parallel.push((threads, rate));
+    }
+
+    println!("[BENCH] Writing {} index entries...", args.files);
+    let db = DbPool::open(1)?;
+    let upserts_folder = work.join("upserts");
+    let folder_id = db
+        .write()
+        .await
+        .add_folder("upserts", error::path_str(&upserts_folder)?)?;
+    let upserts_per_sec = bench::upsert_rate(&*db.write().await, folder_id, args.files)?;
+
+    println!(
+        "[BENCH] Indexing {} new files through the event loop...",
+        args.files
+    );
+    let events_per_sec = bench::event_rate(db, &work.join("events"), args.files).await?;
+
+    Ok(Results {
+        hashing,
+        parallel,
+        upserts_per_sec,
+        events_per_sec,
+    })
+}
+
+fn print_results(results: &Results, args: &BenchArgs, base: &Path) {
+    let cpus = results.parallel.last().map_or(1, |(threads, _)| *threads);
+    println!();
+    println!(
+        "sync_rs {} on {}/{}, {} CPU(s), measured in {}",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        cpus,
+        base.display()
+    );
+    println!(
+        "Hashing {} (mostly from the page cache, so this is the CPU's speed):",
+        format_bytes(args.size)
+    );
+    for rate in &results.hashing {
+        println!("  {:<26} {}", rate.algorithm, per_sec(rate.bytes_per_sec));
+    }
+    println!("Hashing many files at once:");
+    for (threads, rate) in &results.parallel {
+        println!(
+            "  {:>2} thread(s)               {}",
+            threads,
+            per_sec(*rate)
+        );
+    }
+    println!(
+        "Database: {:.0} index entries written per second",
+        results.upserts_per_sec
+    );
+    println!(
+        "Event loop: {:.0} new {} files indexed per second",
+        results.events_per_sec,
+        format_bytes(4096)
+    );
+
+    // The fewest threads within 10% of the fastest; more only add contention.
+    let best = results
+        .parallel
+        .iter()
+        .map(|(_, rate)| *rate)
+        .fold(0.0, f64::max);
+    let hash_threads = results
+        .parallel
+        .iter()
+        .find(|(_, rate)| *rate >= best * 0.9)
+        .map_or(1, |(threads, _)| *threads);
+    let automatic = cpus.min(MAX_AUTO_HASH_THREADS);
+    // About a second of events, so bursts wait in the queue instead of
+    // holding up the watchers.
+    let default_queue = QueueSizes::default().event_queue;
+    let event_queue = ((results.events_per_sec / 100.0).round() as usize * 100).max(default_queue);
+
+    println!();
+    if hash_threads == automatic && event_queue == default_queue {
+        println!("The defaults suit this machine; nothing to change in sync_rs.toml.");
+        return;
+    }
+    println!("Suggested settings for sync_rs.toml:");
+    if event_queue != default_queue {
+        println!();
+        println!("[queues]");
+        println!("event_queue = {}", event_queue);
+    }
+    if hash_threads != automatic {
+        println!();
+        println!("# In each [profiles.<name>] section, or per folder with");
+        println!(
+            "# `sync_rs folders set <folder> --hash-threads {}`:",
+            hash_threads
+        );
+        println!("hash_threads = {}", hash_threads);
+        println!();
+        println!("Hashing files that are not cached is limited by the disk, so on a spinning");
+        println!("disk hash_threads = 1 is usually fastest whatever the numbers above say.");
+    }
+}
+
+fn per_sec(bytes_per_sec: f64) -> String {
+    format!("{}/s", format_bytes(bytes_per_sec as u64))
+}
@@ -0,0 +1,47 @@
+use sync_rs::database::Database;
+use sync_rs::mqtt;
+
+use crate::cli::{ConfigAction, ConfigKey};
+
+fn setting_name(key: ConfigKey) -> &'static str {
+    match key {
+        ConfigKey::DeviceName => "device_name",
+        ConfigKey::MqttUrl => mqtt::URL_SETTING,
+        ConfigKey::MqttTopicPrefix => mqtt::TOPIC_PREFIX_SETTING,
+    }
+}
+
+/// Returns an error message if `value` is not acceptable for `key`.
+fn validate(key: ConfigKey, value: &str) -> Option<String> {
+    match key {
+        ConfigKey::DeviceName if value.is_empty() => Some("Device name cannot be empty".into()),
+        ConfigKey::MqttUrl => mqtt::BrokerAddress::parse(value).err(),
+        ConfigKey::MqttTopicPrefix if value.is_empty() || value.contains(['+', '#']) => {
+            Some("MQTT topic prefix must be non-empty and cannot contain + or #".into())
+        }
+        _ => None,
+    }
+}
+
+pub fn run(db: &Database, action: ConfigAction) -> Result<(), rusqlite::Error> {
+    match action {
+        ConfigAction::Set { key, value } => {
+            let value = value.trim();
+            if let Some(message) = validate(key, value) {
+                eprintln!("[CONFIG] {}", message);
+                return Ok(());
+            }
+            db.set_setting(setting_name(key), value)?;
+            println!("[CONFIG] {:?} set to {:?}", key, value);
+        }
+        ConfigAction::Get { key } => match db.get_setting(setting_name(key))? {
+            Some(value) => println!("{}", value),
+            None => println!("(not set)"),
+        },
+        ConfigAction::Unset { key } => {
+            db.delete_setting(setting_name(key))?;
+            println!("[CONFIG] {:?} unset", key);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use sync_rs::database::Database;
+use sync_rs::error::{self, SyncError};
+use sync_rs::{conflicts, output};
+
+use super::print_json;
+use super::status::format_time;
+use crate::cli::{ConflictAction, OutputFormat};
+
+pub fn run(db: &Database, action: ConflictAction) -> error::Result<()> {
+    match action {
+        ConflictAction::List { format } => {
+            let conflicts = output::list_conflicts(db)?;
+            if format == OutputFormat::Json {
+                print_json(&conflicts);
+                return Ok(());
+            }
+            if conflicts.is_empty() {
+                println!("No conflicts.");
+            }
+            for output::ConflictOutput {
+                conflict,
+                folder_name,
+            } in conflicts
+            {
+                let folder = folder_name.unwrap_or_else(|| conflict.folder_id.to_string());
+                let device = conflict
+                    .remote
+                    .device_name
+                    .as_deref()
+                    .unwrap_or(&conflict.remote.device_id);
+                println!(
+                    "  {}: {}/{} changed here and on {} ({})",
+                    conflict.id,
+                    folder,
+                    conflict.relative_path.display(),
+                    device,
+                    format_time(conflict.detected_secs)
+                );
+                println!("      their version: {}", conflict.conflict_path.display());
+            }
+        }
+        ConflictAction::Resolve { id, keep } => match db.get_conflict(id)? {
+            Some(conflict) => conflicts::resolve(db, &conflict, keep.into())?,
+            None => return Err(SyncError::UnknownConflict(id)),
+        },
+    }
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use std::net::SocketAddr;
+
+use sync_rs::config::CONFIG_PATH;
+use sync_rs::control::SyncControl;
+use sync_rs::database::Database;
+use sync_rs::error::{self, SyncError};
+use sync_rs::grpc::proto::{self, sync_manager_client::SyncManagerClient};
+use sync_rs::instance_lock::{InstanceLock, LockError};
+use tonic::transport::Channel;
+
+use crate::cli::{MaintenanceArgs, PauseArgs, ReloadArgs, Switch};
+
+enum Change {
+    Pause,
+    Resume,
+    Maintenance(bool),
+}
+
+pub async fn pause(args: PauseArgs) -> error::Result<()> {
+    apply(Change::Pause, args.grpc_listen).await
+}
+
+pub async fn resume(args: PauseArgs) -> error::Result<()> {
+    apply(Change::Resume, args.grpc_listen).await
+}
+
+pub async fn maintenance(args: MaintenanceArgs) -> error::Result<()> {
+    apply(
+        Change::Maintenance(args.state == Switch::On),
+        args.grpc_listen,
+    )
+    .await
+}
+
+/// Asks the running daemon to make the change, or records it for the next
+/// start when the daemon is stopped.
+async fn apply(change: Change, grpc_listen: SocketAddr) -> error::Result<()> {
+    match InstanceLock::acquire() {
+        Ok(_lock) => return apply_stopped(change),
+        Err(LockError::AlreadyRunning { .. }) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let mut client = connect(grpc_listen).await?;
+    let (action, state) = match change {
+        Change::Pause => ("pause", client.pause(proto::PauseRequest {}).await),
+        Change::Resume => ("resume", client.resume(proto::ResumeRequest {}).await),
+        Change::Maintenance(enabled) => (
+            "change maintenance mode",
+            client
+                .set_maintenance(proto::SetMaintenanceRequest { enabled })
+                .await,
+        ),
+    };
+    let state = state.map_err(|status| SyncError::DaemonRefused {
+        action,
+        message: status.message().to_string(),
+    })?;
+    print_state(&state.into_inner());
+    Ok(())
+}
+
+pub async fn reload(args: ReloadArgs) -> error::Result<()> {
+    // Only probed, as taking the lock would record our PID in it.
+    match InstanceLock::probe() {
+        Ok(()) => {
+            println!(
+                "[CONTROL] The daemon is not running; it reads {} when started",
+                CONFIG_PATH
+            );
+            return Ok(());
+        }
+        Err(LockError::AlreadyRunning { .. }) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let mut client = connect(args.grpc_listen).await?;
+    let changes = client
+        .reload(proto::ReloadRequest {})
+        .await
+        .map_err(|status| SyncError::DaemonRefused {
+            action: "reload",
+            message: status.message().to_string(),
+        })?
+        .into_inner()
+        .changes;
+    if changes.is_empty() {
+        println!("Nothing changed.");
+    }
+    for change in changes {
+        println!("  {}", change);
+    }
+    Ok(())
+}
+
+async fn connect(grpc_listen: SocketAddr) -> error::Result<SyncManagerClient<Channel>> {
+    SyncManagerClient::connect(format!("http://{}", grpc_listen))
+        .await
+        .map_err(|source| SyncError::DaemonUnreachable {
+            address: grpc_listen,
+            source,
+        })
+}
+
+/// Pausing and entering maintenance are persisted directly. Resuming and
+/// leaving maintenance need the daemon, which rescans every folder then.
+fn apply_stopped(change: Change) -> error::Result<()> {
+    let db = Database::new()?;
+    let control = SyncControl::load(&db)?;
+    match change {
+        Change::Pause => control.pause(&db)?,
+        Change::Maintenance(true) => control.enter_maintenance(&db)?,
+        Change::Resume | Change::Maintenance(false) => return Err(SyncError::DaemonStopped),
+    }
+    println!("[CONTROL] The daemon is not running; it starts in this state");
+    Ok(())
+}
+
+fn print_state(state: &proto::ControlState) {
+    println!("Sync: {}", if state.paused { "paused" } else { "running" });
+    println!(
+        "Maintenance: {}",
+        if state.maintenance {
+            "on (watchers and transfers stopped)"
+        } else {
+            "off"
+        }
+    );
+}
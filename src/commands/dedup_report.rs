@@ -0,0 +1,115 @@
+use std::path::PathBuf;
+
+use sync_rs::database::{Database, DuplicateGroup};
+use sync_rs::dedup::{self, LinkMode};
+use sync_rs::sync_engine::calculate_hash;
+
+pub fn run(db: &Database, consolidate: Option<LinkMode>) -> Result<(), rusqlite::Error> {
+    let groups = db.get_duplicate_groups()?;
+    if groups.is_empty() {
+        println!("No duplicate files found.");
+        return Ok(());
+    }
+
+    let mut wasted_total = 0;
+    let mut reclaimed_total = 0;
+    for group in &groups {
+        let copies = existing_copies(group);
+        let distinct = distinct_count(&copies);
+        let wasted = group.size_bytes * distinct.saturating_sub(1) as u64;
+        wasted_total += wasted;
+
+        println!(
+            "{} bytes x {} copies ({} bytes wasted), sha256 {}",
+            group.size_bytes,
+            group.files.len(),
+            wasted,
+            &group.hash[..group.hash.len().min(12)]
+        );
+        for (folder_root, relative_path) in &group.files {
+            let path = folder_root.join(relative_path);
+            let missing = if copies.iter().any(|(_, p)| *p == path) {
+                ""
+            } else {
+                " (missing)"
+            };
+            println!("  {}{}", path.display(), missing);
+        }
+
+        if let Some(mode) = consolidate {
+            reclaimed_total += consolidate_group(group, &copies, mode);
+        }
+    }
+
+    println!();
+    println!(
+        "{} duplicate group(s), {} bytes wasted",
+        groups.len(),
+        wasted_total
+    );
+    if consolidate.is_some() {
+        println!("{} bytes reclaimed", reclaimed_total);
+    }
+    Ok(())
+}
+
+/// `(folder root, absolute path)` of the copies that still exist on disk.
+fn existing_copies(group: &DuplicateGroup) -> Vec<(PathBuf, PathBuf)> {
+    group
+        .files
+        .iter()
+        .map(|(root, relative)| (root.clone(), root.join(relative)))
+        .filter(|(_, path)| path.is_file())
+        .collect()
+}
+
+/// Copies that are already hardlinked to an earlier one use no extra space.
+fn distinct_count(copies: &[(PathBuf, PathBuf)]) -> usize {
+    copies
+        .iter()
+        .enumerate()
+        .filter(|(i, (_, path))| {
+            !copies[..*i]
+                .iter()
+                .any(|(_, earlier)| dedup::is_same_file(earlier, path).unwrap_or(false))
+        })
+        .count()
+}
+
+/// Links every copy to the first one and returns the bytes reclaimed. Files
+/// are re-hashed first so content changed since indexing is never replaced.
+fn consolidate_group(group: &DuplicateGroup, copies: &[(PathBuf, PathBuf)], mode: LinkMode) -> u64 {
+    let Some(((_, source), duplicates)) = copies.split_first() else {
+        return 0;
+    };
+    if calculate_hash(source).ok().as_deref() != Some(group.hash.as_str()) {
+        eprintln!(
+            "[DEDUP] {:?} changed since it was indexed; skipping group",
+            source
+        );
+        return 0;
+    }
+
+    let mut reclaimed = 0;
+    for (folder_root, duplicate) in duplicates {
+        if dedup::is_same_file(source, duplicate).unwrap_or(false) {
+            continue;
+        }
+        if calculate_hash(duplicate).ok().as_deref() != Some(group.hash.as_str()) {
+            eprintln!(
+                "[DEDUP] {:?} changed since it was indexed; skipping",
+                duplicate
+            );
+            continue;
+        }
+
+        match dedup::link_duplicate(folder_root, source, duplicate, mode) {
+            Ok(()) => {
+                println!("[DEDUP] Linked {:?} -> {:?}", duplicate, source);
+                reclaimed += group.size_bytes;
+            }
+            Err(e) => eprintln!("[DEDUP] Failed to link {:?}: {}", duplicate, e),
+        }
+    }
+    reclaimed
+}
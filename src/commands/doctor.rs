@@ -0,0 +1,424 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::time::Duration;
+
+use sync_rs::config::{self, Config};
+use sync_rs::control::SyncControl;
+use sync_rs::database::{DB_PATH, Database, SyncedFolder};
+use sync_rs::ignore;
+use sync_rs::instance_lock::{InstanceLock, LockError};
+use sync_rs::mqtt;
+use walkdir::WalkDir;
+
+use crate::cli::DoctorArgs;
+
+/// Watch usage above this share of the inotify limit is reported.
+#[cfg(target_os = "linux")]
+const INOTIFY_WARN_RATIO: f64 = 0.8;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Default)]
+struct Report {
+    failures: usize,
+    warnings: usize,
+}
+
+impl Report {
+    fn ok(&mut self, check: &str, message: impl AsRef<str>) {
+        println!("[OK]   {}: {}", check, message.as_ref());
+    }
+
+    fn skip(&mut self, check: &str, message: impl AsRef<str>) {
+        println!("[SKIP] {}: {}", check, message.as_ref());
+    }
+
+    fn warn(&mut self, check: &str, message: impl AsRef<str>, hint: impl AsRef<str>) {
+        self.warnings += 1;
+        println!("[WARN] {}: {}", check, message.as_ref());
+        println!("       -> {}", hint.as_ref());
+    }
+
+    fn fail(&mut self, check: &str, message: impl AsRef<str>, hint: impl AsRef<str>) {
+        self.failures += 1;
+        println!("[FAIL] {}: {}", check, message.as_ref());
+        println!("       -> {}", hint.as_ref());
+    }
+}
+
+/// Runs every check and returns false if any of them failed.
+pub fn run(args: &DoctorArgs) -> bool {
+    let mut report = Report::default();
+
+    check_data_dir(&mut report);
+    let running = check_instance(&mut report);
+    check_ports(&mut report, args, running);
+
+    check_config_file(&mut report);
+    // Opened read-only, so diagnosing never creates, migrates or otherwise
+    // changes it.
+    if !Path::new(DB_PATH).exists() {
+        report.warn(
+            "database",
+            format!("{} does not exist yet", DB_PATH),
+            "start the daemon here, or run it from the directory holding the database",
+        );
+    } else {
+        match Database::open_read_only() {
+            Ok(db) => {
+                check_database(&mut report, &db);
+                if check_schema(&mut report, &db) {
+                    let folders = match db.get_all_synced_folders() {
+                        Ok(folders) => folders,
+                        Err(e) => {
+                            report.fail(
+                                "folders",
+                                format!("failed to read folders: {}", e),
+                                "see the database findings above",
+                            );
+                            Vec::new()
+                        }
+                    };
+                    check_folders(&mut report, &folders);
+                    check_inotify(&mut report, &folders);
+                    check_config(&mut report, &db);
+                } else {
+                    report.skip("folders", "reading them needs the current schema");
+                }
+            }
+            Err(e) => report.fail(
+                "database",
+                format!("cannot open {}: {}", DB_PATH, e),
+                "check that the file is a sync_rs database and is readable",
+            ),
+        }
+    }
+
+    report.skip(
+        "peers",
+        "connectivity and clock skew checks need the peer transport, which is not available yet",
+    );
+
+    println!();
+    println!(
+        "{} failure(s), {} warning(s)",
+        report.failures, report.warnings
+    );
+    report.failures == 0
+}
+
+fn check_data_dir(report: &mut Report) {
+    let probe = Path::new(".sync_rs_doctor");
+    let written = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(probe)
+        .and_then(|mut file| file.write_all(b"sync_rs doctor"));
+    // Removed whether or not writing worked, as opening may have created it.
+    let removed = match std::fs::remove_file(probe) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        removed => removed,
+    };
+    match written.and(removed) {
+        Ok(()) => report.ok("data dir", "current directory is writable"),
+        Err(e) => report.fail(
+            "data dir",
+            format!("cannot write to the current directory: {}", e),
+            "run sync_rs from a directory owned by this user",
+        ),
+    }
+
+    match std::fs::metadata(DB_PATH) {
+        Ok(meta) if meta.permissions().readonly() => report.fail(
+            "data dir",
+            format!("{} is read-only", DB_PATH),
+            format!("make it writable, e.g. `chmod u+w {}`", DB_PATH),
+        ),
+        _ => {}
+    }
+}
+
+/// Returns true if a daemon currently holds the instance lock.
+fn check_instance(report: &mut Report) -> bool {
+    match InstanceLock::probe() {
+        Ok(()) => {
+            report.ok("daemon", "not running");
+            false
+        }
+        Err(LockError::AlreadyRunning { pid }) => {
+            let pid = pid.map_or_else(|| "unknown pid".to_string(), |pid| format!("pid {}", pid));
+            report.ok("daemon", format!("running ({})", pid));
+            true
+        }
+        Err(LockError::Io(e)) => {
+            report.fail(
+                "daemon",
+                format!("cannot check the instance lock: {}", e),
+                "check the permissions of the data directory",
+            );
+            false
+        }
+    }
+}
+
+fn check_ports(report: &mut Report, args: &DoctorArgs, running: bool) {
+    for (name, addr) in [("gRPC", args.grpc_listen), ("web UI", args.http_listen)] {
+        let check = format!("{} port", name);
+        if running {
+            check_reachable(report, &check, addr);
+        } else {
+            check_bindable(report, &check, addr);
+        }
+    }
+}
+
+fn check_reachable(report: &mut Report, check: &str, addr: SocketAddr) {
+    match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+        Ok(_) => report.ok(check, format!("daemon is listening on {}", addr)),
+        Err(e) => report.warn(
+            check,
+            format!("nothing answers on {}: {}", addr, e),
+            "the daemon may have been started with a different address, or failed to bind",
+        ),
+    }
+}
+
+fn check_bindable(report: &mut Report, check: &str, addr: SocketAddr) {
+    match TcpListener::bind(addr) {
+        Ok(_) => report.ok(check, format!("{} is available", addr)),
+        Err(e) => report.fail(
+            check,
+            format!("cannot bind {}: {}", addr, e),
+            "stop the program using it or pick another address with --grpc-listen/--http-listen",
+        ),
+    }
+}
+
+fn check_database(report: &mut Report, db: &Database) {
+    match db.integrity_check() {
+        Ok(problems) if problems.is_empty() => report.ok("database", "integrity check passed"),
+        Ok(problems) => {
+            for problem in &problems {
+                report.fail(
+                    "database",
+                    problem,
+                    "restore sync_rs.db from a backup or remove it to rebuild the index",
+                );
+            }
+        }
+        Err(e) => report.fail(
+            "database",
+            format!("integrity check failed to run: {}", e),
+            "the database may be corrupt; restore it from a backup",
+        ),
+    }
+}
+
+/// Returns true if the schema is current, so the rest of the database can
+/// be read.
+fn check_schema(report: &mut Report, db: &Database) -> bool {
+    match db.pending_migrations() {
+        Ok(0) => {
+            report.ok("database", "schema is up to date");
+            true
+        }
+        Ok(pending) => {
+            report.warn(
+                "database",
+                format!("{} schema migration(s) pending", pending),
+                "the next daemon start or other sync_rs command applies them; back up sync_rs.db first",
+            );
+            false
+        }
+        Err(e) => {
+            report.fail(
+                "database",
+                format!("cannot read the schema version: {}", e),
+                "the database may be corrupt; restore it from a backup",
+            );
+            false
+        }
+    }
+}
+
+fn check_folders(report: &mut Report, folders: &[SyncedFolder]) {
+    if folders.is_empty() {
+        report.warn(
+            "folders",
+            "no folders are registered",
+            "add one with `sync_rs folders add <path>`",
+        );
+    }
+
+    for folder in folders {
+        let check = format!("folder {}", folder.name);
+        match std::fs::metadata(&folder.local_path) {
+            Ok(meta) if !meta.is_dir() => report.fail(
+                &check,
+                format!("{} is not a directory", folder.local_path.display()),
+                "move the file away or re-add the folder at its new location",
+            ),
+            Ok(meta) if meta.permissions().readonly() => report.fail(
+                &check,
+                format!("{} is read-only", folder.local_path.display()),
+                "sync_rs needs write access to apply remote changes",
+            ),
+            Ok(_) => report.ok(&check, folder.local_path.display().to_string()),
+            Err(e) => report.fail(
+                &check,
+                format!("{}: {}", folder.local_path.display(), e),
+                "reconnect the drive or re-add the folder at its new location",
+            ),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn check_inotify(report: &mut Report, folders: &[SyncedFolder]) {
+    let read_limit = |name: &str| -> Option<u64> {
+        std::fs::read_to_string(format!("/proc/sys/fs/inotify/{}", name))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    };
+    let Some(max_watches) = read_limit("max_user_watches") else {
+        report.skip("inotify", "limits are not readable");
+        return;
+    };
+
+    // The recursive watcher needs one watch per directory.
+    let directories = folders
+        .iter()
+        .flat_map(|folder| {
+            WalkDir::new(&folder.local_path)
+                .into_iter()
+                .filter_entry(|e| !ignore::is_internal_path(e.path()))
+                .filter_map(Result::ok)
+                .filter(|e| e.file_type().is_dir())
+        })
+        .count() as u64;
+
+    let message = format!(
+        "{} directories to watch, max_user_watches is {}",
+        directories, max_watches
+    );
+    if directories as f64 > max_watches as f64 * INOTIFY_WARN_RATIO {
+        report.warn(
+            "inotify",
+            message,
+            format!(
+                "raise the limit, e.g. `sysctl fs.inotify.max_user_watches={}`",
+                (directories * 2).max(524_288)
+            ),
+        );
+    } else {
+        report.ok("inotify", message);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn check_inotify(report: &mut Report, _folders: &[SyncedFolder]) {
+    report.skip("inotify", "only applies to Linux");
+}
+
+fn check_config_file(report: &mut Report) {
+    match Config::load() {
+        Ok(config) if config.profiles.is_empty() => {}
+        Ok(config) => report.ok(
+            "config",
+            format!(
+                "{} defines {} folder profile(s)",
+                config::CONFIG_PATH,
+                config.profiles.len()
+            ),
+        ),
+        Err(e) => report.fail(
+            "config",
+            e.to_string(),
+            format!("fix {} before starting the daemon", config::CONFIG_PATH),
+        ),
+    }
+}
+
+fn check_config(report: &mut Report, db: &Database) {
+    match db.get_device_name() {
+        Ok(Some(name)) if name.trim().is_empty() => report.warn(
+            "config",
+            "device name is blank",
+            "set one with `sync_rs config set device-name <name>`",
+        ),
+        Ok(Some(name)) => report.ok("config", format!("device name is {:?}", name)),
+        Ok(None) => report.warn(
+            "config",
+            "no device name set; peers will only see the device ID",
+            "set one with `sync_rs config set device-name <name>`",
+        ),
+        Err(e) => report.fail(
+            "config",
+            format!("cannot read device name: {}", e),
+            "see the database findings above",
+        ),
+    }
+
+    if let Ok(control) = SyncControl::load(db) {
+        if control.is_paused() {
+            report.warn(
+                "config",
+                "sync is paused",
+                "resume it with `sync_rs resume --all`",
+            );
+        }
+        if control.in_maintenance() {
+            report.warn(
+                "config",
+                "maintenance mode is on; nothing is watched or transferred",
+                "turn it off with `sync_rs maintenance off`",
+            );
+        }
+    }
+
+    match db.get_setting(mqtt::URL_SETTING) {
+        Ok(Some(url)) => match mqtt::BrokerAddress::parse(&url) {
+            Err(e) => report.fail(
+                "config",
+                format!("mqtt-url {:?} is invalid: {}", url, e),
+                "fix it with `sync_rs config set mqtt-url mqtt://host:1883`",
+            ),
+            Ok(_) if !cfg!(feature = "mqtt") => report.warn(
+                "config",
+                "mqtt-url is set but this build lacks the `mqtt` feature",
+                "rebuild with `--features mqtt` or `sync_rs config unset mqtt-url`",
+            ),
+            Ok(_) => report.ok("config", format!("publishing to MQTT broker {}", url)),
+        },
+        Ok(None) => {}
+        Err(e) => report.fail(
+            "config",
+            format!("cannot read mqtt-url: {}", e),
+            "see the database findings above",
+        ),
+    }
+
+    match db.get_webhooks() {
+        Ok(hooks) => {
+            for hook in hooks {
+                if !hook.url.starts_with("http://") && !hook.url.starts_with("https://") {
+                    report.fail(
+                        "config",
+                        format!("webhook {} has an invalid URL {:?}", hook.id, hook.url),
+                        format!("remove it with `sync_rs webhooks remove {}`", hook.id),
+                    );
+                }
+            }
+        }
+        Err(e) => report.fail(
+            "config",
+            format!("cannot read webhooks: {}", e),
+            "see the database findings above",
+        ),
+    }
+}
@@ -0,0 +1,72 @@
+use std::path::Path;
+
+use sync_rs::apply::{self, IncomingFile};
+use sync_rs::database::Database;
+use sync_rs::error::{self, SyncError};
+use sync_rs::placeholder::{self, Placeholder};
+use sync_rs::suppression::ExpectedChanges;
+
+pub fn run(db: &Database, path: &Path) -> error::Result<()> {
+    let mut absolute =
+        std::path::absolute(path).map_err(|e| SyncError::io("failed to resolve", path, e))?;
+    if let Some(target) = placeholder::target_path(&absolute) {
+        absolute = target;
+    }
+    let Some((folder, relative)) = db.find_folder_containing(&absolute)? else {
+        return Err(SyncError::NotInSyncedFolder(absolute));
+    };
+    if folder.observer {
+        return Err(SyncError::ObserverFolder(folder.name));
+    }
+    if !folder.is_unsynced(&relative) {
+        println!(
+            "[FETCH] {:?} is synced locally already; nothing to fetch",
+            absolute
+        );
+        return Ok(());
+    }
+
+    let stub = placeholder::read(&absolute)
+        .map_err(|e| SyncError::io("failed to read placeholder of", &absolute, e))?;
+    if let Some(stub) = stub
+        && fill_from_local_copy(db, &folder.local_path, &relative, &stub)?
+    {
+        return Ok(());
+    }
+
+    db.request_fetch(folder.id, &relative)?;
+    println!(
+        "[FETCH] Queued {:?} in {}; it is downloaded once a peer that has it is connected",
+        relative, folder.name
+    );
+    Ok(())
+}
+
+/// Replaces a placeholder with a copy of identical content found in any
+/// synced folder. Returns false when there is no intact copy.
+fn fill_from_local_copy(
+    db: &Database,
+    folder_root: &Path,
+    relative_path: &Path,
+    stub: &Placeholder,
+) -> error::Result<bool> {
+    let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(stub.modified_secs);
+    let file = IncomingFile {
+        relative_path,
+        hash: &stub.hash,
+        modified,
+        xattrs: None,
+    };
+    // The daemon sees the new file as a regular change; its hash matches
+    // the index, so it is not announced as modified.
+    let expected_changes = ExpectedChanges::default();
+    for source in db.find_files_by_hash(&stub.hash)? {
+        if source.exists()
+            && apply::copy_local_file(folder_root, file, &source, &expected_changes).is_ok()
+        {
+            println!("[FETCH] Filled {:?} from a local copy", relative_path);
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
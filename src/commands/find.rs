@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+use std::time::UNIX_EPOCH;
+
+use sync_rs::database::{Database, FileQuery, FoundFile, MAX_FIND_RESULTS, SyncedFolder};
+use sync_rs::error::{self, SyncError};
+use sync_rs::placeholder;
+
+use super::status::format_time;
+use crate::cli::FindArgs;
+
+pub fn run(db: &Database, args: FindArgs) -> error::Result<()> {
+    let folder_id = match args.folder {
+        Some(name) => match db.find_folder(&name)? {
+            Some(folder) => Some(folder.id),
+            None => return Err(SyncError::UnknownFolder(name)),
+        },
+        None => None,
+    };
+    let query = FileQuery {
+        pattern: args.pattern,
+        folder_id,
+        min_size: args.min_size,
+        modified_since_secs: args.modified_since.map(|time| {
+            time.duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs())
+        }),
+    };
+
+    let files = db.find_files(&query)?;
+    if files.is_empty() {
+        println!("No matching files.");
+        return Ok(());
+    }
+
+    let folders: HashMap<i64, SyncedFolder> = db
+        .get_all_synced_folders()?
+        .into_iter()
+        .map(|folder| (folder.id, folder))
+        .collect();
+    let device_id = db.get_or_create_device_id()?;
+    for file in &files {
+        let Some(folder) = folders.get(&file.folder_id) else {
+            continue;
+        };
+        println!(
+            "{} ({} bytes, modified {})",
+            folder.local_path.join(&file.relative_path).display(),
+            file.size_bytes,
+            format_time(file.modified_secs)
+        );
+        println!(
+            "    {}; changed by {}",
+            local_state(db, folder, file)?,
+            devices(file, &device_id)
+        );
+        let links = db.get_link_group(folder.id, &file.relative_path)?;
+        if !links.is_empty() {
+            let links: Vec<_> = links.iter().map(|link| link.to_string_lossy()).collect();
+            println!("    hard link of {}", links.join(", "));
+        }
+    }
+    if files.len() == MAX_FIND_RESULTS {
+        println!("Showing the first {} matches only.", MAX_FIND_RESULTS);
+    }
+    Ok(())
+}
+
+/// Whether the file's content is on this device, and if not, why.
+fn local_state(
+    db: &Database,
+    folder: &SyncedFolder,
+    file: &FoundFile,
+) -> Result<&'static str, rusqlite::Error> {
+    let path = folder.local_path.join(&file.relative_path);
+    Ok(
+        if db
+            .get_locked_file(folder.id, &file.relative_path)?
+            .is_some()
+        {
+            "in use by another process, retrying"
+        } else if path.exists() {
+            "on this device"
+        } else if folder.observer {
+            "index only (observer)"
+        } else if db.is_fetch_requested(folder.id, &file.relative_path)? {
+            "fetch requested"
+        } else if placeholder::placeholder_path(&path).exists() {
+            "placeholder only"
+        } else if folder.is_unsynced(&file.relative_path) {
+            "not synced locally"
+        } else {
+            "missing on this device"
+        },
+    )
+}
+
+/// Devices from the file's version vector, e.g. `this device (2), 4f1c9a2e (1)`.
+fn devices(file: &FoundFile, device_id: &str) -> String {
+    let devices: Vec<String> = file
+        .version_vector
+        .iter()
+        .map(|(device, counter)| {
+            let label = if device == device_id {
+                "this device"
+            } else {
+                &device[..device.len().min(8)]
+            };
+            format!("{} ({})", label, counter)
+        })
+        .collect();
+    if devices.is_empty() {
+        "no recorded device".to_string()
+    } else {
+        devices.join(", ")
+    }
+}
@@ -0,0 +1,455 @@
+use std::io;
+use std::path::{Component, PathBuf};
+
+use sync_rs::config::Config;
+use sync_rs::database::{Database, SyncedFolder};
+use sync_rs::error::{self, SyncError};
+use sync_rs::mirror;
+use sync_rs::output;
+use sync_rs::placeholder;
+use sync_rs::removable;
+
+use super::print_json;
+use super::status::format_bytes;
+use crate::cli::{FolderAction, OutputFormat};
+
+pub fn run(db: &Database, action: FolderAction) -> error::Result<()> {
+    match action {
+        FolderAction::List { format } => {
+            let folders = output::list_folders(db)?;
+            if format == OutputFormat::Json {
+                print_json(&folders);
+                return Ok(());
+            }
+            if folders.is_empty() {
+                println!("No synced folders.");
+            }
+            for folder in folders {
+                println!(
+                    "  {}: {} [{}] {}: {} files, {}",
+                    folder.id,
+                    folder.name,
+                    folder.folder_uuid,
+                    folder.local_path,
+                    folder.file_count,
+                    format_bytes(folder.total_bytes)
+                );
+            }
+        }
+        FolderAction::Add {
+            path,
+            name,
+            profile,
+            folder_uuid,
+        } => add(db, path, name, profile, folder_uuid)?,
+        FolderAction::Set {
+            folder,
+            ignore_hidden,
+            sync_xattrs,
+            sync_hardlinks,
+            profile,
+            quota,
+            placeholders,
+            observer,
+            manual_push,
+            hash_threads,
+            settle_secs,
+            mirror_of,
+            no_mirror,
+            removable,
+        } => {
+            let Some(found) = db.find_folder(&folder)? else {
+                return Err(SyncError::UnknownFolder(folder));
+            };
+            if ignore_hidden.is_none()
+                && sync_xattrs.is_none()
+                && sync_hardlinks.is_none()
+                && profile.is_none()
+                && quota.is_none()
+                && placeholders.is_none()
+                && observer.is_none()
+                && manual_push.is_none()
+                && hash_threads.is_none()
+                && settle_secs.is_none()
+                && mirror_of.is_none()
+                && !no_mirror
+                && removable.is_none()
+            {
+                println!("[FOLDERS] Nothing to change for {}", found.name);
+                return Ok(());
+            }
+
+            // Applied first so an explicit --ignore-hidden overrides the profile.
+            if let Some(profile) = profile {
+                let config = Config::load()?;
+                if let Some((name, options)) = config.profile(Some(&profile))? {
+                    db.apply_folder_profile(found.id, name, options)?;
+                    println!("[FOLDERS] {}: applied profile {:?}", found.name, name);
+                    println!("[FOLDERS] Restart the daemon for a new debounce to take effect.");
+                }
+            }
+            if let Some(ignore_hidden) = ignore_hidden {
+                db.set_folder_ignore_hidden(found.id, ignore_hidden)?;
+                println!(
+                    "[FOLDERS] {}: hidden files are now {}",
+                    found.name,
+                    if ignore_hidden { "ignored" } else { "synced" }
+                );
+            }
+            if ignore_hidden == Some(true) {
+                println!("[FOLDERS] Hidden files already in the index are kept.");
+            }
+            if let Some(sync_xattrs) = sync_xattrs {
+                db.set_folder_sync_xattrs(found.id, sync_xattrs)?;
+                println!(
+                    "[FOLDERS] {}: extended attributes are now {}",
+                    found.name,
+                    if sync_xattrs { "synced" } else { "not synced" }
+                );
+            }
+            if let Some(sync_hardlinks) = sync_hardlinks {
+                db.set_folder_sync_hardlinks(found.id, sync_hardlinks)?;
+                if sync_hardlinks {
+                    println!(
+                        "[FOLDERS] {}: hard links are now synced; existing links are found by the next scan",
+                        found.name
+                    );
+                } else {
+                    println!("[FOLDERS] {}: hard links are no longer synced", found.name);
+                }
+            }
+            if let Some(quota) = quota {
+                let quota = (quota > 0).then_some(quota);
+                db.set_folder_quota(found.id, quota)?;
+                match quota {
+                    Some(bytes) => {
+                        println!("[FOLDERS] {}: quota set to {} bytes", found.name, bytes)
+                    }
+                    None => println!("[FOLDERS] {}: quota removed", found.name),
+                }
+            }
+            if let Some(placeholders) = placeholders {
+                db.set_folder_placeholders(found.id, placeholders)?;
+                let changed = if placeholders {
+                    write_placeholders(db, &found)?
+                } else {
+                    placeholder::remove_all(&found.local_path).map_err(|e| {
+                        SyncError::io("failed to remove placeholders in", &found.local_path, e)
+                    })?
+                };
+                println!(
+                    "[FOLDERS] {}: placeholders {} ({} {})",
+                    found.name,
+                    if placeholders { "enabled" } else { "disabled" },
+                    changed,
+                    if placeholders { "written" } else { "removed" }
+                );
+            }
+            if let Some(hash_threads) = hash_threads {
+                db.set_folder_hash_threads(found.id, hash_threads)?;
+                match hash_threads {
+                    0 => println!("[FOLDERS] {}: hash threads set to automatic", found.name),
+                    n => println!("[FOLDERS] {}: scans hash {} file(s) at once", found.name, n),
+                }
+            }
+            if let Some(settle_secs) = settle_secs {
+                db.set_folder_settle_secs(found.id, settle_secs)?;
+                match settle_secs {
+                    0 => println!("[FOLDERS] {}: changes are indexed right away", found.name),
+                    n => println!(
+                        "[FOLDERS] {}: files are indexed once unchanged for {}s",
+                        found.name, n
+                    ),
+                }
+            }
+            if let Some(manual_push) = manual_push {
+                let pending = db.get_pending_changes(found.id)?.len();
+                db.set_folder_manual_push(found.id, manual_push)?;
+                if manual_push {
+                    println!(
+                        "[FOLDERS] {}: local changes now wait for `sync_rs push`",
+                        found.name
+                    );
+                } else {
+                    println!(
+                        "[FOLDERS] {}: manual push off; {} pending change(s) approved",
+                        found.name, pending
+                    );
+                }
+            }
+            if let Some(source) = mirror_of {
+                let Some(source) = db.find_folder(&source)? else {
+                    return Err(SyncError::UnknownFolder(source));
+                };
+                if let Some(reason) = mirror::mirror_conflict(&source, &found) {
+                    eprintln!(
+                        "[FOLDERS] {} cannot mirror {}: {}",
+                        found.name, source.name, reason
+                    );
+                    return Ok(());
+                }
+                db.set_folder_mirror_of(found.id, Some(source.id))?;
+                println!(
+                    "[FOLDERS] {}: now a mirror of {}; the daemon copies it over",
+                    found.name, source.name
+                );
+            }
+            if no_mirror {
+                db.set_folder_mirror_of(found.id, None)?;
+                println!("[FOLDERS] {}: no longer a mirror", found.name);
+            }
+            if let Some(is_removable) = removable {
+                if is_removable {
+                    removable::write_marker(&found).map_err(|e| {
+                        SyncError::io("failed to write drive marker in", &found.local_path, e)
+                    })?;
+                    println!(
+                        "[FOLDERS] {}: removable; paused whenever {:?} is missing",
+                        found.name,
+                        found.local_path.join(removable::MARKER_FILE)
+                    );
+                } else {
+                    removable::remove_marker(&found).map_err(|e| {
+                        SyncError::io("failed to remove drive marker in", &found.local_path, e)
+                    })?;
+                    println!("[FOLDERS] {}: no longer removable", found.name);
+                }
+                db.set_folder_removable(found.id, is_removable)?;
+            }
+            if let Some(observer) = observer {
+                db.set_folder_observer(found.id, observer)?;
+                if observer {
+                    println!(
+                        "[FOLDERS] {}: now an observer; only the index of peers is kept",
+                        found.name
+                    );
+                } else {
+                    println!(
+                        "[FOLDERS] {}: no longer an observer; content is synced again",
+                        found.name
+                    );
+                }
+            }
+        }
+        FolderAction::Unsync { folder, paths } => {
+            let Some(found) = db.find_folder(&folder)? else {
+                return Err(SyncError::UnknownFolder(folder));
+            };
+            let Some(paths) = relative_paths(paths) else {
+                return Ok(());
+            };
+
+            let mut unsynced = found.unsynced_paths.clone();
+            for path in paths {
+                if found.is_unsynced(&path) {
+                    println!("[FOLDERS] {:?} is already not synced locally", path);
+                    continue;
+                }
+                // A new parent replaces the subpaths it covers.
+                unsynced.retain(|existing| !existing.starts_with(&path));
+                println!(
+                    "[FOLDERS] {}: {:?} is no longer synced locally",
+                    found.name, path
+                );
+                unsynced.push(path);
+            }
+            unsynced.sort();
+            db.set_folder_unsynced_paths(found.id, &unsynced)?;
+        }
+        FolderAction::Resync { folder, paths } => {
+            let Some(found) = db.find_folder(&folder)? else {
+                return Err(SyncError::UnknownFolder(folder));
+            };
+            let Some(paths) = relative_paths(paths) else {
+                return Ok(());
+            };
+
+            let mut unsynced = found.unsynced_paths.clone();
+            for path in paths {
+                if !unsynced.contains(&path) {
+                    println!("[FOLDERS] {:?} was not marked with unsync", path);
+                    continue;
+                }
+                unsynced.retain(|existing| *existing != path);
+                println!(
+                    "[FOLDERS] {}: {:?} is synced locally again",
+                    found.name, path
+                );
+            }
+            db.set_folder_unsynced_paths(found.id, &unsynced)?;
+        }
+    }
+    Ok(())
+}
+
+/// Registers `path` like the gRPC AddFolder call, without watching it; a
+/// running daemon picks it up on reload.
+fn add(
+    db: &Database,
+    path: PathBuf,
+    name: Option<String>,
+    profile: Option<String>,
+    folder_uuid: Option<String>,
+) -> error::Result<()> {
+    let path =
+        std::fs::canonicalize(&path).map_err(|e| SyncError::io("failed to resolve", &path, e))?;
+    if !path.is_dir() {
+        return Err(SyncError::io(
+            "cannot add",
+            &path,
+            io::Error::new(io::ErrorKind::NotADirectory, "not a directory"),
+        ));
+    }
+    let path_str = error::path_str(&path)?;
+    if db.get_folder_by_path(path_str)?.is_some() {
+        return Err(SyncError::io(
+            "cannot add",
+            &path,
+            io::Error::new(io::ErrorKind::AlreadyExists, "the folder is already synced"),
+        ));
+    }
+    let name = name.unwrap_or_else(|| {
+        path.file_name().map_or_else(
+            || path_str.to_string(),
+            |n| n.to_string_lossy().into_owned(),
+        )
+    });
+    // Looked up first so an unknown profile registers nothing.
+    let config = Config::load()?;
+    let profile = config.profile(profile.as_deref())?;
+
+    let folder_id = match &folder_uuid {
+        Some(folder_uuid) => db.add_shared_folder(folder_uuid, &name, path_str)?,
+        None => db.add_folder(&name, path_str)?,
+    };
+    if let Some((profile_name, options)) = profile {
+        db.apply_folder_profile(folder_id, profile_name, options)?;
+        println!("[FOLDERS] {}: applied profile {:?}", name, profile_name);
+    }
+    println!(
+        "[FOLDERS] Added folder {}: {} -> {}",
+        folder_id, name, path_str
+    );
+    println!("[FOLDERS] Run `sync_rs reload` for a running daemon to start watching it.");
+    Ok(())
+}
+
+/// Writes placeholders for the unsynced files of `folder` that are indexed
+/// but missing locally, and returns how many were written.
+fn write_placeholders(db: &Database, folder: &SyncedFolder) -> error::Result<usize> {
+    let mut written = 0;
+    for path in db
+        .get_folders_and_files(folder.id, &folder.local_path)?
+        .into_keys()
+    {
+        let Ok(relative) = path.strip_prefix(&folder.local_path) else {
+            continue;
+        };
+        if !folder.is_unsynced(relative) || path.exists() {
+            continue;
+        }
+        if let Some(stub) = db.get_placeholder(folder.id, relative)? {
+            placeholder::write(&folder.local_path, relative, &stub)
+                .map_err(|e| SyncError::io("failed to write placeholder for", &path, e))?;
+            written += 1;
+        }
+    }
+    Ok(written)
+}
+
+/// Normalizes paths given relative to a folder root, rejecting any that
+/// could point outside it.
+fn relative_paths(paths: Vec<PathBuf>) -> Option<Vec<PathBuf>> {
+    let mut normalized = Vec::with_capacity(paths.len());
+    for path in paths {
+        let relative: PathBuf = path
+            .components()
+            .filter(|component| *component != Component::CurDir)
+            .collect();
+        let inside = relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if !inside || relative.as_os_str().is_empty() {
+            eprintln!("[FOLDERS] {:?} is not a path inside the folder", path);
+            return None;
+        }
+        normalized.push(relative);
+    }
+    Some(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory to add and a database in the temporary directory, both
+    /// removed on drop.
+    struct TestSetup {
+        db: Database,
+        dir: PathBuf,
+    }
+
+    impl TestSetup {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("sync_rs-test-{}-{}", std::process::id(), name));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(dir.join("photos")).unwrap();
+            Self {
+                db: Database::open(&dir.join("sync_rs.db")).unwrap(),
+                dir,
+            }
+        }
+    }
+
+    impl Drop for TestSetup {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn add_registers_the_directory_under_its_name() {
+        let setup = TestSetup::new("folders-add");
+        add(&setup.db, setup.dir.join("photos"), None, None, None).unwrap();
+
+        let path = std::fs::canonicalize(setup.dir.join("photos")).unwrap();
+        let folder = setup
+            .db
+            .get_folder_by_path(path.to_str().unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(folder.name, "photos");
+    }
+
+    #[test]
+    fn add_rejects_a_folder_that_is_synced_already() {
+        let setup = TestSetup::new("folders-add-twice");
+        add(&setup.db, setup.dir.join("photos"), None, None, None).unwrap();
+
+        let again = add(
+            &setup.db,
+            setup.dir.join("photos"),
+            Some("again".into()),
+            None,
+            None,
+        );
+        assert!(again.is_err());
+        assert_eq!(setup.db.get_all_synced_folders().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn add_registers_nothing_for_an_unknown_profile() {
+        let setup = TestSetup::new("folders-add-profile");
+        let added = add(
+            &setup.db,
+            setup.dir.join("photos"),
+            None,
+            Some("no-such-profile".into()),
+            None,
+        );
+
+        assert!(matches!(added, Err(SyncError::UnknownProfile(_))));
+        assert!(setup.db.get_all_synced_folders().unwrap().is_empty());
+    }
+}
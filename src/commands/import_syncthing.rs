@@ -0,0 +1,120 @@
+use std::collections::BTreeSet;
+
+use sync_rs::database::Database;
+use sync_rs::error;
+use sync_rs::syncthing::{self, SyncthingFolder};
+
+use crate::cli::ImportSyncthingArgs;
+
+pub fn run(db: &Database, args: &ImportSyncthingArgs) -> error::Result<()> {
+    let config = syncthing::read_config(&args.config)?;
+    let prefix = if args.dry_run {
+        "Would import"
+    } else {
+        "Imported"
+    };
+
+    // Devices sharing at least one imported folder.
+    let mut shared_with = BTreeSet::new();
+    let mut imported = 0;
+    for folder in &config.folders {
+        if let Some(reason) = skip_reason(db, folder)? {
+            println!("[IMPORT] Skipping {}: {}", folder.name(), reason);
+            continue;
+        }
+        let ignores = syncthing::convert_stignore(&folder.path)?;
+        let name = free_name(db, folder)?;
+        println!(
+            "[IMPORT] {} {} at {:?} ({} ignore pattern(s){})",
+            prefix,
+            name,
+            folder.path,
+            ignores.patterns.len(),
+            if folder.folder_type == "receiveonly" {
+                ", receive only"
+            } else {
+                ""
+            }
+        );
+        for (line, reason) in &ignores.skipped {
+            println!("[IMPORT]   ignore line {:?} left out: {}", line, reason);
+        }
+        if folder.folder_type == "sendonly" {
+            println!("[IMPORT]   send-only has no equivalent; the folder sends and receives");
+        }
+        if folder.paused {
+            println!("[IMPORT]   paused in Syncthing; it syncs once the daemon runs");
+        }
+        shared_with.extend(folder.device_ids.iter().map(String::as_str));
+        imported += 1;
+        if args.dry_run {
+            continue;
+        }
+
+        let path = error::path_str(&folder.path)?;
+        // Keeping Syncthing's folder ID lets devices that import the same
+        // folder recognize it as shared.
+        let folder_id = db.add_shared_folder(&folder.id, &name, path)?;
+        db.set_folder_ignore_patterns(folder_id, &ignores.patterns)?;
+        db.set_folder_receive_only(folder_id, folder.folder_type == "receiveonly")?;
+        db.set_folder_sync_xattrs(folder_id, folder.sync_xattrs)?;
+    }
+
+    let mut peers = 0;
+    for device in &config.devices {
+        if Some(&device.id) == args.this_device.as_ref()
+            || !shared_with.contains(device.id.as_str())
+        {
+            continue;
+        }
+        let label = device.name.as_deref().unwrap_or(&device.id);
+        if args.dry_run || db.add_peer(&device.id, device.name.as_deref())? {
+            println!("[IMPORT] {} peer {}", prefix, label);
+            peers += 1;
+        }
+    }
+
+    println!(
+        "[IMPORT] {} {} folder(s) and {} peer(s) from {:?}",
+        prefix, imported, peers, args.config
+    );
+    if args.this_device.is_none() && peers > 0 {
+        println!("[IMPORT] Pass --this-device to leave this machine's own entry out of the peers.");
+    }
+    if imported > 0 && !args.dry_run {
+        println!("[IMPORT] Restart the daemon to start watching the imported folders.");
+    }
+    Ok(())
+}
+
+fn skip_reason(db: &Database, folder: &SyncthingFolder) -> error::Result<Option<String>> {
+    if folder.folder_type == "receiveencrypted" {
+        return Ok(Some(
+            "untrusted (encrypted) folders are not supported".to_string(),
+        ));
+    }
+    if !folder.path.is_dir() {
+        return Ok(Some(format!("{:?} does not exist", folder.path)));
+    }
+    if let Some(existing) = db.get_folder_by_uuid(&folder.id)? {
+        return Ok(Some(format!("already synced as {}", existing.name)));
+    }
+    if let Some(existing) = db.get_folder_by_path(error::path_str(&folder.path)?)? {
+        return Ok(Some(format!(
+            "its path is synced already as {}",
+            existing.name
+        )));
+    }
+    Ok(None)
+}
+
+/// The folder's Syncthing name, or with its ID appended when a folder of
+/// that name exists already.
+fn free_name(db: &Database, folder: &SyncthingFolder) -> error::Result<String> {
+    let name = folder.name();
+    if db.find_folder(name)?.is_none() {
+        Ok(name.to_string())
+    } else {
+        Ok(format!("{} ({})", name, folder.id))
+    }
+}
@@ -0,0 +1,14 @@
+use std::path::Path;
+
+use clap::CommandFactory;
+
+use crate::cli::Cli;
+
+/// Writes `sync_rs.1` plus one page per subcommand (`sync_rs-config.1`, ...)
+/// into `dir`, creating it if needed.
+pub fn run(dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    clap_mangen::generate_to(Cli::command(), dir)?;
+    println!("[MANPAGES] Wrote man pages to {}", dir.display());
+    Ok(())
+}
@@ -0,0 +1,31 @@
+pub mod backup;
+pub mod bench;
+pub mod completions;
+pub mod config;
+pub mod conflicts;
+pub mod control;
+pub mod dedup_report;
+pub mod doctor;
+pub mod fetch;
+pub mod find;
+pub mod folders;
+pub mod import_syncthing;
+pub mod manpages;
+pub mod move_folder;
+pub mod push;
+pub mod replay;
+pub mod service;
+pub mod snapshot;
+pub mod stats;
+pub mod status;
+pub mod tags;
+pub mod versions;
+pub mod webhooks;
+
+/// Prints `value` for `--format json`.
+pub fn print_json(value: &impl serde::Serialize) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).unwrap_or_default()
+    );
+}
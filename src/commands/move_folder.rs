@@ -0,0 +1,78 @@
+use sync_rs::database::Database;
+use sync_rs::error::{self, SyncError};
+use sync_rs::instance_lock::{InstanceLock, LockError};
+use sync_rs::relocate;
+
+use crate::cli::MoveFolderArgs;
+
+pub fn run(db: &Database, args: MoveFolderArgs) -> error::Result<()> {
+    // Held until the move is recorded, so a daemon started meanwhile does
+    // not scan the old location and delete everything on peers.
+    let _lock = match InstanceLock::acquire() {
+        Ok(lock) => lock,
+        Err(LockError::AlreadyRunning { .. }) => {
+            eprintln!("[MOVE] The daemon is running; stop it before moving a folder");
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let Some(folder) = db.find_folder(&args.folder)? else {
+        return Err(SyncError::UnknownFolder(args.folder));
+    };
+    let new_root = std::fs::canonicalize(&args.new_path)
+        .map_err(|e| SyncError::io("failed to resolve", &args.new_path, e))?;
+    if !new_root.is_dir() {
+        eprintln!("[MOVE] {:?} is not a directory", new_root);
+        return Ok(());
+    }
+    if new_root == folder.local_path {
+        println!("[MOVE] {} is at {:?} already", folder.name, new_root);
+        return Ok(());
+    }
+    if let Some((other, _)) = db.find_folder_containing(&new_root)?
+        && other.id != folder.id
+    {
+        eprintln!(
+            "[MOVE] {:?} is inside the synced folder {}",
+            new_root, other.name
+        );
+        return Ok(());
+    }
+
+    println!(
+        "[MOVE] Checking {:?} against the index of {}...",
+        new_root, folder.name
+    );
+    let checked = relocate::check(db, &folder, &new_root, args.spot_checks)?;
+    for path in &checked.missing {
+        println!("  missing: {}", path.display());
+    }
+    for path in &checked.changed {
+        println!("  differs: {}", path.display());
+    }
+    println!(
+        "[MOVE] {} file(s) match, {} with a new modification time; {} spot-checked; \
+         {} missing, {} differ",
+        checked.matched + checked.retimed.len(),
+        checked.retimed.len(),
+        checked.spot_checked,
+        checked.missing.len(),
+        checked.changed.len()
+    );
+    if !checked.is_intact() && !args.force {
+        eprintln!(
+            "[MOVE] Not moving {}: complete the copy, or pass --force to delete missing \
+             files on peers and sync the differing ones to them",
+            folder.name
+        );
+        return Ok(());
+    }
+
+    relocate::apply(db, &folder, &new_root, &checked)?;
+    println!(
+        "[MOVE] {} moved from {:?} to {:?}; the daemon watches the new location once started",
+        folder.name, folder.local_path, new_root
+    );
+    Ok(())
+}
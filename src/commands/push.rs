@@ -0,0 +1,57 @@
+use sync_rs::database::{Database, LocalChange, PendingChange};
+use sync_rs::error::{self, SyncError};
+
+use super::status::format_time;
+use crate::cli::PushArgs;
+
+pub fn run(db: &Database, args: PushArgs) -> error::Result<()> {
+    let Some(folder) = db.find_folder(&args.folder)? else {
+        return Err(SyncError::UnknownFolder(args.folder));
+    };
+    if !folder.manual_push {
+        println!(
+            "[PUSH] {} announces its changes right away; enable manual push with \
+             `sync_rs folders set {} --manual-push true`",
+            folder.name, folder.name
+        );
+        return Ok(());
+    }
+
+    if args.list {
+        let pending = db.get_pending_changes(folder.id)?;
+        if pending.is_empty() {
+            println!("No pending changes in {}.", folder.name);
+        }
+        for change in &pending {
+            println!("  {}", describe(change));
+        }
+        return Ok(());
+    }
+
+    let approved = db.approve_pending_changes(folder.id, &args.paths)?;
+    for change in &approved {
+        println!("[PUSH] Approved {}", describe(change));
+    }
+    println!(
+        "[PUSH] {}: {} change(s) approved, {} still pending",
+        folder.name,
+        approved.len(),
+        db.get_pending_changes(folder.id)?.len()
+    );
+    Ok(())
+}
+
+/// One line per change, e.g. `modified report.pdf (2024-05-01 12:00:00)`.
+fn describe(pending: &PendingChange) -> String {
+    let change = match &pending.change {
+        LocalChange::Modified => "modified".to_string(),
+        LocalChange::Removed => "removed".to_string(),
+        LocalChange::Renamed { from } => format!("renamed from {} to", from.display()),
+    };
+    format!(
+        "{} {} ({})",
+        change,
+        pending.relative_path.display(),
+        format_time(pending.detected_secs)
+    )
+}
@@ -0,0 +1,68 @@
+use chrono::{DateTime, Local};
+use sync_rs::config::Config;
+use sync_rs::db_pool::DbPool;
+use sync_rs::error::{self, SyncError};
+use sync_rs::instance_lock::{InstanceLock, LockError};
+use sync_rs::replay::{self, RecordedEvent, RecordedKind};
+
+use crate::cli::ReplayArgs;
+
+pub async fn run(args: ReplayArgs) -> error::Result<()> {
+    // The replayed events change the index like the daemon's would.
+    let _lock = if args.list {
+        None
+    } else {
+        match InstanceLock::acquire() {
+            Ok(lock) => Some(lock),
+            Err(LockError::AlreadyRunning { .. }) => {
+                eprintln!("[REPLAY] The daemon is running; stop it before replaying events");
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+
+    let db = DbPool::open(1)?;
+    let since = replay::unix_millis(args.since);
+    let until = args.until.map_or(i64::MAX as u64, replay::unix_millis);
+    let mut recorded = db.read().await.get_recorded_events(since, until)?;
+    if let Some(folder) = &args.folder {
+        let Some(found) = db.read().await.find_folder(folder)? else {
+            return Err(SyncError::UnknownFolder(folder.clone()));
+        };
+        recorded.retain(|event| event.path.starts_with(&found.local_path));
+    }
+    if recorded.is_empty() {
+        println!("No watcher events recorded in that time.");
+        return Ok(());
+    }
+
+    for event in &recorded {
+        println!("  {}", describe(event));
+    }
+    if args.list {
+        return Ok(());
+    }
+    println!("[REPLAY] Replaying {} event(s)...", recorded.len());
+    replay::replay(db, Config::load()?, &recorded).await?;
+    println!("[REPLAY] Done");
+    Ok(())
+}
+
+/// One line per event, e.g. `2026-01-31 14:05:01.250 modify "/home/a/x.txt"`.
+fn describe(event: &RecordedEvent) -> String {
+    let time = DateTime::from_timestamp_millis(event.recorded_ms as i64).map_or_else(
+        || event.recorded_ms.to_string(),
+        |time| {
+            time.with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S%.3f")
+                .to_string()
+        },
+    );
+    match &event.kind {
+        RecordedKind::Rename { old_path } => {
+            format!("{} rename {:?} -> {:?}", time, old_path, event.path)
+        }
+        kind => format!("{} {} {:?}", time, kind.as_str(), event.path),
+    }
+}
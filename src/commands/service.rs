@@ -0,0 +1,57 @@
+use sync_rs::error::{self, SyncError};
+use sync_rs::service::{self, ServiceSpec};
+
+use crate::cli::ServiceAction;
+
+pub fn run(action: ServiceAction) -> error::Result<()> {
+    match action {
+        ServiceAction::Install {
+            grpc_listen,
+            http_listen,
+            dry_run,
+        } => {
+            let args = vec![
+                "run".to_string(),
+                "--grpc-listen".to_string(),
+                grpc_listen.to_string(),
+                "--http-listen".to_string(),
+                http_listen.to_string(),
+            ];
+            let spec = ServiceSpec::current(args).map_err(failed("install"))?;
+            if dry_run {
+                if let Some(path) = service::definition_path().map_err(failed("install"))? {
+                    println!("Would write {}:", path.display());
+                }
+                print!("{}", service::definition(&spec));
+                println!();
+                return Ok(());
+            }
+            service::install(&spec).map_err(failed("install"))?;
+            println!(
+                "[SERVICE] Installed; the daemon runs in {} from the next {}, \
+                 or now after `sync_rs service start`",
+                spec.data_dir.display(),
+                if cfg!(windows) { "boot" } else { "login" }
+            );
+            if cfg!(target_os = "linux") {
+                println!(
+                    "[SERVICE] To keep it running while logged out: loginctl enable-linger {}",
+                    std::env::var("USER").unwrap_or_else(|_| "$USER".to_string())
+                );
+            }
+        }
+        ServiceAction::Uninstall => {
+            service::uninstall().map_err(failed("uninstall"))?;
+            println!("[SERVICE] Uninstalled; the database and sync_rs.toml are kept");
+        }
+        ServiceAction::Start => {
+            service::start().map_err(failed("start"))?;
+            println!("[SERVICE] Started");
+        }
+    }
+    Ok(())
+}
+
+fn failed(action: &'static str) -> impl Fn(std::io::Error) -> SyncError {
+    move |source| SyncError::Service { action, source }
+}
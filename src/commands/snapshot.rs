@@ -0,0 +1,42 @@
+use std::path::Path;
+
+use sync_rs::database::Database;
+use sync_rs::error::{self, SyncError};
+use sync_rs::snapshot;
+
+use crate::cli::{SnapshotAction, SnapshotArgs};
+
+pub fn run(args: SnapshotArgs) -> error::Result<()> {
+    match (args.action, args.folder, args.output) {
+        (Some(SnapshotAction::Restore { archive, target }), _, _) => restore(&archive, &target),
+        (None, Some(folder), Some(output)) => export(&folder, &output),
+        // clap requires both arguments when no subcommand is given.
+        _ => Ok(()),
+    }
+}
+
+fn export(folder: &str, output: &Path) -> error::Result<()> {
+    let db = Database::new()?;
+    let Some(folder) = db.find_folder(folder)? else {
+        return Err(SyncError::UnknownFolder(folder.to_string()));
+    };
+
+    let summary = snapshot::export(&db, &folder, output)?;
+    for (path, reason) in &summary.skipped {
+        println!("[SNAPSHOT] Skipped {:?}: {}", path, reason);
+    }
+    println!(
+        "[SNAPSHOT] Wrote {} file(s), {} bytes from {} to {:?}",
+        summary.archived, summary.total_bytes, folder.name, output
+    );
+    Ok(())
+}
+
+fn restore(archive: &Path, target: &Path) -> error::Result<()> {
+    let summary = snapshot::restore(archive, target)?;
+    println!(
+        "[SNAPSHOT] Restored {} file(s), {} bytes of {} into {:?}",
+        summary.restored, summary.total_bytes, summary.manifest.folder_name, target
+    );
+    Ok(())
+}
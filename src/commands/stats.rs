@@ -0,0 +1,159 @@
+use std::collections::{BTreeSet, HashMap};
+
+use chrono::{Days, Local, NaiveDate};
+use sync_rs::config::Config;
+use sync_rs::database::Database;
+use sync_rs::error::{self, SyncError};
+use sync_rs::output::{HistoryOutput, TransfersOutput};
+use sync_rs::stats_history::KEEP_DAYS;
+use sync_rs::transfers;
+
+use super::print_json;
+use super::status::format_bytes;
+use crate::cli::{OutputFormat, StatsAction};
+
+pub fn run(db: &Database, action: StatsAction) -> error::Result<()> {
+    match action {
+        StatsAction::Transfers {
+            days,
+            peer,
+            folder,
+            format,
+        } => {
+            let caps = Config::load()?.transfers;
+            let peer_names: HashMap<String, String> = db
+                .get_peers()?
+                .into_iter()
+                .map(|peer| (peer.device_id.clone(), peer.display_name().to_string()))
+                .collect();
+            let peer_name = |device_id: &str| {
+                peer_names
+                    .get(device_id)
+                    .cloned()
+                    .unwrap_or_else(|| device_id.to_string())
+            };
+            let peer = peer.map(|peer| {
+                peer_names
+                    .iter()
+                    .find(|(_, name)| **name == peer)
+                    .map_or(peer.clone(), |(device_id, _)| device_id.clone())
+            });
+            let folder_id = match folder {
+                Some(folder) => match db.find_folder(&folder)? {
+                    Some(found) => Some(found.id),
+                    None => return Err(SyncError::UnknownFolder(folder)),
+                },
+                None => None,
+            };
+
+            let since = transfers::day(first_day(days));
+            let records: Vec<_> = db
+                .get_transfers(&since)?
+                .into_iter()
+                .filter(|record| peer.as_ref().is_none_or(|peer| record.device_id == *peer))
+                .filter(|record| folder_id.is_none_or(|id| record.folder_id == id))
+                .collect();
+            let mut device_ids: BTreeSet<String> = peer_names.keys().cloned().collect();
+            device_ids.extend(caps.peers.keys().cloned());
+            device_ids.extend(records.iter().map(|record| record.device_id.clone()));
+            if let Some(peer) = &peer {
+                device_ids.retain(|device_id| device_id == peer);
+            }
+            let month = device_ids
+                .iter()
+                .map(|device_id| transfers::month_usage(db, &caps, device_id))
+                .collect::<Result<Vec<_>, _>>()?;
+            if format == OutputFormat::Json {
+                print_json(&TransfersOutput {
+                    since,
+                    transfers: records,
+                    month,
+                });
+                return Ok(());
+            }
+
+            if records.is_empty() {
+                println!("No transfers since {}.", since);
+            }
+            let folder_names: HashMap<i64, String> = db
+                .get_all_synced_folders()?
+                .into_iter()
+                .map(|folder| (folder.id, folder.name))
+                .collect();
+            for record in &records {
+                println!(
+                    "  {}  {}  {}: sent {}, received {}",
+                    record.day,
+                    peer_name(&record.device_id),
+                    folder_names
+                        .get(&record.folder_id)
+                        .map_or("?", String::as_str),
+                    format_bytes(record.bytes_sent),
+                    format_bytes(record.bytes_received)
+                );
+            }
+            if month.is_empty() {
+                return Ok(());
+            }
+            println!("This month (since {}):", transfers::month_start());
+            for usage in month {
+                let cap = match usage.cap_bytes {
+                    Some(cap) if usage.is_over_cap() => {
+                        format!(" of {} cap, TRANSFERS PAUSED", format_bytes(cap))
+                    }
+                    Some(cap) => format!(" of {} cap", format_bytes(cap)),
+                    None => String::new(),
+                };
+                println!(
+                    "  {}: {}{} (sent {}, received {})",
+                    peer_name(&usage.device_id),
+                    format_bytes(usage.used_bytes()),
+                    cap,
+                    format_bytes(usage.bytes_sent),
+                    format_bytes(usage.bytes_received)
+                );
+            }
+        }
+        StatsAction::History {
+            folder,
+            days,
+            format,
+        } => {
+            let Some(found) = db.find_folder(&folder)? else {
+                return Err(SyncError::UnknownFolder(folder));
+            };
+            let history = HistoryOutput {
+                folder_id: found.id,
+                folder_name: found.name,
+                days: db.get_stats_history(found.id, days.min(KEEP_DAYS))?,
+            };
+            if format == OutputFormat::Json {
+                print_json(&history);
+                return Ok(());
+            }
+
+            if history.days.is_empty() {
+                println!("No statistics recorded for {} yet.", history.folder_name);
+            }
+            for entry in history.days {
+                println!(
+                    "  {}  {} files, {}; sent {}, received {}",
+                    entry.day,
+                    entry.file_count,
+                    format_bytes(entry.size_bytes),
+                    format_bytes(entry.bytes_sent),
+                    format_bytes(entry.bytes_received)
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// First of the last `days` days, today included.
+fn first_day(days: u32) -> NaiveDate {
+    let today = Local::now().date_naive();
+    today
+        .checked_sub_days(Days::new(u64::from(days.max(1)) - 1))
+        .unwrap_or(today)
+}
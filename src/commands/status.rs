@@ -0,0 +1,235 @@
+use chrono::{DateTime, Local};
+use sync_rs::control::SyncControl;
+use sync_rs::database::{Database, FolderStats};
+use sync_rs::{output, removable, watchdog};
+
+use super::print_json;
+use crate::cli::OutputFormat;
+
+pub fn run(db: &Database, format: OutputFormat) -> Result<(), rusqlite::Error> {
+    if format == OutputFormat::Json {
+        print_json(&output::status(db)?);
+        return Ok(());
+    }
+    let device_id = db.get_or_create_device_id()?;
+    match db.get_device_name()? {
+        Some(name) => println!("Device: {} ({})", name, device_id),
+        None => println!("Device: {}", device_id),
+    }
+    let control = SyncControl::load(db)?;
+    if control.in_maintenance() {
+        println!("Maintenance mode: watchers and transfers stopped");
+    }
+    if control.is_paused() {
+        println!("Sync paused");
+    }
+    // Recorded by the daemon's watchdog while an event takes too long.
+    if let Some(slow) = watchdog::load_slow_event(db)? {
+        println!(
+            "Stuck on {:?} since {} ({}, trace {})",
+            slow.path,
+            format_time(slow.started_secs),
+            slow.stage,
+            slow.trace
+        );
+    }
+
+    let folders = db.get_all_synced_folders()?;
+    if folders.is_empty() {
+        println!("No synced folders.");
+        return Ok(());
+    }
+
+    println!("Folders:");
+    for folder in folders {
+        let (file_count, total_bytes) = db.get_folder_totals(folder.id)?;
+        println!(
+            "  {} [{}] {:?}: {} files, {} bytes",
+            folder.name, folder.folder_uuid, folder.local_path, file_count, total_bytes
+        );
+
+        let mut options = Vec::new();
+        if let Some(profile) = &folder.profile {
+            options.push(format!("profile {}", profile));
+        }
+        if folder.receive_only {
+            options.push("receive-only".to_string());
+        }
+        if folder.ignore_hidden {
+            options.push("hidden files ignored".to_string());
+        }
+        if !folder.ignore_patterns.is_empty() {
+            options.push(format!("ignoring {}", folder.ignore_patterns.join(", ")));
+        }
+        if folder.sync_xattrs {
+            options.push("xattrs synced".to_string());
+        }
+        if folder.sync_hardlinks {
+            options.push("hard links synced".to_string());
+        }
+        if let Some(quota) = folder.quota_bytes {
+            if folder.is_over_quota(total_bytes) {
+                options.push(format!("OVER QUOTA of {} bytes, receiving paused", quota));
+            } else {
+                options.push(format!("quota {} bytes", quota));
+            }
+        }
+        if !folder.unsynced_paths.is_empty() {
+            let paths: Vec<_> = folder
+                .unsynced_paths
+                .iter()
+                .map(|path| path.to_string_lossy())
+                .collect();
+            options.push(format!("not synced locally: {}", paths.join(", ")));
+        }
+        if folder.observer {
+            options.push("observer, index only".to_string());
+        }
+        if let Some(source_id) = folder.mirror_of {
+            let source = db
+                .get_folder_by_id(source_id)?
+                .map_or_else(|| source_id.to_string(), |source| source.name);
+            options.push(format!("mirror of {}", source));
+        }
+        if folder.removable {
+            options.push(if removable::is_mounted(&folder) {
+                "removable".to_string()
+            } else {
+                "removable, NOT MOUNTED, paused".to_string()
+            });
+        }
+        if folder.manual_push {
+            let pending = db.get_pending_changes(folder.id)?.len();
+            options.push(format!("manual push, {} change(s) pending", pending));
+        }
+        if folder.placeholders {
+            options.push("placeholders".to_string());
+        }
+        if folder.hash_threads > 0 {
+            options.push(format!("{} hash thread(s)", folder.hash_threads));
+        }
+        if folder.debounce_ms > 0 {
+            options.push(format!("debounce {} ms", folder.debounce_ms));
+        }
+        if folder.settle_secs > 0 {
+            options.push(format!("settle {} s", folder.settle_secs));
+        }
+        if !options.is_empty() {
+            println!("    {}", options.join("; "));
+        }
+        print_health(&db.get_folder_stats(folder.id)?);
+        for locked in db.get_locked_files(folder.id)? {
+            println!(
+                "    in use by another process, skipped {} time(s): {:?}",
+                locked.attempts, locked.relative_path
+            );
+        }
+    }
+    print_peers(db)
+}
+
+/// Each known peer's connection state and how far behind it is per folder,
+/// e.g. `Documents: 42 files / 1.3 GiB behind`.
+fn print_peers(db: &Database) -> Result<(), rusqlite::Error> {
+    let peers = db.get_peers()?;
+    if peers.is_empty() {
+        return Ok(());
+    }
+
+    println!("Peers:");
+    for peer in peers {
+        let state = match (peer.connected, peer.last_seen_secs) {
+            (true, _) => "online".to_string(),
+            (false, Some(secs)) => format!("offline, last seen {}", format_time(secs)),
+            (false, None) => "offline".to_string(),
+        };
+        println!("  {} ({}): {}", peer.display_name(), peer.device_id, state);
+        for backlog in db.get_peer_backlog(&peer.device_id)? {
+            let folder = db
+                .get_folder_by_id(backlog.folder_id)?
+                .map(|folder| folder.name)
+                .unwrap_or_else(|| backlog.folder_id.to_string());
+            let synced = match backlog.last_synced_secs {
+                Some(secs) => format!("last synced {}", format_time(secs)),
+                None => "never synced".to_string(),
+            };
+            if backlog.files == 0 {
+                println!("    {}: up to date ({})", folder, synced);
+            } else {
+                println!(
+                    "    {}: {} files / {} behind ({})",
+                    folder,
+                    backlog.files,
+                    format_bytes(backlog.bytes),
+                    synced
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Formats a size in binary units like the web UI, e.g. `1.3 GiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+fn print_health(stats: &FolderStats) {
+    let last_scan = match stats.last_scan_secs {
+        Some(secs) => format_time(secs),
+        None => "never".to_string(),
+    };
+    println!(
+        "    {} events, {} failure(s), last full scan {}",
+        stats.events_processed, stats.failures, last_scan
+    );
+    if let Some(failure) = &stats.last_failure {
+        println!("    last failure: {}", failure);
+    }
+    if stats.bytes_sent > 0 || stats.bytes_received > 0 {
+        println!(
+            "    {} sent, {} received",
+            format_bytes(stats.bytes_sent),
+            format_bytes(stats.bytes_received)
+        );
+    }
+    if stats.verification_failures > 0 {
+        println!(
+            "    {} received file(s) failed verification and were discarded",
+            stats.verification_failures
+        );
+    }
+    if stats.audited_files > 0 {
+        println!(
+            "    {} file(s) re-verified while idle, {} mismatch(es)",
+            stats.audited_files, stats.audit_mismatches
+        );
+    }
+    if let Some(mismatch) = &stats.last_audit_mismatch {
+        println!("    last audit mismatch: {}", mismatch);
+    }
+    if let (Some(error), Some(secs)) = (&stats.last_watcher_error, stats.last_watcher_error_secs) {
+        println!("    last watcher error at {}: {}", format_time(secs), error);
+    }
+}
+
+pub fn format_time(unix_secs: u64) -> String {
+    DateTime::from_timestamp(unix_secs as i64, 0)
+        .map(|time| {
+            time.with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_else(|| unix_secs.to_string())
+}
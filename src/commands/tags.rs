@@ -0,0 +1,44 @@
+use std::path::{Path, PathBuf};
+
+use sync_rs::database::{Database, FileTags, SyncedFolder};
+use sync_rs::error::{self, SyncError};
+
+use crate::cli::TagAction;
+
+pub fn run(db: &Database, action: TagAction) -> error::Result<()> {
+    match action {
+        TagAction::List { path } => {
+            let (folder, relative) = resolve(db, &path)?;
+            let tags = db.get_file_tags(folder.id, &relative)?;
+            if tags.is_empty() {
+                println!("No tags on {:?}.", relative);
+            }
+            for (key, value) in tags {
+                println!("  {}={}", key, value);
+            }
+        }
+        TagAction::Set { path, tags } => {
+            let set: FileTags = tags.into_iter().collect();
+            update(db, &path, &set, &[])?;
+        }
+        TagAction::Remove { path, keys } => update(db, &path, &FileTags::new(), &keys)?,
+    }
+    Ok(())
+}
+
+fn update(db: &Database, path: &Path, set: &FileTags, remove: &[String]) -> error::Result<()> {
+    let (folder, relative) = resolve(db, path)?;
+    if !db.update_file_tags(folder.id, &relative, set, remove)? {
+        return Err(SyncError::NotIndexed(path.to_path_buf()));
+    }
+    println!("[TAGS] Updated tags of {:?} in {}", relative, folder.name);
+    Ok(())
+}
+
+/// Finds the folder of `path` and the path relative to its root.
+fn resolve(db: &Database, path: &Path) -> error::Result<(SyncedFolder, PathBuf)> {
+    let absolute =
+        std::path::absolute(path).map_err(|e| SyncError::io("failed to resolve", path, e))?;
+    db.find_folder_containing(&absolute)?
+        .ok_or(SyncError::NotInSyncedFolder(absolute))
+}
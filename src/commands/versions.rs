@@ -0,0 +1,62 @@
+use sync_rs::config::Config;
+use sync_rs::database::Database;
+use sync_rs::error;
+use sync_rs::{removable, versions};
+
+use crate::cli::VersionsAction;
+
+pub fn run(db: &Database, action: VersionsAction) -> error::Result<()> {
+    match action {
+        VersionsAction::Prune {
+            folder,
+            keep,
+            max_size,
+            max_age_days,
+            dry_run,
+        } => {
+            let mut retention = Config::load()?.versions;
+            retention.keep_versions = keep.or(retention.keep_versions);
+            retention.max_bytes = max_size.or(retention.max_bytes);
+            retention.max_age_days = max_age_days.or(retention.max_age_days);
+            if !retention.is_set() {
+                eprintln!(
+                    "[VERSIONS] No limits given and none set in the [versions] section of sync_rs.toml"
+                );
+                return Ok(());
+            }
+
+            let folders = match folder {
+                Some(folder) => match db.find_folder(&folder)? {
+                    Some(found) => vec![found],
+                    None => {
+                        eprintln!("[VERSIONS] No folder matches {:?}", folder);
+                        return Ok(());
+                    }
+                },
+                None => db.get_all_synced_folders()?,
+            };
+            for folder in folders {
+                if !removable::is_mounted(&folder) {
+                    println!("[VERSIONS] {}: drive not mounted, skipped", folder.name);
+                    continue;
+                }
+                let summary = versions::prune(&folder, &retention, dry_run)?;
+                if dry_run {
+                    for path in &summary.removed {
+                        println!("[VERSIONS] Would remove {:?}", path);
+                    }
+                }
+                println!(
+                    "[VERSIONS] {}: {} {} version(s) ({} bytes), kept {} ({} bytes)",
+                    folder.name,
+                    if dry_run { "would remove" } else { "removed" },
+                    summary.removed.len(),
+                    summary.removed_bytes,
+                    summary.kept,
+                    summary.kept_bytes
+                );
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use sync_rs::database::Database;
+use sync_rs::error::{self, SyncError};
+
+use crate::cli::WebhookAction;
+
+pub fn run(db: &Database, action: WebhookAction) -> error::Result<()> {
+    match action {
+        WebhookAction::Add {
+            url,
+            secret,
+            events,
+        } => {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(SyncError::InvalidWebhookUrl(url));
+            }
+            let id = db.add_webhook(&url, secret.as_deref(), &events)?;
+            println!("[WEBHOOK] Added webhook {} -> {}", id, url);
+        }
+        WebhookAction::List => {
+            let hooks = db.get_webhooks()?;
+            if hooks.is_empty() {
+                println!("No webhooks configured.");
+            }
+            for hook in hooks {
+                let events = if hook.events.is_empty() {
+                    "all events".to_string()
+                } else {
+                    hook.events.join(", ")
+                };
+                let signed = if hook.secret.is_some() {
+                    ", signed"
+                } else {
+                    ""
+                };
+                println!("  {}: {} ({}{})", hook.id, hook.url, events, signed);
+            }
+        }
+        WebhookAction::Remove { id } => {
+            if db.remove_webhook(id)? {
+                println!("[WEBHOOK] Removed webhook {}", id);
+            } else {
+                eprintln!("[WEBHOOK] No webhook with ID {}", id);
+            }
+        }
+    }
+    Ok(())
+}
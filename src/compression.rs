@@ -0,0 +1,119 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// Cheap level: on NAS-class CPUs, compression must not become the
+/// bottleneck of a transfer.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Bytes read from the start of a file to judge whether it compresses.
+const PROBE_SIZE: u64 = 64 * 1024;
+
+/// Shannon entropy in bits per byte above which data is treated as already
+/// compressed or encrypted; zstd saves next to nothing on it.
+const ENTROPY_THRESHOLD: f64 = 7.5;
+
+/// Extensions of formats that are compressed or encrypted by design.
+const INCOMPRESSIBLE_EXTENSIONS: &[&str] = &[
+    // Images
+    "jpg", "jpeg", "png", "gif", "webp", "heic", "heif", "avif", "jxl",
+    // Audio and video
+    "mp3", "aac", "m4a", "ogg", "opus", "flac", "mp4", "m4v", "mkv", "mov", "avi", "webm",
+    // Archives and compressed files
+    "zip", "gz", "tgz", "bz2", "xz", "txz", "zst", "7z", "rar", "lz4", "br",
+    // Documents and packages that are zip archives
+    "docx", "xlsx", "pptx", "odt", "ods", "odp", "epub", "jar", "apk", "whl",
+    // Encrypted files
+    "gpg", "pgp", "age", "enc", "kdbx",
+];
+
+/// How a file's data is encoded on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    Raw,
+    Zstd,
+}
+
+impl Encoding {
+    /// Picks the encoding for sending the file at `path`: [`Encoding::Raw`]
+    /// for known incompressible formats and for files whose first block
+    /// looks random, [`Encoding::Zstd`] otherwise.
+    pub fn for_file(path: &Path) -> io::Result<Self> {
+        if has_incompressible_extension(path) {
+            return Ok(Self::Raw);
+        }
+        let mut probe = Vec::new();
+        File::open(path)?.take(PROBE_SIZE).read_to_end(&mut probe)?;
+        if entropy(&probe) >= ENTROPY_THRESHOLD {
+            Ok(Self::Raw)
+        } else {
+            Ok(Self::Zstd)
+        }
+    }
+
+    /// Encodes a block of file data for sending.
+    pub fn encode<'a>(&self, block: &'a [u8]) -> io::Result<Cow<'a, [u8]>> {
+        match self {
+            Self::Raw => Ok(Cow::Borrowed(block)),
+            Self::Zstd => zstd::bulk::compress(block, ZSTD_LEVEL).map(Cow::Owned),
+        }
+    }
+
+    /// Decodes a block received with this encoding. Data that decodes to
+    /// more than `max_len` bytes is an `InvalidData` error, so a small
+    /// block from a peer cannot expand to fill the memory.
+    pub fn decode<'a>(&self, block: &'a [u8], max_len: usize) -> io::Result<Cow<'a, [u8]>> {
+        let decoded = match self {
+            Self::Raw => Cow::Borrowed(block),
+            // Fails rather than growing past `max_len`.
+            Self::Zstd => Cow::Owned(zstd::bulk::decompress(block, max_len).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("cannot decode block within {} bytes: {}", max_len, e),
+                )
+            })?),
+        };
+        if decoded.len() > max_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("block decodes to more than {} bytes", max_len),
+            ));
+        }
+        Ok(decoded)
+    }
+}
+
+fn has_incompressible_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            INCOMPRESSIBLE_EXTENSIONS
+                .iter()
+                .any(|known| extension.eq_ignore_ascii_case(known))
+        })
+}
+
+/// Shannon entropy of `data` in bits per byte, from 0 (one repeated byte)
+/// to 8 (uniformly random).
+fn entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0u64; 256];
+    for &byte in data {
+        counts[byte as usize] += 1;
+    }
+    let len = data.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
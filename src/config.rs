@@ -0,0 +1,323 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::error::{Result, SyncError};
+
+/// Daemon configuration file, read from the working directory like the database.
+pub const CONFIG_PATH: &str = "sync_rs.toml";
+
+/// Contents of [`CONFIG_PATH`]. A missing file is the same as an empty one.
+/// The daemon reads it again on SIGHUP or `sync_rs reload`.
+///
+/// ```toml
+/// default_profile = "code"
+/// slow_event_secs = 60
+///
+/// [profiles.photos]
+/// ignore = ["*.xmp", "*.thm"]
+/// receive_only = true
+/// hash_threads = 1
+///
+/// [profiles.code]
+/// ignore = ["node_modules", "target"]
+/// debounce_ms = 2000
+///
+/// [profiles.recordings]
+/// settle_secs = 120
+///
+/// [queues]
+/// event_queue = 1000
+///
+/// [versions]
+/// keep_versions = 5
+/// max_age_days = 90
+///
+/// [conflicts]
+/// append_only = ["*.log", "journal/*"]
+/// text_merge = ["*.md", "*.txt"]
+///
+/// [transfers]
+/// monthly_cap_bytes = 100_000_000_000
+///
+/// [audit]
+/// files_per_hour = 120
+/// ```
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Profile applied to folders added without naming one.
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, FolderProfile>,
+    #[serde(default)]
+    pub queues: QueueSizes,
+    /// Handling a single event for longer than this logs a warning and
+    /// shows the event in `sync_rs status`; 0 turns the watchdog off.
+    /// Defaults to [`DEFAULT_SLOW_EVENT_SECS`].
+    pub slow_event_secs: Option<u64>,
+    #[serde(default)]
+    pub versions: VersionRetention,
+    #[serde(default)]
+    pub conflicts: ConflictPolicy,
+    #[serde(default)]
+    pub transfers: TransferCaps,
+    #[serde(default)]
+    pub audit: AuditRate,
+}
+
+pub const DEFAULT_SLOW_EVENT_SECS: u64 = 30;
+
+/// Buffer sizes of the daemon's internal channels.
+///
+/// Watcher events are never dropped: each watcher buffers them without
+/// limit and hands them to the event queue as it drains. A full event queue
+/// only slows the watchers down. Subscribers of the event bus (web UI, gRPC
+/// streams, webhooks, MQTT) that fall further behind than `event_bus` events
+/// skip the oldest ones instead of holding up syncing.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueueSizes {
+    /// File changes waiting for the event loop.
+    pub event_queue: usize,
+    /// Sync events kept for slow subscribers.
+    pub event_bus: usize,
+}
+
+impl Default for QueueSizes {
+    fn default() -> Self {
+        Self {
+            event_queue: 100,
+            event_bus: 256,
+        }
+    }
+}
+
+/// Limits on the previous versions and deleted files kept in each folder's
+/// versions directory (see [`crate::versions`]). Each limit is off when
+/// unset; versions are only removed by a limit that is set.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VersionRetention {
+    /// Versions kept of each file, newest first.
+    pub keep_versions: Option<usize>,
+    /// Total size of a folder's versions; the oldest go first.
+    pub max_bytes: Option<u64>,
+    pub max_age_days: Option<u64>,
+}
+
+impl VersionRetention {
+    /// True when any limit is set.
+    pub fn is_set(&self) -> bool {
+        self.keep_versions.is_some() || self.max_bytes.is_some() || self.max_age_days.is_some()
+    }
+
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age_days
+            .map(|days| Duration::from_secs(days * 24 * 60 * 60))
+    }
+}
+
+/// How conflicting changes to matching files are settled without conflict
+/// copies (see [`crate::conflicts::handle_conflict`]). Patterns follow
+/// [`crate::ignore::matches_patterns`].
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConflictPolicy {
+    /// Files only ever appended to, such as logs and journals. When both
+    /// sides appended, the remote additions are appended to the local file.
+    pub append_only: Vec<String>,
+    /// Text files merged line by line against the last version both sides
+    /// had, like git merges, unless both changed the same lines.
+    pub text_merge: Vec<String>,
+}
+
+impl ConflictPolicy {
+    pub fn is_append_only(&self, relative_path: &Path) -> bool {
+        crate::ignore::matches_patterns(&self.append_only, relative_path)
+    }
+
+    pub fn is_text_merge(&self, relative_path: &Path) -> bool {
+        crate::ignore::matches_patterns(&self.text_merge, relative_path)
+    }
+}
+
+/// Monthly limits on the data exchanged with peers, e.g. for a metered VPS
+/// (see [`crate::transfers`]). Bytes sent and received both count, over the
+/// calendar month in local time.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransferCaps {
+    /// Limit for each peer.
+    pub monthly_cap_bytes: Option<u64>,
+    /// Limits of single peers by device ID, overriding `monthly_cap_bytes`.
+    pub peers: BTreeMap<String, u64>,
+}
+
+impl TransferCaps {
+    pub fn cap_for(&self, device_id: &str) -> Option<u64> {
+        self.peers
+            .get(device_id)
+            .copied()
+            .or(self.monthly_cap_bytes)
+    }
+}
+
+/// How fast the daemon re-verifies indexed files against disk while idle
+/// (see [`crate::audit`]).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditRate {
+    /// Files hashed again per hour, one at a time; 0 turns the audit off.
+    pub files_per_hour: u64,
+}
+
+impl Default for AuditRate {
+    fn default() -> Self {
+        Self { files_per_hour: 60 }
+    }
+}
+
+impl AuditRate {
+    /// Time between two files, or `None` when the audit is off.
+    pub fn interval(&self) -> Option<Duration> {
+        match self.files_per_hour {
+            0 => None,
+            files => Some(Duration::from_secs(3600) / files.min(3600) as u32),
+        }
+    }
+}
+
+/// Folder options applied together when a folder is added. They are copied
+/// onto the folder, so editing a profile later does not change existing folders.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FolderProfile {
+    /// Glob patterns of files and directories to skip, see
+    /// [`crate::ignore::matches_patterns`].
+    pub ignore: Vec<String>,
+    pub ignore_hidden: bool,
+    /// Never announce local changes to peers; only accept theirs.
+    pub receive_only: bool,
+    /// Wait until a file has been quiet this long before indexing it.
+    pub debounce_ms: u64,
+    /// Sync extended attributes; not all filesystems support them.
+    pub sync_xattrs: bool,
+    /// Files a scan reads and hashes at once, e.g. 1 for a spinning disk;
+    /// 0 picks a number from the CPU count.
+    pub hash_threads: usize,
+    /// Only hash and announce a file once it has gone unmodified this long,
+    /// e.g. for video captures or database dumps written over minutes.
+    pub settle_secs: u64,
+}
+
+impl Config {
+    pub fn load() -> Result<Self> {
+        Self::load_from(Path::new(CONFIG_PATH))
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(SyncError::io("failed to read config", path, e)),
+        };
+        let config: Config = toml::from_str(&text).map_err(|e| invalid(path, e.message()))?;
+
+        if let Some(name) = &config.default_profile
+            && !config.profiles.contains_key(name)
+        {
+            return Err(invalid(
+                path,
+                &format!("default_profile {:?} is not defined", name),
+            ));
+        }
+        for (name, profile) in &config.profiles {
+            for pattern in &profile.ignore {
+                if let Err(e) = glob::Pattern::new(pattern) {
+                    return Err(invalid(
+                        path,
+                        &format!(
+                            "profile {:?}: bad ignore pattern {:?}: {}",
+                            name, pattern, e
+                        ),
+                    ));
+                }
+            }
+        }
+        let conflicts = &config.conflicts;
+        for (key, patterns) in [
+            ("append_only", &conflicts.append_only),
+            ("text_merge", &conflicts.text_merge),
+        ] {
+            for pattern in patterns {
+                if let Err(e) = glob::Pattern::new(pattern) {
+                    return Err(invalid(
+                        path,
+                        &format!("bad {} pattern {:?}: {}", key, pattern, e),
+                    ));
+                }
+            }
+        }
+        if config.queues.event_queue == 0 || config.queues.event_bus == 0 {
+            return Err(invalid(path, "queue sizes must be at least 1"));
+        }
+        Ok(config)
+    }
+
+    /// Threshold of the slow-event watchdog, or `None` when it is off.
+    pub fn slow_event_threshold(&self) -> Option<Duration> {
+        match self.slow_event_secs.unwrap_or(DEFAULT_SLOW_EVENT_SECS) {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    /// Looks up the profile to apply to a new folder: `name` if given,
+    /// otherwise the default profile, if one is configured.
+    pub fn profile(&self, name: Option<&str>) -> Result<Option<(&str, &FolderProfile)>> {
+        let Some(name) = name.or(self.default_profile.as_deref()) else {
+            return Ok(None);
+        };
+        self.profiles
+            .get_key_value(name)
+            .map(|(name, profile)| Some((name.as_str(), profile)))
+            .ok_or_else(|| SyncError::UnknownProfile(name.to_string()))
+    }
+}
+
+/// The daemon's configuration, replaced as a whole when it is reloaded (see
+/// [`crate::reload`]), so readers see either the old or the new file.
+#[derive(Debug)]
+pub struct LiveConfig {
+    current: RwLock<Arc<Config>>,
+}
+
+impl LiveConfig {
+    pub fn new(config: Config) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(config)),
+        }
+    }
+
+    pub fn get(&self) -> Arc<Config> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Installs `config` and returns the one it replaces.
+    pub fn replace(&self, config: Config) -> Arc<Config> {
+        std::mem::replace(&mut *self.current.write().unwrap(), Arc::new(config))
+    }
+}
+
+fn invalid(path: &Path, message: &str) -> SyncError {
+    SyncError::Config {
+        path: PathBuf::from(path),
+        message: message.to_string(),
+    }
+}
@@ -0,0 +1,243 @@
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+use crate::apply::{self, IncomingFile};
+use crate::config::ConflictPolicy;
+use crate::database::{Conflict, Database, RemoteVersion, SyncedFolder};
+use crate::error::{self, SyncError};
+use crate::staging;
+use crate::suppression::ExpectedChanges;
+use crate::sync_engine::{self, calculate_hash};
+use crate::versions;
+
+/// Which side of a conflict survives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// Drop the remote copy; the local version wins everywhere.
+    KeepLocal,
+    /// Replace the local version with the remote copy.
+    KeepRemote,
+    /// Keep the remote copy as a file of its own next to the local version.
+    KeepBoth,
+}
+
+/// What became of a remote version that conflicted with the local one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictOutcome {
+    /// Both versions were merged into the local file, which supersedes them.
+    Merged,
+    /// The remote version was stored as a conflict copy with this ID.
+    Stored(i64),
+}
+
+/// Settles a remote version that conflicts with the local one: merged into
+/// the local file when `policy` allows it for the file and the versions can
+/// be merged, otherwise stored as a conflict copy with [`store_remote_copy`].
+pub fn handle_conflict<R: Read>(
+    db: &Database,
+    folder: &SyncedFolder,
+    policy: &ConflictPolicy,
+    file: IncomingFile,
+    mut data: R,
+    remote: &RemoteVersion,
+    expected_changes: &ExpectedChanges,
+) -> error::Result<ConflictOutcome> {
+    let append_only = policy.is_append_only(file.relative_path);
+    if !append_only && !policy.is_text_merge(file.relative_path) {
+        return store_remote_copy(db, folder, file, data, remote, expected_changes)
+            .map(ConflictOutcome::Stored);
+    }
+    let mut remote_data = Vec::new();
+    data.read_to_end(&mut remote_data)
+        .map_err(|e| SyncError::io("failed to receive", file.relative_path, e))?;
+    let path = apply::folder_path(&folder.local_path, file.relative_path)
+        .map_err(|e| SyncError::io("failed to read", file.relative_path, e))?;
+    let local_data = fs::read(&path).map_err(|e| SyncError::io("failed to read", &path, e))?;
+    let merged = if append_only {
+        merge_appends(&local_data, &remote_data)
+    } else {
+        merge_text(
+            &folder.local_path,
+            file.relative_path,
+            &local_data,
+            &remote_data,
+        )?
+    };
+    let Some(merged) = merged else {
+        println!(
+            "[CONFLICT] {:?} could not be merged automatically",
+            file.relative_path
+        );
+        return store_remote_copy(db, folder, file, &remote_data[..], remote, expected_changes)
+            .map(ConflictOutcome::Stored);
+    };
+
+    if merged != local_data {
+        let hash = format!("{:x}", Sha256::digest(&merged));
+        let merged_file = IncomingFile {
+            hash: &hash,
+            modified: SystemTime::now(),
+            ..file
+        };
+        apply::write_remote_file(
+            &folder.local_path,
+            merged_file,
+            &merged[..],
+            expected_changes,
+        )
+        .map_err(|e| SyncError::io("failed to write merged file", &path, e))?;
+        index_path(db, folder, file.relative_path)?;
+    }
+    db.supersede_file_version(folder.id, file.relative_path, &remote.version_vector)?;
+    let device = remote.device_name.as_deref().unwrap_or(&remote.device_id);
+    println!(
+        "[CONFLICT] {:?} changed on this device and on {}; merged both versions",
+        file.relative_path, device
+    );
+    Ok(ConflictOutcome::Merged)
+}
+
+/// Merges both versions line by line against the newest version kept with
+/// [`versions::keep_merge_base`], which both sides had. `None` if no such
+/// version was kept or both sides changed the same lines.
+fn merge_text(
+    folder_root: &Path,
+    relative_path: &Path,
+    local: &[u8],
+    remote: &[u8],
+) -> error::Result<Option<Vec<u8>>> {
+    let Some(base_path) = versions::latest_version(folder_root, relative_path)? else {
+        return Ok(None);
+    };
+    let base = fs::read(&base_path).map_err(|e| SyncError::io("failed to read", &base_path, e))?;
+    Ok(diffy::merge_bytes(&base, local, remote).ok())
+}
+
+/// Merges two versions of an append-only file that both grew from a common
+/// version: the local content followed by what the remote side appended.
+/// The common version is taken to end at the last line break both share,
+/// so two appends starting alike are not mistaken for one. `None` if the
+/// versions share no such start, i.e. a side did more than append.
+fn merge_appends(local: &[u8], remote: &[u8]) -> Option<Vec<u8>> {
+    let common = local.iter().zip(remote).take_while(|(a, b)| a == b).count();
+    let base = if common == remote.len() || common == local.len() {
+        // One side holds all of the other; only the longer one is new.
+        common
+    } else {
+        match local[..common].iter().rposition(|&byte| byte == b'\n') {
+            Some(newline) => newline + 1,
+            None if common == 0 => 0,
+            None => return None,
+        }
+    };
+    let mut merged = local.to_vec();
+    merged.extend_from_slice(&remote[base..]);
+    Some(merged)
+}
+
+/// Writes a remote version that conflicts with the local one next to it as
+/// a `*.sync-conflict-*` copy (see [`sync_engine::conflict_file_name`]) and
+/// records the conflict for review. Returns the conflict's ID.
+pub fn store_remote_copy<R: Read>(
+    db: &Database,
+    folder: &SyncedFolder,
+    file: IncomingFile,
+    data: R,
+    remote: &RemoteVersion,
+    expected_changes: &ExpectedChanges,
+) -> error::Result<i64> {
+    let device = remote.device_name.as_deref().unwrap_or(&remote.device_id);
+    let conflict_path =
+        sync_engine::conflict_file_name(file.relative_path, device, SystemTime::now());
+    let copy = IncomingFile {
+        relative_path: &conflict_path,
+        ..file
+    };
+    apply::write_remote_file(&folder.local_path, copy, data, expected_changes)
+        .map_err(|e| SyncError::io("failed to write conflict copy", &conflict_path, e))?;
+
+    let id = db.record_conflict(folder.id, file.relative_path, &conflict_path, remote)?;
+    println!(
+        "[CONFLICT] {:?} changed on this device and on {}; their version is in {:?}",
+        file.relative_path, device, conflict_path
+    );
+    Ok(id)
+}
+
+/// Resolves a conflict on disk and in the index. Every resolution counts as
+/// a new version by this device that supersedes both sides, so peers apply
+/// it instead of reporting the conflict again.
+pub fn resolve(db: &Database, conflict: &Conflict, resolution: Resolution) -> error::Result<()> {
+    let Some(folder) = db.get_folder_by_id(conflict.folder_id)? else {
+        db.remove_conflict(conflict.id)?;
+        return Ok(());
+    };
+    let original = folder.local_path.join(&conflict.relative_path);
+    let copy = folder.local_path.join(&conflict.conflict_path);
+
+    match resolution {
+        Resolution::KeepLocal => {
+            match fs::remove_file(&copy) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(SyncError::io("failed to remove conflict copy", &copy, e));
+                }
+                _ => {}
+            }
+            db.remove_file_entry(folder.id, &conflict.conflict_path)?;
+        }
+        Resolution::KeepRemote => {
+            staging::move_into_place(&copy, &original)
+                .map_err(|e| SyncError::io("failed to restore conflict copy", &copy, e))?;
+            db.remove_file_entry(folder.id, &conflict.conflict_path)?;
+            index_path(db, &folder, &conflict.relative_path)?;
+        }
+        Resolution::KeepBoth => {
+            if db
+                .get_file_version(folder.id, &conflict.conflict_path)?
+                .is_none()
+            {
+                index_path(db, &folder, &conflict.conflict_path)?;
+            }
+        }
+    }
+
+    db.supersede_file_version(
+        folder.id,
+        &conflict.relative_path,
+        &conflict.remote.version_vector,
+    )?;
+    db.remove_conflict(conflict.id)?;
+    println!(
+        "[CONFLICT] Resolved {:?} in {} ({:?})",
+        conflict.relative_path, folder.name, resolution
+    );
+    Ok(())
+}
+
+/// Indexes a file right away, e.g. when the daemon is not running.
+fn index_path(db: &Database, folder: &SyncedFolder, relative_path: &Path) -> error::Result<()> {
+    let path = folder.local_path.join(relative_path);
+    let metadata = path
+        .metadata()
+        .map_err(|e| SyncError::io("failed to read metadata of", &path, e))?;
+    let hash = calculate_hash(&path)
+        .map_err(|e| SyncError::io("failed to calculate hash of", &path, e))?;
+    let modified_secs = metadata
+        .modified()
+        .unwrap_or_else(|_| SystemTime::now())
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    db.upsert_file_record(
+        folder.id,
+        relative_path,
+        metadata.len(),
+        &hash,
+        modified_secs,
+    )?;
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tokio::sync::watch;
+
+use crate::database::Database;
+use crate::db_pool::DbPool;
+use crate::event_queue::{EventQueue, QueueEvent};
+
+const PAUSED_SETTING: &str = "paused";
+const MAINTENANCE_SETTING: &str = "maintenance";
+
+/// Runtime switches shared between the event loop and the management APIs.
+#[derive(Debug)]
+pub struct SyncControl {
+    paused: AtomicBool,
+    /// Watched by file watchers, which stop watching while it is set.
+    maintenance: watch::Sender<bool>,
+}
+
+impl Default for SyncControl {
+    fn default() -> Self {
+        Self {
+            paused: AtomicBool::new(false),
+            maintenance: watch::Sender::new(false),
+        }
+    }
+}
+
+impl SyncControl {
+    /// Restores the persisted pause and maintenance state.
+    pub fn load(db: &Database) -> Result<Self, rusqlite::Error> {
+        let paused = db.get_setting(PAUSED_SETTING)?.as_deref() == Some("true");
+        let maintenance = db.get_setting(MAINTENANCE_SETTING)?.as_deref() == Some("true");
+        Ok(Self {
+            paused: AtomicBool::new(paused),
+            maintenance: watch::Sender::new(maintenance),
+        })
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    pub fn in_maintenance(&self) -> bool {
+        *self.maintenance.borrow()
+    }
+
+    /// True while local changes are not indexed: when paused or in
+    /// maintenance.
+    pub fn is_stopped(&self) -> bool {
+        self.is_paused() || self.in_maintenance()
+    }
+
+    /// False in maintenance: no file content may be sent to or received from
+    /// peers, so a folder being reorganized is not synced half-done.
+    pub fn transfers_allowed(&self) -> bool {
+        !self.in_maintenance()
+    }
+
+    /// Notified when maintenance mode is entered or left.
+    pub fn subscribe_maintenance(&self) -> watch::Receiver<bool> {
+        self.maintenance.subscribe()
+    }
+
+    /// Maintenance mode, for large offline reorganizations: file watchers
+    /// stop watching, nothing is indexed and no content is transferred,
+    /// while the management APIs keep answering. It survives restarts. On
+    /// leaving it, every folder is rescanned unless sync is paused.
+    pub async fn set_maintenance(
+        &self,
+        db: &DbPool,
+        queue: &EventQueue,
+        enabled: bool,
+    ) -> Result<(), rusqlite::Error> {
+        if enabled {
+            return self.enter_maintenance(&*db.write().await);
+        }
+        let folders = {
+            let db = db.write().await;
+            db.set_setting(MAINTENANCE_SETTING, "false")?;
+            if !self.maintenance.send_replace(false) {
+                return Ok(());
+            }
+            db.get_all_synced_folders()?
+        };
+        println!("[CONTROL] Maintenance mode left");
+        if self.is_paused() {
+            return Ok(());
+        }
+        for folder in folders {
+            queue
+                .send(QueueEvent::FolderAdded {
+                    path: folder.local_path,
+                })
+                .await;
+        }
+        Ok(())
+    }
+
+    /// Enters maintenance mode; see [`SyncControl::set_maintenance`]. Also
+    /// used while the daemon is stopped, so that it starts in it.
+    pub fn enter_maintenance(&self, db: &Database) -> Result<(), rusqlite::Error> {
+        db.set_setting(MAINTENANCE_SETTING, "true")?;
+        if !self.maintenance.send_replace(true) {
+            println!("[CONTROL] Maintenance mode entered; watchers and transfers stopped");
+        }
+        Ok(())
+    }
+
+    /// Stops indexing filesystem changes until [`SyncControl::resume`] is called.
+    pub fn pause(&self, db: &Database) -> Result<(), rusqlite::Error> {
+        db.set_setting(PAUSED_SETTING, "true")?;
+        self.paused.store(true, Ordering::SeqCst);
+        println!("[CONTROL] Sync paused");
+        Ok(())
+    }
+
+    /// Resumes indexing and queues a rescan of every folder to pick up the
+    /// changes that were ignored while paused.
+    pub async fn resume(&self, db: &DbPool, queue: &EventQueue) -> Result<(), rusqlite::Error> {
+        let folders = {
+            let db = db.write().await;
+            db.set_setting(PAUSED_SETTING, "false")?;
+            // Leaving maintenance rescans instead.
+            if !self.paused.swap(false, Ordering::SeqCst) || self.in_maintenance() {
+                return Ok(());
+            }
+            db.get_all_synced_folders()?
+        };
+        println!("[CONTROL] Sync resumed, rescanning folders");
+
+        for folder in folders {
+            queue
+                .send(QueueEvent::FolderAdded {
+                    path: folder.local_path,
+                })
+                .await;
+        }
+        Ok(())
+    }
+}
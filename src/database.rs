@@ -1,12 +1,611 @@
-use rusqlite::{Connection, Result, params};
+use rusqlite::{Connection, OpenFlags, Result, params};
+use serde::Serialize;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     path::{Path, PathBuf},
+    time::Duration,
 };
 
+use crate::config::FolderProfile;
+use crate::journal::Intent;
+use crate::placeholder::Placeholder;
+use crate::protocol::IndexEntry;
+use crate::replay::{RecordedEvent, RecordedKind};
 use crate::sync_engine::FileEntry;
+use crate::version_vector::VersionVector;
+use crate::xattrs::Xattrs;
 
-const DB_PATH: &str = "sync_rs.db";
+pub const DB_PATH: &str = "sync_rs.db";
+
+/// How long a statement waits for another connection's lock before failing
+/// with `SQLITE_BUSY`.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Schema changes applied on top of the base tables, in order. The index of
+/// the last applied entry (plus one) is stored in `PRAGMA user_version`.
+const MIGRATIONS: &[&str] = &[
+    // 1: globally unique folder IDs shared across devices.
+    "ALTER TABLE synced_folders ADD COLUMN folder_uuid TEXT;
+     CREATE UNIQUE INDEX IF NOT EXISTS idx_synced_folders_uuid ON synced_folders(folder_uuid);",
+    // 2: webhook subscriptions.
+    "CREATE TABLE webhooks (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        url TEXT NOT NULL,
+        secret TEXT,
+        events TEXT NOT NULL DEFAULT '',
+        created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+     );",
+    // 3: progress of folder scans, so an interrupted scan can resume.
+    "CREATE TABLE scan_checkpoints (
+        folder_id INTEGER PRIMARY KEY,
+        last_path TEXT NOT NULL DEFAULT '',
+        updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+        FOREIGN KEY(folder_id) REFERENCES synced_folders(id) ON DELETE CASCADE
+     );",
+    // 4: content lookups for local copies and duplicate reports.
+    "CREATE INDEX IF NOT EXISTS idx_file_index_hash ON file_index(sha256_hash);",
+    // 5: per-folder option to skip hidden files.
+    "ALTER TABLE synced_folders ADD COLUMN ignore_hidden INTEGER NOT NULL DEFAULT 0;",
+    // 6: options copied from folder profiles.
+    "ALTER TABLE synced_folders ADD COLUMN profile TEXT;
+     ALTER TABLE synced_folders ADD COLUMN ignore_patterns TEXT NOT NULL DEFAULT '';
+     ALTER TABLE synced_folders ADD COLUMN receive_only INTEGER NOT NULL DEFAULT 0;
+     ALTER TABLE synced_folders ADD COLUMN debounce_ms INTEGER NOT NULL DEFAULT 0;",
+    // 7: version vectors, so changes are ordered without comparing clocks.
+    "ALTER TABLE file_index ADD COLUMN version_vector TEXT NOT NULL DEFAULT '';",
+    // 8: opt-in extended attribute sync.
+    "ALTER TABLE synced_folders ADD COLUMN sync_xattrs INTEGER NOT NULL DEFAULT 0;
+     CREATE TABLE file_xattrs (
+        folder_id INTEGER NOT NULL,
+        relative_path TEXT NOT NULL,
+        name TEXT NOT NULL,
+        value BLOB NOT NULL,
+        PRIMARY KEY (folder_id, relative_path, name),
+        FOREIGN KEY(folder_id) REFERENCES synced_folders(id) ON DELETE CASCADE
+     );",
+    // 9: per-folder size limit; NULL means unlimited.
+    "ALTER TABLE synced_folders ADD COLUMN quota_bytes INTEGER;",
+    // 10: selective sync, with on-demand fetches of unsynced files.
+    "ALTER TABLE synced_folders ADD COLUMN unsynced_paths TEXT NOT NULL DEFAULT '';
+     CREATE TABLE fetch_requests (
+        folder_id INTEGER NOT NULL,
+        relative_path TEXT NOT NULL,
+        requested_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+        PRIMARY KEY (folder_id, relative_path),
+        FOREIGN KEY(folder_id) REFERENCES synced_folders(id) ON DELETE CASCADE
+     );",
+    // 11: placeholder stubs for files whose content is not downloaded.
+    "ALTER TABLE synced_folders ADD COLUMN placeholders INTEGER NOT NULL DEFAULT 0;",
+    // 12: files skipped because another process has them locked.
+    "CREATE TABLE locked_files (
+        folder_id INTEGER NOT NULL,
+        relative_path TEXT NOT NULL,
+        attempts INTEGER NOT NULL,
+        next_retry_secs INTEGER NOT NULL,
+        PRIMARY KEY (folder_id, relative_path),
+        FOREIGN KEY(folder_id) REFERENCES synced_folders(id) ON DELETE CASCADE
+     );",
+    // 13: per-folder health counters.
+    "CREATE TABLE folder_stats (
+        folder_id INTEGER PRIMARY KEY,
+        events_processed INTEGER NOT NULL DEFAULT 0,
+        failures INTEGER NOT NULL DEFAULT 0,
+        last_failure TEXT,
+        last_scan_secs INTEGER,
+        last_watcher_error TEXT,
+        last_watcher_error_secs INTEGER,
+        FOREIGN KEY(folder_id) REFERENCES synced_folders(id) ON DELETE CASCADE
+     );",
+    // 14: user-defined key/value tags on indexed files.
+    "CREATE TABLE file_tags (
+        folder_id INTEGER NOT NULL,
+        relative_path TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (folder_id, relative_path, key),
+        FOREIGN KEY(folder_id) REFERENCES synced_folders(id) ON DELETE CASCADE
+     );",
+    // 15: searching the index; trigrams make substring matches indexed.
+    "CREATE INDEX IF NOT EXISTS idx_file_index_size ON file_index(size_bytes);
+     CREATE INDEX IF NOT EXISTS idx_file_index_modified ON file_index(last_modified_secs);
+     CREATE VIRTUAL TABLE file_index_fts USING fts5(
+        relative_path, content = 'file_index', content_rowid = 'id', tokenize = 'trigram'
+     );
+     INSERT INTO file_index_fts(file_index_fts) VALUES ('rebuild');
+     CREATE TRIGGER file_index_fts_insert AFTER INSERT ON file_index BEGIN
+        INSERT INTO file_index_fts(rowid, relative_path) VALUES (new.id, new.relative_path);
+     END;
+     CREATE TRIGGER file_index_fts_delete AFTER DELETE ON file_index BEGIN
+        INSERT INTO file_index_fts(file_index_fts, rowid, relative_path)
+        VALUES ('delete', old.id, old.relative_path);
+     END;
+     CREATE TRIGGER file_index_fts_update AFTER UPDATE OF relative_path ON file_index BEGIN
+        INSERT INTO file_index_fts(file_index_fts, rowid, relative_path)
+        VALUES ('delete', old.id, old.relative_path);
+        INSERT INTO file_index_fts(rowid, relative_path) VALUES (new.id, new.relative_path);
+     END;",
+    // 16: conflicting remote versions kept as copies, awaiting resolution.
+    "CREATE TABLE conflicts (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        folder_id INTEGER NOT NULL,
+        relative_path TEXT NOT NULL,
+        conflict_path TEXT NOT NULL,
+        remote_device_id TEXT NOT NULL,
+        remote_device_name TEXT,
+        remote_hash TEXT NOT NULL,
+        remote_version_vector TEXT NOT NULL,
+        detected_secs INTEGER NOT NULL,
+        FOREIGN KEY(folder_id) REFERENCES synced_folders(id) ON DELETE CASCADE
+     );",
+    // 17: intents of remote changes being applied, for crash recovery.
+    "CREATE TABLE apply_journal (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        batch_id INTEGER NOT NULL,
+        folder_id INTEGER NOT NULL,
+        op TEXT NOT NULL,
+        relative_path TEXT NOT NULL,
+        from_path TEXT,
+        sha256_hash TEXT,
+        version_vector TEXT,
+        done INTEGER NOT NULL DEFAULT 0,
+        FOREIGN KEY(folder_id) REFERENCES synced_folders(id) ON DELETE CASCADE
+     );
+     CREATE INDEX IF NOT EXISTS idx_apply_journal_batch ON apply_journal(batch_id);",
+    // 18: folders that only mirror the index of peers.
+    "ALTER TABLE synced_folders ADD COLUMN observer INTEGER NOT NULL DEFAULT 0;",
+    // 19: per-folder hashing parallelism; 0 picks one from the CPU count.
+    "ALTER TABLE synced_folders ADD COLUMN hash_threads INTEGER NOT NULL DEFAULT 0;",
+    // 20: hard links; files with the same link_group share one inode.
+    "ALTER TABLE synced_folders ADD COLUMN sync_hardlinks INTEGER NOT NULL DEFAULT 0;
+     ALTER TABLE file_index ADD COLUMN link_group TEXT;
+     CREATE INDEX IF NOT EXISTS idx_file_index_link_group ON file_index(folder_id, link_group);",
+    // 21: received files discarded because they did not match their hash.
+    "ALTER TABLE folder_stats ADD COLUMN verification_failures INTEGER NOT NULL DEFAULT 0;",
+    // 22: manual push; local changes wait in pending_changes until approved.
+    "ALTER TABLE synced_folders ADD COLUMN manual_push INTEGER NOT NULL DEFAULT 0;
+     CREATE TABLE pending_changes (
+        folder_id INTEGER NOT NULL,
+        relative_path TEXT NOT NULL,
+        change TEXT NOT NULL,
+        from_path TEXT,
+        detected_secs INTEGER NOT NULL,
+        PRIMARY KEY (folder_id, relative_path),
+        FOREIGN KEY(folder_id) REFERENCES synced_folders(id) ON DELETE CASCADE
+     );",
+    // 23: per-folder sequence numbers of index changes, and how far each
+    // peer has acknowledged them. Existing rows are numbered by ID.
+    "ALTER TABLE synced_folders ADD COLUMN max_sequence INTEGER NOT NULL DEFAULT 0;
+     ALTER TABLE file_index ADD COLUMN sequence INTEGER NOT NULL DEFAULT 0;
+     UPDATE file_index SET sequence = id;
+     UPDATE synced_folders SET max_sequence =
+        (SELECT COALESCE(MAX(sequence), 0) FROM file_index WHERE folder_id = synced_folders.id);
+     CREATE INDEX IF NOT EXISTS idx_file_index_sequence ON file_index(folder_id, sequence);
+     CREATE TRIGGER file_index_sequence_insert AFTER INSERT ON file_index BEGIN
+        UPDATE synced_folders SET max_sequence = max_sequence + 1 WHERE id = new.folder_id;
+        UPDATE file_index SET sequence =
+            (SELECT max_sequence FROM synced_folders WHERE id = new.folder_id)
+        WHERE id = new.id;
+     END;
+     CREATE TRIGGER file_index_sequence_update
+     AFTER UPDATE OF folder_id, relative_path, sha256_hash, version_vector ON file_index
+     WHEN old.folder_id IS NOT new.folder_id OR old.relative_path IS NOT new.relative_path
+        OR old.sha256_hash IS NOT new.sha256_hash OR old.version_vector IS NOT new.version_vector
+     BEGIN
+        UPDATE synced_folders SET max_sequence = max_sequence + 1 WHERE id = new.folder_id;
+        UPDATE file_index SET sequence =
+            (SELECT max_sequence FROM synced_folders WHERE id = new.folder_id)
+        WHERE id = new.id;
+     END;
+     CREATE TABLE peers (
+        device_id TEXT PRIMARY KEY,
+        device_name TEXT,
+        connected INTEGER NOT NULL DEFAULT 0,
+        last_seen_secs INTEGER
+     );
+     CREATE TABLE peer_cursors (
+        device_id TEXT NOT NULL,
+        folder_id INTEGER NOT NULL,
+        acked_sequence INTEGER NOT NULL DEFAULT 0,
+        last_synced_secs INTEGER,
+        PRIMARY KEY (device_id, folder_id),
+        FOREIGN KEY(device_id) REFERENCES peers(device_id) ON DELETE CASCADE,
+        FOREIGN KEY(folder_id) REFERENCES synced_folders(id) ON DELETE CASCADE
+     );",
+    // 24: how far this device has received each peer's index, so an
+    // interrupted index exchange resumes instead of starting over.
+    "ALTER TABLE peer_cursors ADD COLUMN received_sequence INTEGER NOT NULL DEFAULT 0;",
+    // 25: tombstones for entries that left the index, numbered like changes
+    // so delta index updates carry removals and renames too.
+    "CREATE TABLE file_tombstones (
+        folder_id INTEGER NOT NULL,
+        relative_path TEXT NOT NULL,
+        version_vector TEXT NOT NULL DEFAULT '{}',
+        sequence INTEGER NOT NULL,
+        PRIMARY KEY (folder_id, relative_path),
+        FOREIGN KEY(folder_id) REFERENCES synced_folders(id) ON DELETE CASCADE
+     );
+     CREATE INDEX IF NOT EXISTS idx_file_tombstones_sequence
+        ON file_tombstones(folder_id, sequence);
+     CREATE TRIGGER file_index_tombstone_delete AFTER DELETE ON file_index BEGIN
+        UPDATE synced_folders SET max_sequence = max_sequence + 1 WHERE id = old.folder_id;
+        INSERT OR REPLACE INTO file_tombstones (folder_id, relative_path, version_vector, sequence)
+        SELECT old.folder_id, old.relative_path, old.version_vector, max_sequence
+        FROM synced_folders WHERE id = old.folder_id;
+     END;
+     CREATE TRIGGER file_index_tombstone_move
+     AFTER UPDATE OF folder_id, relative_path ON file_index
+     WHEN old.folder_id IS NOT new.folder_id OR old.relative_path IS NOT new.relative_path
+     BEGIN
+        UPDATE synced_folders SET max_sequence = max_sequence + 1 WHERE id = old.folder_id;
+        INSERT OR REPLACE INTO file_tombstones (folder_id, relative_path, version_vector, sequence)
+        SELECT old.folder_id, old.relative_path, old.version_vector, max_sequence
+        FROM synced_folders WHERE id = old.folder_id;
+        DELETE FROM file_tombstones
+        WHERE folder_id = new.folder_id AND relative_path = new.relative_path;
+     END;
+     CREATE TRIGGER file_index_tombstone_insert AFTER INSERT ON file_index BEGIN
+        DELETE FROM file_tombstones
+        WHERE folder_id = new.folder_id AND relative_path = new.relative_path;
+     END;",
+    // 26: folders kept as a one-way copy of another folder on this machine.
+    "ALTER TABLE synced_folders ADD COLUMN mirror_of INTEGER;",
+    // 27: folders on drives that come and go.
+    "ALTER TABLE synced_folders ADD COLUMN removable INTEGER NOT NULL DEFAULT 0;",
+    // 28: bytes transferred per folder, and a daily history of folder
+    // statistics for trend graphs.
+    "ALTER TABLE folder_stats ADD COLUMN bytes_sent INTEGER NOT NULL DEFAULT 0;
+     ALTER TABLE folder_stats ADD COLUMN bytes_received INTEGER NOT NULL DEFAULT 0;
+     CREATE TABLE stats_history (
+        folder_id INTEGER NOT NULL,
+        day TEXT NOT NULL,
+        file_count INTEGER NOT NULL,
+        size_bytes INTEGER NOT NULL,
+        sent_start INTEGER NOT NULL,
+        received_start INTEGER NOT NULL,
+        sent_end INTEGER NOT NULL,
+        received_end INTEGER NOT NULL,
+        recorded_secs INTEGER NOT NULL,
+        PRIMARY KEY (folder_id, day),
+        FOREIGN KEY(folder_id) REFERENCES synced_folders(id) ON DELETE CASCADE
+     );",
+    // 29: bytes exchanged with each peer per folder and day.
+    "CREATE TABLE transfer_stats (
+        day TEXT NOT NULL,
+        device_id TEXT NOT NULL,
+        folder_id INTEGER NOT NULL,
+        bytes_sent INTEGER NOT NULL DEFAULT 0,
+        bytes_received INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (day, device_id, folder_id),
+        FOREIGN KEY(folder_id) REFERENCES synced_folders(id) ON DELETE CASCADE
+     );",
+    // 30: rolling window of file watcher events for `sync_rs replay`.
+    "CREATE TABLE recorded_events (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        recorded_ms INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        path TEXT NOT NULL,
+        old_path TEXT
+     );
+     CREATE INDEX idx_recorded_events_time ON recorded_events(recorded_ms);",
+    // 31: files left alone until unchanged for a while, for slow writers.
+    "ALTER TABLE synced_folders ADD COLUMN settle_secs INTEGER NOT NULL DEFAULT 0;",
+    // 32: results of re-verifying indexed files while idle.
+    "ALTER TABLE folder_stats ADD COLUMN audited_files INTEGER NOT NULL DEFAULT 0;
+     ALTER TABLE folder_stats ADD COLUMN audit_mismatches INTEGER NOT NULL DEFAULT 0;
+     ALTER TABLE folder_stats ADD COLUMN last_audit_mismatch TEXT;",
+];
+
+/// Columns read by [`Database::map_synced_folder`], in order.
+const FOLDER_COLUMNS: &str = "id, folder_uuid, name, local_path, ignore_hidden, profile, \
+     ignore_patterns, receive_only, debounce_ms, sync_xattrs, quota_bytes, unsynced_paths, placeholders, \
+     observer, hash_threads, sync_hardlinks, manual_push, mirror_of, removable, settle_secs";
+
+/// Columns of `folder_stats` read by [`Database::map_folder_stats`], in order.
+const FOLDER_STATS_COLUMNS: &str = "events_processed, failures, last_failure, last_scan_secs, \
+     last_watcher_error, last_watcher_error_secs, verification_failures, bytes_sent, bytes_received, \
+     audited_files, audit_mismatches, last_audit_mismatch";
+
+/// Columns of `conflicts` read by [`Database::map_conflict`], in order.
+const CONFLICT_COLUMNS: &str = "id, folder_id, relative_path, conflict_path, remote_device_id, \
+     remote_device_name, remote_hash, remote_version_vector, detected_secs";
+
+/// Binds a path as the UTF-8 text the index stores, failing the statement
+/// instead of panicking on paths that are not valid UTF-8.
+fn path_param(path: &Path) -> Result<&str> {
+    crate::error::path_str(path).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Key/value tags of an indexed file, e.g. `status=reviewed`.
+pub type FileTags = BTreeMap<String, String>;
+
+/// Tag keys are non-empty and contain neither `=` nor whitespace, so they
+/// can be written as `key=value` on the command line.
+pub fn is_valid_tag_key(key: &str) -> bool {
+    !key.is_empty()
+        && !key
+            .chars()
+            .any(|c| c == '=' || c.is_whitespace() || c.is_control())
+}
+
+/// A row of the `webhooks` table.
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+    pub secret: Option<String>,
+    /// Event names the hook receives; empty means all events.
+    pub events: Vec<String>,
+}
+
+/// A row of the `synced_folders` table.
+#[derive(Debug, Clone)]
+pub struct SyncedFolder {
+    pub id: i64,
+    pub folder_uuid: String,
+    pub name: String,
+    pub local_path: PathBuf,
+    /// Skip hidden files and directories in scans and watcher events.
+    pub ignore_hidden: bool,
+    /// Name of the profile the folder's options were copied from.
+    pub profile: Option<String>,
+    pub ignore_patterns: Vec<String>,
+    pub receive_only: bool,
+    pub debounce_ms: u64,
+    /// Index and apply extended attributes (see [`crate::xattrs`]).
+    pub sync_xattrs: bool,
+    /// Maximum total size of the folder's indexed files.
+    pub quota_bytes: Option<u64>,
+    /// Subdirectories or files whose content is not kept on this device.
+    pub unsynced_paths: Vec<PathBuf>,
+    /// Write placeholder stubs for unsynced files (see [`crate::placeholder`]).
+    pub placeholders: bool,
+    /// Only keep the index announced by peers; never download content or
+    /// index local files. For monitoring nodes and admin dashboards.
+    pub observer: bool,
+    /// Files a scan reads and hashes at once; 0 means automatic.
+    pub hash_threads: usize,
+    /// Record which files are hard links of each other, so peers link them
+    /// too instead of storing the content twice.
+    pub sync_hardlinks: bool,
+    /// Hold local changes in a pending list until they are approved with
+    /// `sync_rs push`, instead of announcing them right away.
+    pub manual_push: bool,
+    /// ID of the folder this one is a local mirror of (see [`crate::mirror`]).
+    pub mirror_of: Option<i64>,
+    /// Lives on a drive that may be unplugged (see [`crate::removable`]).
+    pub removable: bool,
+    /// Seconds a file must go unmodified before it is hashed and announced,
+    /// so files written over minutes are not hashed and synced half-done.
+    pub settle_secs: u64,
+}
+
+/// Upper bound for the automatic number of hash threads; more rarely helps,
+/// since hashing is limited by the disk long before the CPU.
+pub const MAX_AUTO_HASH_THREADS: usize = 4;
+
+impl SyncedFolder {
+    /// How long the watcher waits for a file to settle before queueing it.
+    pub fn debounce(&self) -> Duration {
+        Duration::from_millis(self.debounce_ms)
+    }
+
+    pub fn settle_time(&self) -> Duration {
+        Duration::from_secs(self.settle_secs)
+    }
+
+    /// Files a scan reads and hashes at once: the configured number, or one
+    /// per CPU up to [`MAX_AUTO_HASH_THREADS`].
+    pub fn hash_parallelism(&self) -> usize {
+        if self.hash_threads > 0 {
+            return self.hash_threads;
+        }
+        std::thread::available_parallelism()
+            .map_or(1, |threads| threads.get())
+            .min(MAX_AUTO_HASH_THREADS)
+    }
+
+    /// True when the folder holds more than its quota allows. Receiving new
+    /// data for it is paused until files are removed or the quota is raised.
+    pub fn is_over_quota(&self, used_bytes: u64) -> bool {
+        self.quota_bytes.is_some_and(|quota| used_bytes > quota)
+    }
+
+    /// True when `relative_path` lies in a subpath that is not synced
+    /// locally, so its content is only downloaded when fetched explicitly.
+    /// Nothing is synced locally in observer folders.
+    pub fn is_unsynced(&self, relative_path: &Path) -> bool {
+        self.observer
+            || self
+                .unsynced_paths
+                .iter()
+                .any(|unsynced| relative_path.starts_with(unsynced))
+    }
+
+    /// True when `incoming` more bytes still fit within the quota.
+    pub fn has_room_for(&self, used_bytes: u64, incoming: u64) -> bool {
+        self.quota_bytes
+            .is_none_or(|quota| used_bytes.saturating_add(incoming) <= quota)
+    }
+}
+
+/// A row of the `locked_files` table.
+#[derive(Debug, Clone)]
+pub struct LockedFile {
+    pub relative_path: PathBuf,
+    /// Failed attempts to read the file so far.
+    pub attempts: u32,
+    /// Unix time of the next attempt.
+    pub next_retry_secs: u64,
+}
+
+/// The remote side of a conflict: a version of a file announced by a peer.
+#[derive(Debug, Clone, Serialize)]
+pub struct RemoteVersion {
+    pub device_id: String,
+    pub device_name: Option<String>,
+    pub hash: String,
+    pub version_vector: VersionVector,
+}
+
+/// How a file changed locally.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LocalChange {
+    Modified,
+    Removed,
+    Renamed { from: PathBuf },
+}
+
+/// A row of the `pending_changes` table: a local change in a manual-push
+/// folder that peers do not learn about until it is approved.
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingChange {
+    pub relative_path: PathBuf,
+    pub change: LocalChange,
+    pub detected_secs: u64,
+}
+
+/// A row of the `peers` table: a device this one has connected to.
+#[derive(Debug, Clone, Serialize)]
+pub struct Peer {
+    pub device_id: String,
+    pub device_name: Option<String>,
+    pub connected: bool,
+    /// Unix time the peer last connected or disconnected.
+    pub last_seen_secs: Option<u64>,
+}
+
+impl Peer {
+    pub fn display_name(&self) -> &str {
+        self.device_name.as_deref().unwrap_or(&self.device_id)
+    }
+}
+
+/// How far a peer is behind on one folder: the index changes it has not
+/// acknowledged yet, and the size of the files they touch.
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerBacklog {
+    pub folder_id: i64,
+    /// Highest sequence number the peer acknowledged (see
+    /// [`Database::ack_sequence`]).
+    pub acked_sequence: u64,
+    pub last_synced_secs: Option<u64>,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Bytes exchanged with a peer for one folder on one day (see
+/// [`crate::transfers`]).
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferRecord {
+    /// Local date, e.g. `2026-01-31`.
+    pub day: String,
+    pub device_id: String,
+    pub folder_id: i64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+/// A row of the `conflicts` table (see [`crate::conflicts`]).
+#[derive(Debug, Clone, Serialize)]
+pub struct Conflict {
+    pub id: i64,
+    pub folder_id: i64,
+    /// The file that kept the local version.
+    pub relative_path: PathBuf,
+    /// The copy holding the remote version, in the same folder.
+    pub conflict_path: PathBuf,
+    pub remote: RemoteVersion,
+    pub detected_secs: u64,
+}
+
+/// A row of the `folder_stats` table (see [`crate::health`]).
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FolderStats {
+    pub events_processed: u64,
+    pub failures: u64,
+    pub last_failure: Option<String>,
+    /// Unix time the last full scan finished.
+    pub last_scan_secs: Option<u64>,
+    pub last_watcher_error: Option<String>,
+    pub last_watcher_error_secs: Option<u64>,
+    /// Received files discarded because their content did not match the
+    /// announced hash (see [`crate::verify`]).
+    pub verification_failures: u64,
+    /// File content sent to and received from peers, in bytes.
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Files re-verified against disk while idle (see [`crate::audit`]),
+    /// and those that no longer matched the index.
+    pub audited_files: u64,
+    pub audit_mismatches: u64,
+    pub last_audit_mismatch: Option<String>,
+}
+
+/// A day of a folder's `stats_history` (see [`crate::stats_history`]).
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsHistoryEntry {
+    /// Local date, e.g. `2026-01-31`.
+    pub day: String,
+    pub file_count: u64,
+    pub size_bytes: u64,
+    /// Bytes sent and received that day.
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Unix time of the day's last snapshot, which the values are from.
+    pub recorded_secs: u64,
+}
+
+/// Indexed files sharing the same content hash.
+#[derive(Debug, Clone)]
+pub struct DuplicateGroup {
+    pub hash: String,
+    pub size_bytes: u64,
+    /// `(folder root, path relative to it)` of every copy.
+    pub files: Vec<(PathBuf, PathBuf)>,
+}
+
+/// Filters for [`Database::find_files`]; unset fields match every file.
+#[derive(Debug, Clone, Default)]
+pub struct FileQuery {
+    /// A glob (`*`, `?`, `[...]`) matched against the relative path or its
+    /// last components, or without wildcards a case-insensitive substring.
+    pub pattern: String,
+    pub folder_id: Option<i64>,
+    pub min_size: Option<u64>,
+    pub modified_since_secs: Option<u64>,
+}
+
+/// An index entry found by [`Database::find_files`].
+#[derive(Debug, Clone)]
+pub struct FoundFile {
+    pub folder_id: i64,
+    pub relative_path: PathBuf,
+    pub size_bytes: u64,
+    pub modified_secs: u64,
+    /// Devices that changed the file and how often.
+    pub version_vector: VersionVector,
+}
+
+/// An index entry picked by [`Database::random_indexed_file`].
+#[derive(Debug, Clone)]
+pub struct IndexedFile {
+    pub folder_id: i64,
+    pub relative_path: PathBuf,
+    pub size_bytes: u64,
+    pub modified_secs: u64,
+    pub hash: String,
+}
+
+/// Upper bound on the results of a single search.
+pub const MAX_FIND_RESULTS: usize = 1000;
 
 #[derive(Debug)]
 pub struct Database {
@@ -15,12 +614,32 @@ pub struct Database {
 
 impl Database {
     pub fn new() -> Result<Self, rusqlite::Error> {
-        let conn = Connection::open(DB_PATH)?;
+        Self::open(Path::new(DB_PATH))
+    }
+
+    /// Opens, creating and migrating as needed, the database at `path`
+    /// rather than the one of the current directory.
+    pub fn open(path: &Path) -> Result<Self, rusqlite::Error> {
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        // WAL lets readers run while a write is in progress.
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
         let db = Self { conn };
         db.initialize()?;
         Ok(db)
     }
 
+    /// Opens a read-only connection to a database that [`Database::new`] has
+    /// already created and migrated.
+    pub fn open_read_only() -> Result<Self, rusqlite::Error> {
+        let conn = Connection::open_with_flags(
+            DB_PATH,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        Ok(Self { conn })
+    }
+
     fn initialize(&self) -> Result<(), rusqlite::Error> {
         self.conn.execute_batch(
             "BEGIN;
@@ -52,6 +671,77 @@ impl Database {
             COMMIT;",
         )?;
 
+        self.migrate()
+    }
+
+    fn migrate(&self) -> Result<(), rusqlite::Error> {
+        let current: usize = self
+            .conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+            let version = index + 1;
+            self.conn.execute_batch(&format!(
+                "BEGIN;
+                {}
+                PRAGMA user_version = {};
+                COMMIT;",
+                migration, version
+            ))?;
+            // On stderr, so it does not end up in `--format json` output.
+            eprintln!("[DATABASE] Applied schema migration {}", version);
+        }
+
+        self.backfill_folder_uuids()?;
+        self.backfill_version_vectors()
+    }
+
+    /// Schema migrations not yet applied to the database, e.g. one opened
+    /// with [`Database::open_read_only`] that an older version created.
+    pub fn pending_migrations(&self) -> Result<usize, rusqlite::Error> {
+        let current: usize = self
+            .conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        Ok(MIGRATIONS.len().saturating_sub(current))
+    }
+
+    /// Runs SQLite's integrity check and returns the problems it reports;
+    /// an empty list means the database is healthy.
+    pub fn integrity_check(&self) -> Result<Vec<String>, rusqlite::Error> {
+        let mut stmt = self.conn.prepare("PRAGMA integrity_check")?;
+        let messages = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(messages.into_iter().filter(|m| m != "ok").collect())
+    }
+
+    /// Assigns a UUID to folders created before folder UUIDs existed.
+    fn backfill_folder_uuids(&self) -> Result<(), rusqlite::Error> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id FROM synced_folders WHERE folder_uuid IS NULL")?;
+        let ids = stmt
+            .query_map([], |row| row.get::<_, i64>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for id in ids {
+            self.conn.execute(
+                "UPDATE synced_folders SET folder_uuid = ?1 WHERE id = ?2",
+                params![uuid::Uuid::new_v4().to_string(), id],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Gives files indexed before version vectors existed one with this
+    /// device as the only author, at the file's current version.
+    fn backfill_version_vectors(&self) -> Result<(), rusqlite::Error> {
+        let device_id = self.get_or_create_device_id()?;
+        self.conn.execute(
+            "UPDATE file_index SET version_vector = json_object(?1, version)
+             WHERE version_vector = ''",
+            params![device_id],
+        )?;
         Ok(())
     }
 
@@ -77,10 +767,66 @@ impl Database {
         }
     }
 
+    pub fn get_setting(&self, key: &str) -> Result<Option<String>, rusqlite::Error> {
+        let query_result = self.conn.query_row(
+            "SELECT value FROM settings WHERE key = ?1",
+            params![key],
+            |row| row.get(0),
+        );
+
+        match query_result {
+            Ok(value) => Ok(Some(value)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn set_setting(&self, key: &str, value: &str) -> Result<(), rusqlite::Error> {
+        self.conn.execute(
+            "INSERT INTO settings (key, value) VALUES (?1, ?2)
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_setting(&self, key: &str) -> Result<(), rusqlite::Error> {
+        self.conn
+            .execute("DELETE FROM settings WHERE key = ?1", params![key])?;
+        Ok(())
+    }
+
+    pub fn get_device_name(&self) -> Result<Option<String>, rusqlite::Error> {
+        self.get_setting("device_name")
+    }
+
+    pub fn set_device_name(&self, name: &str) -> Result<(), rusqlite::Error> {
+        self.set_setting("device_name", name)
+    }
+
+    /// Returns the human-readable device name, falling back to the device ID
+    /// when no name has been configured.
+    pub fn get_device_label(&self) -> Result<String, rusqlite::Error> {
+        match self.get_device_name()? {
+            Some(name) => Ok(name),
+            None => self.get_or_create_device_id(),
+        }
+    }
+
     pub fn add_folder(&self, name: &str, path: &str) -> Result<i64, rusqlite::Error> {
+        self.add_shared_folder(&uuid::Uuid::new_v4().to_string(), name, path)
+    }
+
+    /// Adds a folder under a UUID chosen elsewhere, e.g. one shared by a peer.
+    pub fn add_shared_folder(
+        &self,
+        folder_uuid: &str,
+        name: &str,
+        path: &str,
+    ) -> Result<i64, rusqlite::Error> {
         self.conn.execute(
-            "INSERT INTO synced_folders (name, local_path) VALUES (?1, ?2)",
-            rusqlite::params![name, path],
+            "INSERT INTO synced_folders (folder_uuid, name, local_path) VALUES (?1, ?2, ?3)",
+            rusqlite::params![folder_uuid, name, path],
         )?;
         Ok(self.conn.last_insert_rowid())
     }
@@ -126,67 +872,1833 @@ impl Database {
     pub fn get_folder_by_path(
         &self,
         path_str: &str,
-    ) -> Result<Option<(i64, PathBuf)>, rusqlite::Error> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT id, local_path FROM synced_folders WHERE local_path = ?1")?;
-        let mut rows = stmt.query_map(params![path_str], |row| {
-            Ok((row.get(0)?, row.get::<_, String>(1)?.into()))
-        })?;
+    ) -> Result<Option<SyncedFolder>, rusqlite::Error> {
+        self.query_synced_folder("local_path", path_str)
+    }
 
-        match rows.next() {
-            Some(Ok(row)) => Ok(Some(row)),
-            Some(Err(e)) => Err(e),
-            None => Ok(None),
-        }
+    pub fn get_folder_by_uuid(
+        &self,
+        folder_uuid: &str,
+    ) -> Result<Option<SyncedFolder>, rusqlite::Error> {
+        self.query_synced_folder("folder_uuid", folder_uuid)
     }
 
-    pub fn get_all_synced_folders(&self) -> Result<Vec<(i64, PathBuf)>, rusqlite::Error> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT id, local_path FROM synced_folders")?;
-        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get::<_, String>(1)?.into())))?;
+    pub fn get_folder_by_id(
+        &self,
+        folder_id: i64,
+    ) -> Result<Option<SyncedFolder>, rusqlite::Error> {
+        self.query_synced_folder("id", folder_id)
+    }
 
-        let mut folders = Vec::new();
-        for row in rows {
-            folders.push(row?);
+    /// Looks a folder up by ID, name or UUID, as given on the command line.
+    pub fn find_folder(&self, name_or_id: &str) -> Result<Option<SyncedFolder>, rusqlite::Error> {
+        if let Ok(folder_id) = name_or_id.parse::<i64>()
+            && let Some(folder) = self.get_folder_by_id(folder_id)?
+        {
+            return Ok(Some(folder));
+        }
+        match self.query_synced_folder("name", name_or_id)? {
+            Some(folder) => Ok(Some(folder)),
+            None => self.get_folder_by_uuid(name_or_id),
         }
-        Ok(folders)
     }
 
-    pub fn upsert_file_record(
+    pub fn set_folder_ignore_hidden(
         &self,
         folder_id: i64,
-        relative_path: &Path,
-        size_bytes: u64,
-        sha256_hash: &str,
-        modified_secs: u64,
+        ignore_hidden: bool,
     ) -> Result<(), rusqlite::Error> {
         self.conn.execute(
-            "INSERT INTO file_index (folder_id, relative_path, last_modified_secs, size_bytes, sha256_hash, version)
-             VALUES (?1, ?2, ?3, ?4, ?5, 1)
-             ON CONFLICT(folder_id, relative_path) DO UPDATE SET
-                last_modified_secs = excluded.last_modified_secs,
-                size_bytes = excluded.size_bytes,
-                sha256_hash = excluded.sha256_hash,
-                version = version + 1,
-                last_synced_at = CURRENT_TIMESTAMP",
+            "UPDATE synced_folders SET ignore_hidden = ?1 WHERE id = ?2",
+            params![ignore_hidden, folder_id],
+        )?;
+        Ok(())
+    }
+
+    /// Copies the options of a folder profile onto a folder. Ignore patterns
+    /// are stored one per line.
+    pub fn apply_folder_profile(
+        &self,
+        folder_id: i64,
+        name: &str,
+        profile: &FolderProfile,
+    ) -> Result<(), rusqlite::Error> {
+        self.conn.execute(
+            "UPDATE synced_folders SET profile = ?1, ignore_patterns = ?2, ignore_hidden = ?3,
+                receive_only = ?4, debounce_ms = ?5, sync_xattrs = ?6, hash_threads = ?7,
+                settle_secs = ?8
+             WHERE id = ?9",
             params![
-                folder_id,
-                relative_path.to_str().expect("Path contains invalid UTF-8"),
-                modified_secs,
-                size_bytes,
-                sha256_hash
+                name,
+                profile.ignore.join("\n"),
+                profile.ignore_hidden,
+                profile.receive_only,
+                profile.debounce_ms,
+                profile.sync_xattrs,
+                profile.hash_threads,
+                profile.settle_secs,
+                folder_id
             ],
         )?;
         Ok(())
     }
 
-    pub fn remove_file_entry(&self, folder_id: i64, file_name: &Path) -> Result<()> {
+    pub fn set_folder_ignore_patterns(&self, folder_id: i64, patterns: &[String]) -> Result<()> {
         self.conn.execute(
-            "DELETE FROM file_index WHERE folder_id = ?1 AND relative_path = ?2",
-            params![folder_id, file_name.to_str().unwrap()],
+            "UPDATE synced_folders SET ignore_patterns = ?1 WHERE id = ?2",
+            params![patterns.join("\n"), folder_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_folder_receive_only(&self, folder_id: i64, receive_only: bool) -> Result<()> {
+        self.conn.execute(
+            "UPDATE synced_folders SET receive_only = ?1 WHERE id = ?2",
+            params![receive_only, folder_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_folder_sync_xattrs(
+        &self,
+        folder_id: i64,
+        sync_xattrs: bool,
+    ) -> Result<(), rusqlite::Error> {
+        self.conn.execute(
+            "UPDATE synced_folders SET sync_xattrs = ?1 WHERE id = ?2",
+            params![sync_xattrs, folder_id],
+        )?;
+        if !sync_xattrs {
+            self.conn.execute(
+                "DELETE FROM file_xattrs WHERE folder_id = ?1",
+                params![folder_id],
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn set_folder_sync_hardlinks(
+        &self,
+        folder_id: i64,
+        sync_hardlinks: bool,
+    ) -> Result<(), rusqlite::Error> {
+        self.conn.execute(
+            "UPDATE synced_folders SET sync_hardlinks = ?1 WHERE id = ?2",
+            params![sync_hardlinks, folder_id],
+        )?;
+        if !sync_hardlinks {
+            self.conn.execute(
+                "UPDATE file_index SET link_group = NULL WHERE folder_id = ?1",
+                params![folder_id],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Turning manual push off approves every pending change of the folder,
+    /// as [`Database::approve_pending_changes`] does.
+    pub fn set_folder_manual_push(&self, folder_id: i64, manual_push: bool) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "UPDATE synced_folders SET manual_push = ?1 WHERE id = ?2",
+            params![manual_push, folder_id],
+        )?;
+        if !manual_push {
+            self.release_pending_changes(folder_id, &[])?;
+        }
+        tx.commit()
+    }
+
+    pub fn set_folder_placeholders(
+        &self,
+        folder_id: i64,
+        placeholders: bool,
+    ) -> Result<(), rusqlite::Error> {
+        self.conn.execute(
+            "UPDATE synced_folders SET placeholders = ?1 WHERE id = ?2",
+            params![placeholders, folder_id],
         )?;
         Ok(())
     }
+
+    pub fn set_folder_settle_secs(&self, folder_id: i64, settle_secs: u64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE synced_folders SET settle_secs = ?1 WHERE id = ?2",
+            params![settle_secs, folder_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_folder_hash_threads(&self, folder_id: i64, hash_threads: usize) -> Result<()> {
+        self.conn.execute(
+            "UPDATE synced_folders SET hash_threads = ?1 WHERE id = ?2",
+            params![hash_threads, folder_id],
+        )?;
+        Ok(())
+    }
+
+    /// Makes the folder a mirror of the folder with ID `source_id`, or a
+    /// regular folder again with `None`.
+    pub fn set_folder_mirror_of(&self, folder_id: i64, source_id: Option<i64>) -> Result<()> {
+        self.conn.execute(
+            "UPDATE synced_folders SET mirror_of = ?1 WHERE id = ?2",
+            params![source_id, folder_id],
+        )?;
+        Ok(())
+    }
+
+    /// Moves a folder to another directory; the index, being relative to the
+    /// folder root, is kept (see [`crate::relocate`]).
+    pub fn set_folder_local_path(&self, folder_id: i64, local_path: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE synced_folders SET local_path = ?1 WHERE id = ?2",
+            params![local_path, folder_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_folder_removable(&self, folder_id: i64, removable: bool) -> Result<()> {
+        self.conn.execute(
+            "UPDATE synced_folders SET removable = ?1 WHERE id = ?2",
+            params![removable, folder_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_folder_observer(&self, folder_id: i64, observer: bool) -> Result<()> {
+        self.conn.execute(
+            "UPDATE synced_folders SET observer = ?1 WHERE id = ?2",
+            params![observer, folder_id],
+        )?;
+        Ok(())
+    }
+
+    /// Replaces the subpaths of a folder that are not synced locally, stored
+    /// one per line.
+    pub fn set_folder_unsynced_paths(
+        &self,
+        folder_id: i64,
+        unsynced_paths: &[PathBuf],
+    ) -> Result<(), rusqlite::Error> {
+        let mut lines = Vec::with_capacity(unsynced_paths.len());
+        for path in unsynced_paths {
+            lines.push(path_param(path)?);
+        }
+        self.conn.execute(
+            "UPDATE synced_folders SET unsynced_paths = ?1 WHERE id = ?2",
+            params![lines.join("\n"), folder_id],
+        )?;
+        Ok(())
+    }
+
+    /// Asks for the content of an unsynced file or directory to be
+    /// downloaded once, without syncing it from then on, or for the indexed
+    /// content of a file found corrupted on disk to be downloaded again.
+    pub fn request_fetch(
+        &self,
+        folder_id: i64,
+        relative_path: &Path,
+    ) -> Result<(), rusqlite::Error> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO fetch_requests (folder_id, relative_path) VALUES (?1, ?2)",
+            params![folder_id, path_param(relative_path)?],
+        )?;
+        Ok(())
+    }
+
+    /// Pending fetches of a folder, oldest first.
+    pub fn get_fetch_requests(&self, folder_id: i64) -> Result<Vec<PathBuf>, rusqlite::Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT relative_path FROM fetch_requests WHERE folder_id = ?1
+             ORDER BY requested_at, relative_path",
+        )?;
+        let rows = stmt.query_map(params![folder_id], |row| row.get::<_, String>(0))?;
+        rows.map(|row| row.map(PathBuf::from)).collect()
+    }
+
+    /// True when a pending fetch covers `relative_path`.
+    pub fn is_fetch_requested(
+        &self,
+        folder_id: i64,
+        relative_path: &Path,
+    ) -> Result<bool, rusqlite::Error> {
+        Ok(self
+            .get_fetch_requests(folder_id)?
+            .iter()
+            .any(|requested| relative_path.starts_with(requested)))
+    }
+
+    /// True when the content of `relative_path` should be downloaded: it is
+    /// synced locally, or an unsynced path that was fetched on demand.
+    pub fn wants_content(
+        &self,
+        folder: &SyncedFolder,
+        relative_path: &Path,
+    ) -> Result<bool, rusqlite::Error> {
+        if folder.observer {
+            return Ok(false);
+        }
+        if !folder.is_unsynced(relative_path) {
+            return Ok(true);
+        }
+        self.is_fetch_requested(folder.id, relative_path)
+    }
+
+    /// Marks a fetch of exactly `relative_path` as done.
+    pub fn clear_fetch_request(
+        &self,
+        folder_id: i64,
+        relative_path: &Path,
+    ) -> Result<(), rusqlite::Error> {
+        self.conn.execute(
+            "DELETE FROM fetch_requests WHERE folder_id = ?1 AND relative_path = ?2",
+            params![folder_id, path_param(relative_path)?],
+        )?;
+        Ok(())
+    }
+
+    fn query_synced_folder(
+        &self,
+        column: &str,
+        value: impl rusqlite::ToSql,
+    ) -> Result<Option<SyncedFolder>, rusqlite::Error> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM synced_folders WHERE {} = ?1",
+            FOLDER_COLUMNS, column
+        ))?;
+        let mut rows = stmt.query_map(params![value], Self::map_synced_folder)?;
+
+        match rows.next() {
+            Some(Ok(row)) => Ok(Some(row)),
+            Some(Err(e)) => Err(e),
+            None => Ok(None),
+        }
+    }
+
+    /// Finds the folder that contains `path` and returns it with the path
+    /// relative to its root. Nested folders resolve to the innermost one.
+    pub fn find_folder_containing(
+        &self,
+        path: &Path,
+    ) -> Result<Option<(SyncedFolder, PathBuf)>, rusqlite::Error> {
+        Ok(self
+            .get_all_synced_folders()?
+            .into_iter()
+            .filter_map(|folder| {
+                let relative = path.strip_prefix(&folder.local_path).ok()?.to_path_buf();
+                Some((folder, relative))
+            })
+            .min_by_key(|(_, relative)| relative.components().count()))
+    }
+
+    pub fn get_all_synced_folders(&self) -> Result<Vec<SyncedFolder>, rusqlite::Error> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("SELECT {} FROM synced_folders", FOLDER_COLUMNS))?;
+        let rows = stmt.query_map([], Self::map_synced_folder)?;
+
+        let mut folders = Vec::new();
+        for row in rows {
+            folders.push(row?);
+        }
+        Ok(folders)
+    }
+
+    /// Sets or, with `None`, removes the folder's size limit.
+    pub fn set_folder_quota(
+        &self,
+        folder_id: i64,
+        quota_bytes: Option<u64>,
+    ) -> Result<(), rusqlite::Error> {
+        self.conn.execute(
+            "UPDATE synced_folders SET quota_bytes = ?1 WHERE id = ?2",
+            params![quota_bytes, folder_id],
+        )?;
+        Ok(())
+    }
+
+    /// Returns the number of indexed files and their total size for a folder.
+    pub fn get_folder_totals(&self, folder_id: i64) -> Result<(u64, u64), rusqlite::Error> {
+        self.conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(size_bytes), 0) FROM file_index WHERE folder_id = ?1",
+            params![folder_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+    }
+
+    fn map_synced_folder(row: &rusqlite::Row) -> Result<SyncedFolder, rusqlite::Error> {
+        Ok(SyncedFolder {
+            id: row.get(0)?,
+            folder_uuid: row.get(1)?,
+            name: row.get(2)?,
+            local_path: row.get::<_, String>(3)?.into(),
+            ignore_hidden: row.get(4)?,
+            profile: row.get(5)?,
+            ignore_patterns: row
+                .get::<_, String>(6)?
+                .lines()
+                .map(str::to_string)
+                .collect(),
+            receive_only: row.get(7)?,
+            debounce_ms: row.get(8)?,
+            sync_xattrs: row.get(9)?,
+            quota_bytes: row.get(10)?,
+            unsynced_paths: row
+                .get::<_, String>(11)?
+                .lines()
+                .map(PathBuf::from)
+                .collect(),
+            placeholders: row.get(12)?,
+            observer: row.get(13)?,
+            hash_threads: row.get(14)?,
+            sync_hardlinks: row.get(15)?,
+            manual_push: row.get(16)?,
+            mirror_of: row.get(17)?,
+            removable: row.get(18)?,
+            settle_secs: row.get(19)?,
+        })
+    }
+
+    pub fn upsert_file_record(
+        &self,
+        folder_id: i64,
+        relative_path: &Path,
+        size_bytes: u64,
+        sha256_hash: &str,
+        modified_secs: u64,
+    ) -> Result<(), rusqlite::Error> {
+        // A local content change is a new version authored by this device.
+        let (mut vector, changed) = match self.get_file_version(folder_id, relative_path)? {
+            Some((hash, vector)) => (vector, hash.as_deref() != Some(sha256_hash)),
+            None => (VersionVector::default(), true),
+        };
+        if changed {
+            vector.increment(&self.get_or_create_device_id()?);
+        }
+
+        self.conn.execute(
+            "INSERT INTO file_index (folder_id, relative_path, last_modified_secs, size_bytes, sha256_hash, version, version_vector)
+             VALUES (?1, ?2, ?3, ?4, ?5, 1, ?6)
+             ON CONFLICT(folder_id, relative_path) DO UPDATE SET
+                last_modified_secs = excluded.last_modified_secs,
+                size_bytes = excluded.size_bytes,
+                sha256_hash = excluded.sha256_hash,
+                version = CASE WHEN sha256_hash IS excluded.sha256_hash THEN version ELSE version + 1 END,
+                version_vector = excluded.version_vector,
+                last_synced_at = CURRENT_TIMESTAMP",
+            params![
+                folder_id,
+                path_param(relative_path)?,
+                modified_secs,
+                size_bytes,
+                sha256_hash,
+                vector.to_json()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Stores a version received from a peer as is, including its version
+    /// vector, so applying it is not mistaken for a local change.
+    pub fn record_remote_file(
+        &self,
+        folder_id: i64,
+        relative_path: &Path,
+        size_bytes: u64,
+        sha256_hash: &str,
+        modified_secs: u64,
+        version_vector: &VersionVector,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO file_index (folder_id, relative_path, last_modified_secs, size_bytes, sha256_hash, version, version_vector)
+             VALUES (?1, ?2, ?3, ?4, ?5, 1, ?6)
+             ON CONFLICT(folder_id, relative_path) DO UPDATE SET
+                last_modified_secs = excluded.last_modified_secs,
+                size_bytes = excluded.size_bytes,
+                sha256_hash = excluded.sha256_hash,
+                version = CASE WHEN sha256_hash IS excluded.sha256_hash THEN version ELSE version + 1 END,
+                version_vector = excluded.version_vector,
+                last_synced_at = CURRENT_TIMESTAMP",
+            params![
+                folder_id,
+                path_param(relative_path)?,
+                modified_secs,
+                size_bytes,
+                sha256_hash,
+                version_vector.to_json()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Updates the modification time of an indexed file whose content is
+    /// unchanged. Returns false when the file is not indexed with a hash or
+    /// its size differs, i.e. its content has to be hashed after all.
+    pub fn update_file_modified(
+        &self,
+        folder_id: i64,
+        relative_path: &Path,
+        size_bytes: u64,
+        modified_secs: u64,
+    ) -> Result<bool> {
+        let updated = self.conn.execute(
+            "UPDATE file_index SET last_modified_secs = ?3
+             WHERE folder_id = ?1 AND relative_path = ?2
+                AND size_bytes = ?4 AND sha256_hash IS NOT NULL",
+            params![
+                folder_id,
+                path_param(relative_path)?,
+                modified_secs,
+                size_bytes
+            ],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Returns the indexed hash and version vector of a file.
+    pub fn get_file_version(
+        &self,
+        folder_id: i64,
+        relative_path: &Path,
+    ) -> Result<Option<(Option<String>, VersionVector)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT sha256_hash, version_vector FROM file_index
+             WHERE folder_id = ?1 AND relative_path = ?2",
+        )?;
+        let mut rows = stmt.query_map(params![folder_id, path_param(relative_path)?], |row| {
+            Ok((row.get(0)?, VersionVector::parse(&row.get::<_, String>(1)?)))
+        })?;
+        rows.next().transpose()
+    }
+
+    /// Describes an indexed file for its placeholder; `None` when the file is
+    /// not indexed or not hashed yet.
+    pub fn get_placeholder(
+        &self,
+        folder_id: i64,
+        relative_path: &Path,
+    ) -> Result<Option<Placeholder>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT size_bytes, sha256_hash, last_modified_secs FROM file_index
+             WHERE folder_id = ?1 AND relative_path = ?2 AND sha256_hash IS NOT NULL",
+        )?;
+        let mut rows = stmt.query_map(params![folder_id, path_param(relative_path)?], |row| {
+            Ok(Placeholder {
+                size: row.get(0)?,
+                hash: row.get(1)?,
+                modified_secs: row.get(2)?,
+            })
+        })?;
+        rows.next().transpose()
+    }
+
+    pub fn remove_file_entry(&self, folder_id: i64, file_name: &Path) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM file_index WHERE folder_id = ?1 AND relative_path = ?2",
+            params![folder_id, path_param(file_name)?],
+        )?;
+        self.conn.execute(
+            "DELETE FROM file_xattrs WHERE folder_id = ?1 AND relative_path = ?2",
+            params![folder_id, path_param(file_name)?],
+        )?;
+        self.conn.execute(
+            "DELETE FROM file_tags WHERE folder_id = ?1 AND relative_path = ?2",
+            params![folder_id, path_param(file_name)?],
+        )?;
+        self.clear_locked_file(folder_id, file_name)
+    }
+
+    pub fn get_locked_file(
+        &self,
+        folder_id: i64,
+        relative_path: &Path,
+    ) -> Result<Option<LockedFile>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT relative_path, attempts, next_retry_secs FROM locked_files
+             WHERE folder_id = ?1 AND relative_path = ?2",
+        )?;
+        let mut rows = stmt.query_map(
+            params![folder_id, path_param(relative_path)?],
+            Self::map_locked_file,
+        )?;
+        rows.next().transpose()
+    }
+
+    /// Locked files of a folder, by path.
+    pub fn get_locked_files(&self, folder_id: i64) -> Result<Vec<LockedFile>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT relative_path, attempts, next_retry_secs FROM locked_files
+             WHERE folder_id = ?1 ORDER BY relative_path",
+        )?;
+        let rows = stmt.query_map(params![folder_id], Self::map_locked_file)?;
+        rows.collect()
+    }
+
+    /// Records a failed attempt to read a locked file and when to retry.
+    pub fn record_locked_file(
+        &self,
+        folder_id: i64,
+        relative_path: &Path,
+        attempts: u32,
+        next_retry_secs: u64,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO locked_files
+                (folder_id, relative_path, attempts, next_retry_secs)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                folder_id,
+                path_param(relative_path)?,
+                attempts,
+                next_retry_secs
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn clear_locked_file(&self, folder_id: i64, relative_path: &Path) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM locked_files WHERE folder_id = ?1 AND relative_path = ?2",
+            params![folder_id, path_param(relative_path)?],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_folder_stats(&self, folder_id: i64) -> Result<FolderStats> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM folder_stats WHERE folder_id = ?1",
+            FOLDER_STATS_COLUMNS
+        ))?;
+        let mut rows = stmt.query_map(params![folder_id], Self::map_folder_stats)?;
+        Ok(rows.next().transpose()?.unwrap_or_default())
+    }
+
+    pub fn get_all_folder_stats(&self) -> Result<HashMap<i64, FolderStats>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {}, folder_id FROM folder_stats",
+            FOLDER_STATS_COLUMNS
+        ))?;
+        let rows = stmt.query_map([], |row| Ok((row.get(12)?, Self::map_folder_stats(row)?)))?;
+        rows.collect()
+    }
+
+    pub fn save_folder_stats(&self, folder_id: i64, stats: &FolderStats) -> Result<()> {
+        self.conn.execute(
+            &format!(
+                "INSERT OR REPLACE INTO folder_stats (folder_id, {})
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                FOLDER_STATS_COLUMNS
+            ),
+            params![
+                folder_id,
+                stats.events_processed,
+                stats.failures,
+                stats.last_failure,
+                stats.last_scan_secs,
+                stats.last_watcher_error,
+                stats.last_watcher_error_secs,
+                stats.verification_failures,
+                stats.bytes_sent,
+                stats.bytes_received,
+                stats.audited_files,
+                stats.audit_mismatches,
+                stats.last_audit_mismatch
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn map_folder_stats(row: &rusqlite::Row) -> Result<FolderStats> {
+        Ok(FolderStats {
+            events_processed: row.get(0)?,
+            failures: row.get(1)?,
+            last_failure: row.get(2)?,
+            last_scan_secs: row.get(3)?,
+            last_watcher_error: row.get(4)?,
+            last_watcher_error_secs: row.get(5)?,
+            verification_failures: row.get(6)?,
+            bytes_sent: row.get(7)?,
+            bytes_received: row.get(8)?,
+            audited_files: row.get(9)?,
+            audit_mismatches: row.get(10)?,
+            last_audit_mismatch: row.get(11)?,
+        })
+    }
+
+    /// Records the folder's current totals and transfer counters as its
+    /// statistics for `day`, replacing an earlier snapshot of the same day.
+    /// The day's transfers are counted from where the previous day's
+    /// counters ended, or from the first snapshot of the folder.
+    pub fn record_stats_snapshot(
+        &self,
+        folder_id: i64,
+        day: &str,
+        (file_count, size_bytes): (u64, u64),
+        stats: &FolderStats,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO stats_history (folder_id, day, file_count, size_bytes, sent_start,
+                received_start, sent_end, received_end, recorded_secs)
+             VALUES (?1, ?2, ?3, ?4,
+                COALESCE((SELECT sent_end FROM stats_history WHERE folder_id = ?1 AND day < ?2
+                          ORDER BY day DESC LIMIT 1), ?5),
+                COALESCE((SELECT received_end FROM stats_history WHERE folder_id = ?1 AND day < ?2
+                          ORDER BY day DESC LIMIT 1), ?6),
+                ?5, ?6, ?7)
+             ON CONFLICT (folder_id, day) DO UPDATE SET
+                file_count = excluded.file_count,
+                size_bytes = excluded.size_bytes,
+                sent_end = excluded.sent_end,
+                received_end = excluded.received_end,
+                recorded_secs = excluded.recorded_secs",
+            params![
+                folder_id,
+                day,
+                file_count,
+                size_bytes,
+                stats.bytes_sent,
+                stats.bytes_received,
+                unix_now()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The folder's statistics for its last `days` recorded days, oldest
+    /// first.
+    pub fn get_stats_history(&self, folder_id: i64, days: u32) -> Result<Vec<StatsHistoryEntry>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT day, file_count, size_bytes, sent_start, received_start, sent_end,
+                    received_end, recorded_secs
+             FROM stats_history WHERE folder_id = ?1 ORDER BY day DESC LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![folder_id, days], |row| {
+            let (sent_start, received_start): (u64, u64) = (row.get(3)?, row.get(4)?);
+            let (sent_end, received_end): (u64, u64) = (row.get(5)?, row.get(6)?);
+            Ok(StatsHistoryEntry {
+                day: row.get(0)?,
+                file_count: row.get(1)?,
+                size_bytes: row.get(2)?,
+                bytes_sent: sent_end.saturating_sub(sent_start),
+                bytes_received: received_end.saturating_sub(received_start),
+                recorded_secs: row.get(7)?,
+            })
+        })?;
+        let mut history = rows.collect::<Result<Vec<_>>>()?;
+        history.reverse();
+        Ok(history)
+    }
+
+    /// Drops the statistics of days before `day`. Returns the rows removed.
+    pub fn prune_stats_history(&self, day: &str) -> Result<usize> {
+        self.conn
+            .execute("DELETE FROM stats_history WHERE day < ?1", params![day])
+    }
+
+    fn map_locked_file(row: &rusqlite::Row) -> Result<LockedFile> {
+        Ok(LockedFile {
+            relative_path: row.get::<_, String>(0)?.into(),
+            attempts: row.get(1)?,
+            next_retry_secs: row.get(2)?,
+        })
+    }
+
+    /// Adds a local change of a manual-push folder to its pending list,
+    /// replacing an earlier pending change of the same path. A rename takes
+    /// over the pending change of the path it was renamed from.
+    pub fn record_pending_change(
+        &self,
+        folder_id: i64,
+        relative_path: &Path,
+        change: &LocalChange,
+    ) -> Result<()> {
+        let now = unix_now();
+        let (name, from_path) = match change {
+            LocalChange::Modified => ("modified", None),
+            LocalChange::Removed => ("removed", None),
+            LocalChange::Renamed { from } => ("renamed", Some(path_param(from)?)),
+        };
+        let tx = self.conn.unchecked_transaction()?;
+        if let Some(from_path) = from_path {
+            tx.execute(
+                "DELETE FROM pending_changes WHERE folder_id = ?1 AND relative_path = ?2",
+                params![folder_id, from_path],
+            )?;
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO pending_changes
+                (folder_id, relative_path, change, from_path, detected_secs)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![folder_id, path_param(relative_path)?, name, from_path, now],
+        )?;
+        tx.commit()
+    }
+
+    /// Pending changes of a folder, oldest first.
+    pub fn get_pending_changes(&self, folder_id: i64) -> Result<Vec<PendingChange>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT relative_path, change, from_path, detected_secs FROM pending_changes
+             WHERE folder_id = ?1 ORDER BY detected_secs, relative_path",
+        )?;
+        let rows = stmt.query_map(params![folder_id], |row| {
+            let change = match (
+                row.get::<_, String>(1)?.as_str(),
+                row.get::<_, Option<String>>(2)?,
+            ) {
+                ("removed", _) => LocalChange::Removed,
+                ("renamed", Some(from)) => LocalChange::Renamed { from: from.into() },
+                _ => LocalChange::Modified,
+            };
+            Ok(PendingChange {
+                relative_path: row.get::<_, String>(0)?.into(),
+                change,
+                detected_secs: row.get(3)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Approves the pending changes of a folder at or below `paths`, or all
+    /// of them when `paths` is empty, so they are announced to peers. Their
+    /// entries get new sequence numbers, as peers may have acknowledged past
+    /// them while they were held back. Returns the approved changes.
+    pub fn approve_pending_changes(
+        &self,
+        folder_id: i64,
+        paths: &[PathBuf],
+    ) -> Result<Vec<PendingChange>> {
+        let tx = self.conn.unchecked_transaction()?;
+        let approved = self.release_pending_changes(folder_id, paths)?;
+        tx.commit()?;
+        Ok(approved)
+    }
+
+    /// Does the work of [`Database::approve_pending_changes`] for callers
+    /// that already hold a transaction.
+    fn release_pending_changes(
+        &self,
+        folder_id: i64,
+        paths: &[PathBuf],
+    ) -> Result<Vec<PendingChange>> {
+        let approved: Vec<PendingChange> = self
+            .get_pending_changes(folder_id)?
+            .into_iter()
+            .filter(|pending| {
+                paths.is_empty()
+                    || paths
+                        .iter()
+                        .any(|path| pending.relative_path.starts_with(path))
+            })
+            .collect();
+        for pending in &approved {
+            let relative_path = path_param(&pending.relative_path)?;
+            self.conn.execute(
+                "DELETE FROM pending_changes WHERE folder_id = ?1 AND relative_path = ?2",
+                params![folder_id, relative_path],
+            )?;
+            let mut paths = vec![relative_path];
+            if let LocalChange::Renamed { from } = &pending.change {
+                paths.push(path_param(from)?);
+            }
+            for table in ["file_index", "file_tombstones"] {
+                for path in &paths {
+                    self.conn.execute(
+                        "UPDATE synced_folders SET max_sequence = max_sequence + 1 WHERE id = ?1",
+                        params![folder_id],
+                    )?;
+                    self.conn.execute(
+                        &format!(
+                            "UPDATE {} SET sequence =
+                                (SELECT max_sequence FROM synced_folders WHERE id = ?1)
+                             WHERE folder_id = ?1 AND relative_path = ?2",
+                            table
+                        ),
+                        params![folder_id, path],
+                    )?;
+                }
+            }
+        }
+        Ok(approved)
+    }
+
+    /// Records that a peer connected or disconnected.
+    pub fn set_peer_connected(
+        &self,
+        device_id: &str,
+        device_name: Option<&str>,
+        connected: bool,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO peers (device_id, device_name, connected, last_seen_secs)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(device_id) DO UPDATE SET
+                device_name = COALESCE(excluded.device_name, device_name),
+                connected = excluded.connected,
+                last_seen_secs = excluded.last_seen_secs",
+            params![device_id, device_name, connected, unix_now()],
+        )?;
+        Ok(())
+    }
+
+    /// Records a device to sync with before it ever connected, e.g. one
+    /// imported from another tool. Returns false if it is known already.
+    pub fn add_peer(&self, device_id: &str, device_name: Option<&str>) -> Result<bool> {
+        let added = self.conn.execute(
+            "INSERT INTO peers (device_id, device_name) VALUES (?1, ?2)
+             ON CONFLICT(device_id) DO NOTHING",
+            params![device_id, device_name],
+        )?;
+        Ok(added > 0)
+    }
+
+    /// Marks every peer as disconnected, e.g. on startup after a crash left
+    /// connections recorded as open.
+    pub fn disconnect_all_peers(&self) -> Result<()> {
+        self.conn
+            .execute("UPDATE peers SET connected = 0 WHERE connected = 1", [])?;
+        Ok(())
+    }
+
+    /// Known peers, connected ones first.
+    pub fn get_peers(&self) -> Result<Vec<Peer>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT device_id, device_name, connected, last_seen_secs FROM peers
+             ORDER BY connected DESC, COALESCE(device_name, device_id)",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(Peer {
+                device_id: row.get(0)?,
+                device_name: row.get(1)?,
+                connected: row.get(2)?,
+                last_seen_secs: row.get(3)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Adds to the bytes exchanged with `device_id` for the folder on `day`.
+    pub fn add_transfer(
+        &self,
+        day: &str,
+        device_id: &str,
+        folder_id: i64,
+        (sent, received): (u64, u64),
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO transfer_stats (day, device_id, folder_id, bytes_sent, bytes_received)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (day, device_id, folder_id) DO UPDATE SET
+                bytes_sent = bytes_sent + excluded.bytes_sent,
+                bytes_received = bytes_received + excluded.bytes_received",
+            params![day, device_id, folder_id, sent, received],
+        )?;
+        Ok(())
+    }
+
+    /// Transfers from `since_day` on, oldest first.
+    pub fn get_transfers(&self, since_day: &str) -> Result<Vec<TransferRecord>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT day, device_id, folder_id, bytes_sent, bytes_received FROM transfer_stats
+             WHERE day >= ?1 ORDER BY day, device_id, folder_id",
+        )?;
+        let rows = stmt.query_map(params![since_day], |row| {
+            Ok(TransferRecord {
+                day: row.get(0)?,
+                device_id: row.get(1)?,
+                folder_id: row.get(2)?,
+                bytes_sent: row.get(3)?,
+                bytes_received: row.get(4)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Bytes sent to and received from `device_id` from `since_day` on.
+    pub fn get_peer_transfer_total(&self, device_id: &str, since_day: &str) -> Result<(u64, u64)> {
+        self.conn.query_row(
+            "SELECT COALESCE(SUM(bytes_sent), 0), COALESCE(SUM(bytes_received), 0)
+             FROM transfer_stats WHERE device_id = ?1 AND day >= ?2",
+            params![device_id, since_day],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+    }
+
+    /// Stores watcher events for `sync_rs replay`. Events on paths that are
+    /// not valid UTF-8 are left out.
+    pub fn record_events(&self, events: &[RecordedEvent]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO recorded_events (recorded_ms, kind, path, old_path)
+                 VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for event in events {
+                let old_path = match &event.kind {
+                    RecordedKind::Rename { old_path } => old_path.to_str(),
+                    _ => None,
+                };
+                let Some(path) = event.path.to_str() else {
+                    continue;
+                };
+                stmt.execute(params![
+                    event.recorded_ms,
+                    event.kind.as_str(),
+                    path,
+                    old_path
+                ])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// Recorded watcher events from `since_ms` to `until_ms`, in the order
+    /// they were queued.
+    pub fn get_recorded_events(&self, since_ms: u64, until_ms: u64) -> Result<Vec<RecordedEvent>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT recorded_ms, kind, path, old_path FROM recorded_events
+             WHERE recorded_ms >= ?1 AND recorded_ms <= ?2 ORDER BY id",
+        )?;
+        let rows = stmt.query_map(params![since_ms, until_ms], |row| {
+            let recorded_ms: u64 = row.get(0)?;
+            let kind: String = row.get(1)?;
+            let path: String = row.get(2)?;
+            let old_path: Option<String> = row.get(3)?;
+            // Kinds written by a newer version are skipped.
+            Ok(
+                RecordedKind::parse(&kind, old_path.map(PathBuf::from)).map(|kind| RecordedEvent {
+                    recorded_ms,
+                    path: PathBuf::from(path),
+                    kind,
+                }),
+            )
+        })?;
+        let mut events = Vec::new();
+        for event in rows {
+            events.extend(event?);
+        }
+        Ok(events)
+    }
+
+    /// Forgets watcher events recorded before `before_ms`.
+    pub fn prune_recorded_events(&self, before_ms: u64) -> Result<usize> {
+        self.conn.execute(
+            "DELETE FROM recorded_events WHERE recorded_ms < ?1",
+            params![before_ms],
+        )
+    }
+
+    /// Highest sequence number handed out for the folder's index changes.
+    pub fn get_folder_sequence(&self, folder_id: i64) -> Result<u64> {
+        self.conn.query_row(
+            "SELECT max_sequence FROM synced_folders WHERE id = ?1",
+            params![folder_id],
+            |row| row.get(0),
+        )
+    }
+
+    /// Records that a peer has every index change of the folder up to
+    /// `sequence`. The cursor never moves backwards. Tombstones every peer
+    /// of the folder has received are dropped.
+    pub fn ack_sequence(&self, device_id: &str, folder_id: i64, sequence: u64) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO peers (device_id) VALUES (?1) ON CONFLICT(device_id) DO NOTHING",
+            params![device_id],
+        )?;
+        self.conn.execute(
+            "INSERT INTO peer_cursors (device_id, folder_id, acked_sequence, last_synced_secs)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(device_id, folder_id) DO UPDATE SET
+                acked_sequence = MAX(acked_sequence, excluded.acked_sequence),
+                last_synced_secs = excluded.last_synced_secs",
+            params![device_id, folder_id, sequence, unix_now()],
+        )?;
+        self.conn.execute(
+            "DELETE FROM file_tombstones WHERE folder_id = ?1 AND sequence <=
+                (SELECT MIN(acked_sequence) FROM peer_cursors WHERE folder_id = ?1)",
+            params![folder_id],
+        )?;
+        Ok(())
+    }
+
+    /// Highest sequence number of a peer's index of the folder that was
+    /// received and applied; 0 when none was.
+    pub fn get_received_sequence(&self, device_id: &str, folder_id: i64) -> Result<u64> {
+        let mut stmt = self.conn.prepare(
+            "SELECT received_sequence FROM peer_cursors WHERE device_id = ?1 AND folder_id = ?2",
+        )?;
+        let mut rows = stmt.query_map(params![device_id, folder_id], |row| row.get(0))?;
+        Ok(rows.next().transpose()?.unwrap_or_default())
+    }
+
+    /// Stores the resume point of a peer's index exchange for the folder.
+    pub fn set_received_sequence(
+        &self,
+        device_id: &str,
+        folder_id: i64,
+        sequence: u64,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO peers (device_id) VALUES (?1) ON CONFLICT(device_id) DO NOTHING",
+            params![device_id],
+        )?;
+        self.conn.execute(
+            "INSERT INTO peer_cursors (device_id, folder_id, received_sequence)
+             VALUES (?1, ?2, ?3)
+             ON CONFLICT(device_id, folder_id) DO UPDATE SET
+                received_sequence = excluded.received_sequence",
+            params![device_id, folder_id, sequence],
+        )?;
+        Ok(())
+    }
+
+    /// Up to `limit` index entries of the folder with a sequence number
+    /// above `after_sequence`, in sequence order. Entries removed or renamed
+    /// since then and not yet acknowledged by every peer are included as
+    /// deleted entries. Paths with a change waiting for approval are left out
+    /// until [`Database::approve_pending_changes`] numbers them anew.
+    pub fn get_index_page(
+        &self,
+        folder_id: i64,
+        after_sequence: u64,
+        limit: usize,
+    ) -> Result<Vec<IndexEntry>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT relative_path, size_bytes, last_modified_secs, sha256_hash, version_vector,
+                sequence, 0
+             FROM file_index i WHERE folder_id = ?1 AND sequence > ?2
+                AND NOT EXISTS (SELECT 1 FROM pending_changes p
+                    WHERE p.folder_id = ?1 AND p.relative_path = i.relative_path)
+             UNION ALL
+             SELECT relative_path, 0, 0, NULL, version_vector, sequence, 1
+             FROM file_tombstones t WHERE folder_id = ?1 AND sequence > ?2
+                AND NOT EXISTS (SELECT 1 FROM pending_changes p
+                    WHERE p.folder_id = ?1
+                        AND t.relative_path IN (p.relative_path, p.from_path))
+             ORDER BY 6 LIMIT ?3",
+        )?;
+        let rows = stmt.query_map(params![folder_id, after_sequence, limit], |row| {
+            Ok(IndexEntry {
+                path: row.get(0)?,
+                size_bytes: row.get(1)?,
+                modified_secs: row.get(2)?,
+                sha256_hash: row.get(3)?,
+                version_vector: VersionVector::parse(&row.get::<_, String>(4)?),
+                sequence: row.get(5)?,
+                deleted: row.get(6)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// How far a peer is behind on each folder it has a cursor for.
+    pub fn get_peer_backlog(&self, device_id: &str) -> Result<Vec<PeerBacklog>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT c.folder_id, c.acked_sequence, c.last_synced_secs,
+                COUNT(i.id), COALESCE(SUM(i.size_bytes), 0)
+             FROM peer_cursors c
+             LEFT JOIN file_index i ON i.folder_id = c.folder_id AND i.sequence > c.acked_sequence
+             WHERE c.device_id = ?1
+             GROUP BY c.folder_id
+             ORDER BY c.folder_id",
+        )?;
+        let rows = stmt.query_map(params![device_id], |row| {
+            Ok(PeerBacklog {
+                folder_id: row.get(0)?,
+                acked_sequence: row.get(1)?,
+                last_synced_secs: row.get(2)?,
+                files: row.get(3)?,
+                bytes: row.get(4)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Records a conflict whose remote version was written to `conflict_path`.
+    pub fn record_conflict(
+        &self,
+        folder_id: i64,
+        relative_path: &Path,
+        conflict_path: &Path,
+        remote: &RemoteVersion,
+    ) -> Result<i64> {
+        let now = unix_now();
+        self.conn.execute(
+            "INSERT INTO conflicts (folder_id, relative_path, conflict_path, remote_device_id,
+                remote_device_name, remote_hash, remote_version_vector, detected_secs)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                folder_id,
+                path_param(relative_path)?,
+                path_param(conflict_path)?,
+                remote.device_id,
+                remote.device_name,
+                remote.hash,
+                remote.version_vector.to_json(),
+                now
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Unresolved conflicts of all folders, oldest first.
+    pub fn get_conflicts(&self) -> Result<Vec<Conflict>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM conflicts ORDER BY id",
+            CONFLICT_COLUMNS
+        ))?;
+        let rows = stmt.query_map([], Self::map_conflict)?;
+        rows.collect()
+    }
+
+    pub fn get_conflict(&self, id: i64) -> Result<Option<Conflict>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM conflicts WHERE id = ?1",
+            CONFLICT_COLUMNS
+        ))?;
+        let mut rows = stmt.query_map(params![id], Self::map_conflict)?;
+        rows.next().transpose()
+    }
+
+    pub fn remove_conflict(&self, id: i64) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM conflicts WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    fn map_conflict(row: &rusqlite::Row) -> Result<Conflict> {
+        Ok(Conflict {
+            id: row.get(0)?,
+            folder_id: row.get(1)?,
+            relative_path: row.get::<_, String>(2)?.into(),
+            conflict_path: row.get::<_, String>(3)?.into(),
+            remote: RemoteVersion {
+                device_id: row.get(4)?,
+                device_name: row.get(5)?,
+                hash: row.get(6)?,
+                version_vector: VersionVector::parse(&row.get::<_, String>(7)?),
+            },
+            detected_secs: row.get(8)?,
+        })
+    }
+
+    /// Journals the changes of a batch before any of them is applied and
+    /// returns the batch ID with the entry ID of each change, in order.
+    pub fn begin_journal_batch(
+        &self,
+        folder_id: i64,
+        intents: &[Intent],
+    ) -> Result<(i64, Vec<i64>)> {
+        let tx = self.conn.unchecked_transaction()?;
+        let batch_id: i64 = tx.query_row(
+            "SELECT COALESCE(MAX(batch_id), 0) + 1 FROM apply_journal",
+            [],
+            |row| row.get(0),
+        )?;
+        let mut entry_ids = Vec::with_capacity(intents.len());
+        for intent in intents {
+            let (op, path, from, hash, vector) = match intent {
+                Intent::Write {
+                    relative_path,
+                    hash,
+                    version_vector,
+                } => (
+                    "write",
+                    relative_path,
+                    None,
+                    Some(hash),
+                    Some(version_vector.to_json()),
+                ),
+                Intent::Remove { relative_path } => ("remove", relative_path, None, None, None),
+                Intent::Rename { from, to, hash } => {
+                    ("rename", to, Some(path_param(from)?), Some(hash), None)
+                }
+            };
+            tx.execute(
+                "INSERT INTO apply_journal
+                    (batch_id, folder_id, op, relative_path, from_path, sha256_hash, version_vector)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    batch_id,
+                    folder_id,
+                    op,
+                    path_param(path)?,
+                    from,
+                    hash,
+                    vector
+                ],
+            )?;
+            entry_ids.push(tx.last_insert_rowid());
+        }
+        tx.commit()?;
+        Ok((batch_id, entry_ids))
+    }
+
+    pub fn complete_journal_entry(&self, entry_id: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE apply_journal SET done = 1 WHERE id = ?1",
+            params![entry_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn finish_journal_batch(&self, batch_id: i64) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM apply_journal WHERE batch_id = ?1",
+            params![batch_id],
+        )?;
+        Ok(())
+    }
+
+    /// Forgets every batch, once a previous run's batches are recovered.
+    pub fn clear_journal(&self) -> Result<()> {
+        self.conn.execute("DELETE FROM apply_journal", [])?;
+        Ok(())
+    }
+
+    /// Entries of batches a previous run did not finish, as
+    /// `(batch_id, folder_id, entry_id, intent)`, unfinished entries only.
+    pub fn get_pending_journal_entries(&self) -> Result<Vec<(i64, i64, i64, Intent)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT batch_id, folder_id, id, op, relative_path, from_path, sha256_hash,
+                    version_vector
+             FROM apply_journal WHERE done = 0 ORDER BY id",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            let path = PathBuf::from(row.get::<_, String>(4)?);
+            let hash = row.get::<_, Option<String>>(6)?.unwrap_or_default();
+            let intent = match row.get::<_, String>(3)?.as_str() {
+                "write" => Intent::Write {
+                    relative_path: path,
+                    hash,
+                    version_vector: VersionVector::parse(
+                        &row.get::<_, Option<String>>(7)?.unwrap_or_default(),
+                    ),
+                },
+                "rename" => Intent::Rename {
+                    from: PathBuf::from(row.get::<_, Option<String>>(5)?.unwrap_or_default()),
+                    to: path,
+                    hash,
+                },
+                _ => Intent::Remove {
+                    relative_path: path,
+                },
+            };
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, intent))
+        })?;
+        rows.collect()
+    }
+
+    /// Records that this device's version of a file supersedes `other`:
+    /// merges the vectors and counts a change by this device, so peers
+    /// holding either version take the result. Returns false when the file
+    /// is not indexed.
+    pub fn supersede_file_version(
+        &self,
+        folder_id: i64,
+        relative_path: &Path,
+        other: &VersionVector,
+    ) -> Result<bool> {
+        let Some((_, mut vector)) = self.get_file_version(folder_id, relative_path)? else {
+            return Ok(false);
+        };
+        vector.merge(other);
+        vector.increment(&self.get_or_create_device_id()?);
+        self.conn.execute(
+            "UPDATE file_index SET version = version + 1, version_vector = ?3
+             WHERE folder_id = ?1 AND relative_path = ?2",
+            params![folder_id, path_param(relative_path)?, vector.to_json()],
+        )?;
+        Ok(true)
+    }
+
+    /// Moves a file's index entry and attributes to a new path, replacing any
+    /// entry already there, so its hash and version history are kept.
+    pub fn rename_file_entry(&self, folder_id: i64, from: &Path, to: &Path) -> Result<()> {
+        self.move_file_entry(folder_id, from, folder_id, to)
+    }
+
+    /// Moves an index entry, with its version history and extended
+    /// attributes, to another folder, replacing any entry at the destination.
+    pub fn move_file_entry(
+        &self,
+        from_folder_id: i64,
+        from: &Path,
+        to_folder_id: i64,
+        to: &Path,
+    ) -> Result<()> {
+        let (from, to) = (path_param(from)?, path_param(to)?);
+        let tx = self.conn.unchecked_transaction()?;
+        for table in ["file_index", "file_xattrs", "file_tags"] {
+            tx.execute(
+                &format!(
+                    "DELETE FROM {} WHERE folder_id = ?1 AND relative_path = ?2",
+                    table
+                ),
+                params![to_folder_id, to],
+            )?;
+            tx.execute(
+                &format!(
+                    "UPDATE {} SET folder_id = ?3, relative_path = ?4
+                     WHERE folder_id = ?1 AND relative_path = ?2",
+                    table
+                ),
+                params![from_folder_id, from, to_folder_id, to],
+            )?;
+        }
+        tx.commit()
+    }
+
+    /// Records the hard link group of a file (see
+    /// [`crate::sync_engine::link_group`]); `None` when it has a single link.
+    pub fn set_file_link_group(
+        &self,
+        folder_id: i64,
+        relative_path: &Path,
+        link_group: Option<&str>,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE file_index SET link_group = ?3 WHERE folder_id = ?1 AND relative_path = ?2",
+            params![folder_id, path_param(relative_path)?, link_group],
+        )?;
+        Ok(())
+    }
+
+    /// Other indexed paths in the folder that are hard links of the file at
+    /// `relative_path`, sorted. Empty when it has no recorded links.
+    pub fn get_link_group(&self, folder_id: i64, relative_path: &Path) -> Result<Vec<PathBuf>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT other.relative_path FROM file_index AS file
+             JOIN file_index AS other
+               ON other.folder_id = file.folder_id AND other.link_group = file.link_group
+             WHERE file.folder_id = ?1 AND file.relative_path = ?2
+               AND other.relative_path != file.relative_path
+             ORDER BY other.relative_path",
+        )?;
+        let rows = stmt.query_map(params![folder_id, path_param(relative_path)?], |row| {
+            Ok(PathBuf::from(row.get::<_, String>(0)?))
+        })?;
+        rows.collect()
+    }
+
+    /// Replaces the stored extended attributes of a file.
+    pub fn set_file_xattrs(
+        &self,
+        folder_id: i64,
+        relative_path: &Path,
+        attrs: &Xattrs,
+    ) -> Result<()> {
+        let relative_path = path_param(relative_path)?;
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "DELETE FROM file_xattrs WHERE folder_id = ?1 AND relative_path = ?2",
+            params![folder_id, relative_path],
+        )?;
+        for (name, value) in attrs {
+            tx.execute(
+                "INSERT INTO file_xattrs (folder_id, relative_path, name, value)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![folder_id, relative_path, name, value],
+            )?;
+        }
+        tx.commit()
+    }
+
+    pub fn get_file_xattrs(&self, folder_id: i64, relative_path: &Path) -> Result<Xattrs> {
+        let mut stmt = self.conn.prepare(
+            "SELECT name, value FROM file_xattrs
+             WHERE folder_id = ?1 AND relative_path = ?2 ORDER BY name",
+        )?;
+        let rows = stmt.query_map(params![folder_id, path_param(relative_path)?], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        rows.collect()
+    }
+
+    pub fn get_file_tags(&self, folder_id: i64, relative_path: &Path) -> Result<FileTags> {
+        let mut stmt = self.conn.prepare(
+            "SELECT key, value FROM file_tags WHERE folder_id = ?1 AND relative_path = ?2",
+        )?;
+        let rows = stmt.query_map(params![folder_id, path_param(relative_path)?], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        rows.collect()
+    }
+
+    /// Sets and removes tags of an indexed file. Changing them makes a new
+    /// version of the file authored by this device, so the tags reach peers
+    /// like any other change. Returns false when the file is not indexed.
+    pub fn update_file_tags(
+        &self,
+        folder_id: i64,
+        relative_path: &Path,
+        set: &FileTags,
+        remove: &[String],
+    ) -> Result<bool> {
+        let Some((_, mut vector)) = self.get_file_version(folder_id, relative_path)? else {
+            return Ok(false);
+        };
+        let path = path_param(relative_path)?;
+        let tx = self.conn.unchecked_transaction()?;
+        let mut changed = 0;
+        for (key, value) in set {
+            changed += tx.execute(
+                "INSERT INTO file_tags (folder_id, relative_path, key, value)
+                 VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT(folder_id, relative_path, key) DO UPDATE SET value = excluded.value
+                 WHERE value IS NOT excluded.value",
+                params![folder_id, path, key, value],
+            )?;
+        }
+        for key in remove {
+            changed += tx.execute(
+                "DELETE FROM file_tags WHERE folder_id = ?1 AND relative_path = ?2 AND key = ?3",
+                params![folder_id, path, key],
+            )?;
+        }
+        if changed > 0 {
+            vector.increment(&self.get_or_create_device_id()?);
+            tx.execute(
+                "UPDATE file_index SET version = version + 1, version_vector = ?3
+                 WHERE folder_id = ?1 AND relative_path = ?2",
+                params![folder_id, path, vector.to_json()],
+            )?;
+        }
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Returns the last path (relative to the folder root) a running scan of
+    /// the folder has processed; an empty path means the scan started but
+    /// finished nothing yet. `None` means no scan is in progress.
+    pub fn get_scan_checkpoint(&self, folder_id: i64) -> Result<Option<PathBuf>> {
+        let query_result = self.conn.query_row(
+            "SELECT last_path FROM scan_checkpoints WHERE folder_id = ?1",
+            params![folder_id],
+            |row| row.get::<_, String>(0),
+        );
+
+        match query_result {
+            Ok(last_path) => Ok(Some(PathBuf::from(last_path))),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn set_scan_checkpoint(&self, folder_id: i64, last_path: &Path) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO scan_checkpoints (folder_id, last_path) VALUES (?1, ?2)
+             ON CONFLICT(folder_id) DO UPDATE SET
+                last_path = excluded.last_path,
+                updated_at = CURRENT_TIMESTAMP",
+            params![folder_id, path_param(last_path)?],
+        )?;
+        Ok(())
+    }
+
+    pub fn clear_scan_checkpoint(&self, folder_id: i64) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM scan_checkpoints WHERE folder_id = ?1",
+            params![folder_id],
+        )?;
+        Ok(())
+    }
+
+    /// Folders whose last scan was interrupted before it finished.
+    pub fn get_interrupted_scans(&self) -> Result<Vec<SyncedFolder>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM synced_folders
+             WHERE id IN (SELECT folder_id FROM scan_checkpoints)
+             ORDER BY id",
+            FOLDER_COLUMNS
+        ))?;
+        let rows = stmt.query_map([], Self::map_synced_folder)?;
+        rows.collect()
+    }
+
+    /// Every indexed file with the given content, across all folders.
+    pub fn find_files_by_hash(&self, sha256_hash: &str) -> Result<Vec<PathBuf>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT f.local_path, i.relative_path FROM file_index i
+             JOIN synced_folders f ON f.id = i.folder_id
+             WHERE i.sha256_hash = ?1",
+        )?;
+        let rows = stmt.query_map(params![sha256_hash], |row| {
+            Ok(Path::new(&row.get::<_, String>(0)?).join(row.get::<_, String>(1)?))
+        })?;
+        rows.collect()
+    }
+
+    /// Index entries with the given content as `(folder_id, relative_path)`.
+    pub fn find_entries_by_hash(&self, sha256_hash: &str) -> Result<Vec<(i64, PathBuf)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT folder_id, relative_path FROM file_index WHERE sha256_hash = ?1")?;
+        let rows = stmt.query_map(params![sha256_hash], |row| {
+            Ok((row.get(0)?, PathBuf::from(row.get::<_, String>(1)?)))
+        })?;
+        rows.collect()
+    }
+
+    /// Returns the absolute path of an indexed file with the given content, if
+    /// any folder has one, so it can be copied locally instead of transferred.
+    pub fn find_file_by_hash(&self, sha256_hash: &str) -> Result<Option<PathBuf>> {
+        let query_result = self.conn.query_row(
+            "SELECT f.local_path, i.relative_path FROM file_index i
+             JOIN synced_folders f ON f.id = i.folder_id
+             WHERE i.sha256_hash = ?1
+             LIMIT 1",
+            params![sha256_hash],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+        );
+
+        match query_result {
+            Ok((root, relative)) => Ok(Some(Path::new(&root).join(relative))),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// A hashed index entry of any folder, picked at random. Entries after
+    /// gaps in the row IDs, left by removed files, are picked a bit more
+    /// often, which keeps this from reading the whole index.
+    pub fn random_indexed_file(&self) -> Result<Option<IndexedFile>> {
+        let map = |row: &rusqlite::Row| {
+            Ok(IndexedFile {
+                folder_id: row.get(0)?,
+                relative_path: PathBuf::from(row.get::<_, String>(1)?),
+                size_bytes: row.get(2)?,
+                modified_secs: row.get(3)?,
+                hash: row.get(4)?,
+            })
+        };
+        let columns = "folder_id, relative_path, size_bytes, last_modified_secs, sha256_hash";
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM file_index
+             WHERE sha256_hash IS NOT NULL
+               AND id >= (SELECT abs(random()) % (MAX(id) + 1) FROM file_index)
+             ORDER BY id LIMIT 1",
+            columns
+        ))?;
+        if let Some(file) = stmt.query_map([], map)?.next().transpose()? {
+            return Ok(Some(file));
+        }
+        // The random ID was past the last hashed entry; wrap around.
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM file_index WHERE sha256_hash IS NOT NULL ORDER BY id LIMIT 1",
+            columns
+        ))?;
+        stmt.query_map([], map)?.next().transpose()
+    }
+
+    /// Searches the index of all folders, ordered by folder and path and
+    /// capped at [`MAX_FIND_RESULTS`].
+    pub fn find_files(&self, query: &FileQuery) -> Result<Vec<FoundFile>> {
+        let mut sql = String::from(
+            "SELECT folder_id, relative_path, size_bytes, last_modified_secs, version_vector
+             FROM file_index WHERE 1",
+        );
+        let mut values: Vec<rusqlite::types::Value> = Vec::new();
+        if query.pattern.contains(['*', '?', '[']) {
+            // `*` matches across `/`, so this finds the pattern at any depth.
+            sql += " AND (relative_path GLOB ? OR relative_path GLOB '*/' || ?)";
+            values.push(query.pattern.clone().into());
+            values.push(query.pattern.clone().into());
+        } else if query.pattern.chars().count() >= 3 {
+            // A trigram phrase is an indexed, case-insensitive substring match.
+            sql += " AND id IN (SELECT rowid FROM file_index_fts WHERE file_index_fts MATCH ?)";
+            values.push(format!("\"{}\"", query.pattern.replace('"', "\"\"")).into());
+        } else if !query.pattern.is_empty() {
+            // Too short for trigrams.
+            sql += " AND instr(lower(relative_path), lower(?)) > 0";
+            values.push(query.pattern.clone().into());
+        }
+        if let Some(folder_id) = query.folder_id {
+            sql += " AND folder_id = ?";
+            values.push(folder_id.into());
+        }
+        if let Some(min_size) = query.min_size {
+            sql += " AND size_bytes >= ?";
+            values.push((min_size as i64).into());
+        }
+        if let Some(since) = query.modified_since_secs {
+            sql += " AND last_modified_secs >= ?";
+            values.push((since as i64).into());
+        }
+        sql += &format!(
+            " ORDER BY folder_id, relative_path LIMIT {}",
+            MAX_FIND_RESULTS
+        );
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(values), |row| {
+            Ok(FoundFile {
+                folder_id: row.get(0)?,
+                relative_path: PathBuf::from(row.get::<_, String>(1)?),
+                size_bytes: row.get(2)?,
+                modified_secs: row.get(3)?,
+                version_vector: VersionVector::parse(&row.get::<_, String>(4)?),
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Groups non-empty indexed files whose content hash appears more than
+    /// once, across all folders, largest groups first.
+    pub fn get_duplicate_groups(&self) -> Result<Vec<DuplicateGroup>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT i.sha256_hash, i.size_bytes, f.local_path, i.relative_path
+             FROM file_index i
+             JOIN synced_folders f ON f.id = i.folder_id
+             WHERE i.size_bytes > 0 AND i.sha256_hash IN (
+                SELECT sha256_hash FROM file_index
+                WHERE size_bytes > 0 AND sha256_hash IS NOT NULL
+                GROUP BY sha256_hash HAVING COUNT(*) > 1
+             )
+             ORDER BY i.size_bytes DESC, i.sha256_hash, f.local_path, i.relative_path",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, u64>(1)?,
+                PathBuf::from(row.get::<_, String>(2)?),
+                PathBuf::from(row.get::<_, String>(3)?),
+            ))
+        })?;
+
+        let mut groups: Vec<DuplicateGroup> = Vec::new();
+        for row in rows {
+            let (hash, size_bytes, folder_root, relative_path) = row?;
+            match groups.last_mut() {
+                Some(group) if group.hash == hash => group.files.push((folder_root, relative_path)),
+                _ => groups.push(DuplicateGroup {
+                    hash,
+                    size_bytes,
+                    files: vec![(folder_root, relative_path)],
+                }),
+            }
+        }
+        Ok(groups)
+    }
+
+    pub fn add_webhook(
+        &self,
+        url: &str,
+        secret: Option<&str>,
+        events: &[String],
+    ) -> Result<i64, rusqlite::Error> {
+        self.conn.execute(
+            "INSERT INTO webhooks (url, secret, events) VALUES (?1, ?2, ?3)",
+            params![url, secret, events.join(",")],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn get_webhooks(&self) -> Result<Vec<Webhook>, rusqlite::Error> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, url, secret, events FROM webhooks ORDER BY id")?;
+        let rows = stmt.query_map([], |row| {
+            let events: String = row.get(3)?;
+            Ok(Webhook {
+                id: row.get(0)?,
+                url: row.get(1)?,
+                secret: row.get(2)?,
+                events: events
+                    .split(',')
+                    .filter(|e| !e.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Returns false if no webhook with that ID exists.
+    pub fn remove_webhook(&self, id: i64) -> Result<bool, rusqlite::Error> {
+        let removed = self
+            .conn
+            .execute("DELETE FROM webhooks WHERE id = ?1", params![id])?;
+        Ok(removed > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A database in the temporary directory, removed on drop.
+    struct TestDb {
+        db: Database,
+        path: PathBuf,
+    }
+
+    impl TestDb {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "sync_rs-test-{}-{}.db",
+                std::process::id(),
+                name
+            ));
+            let _ = std::fs::remove_file(&path);
+            Self {
+                db: Database::open(&path).unwrap(),
+                path,
+            }
+        }
+    }
+
+    impl Drop for TestDb {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let mut path = self.path.clone().into_os_string();
+                path.push(suffix);
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    /// A manual-push folder with a change of `report.txt` waiting for
+    /// approval, and the sequence a peer has seen everything up to.
+    fn folder_with_pending_change(db: &Database) -> (i64, u64) {
+        let folder_id = db.add_folder("docs", "/tmp/sync_rs-test-docs").unwrap();
+        db.set_folder_manual_push(folder_id, true).unwrap();
+        let path = Path::new("report.txt");
+        db.upsert_file_record(folder_id, path, 5, &format!("{:064x}", 1), 1_700_000_000)
+            .unwrap();
+        db.record_pending_change(folder_id, path, &LocalChange::Modified)
+            .unwrap();
+        (folder_id, db.get_folder_sequence(folder_id).unwrap())
+    }
+
+    fn announced(db: &Database, folder_id: i64, after_sequence: u64) -> Vec<String> {
+        db.get_index_page(folder_id, after_sequence, crate::protocol::INDEX_PAGE_SIZE)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect()
+    }
+
+    #[test]
+    fn pending_changes_are_not_announced() {
+        let test = TestDb::new("pending");
+        let (folder_id, _) = folder_with_pending_change(&test.db);
+        assert!(announced(&test.db, folder_id, 0).is_empty());
+    }
+
+    #[test]
+    fn approved_changes_appear_past_the_peers_sequence() {
+        let test = TestDb::new("approve");
+        let (folder_id, seen) = folder_with_pending_change(&test.db);
+        test.db.approve_pending_changes(folder_id, &[]).unwrap();
+        assert_eq!(announced(&test.db, folder_id, seen), ["report.txt"]);
+    }
+
+    #[test]
+    fn turning_manual_push_off_announces_pending_changes() {
+        let test = TestDb::new("manual-push-off");
+        let (folder_id, seen) = folder_with_pending_change(&test.db);
+        test.db.set_folder_manual_push(folder_id, false).unwrap();
+        assert!(test.db.get_pending_changes(folder_id).unwrap().is_empty());
+        assert_eq!(announced(&test.db, folder_id, seen), ["report.txt"]);
+    }
 }
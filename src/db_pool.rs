@@ -0,0 +1,97 @@
+use std::ops::Deref;
+use std::sync::Arc;
+
+use tokio::sync::{Mutex, MutexGuard, OwnedSemaphorePermit, Semaphore};
+
+use crate::database::Database;
+
+/// Read-only connections opened next to the writer by default.
+pub const DEFAULT_READERS: usize = 4;
+
+/// Shared access to the database: a single writer connection behind an async
+/// mutex plus a few read-only connections. The database runs in WAL mode, so
+/// status queries on a reader never wait for the event loop holding the
+/// writer, and vice versa.
+#[derive(Debug, Clone)]
+pub struct DbPool {
+    writer: Arc<Mutex<Database>>,
+    readers: Arc<Readers>,
+}
+
+#[derive(Debug)]
+struct Readers {
+    idle: std::sync::Mutex<Vec<Database>>,
+    available: Arc<Semaphore>,
+}
+
+impl DbPool {
+    /// Opens (and migrates) the writer, then `readers` read-only connections.
+    pub fn open(readers: usize) -> Result<Self, rusqlite::Error> {
+        let writer = Database::new()?;
+        let idle = (0..readers.max(1))
+            .map(|_| Database::open_read_only())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            writer: Arc::new(Mutex::new(writer)),
+            readers: Arc::new(Readers {
+                available: Arc::new(Semaphore::new(idle.len())),
+                idle: std::sync::Mutex::new(idle),
+            }),
+        })
+    }
+
+    /// Locks the writer connection. Keep the guard short-lived, and never hold
+    /// it across an `.await` that waits on the event loop.
+    pub async fn write(&self) -> MutexGuard<'_, Database> {
+        self.writer.lock().await
+    }
+
+    /// Borrows a read-only connection, waiting if all of them are in use.
+    pub async fn read(&self) -> ReadGuard {
+        let permit = self
+            .readers
+            .available
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("reader semaphore is never closed");
+        let db = self
+            .readers
+            .idle
+            .lock()
+            .unwrap()
+            .pop()
+            .expect("a permit guarantees an idle reader");
+
+        ReadGuard {
+            db: Some(db),
+            readers: self.readers.clone(),
+            _permit: permit,
+        }
+    }
+}
+
+/// A read-only connection borrowed from a [`DbPool`], returned on drop.
+/// Methods that write fail with `SQLITE_READONLY`.
+pub struct ReadGuard {
+    db: Option<Database>,
+    readers: Arc<Readers>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Deref for ReadGuard {
+    type Target = Database;
+
+    fn deref(&self) -> &Database {
+        self.db.as_ref().expect("reader is only taken on drop")
+    }
+}
+
+impl Drop for ReadGuard {
+    fn drop(&mut self) {
+        if let Some(db) = self.db.take() {
+            self.readers.idle.lock().unwrap().push(db);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::sync::Arc;
+
+use tokio::sync::broadcast::error::RecvError;
+use zbus::fdo;
+use zbus::object_server::SignalEmitter;
+
+use crate::control::SyncControl;
+use crate::db_pool::DbPool;
+use crate::event_queue::EventQueue;
+use crate::events::EventBus;
+
+pub const BUS_NAME: &str = "org.syncrs.Daemon";
+pub const OBJECT_PATH: &str = "/org/syncrs/Daemon";
+
+/// `(id, name, local_path, file_count, total_bytes)` as returned by `Status`.
+type FolderStatus = (i64, String, String, u64, u64);
+
+/// The `org.syncrs.Daemon` interface served on the session bus.
+pub struct DaemonInterface {
+    db: DbPool,
+    queue: EventQueue,
+    control: Arc<SyncControl>,
+}
+
+impl DaemonInterface {
+    pub fn new(db: DbPool, queue: EventQueue, control: Arc<SyncControl>) -> Self {
+        Self { db, queue, control }
+    }
+}
+
+fn db_error(e: rusqlite::Error) -> fdo::Error {
+    fdo::Error::Failed(format!("database error: {}", e))
+}
+
+#[zbus::interface(name = "org.syncrs.Daemon")]
+impl DaemonInterface {
+    /// Returns `(device_id, device_label, paused, folders)`.
+    async fn status(&self) -> fdo::Result<(String, String, bool, Vec<FolderStatus>)> {
+        let db = self.db.read().await;
+        let folders = db
+            .get_all_synced_folders()
+            .and_then(|folders| {
+                folders
+                    .into_iter()
+                    .map(|folder| {
+                        let (file_count, total_bytes) = db.get_folder_totals(folder.id)?;
+                        Ok((
+                            folder.id,
+                            folder.name,
+                            folder.local_path.to_string_lossy().into_owned(),
+                            file_count,
+                            total_bytes,
+                        ))
+                    })
+                    .collect()
+            })
+            .map_err(db_error)?;
+
+        Ok((
+            db.get_or_create_device_id().map_err(db_error)?,
+            db.get_device_label().map_err(db_error)?,
+            self.control.is_paused(),
+            folders,
+        ))
+    }
+
+    async fn pause(&self, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) -> fdo::Result<()> {
+        self.control
+            .pause(&*self.db.write().await)
+            .map_err(db_error)?;
+        self.paused_changed(&emitter).await?;
+        Ok(())
+    }
+
+    async fn resume(&self, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) -> fdo::Result<()> {
+        self.control
+            .resume(&self.db, &self.queue)
+            .await
+            .map_err(db_error)?;
+        self.paused_changed(&emitter).await?;
+        Ok(())
+    }
+
+    #[zbus(property)]
+    fn paused(&self) -> bool {
+        self.control.is_paused()
+    }
+
+    /// Emitted for every sync event; `payload` is the same JSON object the
+    /// web UI receives, and `kind` repeats its `kind` field for filtering.
+    #[zbus(signal)]
+    async fn event(emitter: &SignalEmitter<'_>, kind: &str, payload: &str) -> zbus::Result<()>;
+}
+
+/// Claims [`BUS_NAME`] on the session bus and forwards sync events as
+/// signals until the event bus closes.
+pub async fn serve(interface: DaemonInterface, events: EventBus) -> zbus::Result<()> {
+    let connection = zbus::connection::Builder::session()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, interface)?
+        .build()
+        .await?;
+    println!("[DBUS] Serving {} on the session bus", BUS_NAME);
+
+    let emitter = SignalEmitter::new(&connection, OBJECT_PATH)?;
+    let mut receiver = events.subscribe();
+    loop {
+        let event = match receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                eprintln!("[DBUS] Fell behind, {} event(s) not signalled", missed);
+                continue;
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        };
+
+        let Ok(serde_json::Value::Object(payload)) = serde_json::to_value(&event) else {
+            continue;
+        };
+        let kind = payload
+            .get("kind")
+            .and_then(|kind| kind.as_str())
+            .unwrap_or_default()
+            .to_string();
+        let payload = serde_json::Value::Object(payload).to_string();
+        if let Err(e) = DaemonInterface::event(&emitter, &kind, &payload).await {
+            eprintln!("[DBUS] Failed to emit event signal: {}", e);
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::staging;
+
+/// How a duplicate is replaced by a reference to the kept copy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkMode {
+    /// Both paths share one inode, so editing either changes both.
+    Hardlink,
+    /// Copy-on-write clone: shares blocks until one side is modified.
+    /// Needs Btrfs, XFS, APFS or ReFS.
+    Reflink,
+}
+
+/// Replaces `duplicate` with a link to `source`. The link is created in the
+/// staging directory of `folder_root` (the folder containing `duplicate`) and
+/// renamed over the duplicate, so the file is never missing.
+pub fn link_duplicate(
+    folder_root: &Path,
+    source: &Path,
+    duplicate: &Path,
+    mode: LinkMode,
+) -> io::Result<()> {
+    let staged = staging::staging_path(folder_root)?;
+    let linked = match mode {
+        LinkMode::Hardlink => fs::hard_link(source, &staged),
+        LinkMode::Reflink => reflink_copy::reflink(source, &staged),
+    };
+    if let Err(e) = linked {
+        let _ = fs::remove_file(&staged);
+        return Err(e);
+    }
+
+    // Keep the duplicate's permissions; a hardlink shares the source's.
+    if mode == LinkMode::Reflink
+        && let Ok(meta) = fs::metadata(duplicate)
+    {
+        let _ = fs::set_permissions(&staged, meta.permissions());
+    }
+
+    staging::move_into_place(&staged, duplicate).inspect_err(|_| {
+        let _ = fs::remove_file(&staged);
+    })
+}
+
+/// Returns true if both paths already refer to the same file on disk.
+#[cfg(unix)]
+pub fn is_same_file(a: &Path, b: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let (a, b) = (fs::metadata(a)?, fs::metadata(b)?);
+    Ok(a.dev() == b.dev() && a.ino() == b.ino())
+}
+
+#[cfg(not(unix))]
+pub fn is_same_file(_a: &Path, _b: &Path) -> io::Result<bool> {
+    Ok(false)
+}
@@ -0,0 +1,137 @@
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use crate::instance_lock::LockError;
+
+/// Crate-wide error type for recoverable failures. Handlers return it instead
+/// of panicking so the daemon can log the problem, publish it as an error
+/// event and carry on.
+#[derive(Debug, thiserror::Error)]
+pub enum SyncError {
+    #[error("database error: {0}")]
+    Database(#[from] rusqlite::Error),
+
+    #[error("{context} {path:?}: {source}")]
+    Io {
+        context: &'static str,
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("path is not valid UTF-8: {0:?}")]
+    NonUtf8Path(PathBuf),
+
+    #[error("file watcher error: {0}")]
+    Watcher(#[from] notify::Error),
+
+    #[error(transparent)]
+    Lock(#[from] LockError),
+
+    #[error("invalid config {path:?}: {message}")]
+    Config { path: PathBuf, message: String },
+
+    #[error("unknown folder profile {0:?}")]
+    UnknownProfile(String),
+
+    #[error("no folder matches {0:?}")]
+    UnknownFolder(String),
+
+    #[error("{0:?} is not inside a synced folder")]
+    NotInSyncedFolder(PathBuf),
+
+    #[error("{0:?} is not an indexed file")]
+    NotIndexed(PathBuf),
+
+    #[error("{0} is an observer folder; it never downloads content")]
+    ObserverFolder(String),
+
+    #[error("no conflict with ID {0}")]
+    UnknownConflict(i64),
+
+    #[error("invalid webhook URL {0:?}: must start with http:// or https://")]
+    InvalidWebhookUrl(String),
+
+    #[error("invalid snapshot {path:?}: {message}")]
+    InvalidSnapshot { path: PathBuf, message: String },
+
+    #[error("cannot reach the daemon at {address}: {source}")]
+    DaemonUnreachable {
+        address: SocketAddr,
+        #[source]
+        source: tonic::transport::Error,
+    },
+
+    #[error("the daemon refused to {action}: {message}")]
+    DaemonRefused {
+        action: &'static str,
+        message: String,
+    },
+
+    #[error("the daemon is not running; start it and run this again, so it rescans every folder")]
+    DaemonStopped,
+
+    #[error("failed to {action} the service: {source}")]
+    Service {
+        action: &'static str,
+        #[source]
+        source: io::Error,
+    },
+}
+
+pub type Result<T, E = SyncError> = std::result::Result<T, E>;
+
+impl SyncError {
+    pub fn io(context: &'static str, path: impl Into<PathBuf>, source: io::Error) -> Self {
+        SyncError::Io {
+            context,
+            path: path.into(),
+            source,
+        }
+    }
+
+    /// The file or folder the error is about, if any.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            SyncError::Io { path, .. }
+            | SyncError::NonUtf8Path(path)
+            | SyncError::NotInSyncedFolder(path)
+            | SyncError::NotIndexed(path)
+            | SyncError::Config { path, .. }
+            | SyncError::InvalidSnapshot { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// True when an I/O error means the path no longer exists, which is
+    /// expected for short-lived files removed before they were processed.
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, SyncError::Io { source, .. } if source.kind() == io::ErrorKind::NotFound)
+    }
+
+    /// True when another process holds the file open exclusively, like
+    /// Outlook with its PST or a hypervisor with a running disk image.
+    /// Retrying later usually succeeds.
+    pub fn is_locked(&self) -> bool {
+        matches!(self, SyncError::Io { source, .. } if is_lock_error(source))
+    }
+}
+
+fn is_lock_error(error: &io::Error) -> bool {
+    // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION.
+    #[cfg(windows)]
+    if matches!(error.raw_os_error(), Some(32 | 33)) {
+        return true;
+    }
+    matches!(
+        error.kind(),
+        io::ErrorKind::ResourceBusy | io::ErrorKind::WouldBlock
+    )
+}
+
+/// Returns `path` as UTF-8, which the index requires for stored paths.
+pub fn path_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| SyncError::NonUtf8Path(path.to_path_buf()))
+}
@@ -1,185 +1,1319 @@
 use std::{
-    path::PathBuf,
-    sync::Arc,
+    collections::{HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use tokio::sync::mpsc;
+use tokio::sync::mpsc::{self, error::TrySendError};
 use walkdir::WalkDir;
 
 use crate::{
-    database,
+    config::{Config, LiveConfig},
+    control::SyncControl,
+    database::{Database, LocalChange, SyncedFolder},
+    db_pool::DbPool,
+    error::{self, SyncError},
+    events::{EventBus, SyncEventKind},
+    health::FolderHealth,
+    hooks::{Hooks, IndexChange, Verdict},
+    ignore, placeholder, removable,
+    replay::EventRecorder,
+    suppression::ExpectedChanges,
     sync_engine::{self, calculate_hash},
+    trace::{self, TraceId},
+    xattrs,
 };
 use sync_engine::FsEventKind;
-use tokio::sync::Mutex;
 
 #[derive(Debug)]
 pub enum QueueEvent {
-    FileChanged { path: PathBuf, kind: FsEventKind },
-    FolderAdded { path: PathBuf },
+    FileChanged {
+        path: PathBuf,
+        kind: FsEventKind,
+        /// Shows up in every log line about the event; see [`crate::trace`].
+        trace: TraceId,
+    },
+    FolderAdded {
+        path: PathBuf,
+    },
+    /// Queued every [`CHECKPOINT_INTERVAL`] files of a folder scan; by the
+    /// time it is handled, every file up to `last_path` has been indexed.
+    ScanCheckpoint {
+        folder_id: i64,
+        last_path: PathBuf,
+    },
+    /// Queued after the events of a folder scan, so it is handled once they are.
+    ScanFinished {
+        folder_id: i64,
+    },
+    /// The watcher of the folder at `path` reported an error.
+    WatcherError {
+        path: PathBuf,
+        message: String,
+    },
     Shutdown,
 }
 
+/// Number of files a scan queues between two persisted checkpoints.
+pub const CHECKPOINT_INTERVAL: usize = 1000;
+
+/// Bounded queue feeding the event loop. Senders wait while it is full, so
+/// nothing is dropped; the event loop itself must therefore never wait on it.
 #[derive(Debug, Clone)]
 pub struct EventQueue {
     sender: mpsc::Sender<QueueEvent>,
+    /// Set while the queue is full, so that is logged once per episode.
+    full: Arc<AtomicBool>,
+    /// Hashes computed by scans ahead of the event loop, by path.
+    scanned: Arc<Mutex<HashMap<PathBuf, ScannedFile>>>,
+    /// The event the event loop is handling, if any.
+    current: Arc<Mutex<Option<CurrentEvent>>>,
+    /// Files waiting for their folder's settle time, with a check scheduled.
+    settling: Arc<Mutex<HashSet<PathBuf>>>,
+    /// Directories file watchers were started for.
+    watched: Arc<Mutex<HashSet<PathBuf>>>,
+    /// Events queued by file watchers, kept for `sync_rs replay`.
+    recorder: EventRecorder,
+    hooks: Hooks,
+}
+
+/// The event the event loop is handling, as shown by the status APIs and
+/// watched by [`crate::watchdog`].
+#[derive(Debug, Clone)]
+pub struct CurrentEvent {
+    pub path: PathBuf,
+    pub trace: TraceId,
+    /// What the handler is doing, e.g. waiting for the database.
+    pub stage: &'static str,
+    pub started: Instant,
+}
+
+/// A file as a scan read it, so the event loop does not hash it again.
+#[derive(Debug, Clone)]
+struct ScannedFile {
+    folder_id: i64,
+    size: u64,
+    modified_secs: u64,
+    hash: String,
+}
+
+impl ScannedFile {
+    fn read(folder_id: i64, path: &Path) -> std::io::Result<Self> {
+        let metadata = path.metadata()?;
+        Ok(Self {
+            folder_id,
+            size: metadata.len(),
+            modified_secs: modified_secs(&metadata),
+            hash: calculate_hash(path)?,
+        })
+    }
 }
 
 impl EventQueue {
     pub fn new(buffer: usize) -> (Self, mpsc::Receiver<QueueEvent>) {
         let (sender, receiver) = mpsc::channel(buffer);
-        (EventQueue { sender }, receiver)
+        let queue = EventQueue {
+            sender,
+            full: Arc::new(AtomicBool::new(false)),
+            scanned: Arc::default(),
+            current: Arc::default(),
+            settling: Arc::default(),
+            watched: Arc::default(),
+            recorder: EventRecorder::default(),
+            hooks: Hooks::default(),
+        };
+        (queue, receiver)
     }
 
     pub async fn send(&self, event: QueueEvent) {
+        let event = match self.sender.try_send(event) {
+            Ok(()) => {
+                self.full.store(false, Ordering::Relaxed);
+                return;
+            }
+            Err(TrySendError::Closed(_)) => return,
+            Err(TrySendError::Full(event)) => event,
+        };
+        if !self.full.swap(true, Ordering::Relaxed) {
+            println!(
+                "[EVENT_QUEUE] Queue full ({} events); senders wait until it drains",
+                self.capacity()
+            );
+        }
         let _ = self.sender.send(event).await;
     }
+
+    pub fn recorder(&self) -> &EventRecorder {
+        &self.recorder
+    }
+
+    /// Hooks of applications embedding the crate (see [`crate::hooks`]),
+    /// e.g. `queue.hooks().add(Arc::new(MyHook))` before the event loop
+    /// starts.
+    pub fn hooks(&self) -> &Hooks {
+        &self.hooks
+    }
+
+    /// Records that a file watcher was started for `folder`.
+    pub fn mark_watched(&self, folder: &Path) {
+        self.watched.lock().unwrap().insert(folder.to_path_buf());
+    }
+
+    /// True when a file watcher covers `path`, as it is inside a directory
+    /// one was started for.
+    pub fn is_watched(&self, path: &Path) -> bool {
+        self.watched
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|folder| path.starts_with(folder))
+    }
+
+    /// Events waiting for the event loop.
+    pub fn depth(&self) -> usize {
+        self.capacity() - self.sender.capacity()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.sender.max_capacity()
+    }
+
+    /// The event being handled, if any.
+    pub fn current(&self) -> Option<CurrentEvent> {
+        self.current.lock().unwrap().clone()
+    }
+
+    fn start_handling(&self, path: &Path, trace: TraceId) {
+        *self.current.lock().unwrap() = Some(CurrentEvent {
+            path: path.to_path_buf(),
+            trace,
+            stage: "starting",
+            started: Instant::now(),
+        });
+    }
+
+    fn set_stage(&self, stage: &'static str) {
+        if let Some(current) = self.current.lock().unwrap().as_mut() {
+            current.stage = stage;
+        }
+    }
+
+    fn finish_handling(&self) {
+        *self.current.lock().unwrap() = None;
+    }
+
+    fn remember_scanned(&self, path: PathBuf, file: ScannedFile) {
+        self.scanned.lock().unwrap().insert(path, file);
+    }
+
+    fn take_scanned(&self, path: &Path) -> Option<ScannedFile> {
+        self.scanned.lock().unwrap().remove(path)
+    }
+
+    /// Returns false when a settle check of `path` is scheduled already.
+    fn start_settling(&self, path: &Path) -> bool {
+        self.settling.lock().unwrap().insert(path.to_path_buf())
+    }
+
+    fn finish_settling(&self, path: &Path) {
+        self.settling.lock().unwrap().remove(path);
+    }
+
+    /// Drops what a finished scan hashed but the event loop did not use.
+    fn forget_scanned(&self, folder_id: i64) {
+        self.scanned
+            .lock()
+            .unwrap()
+            .retain(|_, file| file.folder_id != folder_id);
+    }
 }
 
-pub async fn start_event_loop(
-    mut receiver: mpsc::Receiver<QueueEvent>,
-    db: Arc<Mutex<database::Database>>,
-    queue: EventQueue,
-) {
+/// Shared state the event loop works with.
+pub struct EventLoopContext {
+    pub db: DbPool,
+    pub queue: EventQueue,
+    pub expected_changes: Arc<ExpectedChanges>,
+    pub events: EventBus,
+    pub control: Arc<SyncControl>,
+    pub config: Arc<LiveConfig>,
+    pub health: Arc<FolderHealth>,
+}
+
+pub async fn start_event_loop(mut receiver: mpsc::Receiver<QueueEvent>, context: EventLoopContext) {
+    let EventLoopContext {
+        db,
+        queue,
+        expected_changes,
+        events,
+        control,
+        config,
+        health,
+    } = context;
     println!("[EVENT_QUEUE] Starting event loop...");
+    if let Err(e) = reschedule_locked_files(&db, &queue).await {
+        report_error(e, &events, queue.hooks());
+    }
 
-    while let Some(event) = receiver.recv().await {
-        match event {
-            QueueEvent::FileChanged { path, kind } => {
-                handle_file_changed_event(path, kind, &db).await
+    while let Some(mut event) = receiver.recv().await {
+        if let QueueEvent::ScanFinished { folder_id } = &event {
+            queue.forget_scanned(*folder_id);
+        }
+        if matches!(
+            event,
+            QueueEvent::FileChanged { .. } | QueueEvent::FolderAdded { .. }
+        ) && queue.hooks().on_event(&mut event) == Verdict::Veto
+        {
+            println!("[EVENT_QUEUE] Dropped by a hook: {:?}", event);
+            continue;
+        }
+        let result = match event {
+            // Changes made while paused or in maintenance are picked up by
+            // the rescan on resume or when maintenance is left.
+            QueueEvent::FileChanged { path, trace, .. } if control.is_stopped() => {
+                println!(
+                    "[EVENT_QUEUE] [{}] Sync stopped, ignoring change: {:?}",
+                    trace, path
+                );
+                Ok(())
+            }
+            QueueEvent::FileChanged { path, kind, trace } => {
+                queue.start_handling(&path, trace);
+                trace::scope(trace, async {
+                    let result = handle_file_changed_event(
+                        path,
+                        kind,
+                        &db,
+                        &queue,
+                        &expected_changes,
+                        &events,
+                        &health,
+                    )
+                    .await;
+                    // Reported here so the error is logged with its trace.
+                    if let Err(e) = result {
+                        report_error(e, &events, queue.hooks());
+                    }
+                })
+                .await;
+                queue.finish_handling();
+                Ok(())
+            }
+            QueueEvent::FolderAdded { path } if control.in_maintenance() => {
+                println!("[EVENT_QUEUE] In maintenance, not scanning {:?}", path);
+                Ok(())
+            }
+            QueueEvent::FolderAdded { path } => {
+                queue.start_handling(&path, TraceId::new());
+                let result =
+                    handle_folder_added_event(path, &db, &config.get(), &queue, &events).await;
+                queue.finish_handling();
+                result
             }
-            QueueEvent::FolderAdded { path } => handle_folder_added_event(path, &db, &queue).await,
-            QueueEvent::Shutdown => handle_shutdown_event().await,
+            // Changes skipped while stopped must not count as scanned.
+            QueueEvent::ScanCheckpoint { .. } | QueueEvent::ScanFinished { .. }
+                if control.is_stopped() =>
+            {
+                Ok(())
+            }
+            QueueEvent::ScanCheckpoint {
+                folder_id,
+                last_path,
+            } => db
+                .write()
+                .await
+                .set_scan_checkpoint(folder_id, &last_path)
+                .map_err(SyncError::from),
+            QueueEvent::ScanFinished { folder_id } => {
+                println!("[EVENT_QUEUE] Folder {} is up to date", folder_id);
+                let cleared = db.write().await.clear_scan_checkpoint(folder_id);
+                events.publish(SyncEventKind::FolderSynced { folder_id });
+                health.record_scan(folder_id);
+                cleared.map_err(SyncError::from)
+            }
+            QueueEvent::WatcherError { path, message } => {
+                eprintln!("[EVENT_QUEUE] Watcher error for {:?}: {}", path, message);
+                let folder = match error::path_str(&path) {
+                    Ok(path_str) => db.read().await.get_folder_by_path(path_str),
+                    Err(_) => Ok(None),
+                };
+                folder.map_err(SyncError::from).map(|folder| {
+                    if let Some(folder) = folder {
+                        health.record_watcher_error(folder.id, message);
+                    }
+                })
+            }
+            QueueEvent::Shutdown => {
+                handle_shutdown_event().await;
+                break;
+            }
+        };
+
+        if let Err(e) = result {
+            report_error(e, &events, queue.hooks());
         }
     }
 }
 
+/// Logs a failed event and publishes it so API clients and webhooks see it.
+/// Files that vanished before they could be read are only logged: the
+/// removal event that follows takes care of them.
+fn report_error(error: SyncError, events: &EventBus, hooks: &Hooks) {
+    if error.is_not_found() {
+        println!(
+            "[EVENT_QUEUE] {}Skipping vanished file: {}",
+            trace::prefix(),
+            error
+        );
+        return;
+    }
+
+    eprintln!("[HANDLER] {}{}", trace::prefix(), error);
+    hooks.on_error(&error);
+    events.publish(SyncEventKind::Error {
+        path: error.path().map(|p| p.to_path_buf()),
+        message: error.to_string(),
+    });
+}
+
 async fn handle_file_changed_event(
     path: PathBuf,
     kind: FsEventKind,
-    db: &Arc<Mutex<database::Database>>,
-) {
+    db: &DbPool,
+    queue: &EventQueue,
+    expected_changes: &ExpectedChanges,
+    events: &EventBus,
+    health: &FolderHealth,
+) -> error::Result<()> {
     println!(
-        "[EVENT_QUEUE] Handling file changed event: {:?}, kind: {:?}",
-        path, kind
+        "[EVENT_QUEUE] {}Handling file changed event: {:?}, kind: {:?}",
+        trace::prefix(),
+        path,
+        kind
     );
-    let db_guard = db.lock().await;
+    queue.set_stage("waiting for the database");
+    let db_guard = db.write().await;
+    queue.set_stage("updating the index");
 
     // 1. Find the parent sync folder for this file path to get its ID.
     let mut parent = path.parent();
     let mut folder_info = None;
     while let Some(current_path) = parent {
-        if let Ok(Some(info)) = db_guard.get_folder_by_path(current_path.to_str().unwrap()) {
+        if let Some(info) = db_guard.get_folder_by_path(error::path_str(current_path)?)? {
             folder_info = Some(info);
             break;
         }
         parent = current_path.parent();
     }
 
-    let (folder_id, base_path) = match folder_info {
-        Some(info) => info,
+    let folder = match folder_info {
+        Some(folder) => folder,
         None => {
             eprintln!(
-                "[HANDLER] No registered sync folder found for path: {:?}",
+                "[HANDLER] {}No registered sync folder found for path: {:?}",
+                trace::prefix(),
                 path
             );
-            return;
+            return Ok(());
         }
     };
 
+    let result = apply_file_change(
+        &db_guard,
+        &folder,
+        &path,
+        kind,
+        queue,
+        expected_changes,
+        events,
+    );
+    health.record_event(folder.id, result.as_ref().err());
+    result
+}
+
+/// Updates the index for one change of a file in `folder`.
+fn apply_file_change(
+    db: &Database,
+    folder: &SyncedFolder,
+    path: &Path,
+    kind: FsEventKind,
+    queue: &EventQueue,
+    expected_changes: &ExpectedChanges,
+    events: &EventBus,
+) -> error::Result<()> {
+    // The index stores paths as UTF-8 text.
+    error::path_str(path)?;
+
     // 2. Determine the file's path relative to the sync folder root.
-    let relative_path = match path.strip_prefix(&base_path) {
+    let folder_id = folder.id;
+    let relative_path = match path.strip_prefix(&folder.local_path) {
         Ok(p) => p,
         Err(_) => {
-            eprintln!("[HANDLER] Could not determine relative path for {:?}", path);
-            return;
+            eprintln!(
+                "[HANDLER] {}Could not determine relative path for {:?}",
+                trace::prefix(),
+                path
+            );
+            return Ok(());
         }
     };
 
+    // The index mirrors peers; local files are not theirs to announce.
+    if folder.observer && !matches!(kind, FsEventKind::Remove) {
+        println!(
+            "[EVENT_QUEUE] {}Ignoring local change in observer folder: {:?}",
+            trace::prefix(),
+            path
+        );
+        return Ok(());
+    }
+
     match kind {
         FsEventKind::Create | FsEventKind::Modify => {
-            if !path.is_file() {
-                println!("[EVENT_QUEUE] Ignoring non-file event: {:?}", path);
-                return;
+            index_or_defer(
+                db,
+                folder,
+                path,
+                relative_path,
+                queue,
+                expected_changes,
+                events,
+            )?;
+        }
+
+        FsEventKind::Metadata => {
+            let updated = update_metadata(db, folder, path, relative_path)?;
+            if !updated {
+                index_or_defer(
+                    db,
+                    folder,
+                    path,
+                    relative_path,
+                    queue,
+                    expected_changes,
+                    events,
+                )?;
+            }
+        }
+
+        FsEventKind::Rename { old_path, .. } => {
+            if expected_changes.is_expected(&old_path, None) {
+                println!(
+                    "[EVENT_QUEUE] {}Skipping rename made by sync: {:?}",
+                    trace::prefix(),
+                    path
+                );
+                return Ok(());
             }
 
-            let metadata = match path.metadata() {
-                Ok(meta) => meta,
-                Err(e) => {
-                    eprintln!("[EVENT_QUEUE] Failed to get metadata for {:?}: {}", path, e);
-                    return;
+            // Keep the index entry, including its version history, when the
+            // file stays in this folder; otherwise it is a plain create.
+            let old_relative = old_path.strip_prefix(&folder.local_path).ok();
+            if let Some(old_relative) = old_relative
+                && db.get_file_version(folder_id, old_relative)?.is_some()
+            {
+                if is_excluded(folder, path, relative_path) || !path.is_file() {
+                    db.remove_file_entry(folder_id, old_relative)?;
+                    hold_for_push(db, folder, old_relative, LocalChange::Removed)?;
+                    events.publish(SyncEventKind::FileRemoved {
+                        folder_id,
+                        path: old_relative.to_path_buf(),
+                    });
+                    return Ok(());
                 }
-            };
 
-            let hash = match calculate_hash(&path) {
-                Ok(hash) => hash,
-                Err(e) => {
-                    eprintln!(
-                        "[EVENT_QUEUE] Failed to calculate hash for {:?}: {}",
-                        path, e
+                if sync_engine::is_case_only_rename(old_relative, relative_path) {
+                    println!(
+                        "[EVENT_QUEUE] {}Case-only rename: {:?} -> {:?}",
+                        trace::prefix(),
+                        old_relative,
+                        relative_path
                     );
-                    return;
                 }
-            };
+                db.rename_file_entry(folder_id, old_relative, relative_path)?;
+                let from = old_relative.to_path_buf();
+                hold_for_push(db, folder, relative_path, LocalChange::Renamed { from })?;
+                events.publish(SyncEventKind::FileRenamed {
+                    folder_id,
+                    from: old_relative.to_path_buf(),
+                    path: relative_path.to_path_buf(),
+                });
+            }
+            index_or_defer(
+                db,
+                folder,
+                path,
+                relative_path,
+                queue,
+                expected_changes,
+                events,
+            )?;
+        }
 
-            let file_size = metadata.len();
-            let modified_secs = metadata
-                .modified()
-                .unwrap_or_else(|_| std::time::SystemTime::now())
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs() as u64;
+        FsEventKind::Remove => {
+            if expected_changes.is_expected(path, None) {
+                println!(
+                    "[EVENT_QUEUE] {}Skipping removal made by sync: {:?}",
+                    trace::prefix(),
+                    path
+                );
+                return Ok(());
+            }
 
-            if let Err(e) = db_guard.upsert_file_record(
-                folder_id,
-                relative_path,
-                file_size,
-                &hash,
-                modified_secs,
-            ) {
-                eprintln!("[HANDLER] DB Error upserting file {:?}: {}", path, e);
+            // Everything looks deleted while the drive is unplugged.
+            if !removable::is_mounted(folder) {
+                println!(
+                    "[EVENT_QUEUE] {}Drive of {} is not mounted; keeping index entry: {:?}",
+                    trace::prefix(),
+                    folder.name,
+                    path
+                );
+                return Ok(());
+            }
+
+            // The file still exists on peers; only the local copy is gone.
+            if folder.is_unsynced(relative_path) {
+                println!(
+                    "[EVENT_QUEUE] {}Keeping index entry of unsynced file: {:?}",
+                    trace::prefix(),
+                    path
+                );
+                if folder.placeholders
+                    && let Some(stub) = db.get_placeholder(folder_id, relative_path)?
+                {
+                    placeholder::write(&folder.local_path, relative_path, &stub)
+                        .map_err(|e| SyncError::io("failed to write placeholder for", path, e))?;
+                }
+                return Ok(());
+            }
+
+            // Gone already when the file was found again elsewhere by hash.
+            let indexed = db.get_file_version(folder_id, relative_path)?.is_some();
+            let links = db.get_link_group(folder_id, relative_path)?;
+            db.remove_file_entry(folder_id, relative_path)?;
+            refresh_link_groups(db, folder, &links)?;
+            if indexed {
+                hold_for_push(db, folder, relative_path, LocalChange::Removed)?;
+                events.publish(SyncEventKind::FileRemoved {
+                    folder_id,
+                    path: relative_path.to_path_buf(),
+                });
             }
         }
+    }
+    Ok(())
+}
 
-        FsEventKind::Remove => {
-            if let Err(e) = db_guard.remove_file_entry(folder_id, relative_path) {
-                eprintln!("[HANDLER] DB Error deleting file {:?}: {}", path, e);
+/// Adds a local change to the pending list of a manual-push folder, so it is
+/// only announced once approved with `sync_rs push`.
+fn hold_for_push(
+    db: &Database,
+    folder: &SyncedFolder,
+    relative_path: &Path,
+    change: LocalChange,
+) -> error::Result<()> {
+    if folder.manual_push {
+        db.record_pending_change(folder.id, relative_path, &change)?;
+        println!(
+            "[EVENT_QUEUE] {}Holding change for approval in {}: {:?}",
+            trace::prefix(),
+            folder.name,
+            relative_path
+        );
+    }
+    Ok(())
+}
+
+/// Records the current hard link group of indexed files. Adding or removing
+/// a link only changes the link count of the inode's other links, which the
+/// watcher does not report for them, so they are checked whenever one of
+/// their links is indexed or removed.
+fn refresh_link_groups(
+    db: &Database,
+    folder: &SyncedFolder,
+    relative_paths: &[PathBuf],
+) -> error::Result<()> {
+    for relative_path in relative_paths {
+        let link_group = folder
+            .local_path
+            .join(relative_path)
+            .symlink_metadata()
+            .ok()
+            .and_then(|metadata| sync_engine::link_group(&metadata));
+        db.set_file_link_group(folder.id, relative_path, link_group.as_deref())?;
+    }
+    Ok(())
+}
+
+/// True for paths the folder's options exclude from syncing.
+fn is_excluded(folder: &SyncedFolder, path: &Path, relative_path: &Path) -> bool {
+    (folder.ignore_hidden && ignore::is_hidden(&folder.local_path, path))
+        || ignore::matches_patterns(&folder.ignore_patterns, relative_path)
+}
+
+/// First delay before retrying a file another process has locked; it
+/// doubles with every failed attempt up to [`LOCKED_RETRY_MAX`].
+const LOCKED_RETRY_BASE: Duration = Duration::from_secs(30);
+const LOCKED_RETRY_MAX: Duration = Duration::from_secs(15 * 60);
+
+/// Indexes a file, or skips it while another process has it locked and
+/// schedules a retry with backoff, so a file held open for hours does not
+/// fail on every event. Locked files are listed by `sync_rs status`.
+fn index_or_defer(
+    db: &Database,
+    folder: &SyncedFolder,
+    path: &Path,
+    relative_path: &Path,
+    queue: &EventQueue,
+    expected_changes: &ExpectedChanges,
+    events: &EventBus,
+) -> error::Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let locked = db.get_locked_file(folder.id, relative_path)?;
+    if locked
+        .as_ref()
+        .is_some_and(|locked| locked.next_retry_secs > now)
+    {
+        // A retry is scheduled already.
+        return Ok(());
+    }
+    if let Some(remaining) = settle_remaining(path, folder.settle_time()) {
+        if queue.start_settling(path) {
+            println!(
+                "[EVENT_QUEUE] {}{:?} is still being written; indexing it once unchanged for {}s",
+                trace::prefix(),
+                path,
+                folder.settle_secs
+            );
+            let trace = trace::current().unwrap_or_default();
+            schedule_settle_check(queue.clone(), path.to_path_buf(), remaining, trace);
+        }
+        return Ok(());
+    }
+
+    match index_file(
+        db,
+        folder,
+        path,
+        relative_path,
+        queue,
+        expected_changes,
+        events,
+    ) {
+        Err(e) if e.is_locked() => {
+            let attempts = locked.map_or(1, |locked| locked.attempts + 1);
+            let delay = LOCKED_RETRY_BASE
+                .saturating_mul(1 << attempts.min(16).saturating_sub(1))
+                .min(LOCKED_RETRY_MAX);
+            db.record_locked_file(folder.id, relative_path, attempts, now + delay.as_secs())?;
+            println!(
+                "[EVENT_QUEUE] {}{:?} is in use by another process; retrying in {}s",
+                trace::prefix(),
+                path,
+                delay.as_secs()
+            );
+            let trace = trace::current().unwrap_or_default();
+            schedule_retry(queue.clone(), path.to_path_buf(), delay, trace);
+            Ok(())
+        }
+        Err(e) => Err(e),
+        Ok(()) => {
+            if locked.is_some() {
+                println!(
+                    "[EVENT_QUEUE] {}{:?} is no longer locked",
+                    trace::prefix(),
+                    path
+                );
+                db.clear_locked_file(folder.id, relative_path)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Queues the file again after `delay`, as part of the same trace.
+fn schedule_retry(queue: EventQueue, path: PathBuf, delay: Duration, trace: TraceId) {
+    tokio::spawn(async move {
+        tokio::time::sleep(delay).await;
+        queue
+            .send(QueueEvent::FileChanged {
+                path,
+                kind: FsEventKind::Modify,
+                trace,
+            })
+            .await;
+    });
+}
+
+/// How much longer `path` has to go unmodified before it has been quiet for
+/// `settle`; `None` once it has, or when it cannot be read.
+fn settle_remaining(path: &Path, settle: Duration) -> Option<Duration> {
+    if settle.is_zero() {
+        return None;
+    }
+    let modified = path.symlink_metadata().ok()?.modified().ok()?;
+    // A modification time in the future counts as just modified.
+    let age = SystemTime::now()
+        .duration_since(modified)
+        .unwrap_or_default();
+    settle
+        .checked_sub(age)
+        .filter(|remaining| !remaining.is_zero())
+}
+
+/// Queues the file again once it may have settled. Events for it until then
+/// are dropped, so a file written for an hour has one check pending.
+fn schedule_settle_check(queue: EventQueue, path: PathBuf, delay: Duration, trace: TraceId) {
+    tokio::spawn(async move {
+        tokio::time::sleep(delay).await;
+        queue.finish_settling(&path);
+        queue
+            .send(QueueEvent::FileChanged {
+                path,
+                kind: FsEventKind::Modify,
+                trace,
+            })
+            .await;
+    });
+}
+
+/// Schedules the retries of files found locked before a restart.
+async fn reschedule_locked_files(db: &DbPool, queue: &EventQueue) -> error::Result<()> {
+    let db = db.read().await;
+    let now = SystemTime::now();
+    for folder in db.get_all_synced_folders()? {
+        for locked in db.get_locked_files(folder.id)? {
+            let due = UNIX_EPOCH + Duration::from_secs(locked.next_retry_secs);
+            let delay = due.duration_since(now).unwrap_or_default();
+            schedule_retry(
+                queue.clone(),
+                folder.local_path.join(&locked.relative_path),
+                delay,
+                TraceId::new(),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Applies a metadata-only change without hashing the content again.
+/// Returns false when the file has to be indexed in full instead.
+fn update_metadata(
+    db: &Database,
+    folder: &SyncedFolder,
+    path: &Path,
+    relative_path: &Path,
+) -> error::Result<bool> {
+    if is_excluded(folder, path, relative_path) || !path.is_file() {
+        return Ok(true);
+    }
+
+    let metadata = path
+        .metadata()
+        .map_err(|e| SyncError::io("failed to read metadata of", path, e))?;
+    let modified_secs = metadata
+        .modified()
+        .unwrap_or_else(|_| SystemTime::now())
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if !db.update_file_modified(folder.id, relative_path, metadata.len(), modified_secs)? {
+        return Ok(false);
+    }
+    // Adding or removing a hard link only changes the link count.
+    if folder.sync_hardlinks {
+        db.set_file_link_group(
+            folder.id,
+            relative_path,
+            sync_engine::link_group(&metadata).as_deref(),
+        )?;
+    }
+
+    if folder.sync_xattrs {
+        match xattrs::read(path) {
+            Ok(attrs) => db.set_file_xattrs(folder.id, relative_path, &attrs)?,
+            Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {}
+            Err(e) => {
+                return Err(SyncError::io(
+                    "failed to read extended attributes of",
+                    path,
+                    e,
+                ));
+            }
+        }
+    }
+    println!(
+        "[EVENT_QUEUE] {}Metadata-only change, content not rehashed: {:?}",
+        trace::prefix(),
+        path
+    );
+    Ok(true)
+}
+
+/// Hashes a created or modified file and records it in the index.
+fn index_file(
+    db: &Database,
+    folder: &SyncedFolder,
+    path: &Path,
+    relative_path: &Path,
+    queue: &EventQueue,
+    expected_changes: &ExpectedChanges,
+    events: &EventBus,
+) -> error::Result<()> {
+    // Removals still go through, so files indexed before the folder's
+    // options excluded them disappear from the index once deleted.
+    if is_excluded(folder, path, relative_path) {
+        println!(
+            "[EVENT_QUEUE] {}Ignoring excluded file: {:?}",
+            trace::prefix(),
+            path
+        );
+        return Ok(());
+    }
+    if !path.is_file() {
+        println!(
+            "[EVENT_QUEUE] {}Ignoring non-file event: {:?}",
+            trace::prefix(),
+            path
+        );
+        return Ok(());
+    }
+
+    let metadata = path
+        .metadata()
+        .map_err(|e| SyncError::io("failed to read metadata of", path, e))?;
+    let file_size = metadata.len();
+    let modified_secs = modified_secs(&metadata);
+    let scanned = queue.take_scanned(path);
+    queue.set_stage(if scanned.is_some() {
+        "indexing"
+    } else {
+        "hashing"
+    });
+    // The scan's hash is only used while the file is unchanged since.
+    let hash = match scanned {
+        Some(scanned) if scanned.size == file_size && scanned.modified_secs == modified_secs => {
+            scanned.hash
+        }
+        _ => calculate_hash(path)
+            .map_err(|e| SyncError::io("failed to calculate hash of", path, e))?,
+    };
+
+    if expected_changes.is_expected(path, Some(&hash)) {
+        println!(
+            "[EVENT_QUEUE] {}Skipping change made by sync: {:?}",
+            trace::prefix(),
+            path
+        );
+        return Ok(());
+    }
+
+    let change = IndexChange {
+        folder,
+        path,
+        relative_path,
+        size: file_size,
+        hash: &hash,
+    };
+    if queue.hooks().before_index(&change) == Verdict::Veto {
+        println!(
+            "[EVENT_QUEUE] {}Not indexing {:?}: vetoed by a hook",
+            trace::prefix(),
+            path
+        );
+        return Ok(());
+    }
+
+    if file_size > 0
+        && db.get_file_version(folder.id, relative_path)?.is_none()
+        && let Some((source, from)) = find_move_source(db, &hash, folder, relative_path)?
+    {
+        // Keeps the version history, so peers can move their copy too.
+        db.move_file_entry(source.id, &from, folder.id, relative_path)?;
+        if source.id == folder.id {
+            let change = LocalChange::Renamed { from: from.clone() };
+            hold_for_push(db, folder, relative_path, change)?;
+            println!(
+                "[EVENT_QUEUE] {}Renamed by hash: {:?} -> {:?}",
+                trace::prefix(),
+                from,
+                path
+            );
+            events.publish(SyncEventKind::FileRenamed {
+                folder_id: folder.id,
+                from,
+                path: relative_path.to_path_buf(),
+            });
+        } else {
+            hold_for_push(db, &source, &from, LocalChange::Removed)?;
+            hold_for_push(db, folder, relative_path, LocalChange::Modified)?;
+            println!(
+                "[EVENT_QUEUE] {}Moved from folder {}: {:?} -> {:?}",
+                trace::prefix(),
+                source.name,
+                from,
+                path
+            );
+            events.publish(SyncEventKind::FileMoved {
+                from_folder_id: source.id,
+                from,
+                folder_id: folder.id,
+                path: relative_path.to_path_buf(),
+            });
+        }
+    }
+
+    let changed = db
+        .get_file_version(folder.id, relative_path)?
+        .is_none_or(|(indexed, _)| indexed.as_deref() != Some(hash.as_str()));
+    let used_before = quota_usage(db, folder)?;
+    db.upsert_file_record(folder.id, relative_path, file_size, &hash, modified_secs)?;
+    println!(
+        "[EVENT_QUEUE] {}Indexed {:?} ({} bytes, hash {})",
+        trace::prefix(),
+        path,
+        file_size,
+        hash.get(..12).unwrap_or(&hash)
+    );
+    if changed {
+        hold_for_push(db, folder, relative_path, LocalChange::Modified)?;
+    }
+    db.clear_fetch_request(folder.id, relative_path)?;
+    if let (Some(before), Some(after)) = (used_before, quota_usage(db, folder)?)
+        && !folder.is_over_quota(before)
+        && folder.is_over_quota(after)
+    {
+        let quota_bytes = folder.quota_bytes.unwrap_or_default();
+        println!(
+            "[EVENT_QUEUE] {}Folder {} is over its quota ({} of {} bytes); receiving paused",
+            trace::prefix(),
+            folder.name,
+            after,
+            quota_bytes
+        );
+        events.publish(SyncEventKind::QuotaExceeded {
+            folder_id: folder.id,
+            used_bytes: after,
+            quota_bytes,
+        });
+    }
+    if folder.sync_xattrs {
+        match xattrs::read(path) {
+            Ok(attrs) => db.set_file_xattrs(folder.id, relative_path, &attrs)?,
+            Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+                println!(
+                    "[EVENT_QUEUE] {}No extended attribute support for {:?}",
+                    trace::prefix(),
+                    path
+                );
+            }
+            Err(e) => {
+                return Err(SyncError::io(
+                    "failed to read extended attributes of",
+                    path,
+                    e,
+                ));
             }
         }
-        _ => {
-            println!("[EVENT_QUEUE] Unhandled file event kind: {:?}", kind);
+    }
+    // Also clears a group carried over by a move from another folder.
+    match folder
+        .sync_hardlinks
+        .then(|| sync_engine::link_group(&metadata))
+    {
+        // Links of one inode have the same content, so they are found by hash.
+        Some(Some(_)) => {
+            let links: Vec<PathBuf> = db
+                .find_entries_by_hash(&hash)?
+                .into_iter()
+                .filter(|(folder_id, _)| *folder_id == folder.id)
+                .map(|(_, path)| path)
+                .collect();
+            refresh_link_groups(db, folder, &links)?;
         }
+        _ => db.set_file_link_group(folder.id, relative_path, None)?,
+    }
+    queue.hooks().after_index(&change, changed);
+    events.publish(SyncEventKind::FileIndexed {
+        folder_id: folder.id,
+        path: relative_path.to_path_buf(),
+    });
+    Ok(())
+}
+
+/// Finds the index entry a new file at `relative_path` was moved from: one
+/// with the same content whose file is gone. Moves between folders reach the
+/// two watchers separately, usually the create before the removal, as do
+/// renames the watcher could not pair.
+fn find_move_source(
+    db: &Database,
+    hash: &str,
+    folder: &SyncedFolder,
+    relative_path: &Path,
+) -> error::Result<Option<(SyncedFolder, PathBuf)>> {
+    for (folder_id, candidate) in db.find_entries_by_hash(hash)? {
+        if folder_id == folder.id && candidate == relative_path {
+            continue;
+        }
+        let Some(source) = db.get_folder_by_id(folder_id)? else {
+            continue;
+        };
+        // Unsynced files are missing on purpose, not moved.
+        if source.is_unsynced(&candidate) || source.local_path.join(&candidate).exists() {
+            continue;
+        }
+        return Ok(Some((source, candidate)));
+    }
+    Ok(None)
+}
+
+fn modified_secs(metadata: &std::fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .unwrap_or_else(|_| SystemTime::now())
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Bytes the folder's indexed files take up, or `None` when it has no quota.
+fn quota_usage(db: &Database, folder: &SyncedFolder) -> error::Result<Option<u64>> {
+    if folder.quota_bytes.is_none() {
+        return Ok(None);
     }
+    let (_, used_bytes) = db.get_folder_totals(folder.id)?;
+    Ok(Some(used_bytes))
 }
 
 async fn handle_folder_added_event(
     path: PathBuf,
-    db: &Arc<Mutex<database::Database>>,
+    db: &DbPool,
+    config: &Config,
     queue: &EventQueue,
-) {
+    events: &EventBus,
+) -> error::Result<()> {
     println!("[EVENT_QUEUE] Handling folder added event: {:?}", path);
 
-    let db_guard = db.lock().await;
+    let db_guard = db.write().await;
 
-    // 1. Add the folder to the database.
-    let folder_name = path.file_name().unwrap().to_str().unwrap();
-    if let Err(e) = db_guard.add_folder(folder_name, path.to_str().unwrap()) {
-        eprintln!("[HANDLER] DB Error adding folder {:?}: {}", path, e);
-        return;
+    // 1. Add the folder to the database, unless it was registered already
+    // (e.g. through the management API), in which case only scan it.
+    let path_str = error::path_str(&path)?;
+    let folder = match db_guard.get_folder_by_path(path_str)? {
+        Some(folder) => folder,
+        None => {
+            let folder_name = match path.file_name() {
+                Some(name) => error::path_str(name.as_ref())?,
+                None => path_str,
+            };
+            let folder_id = db_guard.add_folder(folder_name, path_str)?;
+            if let Some((name, profile)) = config.profile(None)? {
+                db_guard.apply_folder_profile(folder_id, name, profile)?;
+                println!("[EVENT_QUEUE] Applied profile {:?} to {:?}", name, path);
+            }
+            db_guard
+                .get_folder_by_id(folder_id)?
+                .ok_or(rusqlite::Error::QueryReturnedNoRows)?
+        }
+    };
+    let folder_id = folder.id;
+
+    // Resume an interrupted scan after the last file it processed, or record
+    // that a new one is starting.
+    let resume_after = db_guard.get_scan_checkpoint(folder_id)?;
+    match &resume_after {
+        Some(last_path) if !last_path.as_os_str().is_empty() => {
+            println!(
+                "[EVENT_QUEUE] Resuming scan of {:?} after {:?}",
+                path, last_path
+            );
+        }
+        Some(_) => {}
+        None => db_guard.set_scan_checkpoint(folder_id, Path::new(""))?,
     }
 
     drop(db_guard);
+    events.publish(SyncEventKind::FolderAdded {
+        folder_id,
+        path: path.clone(),
+    });
 
-    // 2. Scan the folder and add its files by sending events.
-    for entry in WalkDir::new(&path).into_iter().filter_map(Result::ok) {
+    // 2. Scan in a separate task: it feeds the queue this loop drains, so
+    // waiting for room in it here would never end once it fills up.
+    let resume_after = resume_after.unwrap_or_default();
+    let (db, queue, events) = (db.clone(), queue.clone(), events.clone());
+    tokio::spawn(async move {
+        if let Err(e) = scan_folder(path, folder, resume_after, &db, &queue).await {
+            report_error(e, &events, queue.hooks());
+        }
+    });
+    Ok(())
+}
+
+/// Queues every file of a folder, then the removals of indexed files that are
+/// gone, then [`QueueEvent::ScanFinished`]. Sorting makes the walk order match
+/// path ordering, so everything up to the checkpoint of an interrupted scan
+/// can be skipped, including whole directories that sort before it.
+///
+/// Files are hashed here, up to [`SyncedFolder::hash_parallelism`] at once,
+/// and queued in walk order as their hashes complete.
+async fn scan_folder(
+    path: PathBuf,
+    folder: SyncedFolder,
+    resume_after: PathBuf,
+    db: &DbPool,
+    queue: &EventQueue,
+) -> error::Result<()> {
+    let folder_id = folder.id;
+    // Left interrupted, so it starts again once the drive is back.
+    if !removable::is_mounted(&folder) {
+        println!(
+            "[EVENT_QUEUE] Drive of {} is not mounted; not scanning it",
+            folder.name
+        );
+        return Ok(());
+    }
+    let parallelism = folder.hash_parallelism();
+    let mut hashing = VecDeque::with_capacity(parallelism);
+    let mut queued = 0;
+    for entry in WalkDir::new(&path)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| {
+            let skip_hidden =
+                folder.ignore_hidden && e.depth() > 0 && ignore::is_hidden_entry(e.path());
+            let skip_ignored = e
+                .path()
+                .strip_prefix(&path)
+                .is_ok_and(|relative| ignore::matches_patterns(&folder.ignore_patterns, relative));
+            !skip_hidden
+                && !skip_ignored
+                && !ignore::is_internal_path(e.path())
+                && !is_before_checkpoint(e, &path, &resume_after)
+        })
+        .filter_map(Result::ok)
+    {
         if entry.file_type().is_file() {
-            queue
-                .send(QueueEvent::FileChanged {
-                    path: entry.path().to_path_buf(),
-                    kind: FsEventKind::Create,
-                })
+            let file_path = entry.path().to_path_buf();
+            let job_path = file_path.clone();
+            let settle = folder.settle_time();
+            // Files still being written are left to the event loop, which
+            // waits for them to settle before hashing.
+            let job = tokio::task::spawn_blocking(move || {
+                settle_remaining(&job_path, settle)
+                    .is_none()
+                    .then(|| ScannedFile::read(folder_id, &job_path).ok())
+                    .flatten()
+            });
+            hashing.push_back((file_path, job));
+            if hashing.len() >= parallelism
+                && let Some((file_path, job)) = hashing.pop_front()
+            {
+                queued += 1;
+                queue_scanned(
+                    queue,
+                    &path,
+                    file_path,
+                    job.await.ok().flatten(),
+                    queued,
+                    folder_id,
+                )
                 .await;
+            }
         }
     }
+    while let Some((file_path, job)) = hashing.pop_front() {
+        queued += 1;
+        queue_scanned(
+            queue,
+            &path,
+            file_path,
+            job.await.ok().flatten(),
+            queued,
+            folder_id,
+        )
+        .await;
+    }
+
+    // 3. Drop index entries for files deleted while nobody was watching,
+    // unless the drive was unplugged during the scan.
+    if !removable::is_mounted(&folder) {
+        println!(
+            "[EVENT_QUEUE] Drive of {} was unplugged during its scan",
+            folder.name
+        );
+        return Ok(());
+    }
+    let indexed = db.write().await.get_folders_and_files(folder_id, &path)?;
+    for file_path in indexed.into_keys().filter(|p| !p.exists()) {
+        queue
+            .send(QueueEvent::FileChanged {
+                path: file_path,
+                kind: FsEventKind::Remove,
+                trace: TraceId::new(),
+            })
+            .await;
+    }
+
+    queue.send(QueueEvent::ScanFinished { folder_id }).await;
+    Ok(())
+}
+
+/// Queues a scanned file, and a checkpoint every [`CHECKPOINT_INTERVAL`]
+/// files. A file that could not be read is queued without a hash, so the
+/// event loop reports the problem.
+async fn queue_scanned(
+    queue: &EventQueue,
+    root: &Path,
+    path: PathBuf,
+    scanned: Option<ScannedFile>,
+    queued: usize,
+    folder_id: i64,
+) {
+    if let Some(scanned) = scanned {
+        queue.remember_scanned(path.clone(), scanned);
+    }
+    let last_path = path.strip_prefix(root).map(Path::to_path_buf);
+    queue
+        .send(QueueEvent::FileChanged {
+            path,
+            kind: FsEventKind::Create,
+            trace: TraceId::new(),
+        })
+        .await;
+
+    if queued.is_multiple_of(CHECKPOINT_INTERVAL)
+        && let Ok(last_path) = last_path
+    {
+        queue
+            .send(QueueEvent::ScanCheckpoint {
+                folder_id,
+                last_path,
+            })
+            .await;
+    }
+}
+
+/// True for entries a resumed scan already covered: files up to and including
+/// `checkpoint`, and directories that sort entirely before it.
+fn is_before_checkpoint(entry: &walkdir::DirEntry, root: &Path, checkpoint: &Path) -> bool {
+    if checkpoint.as_os_str().is_empty() {
+        return false;
+    }
+    let Ok(relative) = entry.path().strip_prefix(root) else {
+        return false;
+    };
+
+    if entry.file_type().is_dir() {
+        relative < checkpoint && !checkpoint.starts_with(relative)
+    } else {
+        relative <= checkpoint
+    }
 }
 
 async fn handle_shutdown_event() {
@@ -0,0 +1,111 @@
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Serializer};
+use tokio::sync::broadcast;
+
+/// Something that happened in the sync pipeline, published for API clients
+/// and other observers.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SyncEventKind {
+    FileIndexed {
+        folder_id: i64,
+        path: PathBuf,
+    },
+    FileRemoved {
+        folder_id: i64,
+        path: PathBuf,
+    },
+    /// A file moved within its folder; `path` is the new location.
+    FileRenamed {
+        folder_id: i64,
+        from: PathBuf,
+        path: PathBuf,
+    },
+    /// A file moved to another folder; its index entry moved with it.
+    FileMoved {
+        from_folder_id: i64,
+        from: PathBuf,
+        folder_id: i64,
+        path: PathBuf,
+    },
+    FolderAdded {
+        folder_id: i64,
+        path: PathBuf,
+    },
+    /// Every change found by a folder scan has been processed.
+    FolderSynced {
+        folder_id: i64,
+    },
+    Conflict {
+        folder_id: i64,
+        path: PathBuf,
+    },
+    /// A folder grew past its quota; receiving data for it is paused.
+    QuotaExceeded {
+        folder_id: i64,
+        used_bytes: u64,
+        quota_bytes: u64,
+    },
+    /// The drive of a removable folder was unplugged; the folder is paused.
+    FolderOffline {
+        folder_id: i64,
+    },
+    /// The drive of a removable folder is back; it is being rescanned.
+    FolderOnline {
+        folder_id: i64,
+    },
+    PeerOffline {
+        device_id: String,
+        device_name: Option<String>,
+    },
+    Error {
+        path: Option<PathBuf>,
+        message: String,
+    },
+}
+
+/// Serializes as the flattened kind plus `timestamp_secs`, e.g.
+/// `{"kind":"file_indexed","folder_id":1,"path":"a.txt","timestamp_secs":...}`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncEvent {
+    #[serde(flatten)]
+    pub kind: SyncEventKind,
+    #[serde(rename = "timestamp_secs", serialize_with = "serialize_unix_secs")]
+    pub timestamp: SystemTime,
+}
+
+fn serialize_unix_secs<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    serializer.serialize_u64(secs)
+}
+
+/// Broadcast channel fanning sync events out to any number of subscribers.
+/// Slow subscribers miss events rather than blocking the pipeline.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<SyncEvent>,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    pub fn publish(&self, kind: SyncEventKind) {
+        // Sending only fails when nobody is subscribed.
+        let _ = self.sender.send(SyncEvent {
+            kind,
+            timestamp: SystemTime::now(),
+        });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<SyncEvent> {
+        self.sender.subscribe()
+    }
+}
@@ -1,60 +1,121 @@
 use crate::event_queue::{EventQueue, QueueEvent};
+use crate::ignore;
 use crate::sync_engine::FsEventKind;
-use notify::event::{ModifyKind, RenameMode};
+use crate::trace::TraceId;
+use notify::event::{CreateKind, ModifyKind, RenameMode};
 use notify::{
     Event, EventKind, RecommendedWatcher, RecursiveMode, Result as NotifyResult, Watcher,
 };
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
 
 /// Starts an async file watcher and forwards events to the event queue.
-pub async fn start_file_watcher(folder: PathBuf, event_queue: EventQueue) -> NotifyResult<()> {
-    // Use a tokio channel for async communication
-    let (tx, mut rx) = tokio::sync::mpsc::channel::<Event>(100);
+/// With a non-zero `debounce`, a path's events are held back until it has
+/// been quiet that long, and only the last one is forwarded. The folder is
+/// not watched while `maintenance` is set (see
+/// [`crate::control::SyncControl::set_maintenance`]).
+pub async fn start_file_watcher(
+    folder: PathBuf,
+    event_queue: EventQueue,
+    debounce: Duration,
+    mut maintenance: watch::Receiver<bool>,
+) -> NotifyResult<()> {
+    // Unbounded, so bursts (a checkout, an unpacked archive) are buffered
+    // in order instead of being dropped or blocking notify's thread while
+    // the event queue is full.
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<NotifyResult<Event>>();
 
-    // Get a handle to the current Tokio runtime
-    let handle = tokio::runtime::Handle::current();
-
-    // The watcher closure runs in notify's thread, but we use the runtime handle
+    // The watcher closure runs in notify's thread; sending never blocks.
     let mut watcher = RecommendedWatcher::new(
         move |res| {
-            if let Ok(event) = res {
-                let tx = tx.clone();
-                let handle = handle.clone();
-                // Spawn the async task using the runtime handle
-                handle.spawn(async move {
-                    let _ = tx.send(event).await;
-                });
-            }
+            let _ = tx.send(res);
         },
         notify::Config::default(),
     )?;
 
-    watcher.watch(&folder, RecursiveMode::Recursive)?;
-
-    println!("[WATCHER] Watching folder: {:?}", folder);
+    let mut watching = !*maintenance.borrow_and_update();
+    if watching {
+        watcher.watch(&folder, RecursiveMode::Recursive)?;
+        println!("[WATCHER] Watching folder: {:?}", folder);
+    } else {
+        println!("[WATCHER] Maintenance mode; not watching {:?} yet", folder);
+    }
+    event_queue.mark_watched(&folder);
 
+    let watch_folder = folder.clone();
     // Spawn a task to process file events
-    let processor_handle = tokio::spawn({
+    let mut processor_handle = tokio::spawn({
         let event_queue = event_queue.clone();
         async move {
-            while let Some(event) = rx.recv().await {
-                for path in event.paths {
-                    if let Some(q_event) = map_notify_event(path, &event.kind) {
-                        let queue = event_queue.clone();
-                        queue.send(q_event).await;
+            let mut buffer = EventBuffer::new(debounce);
+            loop {
+                let next_due = buffer.next_due();
+                let ready = tokio::select! {
+                    event = rx.recv() => match event {
+                        Some(Ok(event)) => buffer.push(event),
+                        // Counted in the folder's health, e.g. an overflowed kernel queue.
+                        Some(Err(e)) => vec![QueueEvent::WatcherError {
+                            path: folder.clone(),
+                            message: e.to_string(),
+                        }],
+                        None => break,
+                    },
+                    _ = tokio::time::sleep_until(next_due.unwrap_or_else(Instant::now)),
+                        if next_due.is_some() =>
+                    {
+                        buffer.take_due(Instant::now())
                     }
+                };
+                for q_event in ready {
+                    event_queue.recorder().record(&q_event);
+                    event_queue.send(q_event).await;
                 }
             }
         }
     });
 
-    // Keep the watcher alive by spawning a task that holds onto it
+    // Keep the watcher alive by spawning a task that holds onto it, and
+    // stop or restart watching as maintenance mode is entered or left.
     tokio::spawn(async move {
-        // This task holds the watcher and keeps it alive
-        let _watcher = watcher;
-
-        // Wait for the processor to finish (which should never happen normally)
-        let _ = processor_handle.await;
+        loop {
+            tokio::select! {
+                // Finishes only if the watcher's channel closes.
+                _ = &mut processor_handle => break,
+                changed = maintenance.changed() => {
+                    if changed.is_err() {
+                        let _ = (&mut processor_handle).await;
+                        break;
+                    }
+                }
+            }
+            let in_maintenance = *maintenance.borrow_and_update();
+            let result = match (in_maintenance, watching) {
+                (true, true) => watcher.unwatch(&watch_folder),
+                (false, false) => watcher.watch(&watch_folder, RecursiveMode::Recursive),
+                _ => continue,
+            };
+            match result {
+                Ok(()) => {
+                    watching = !in_maintenance;
+                    println!(
+                        "[WATCHER] {} folder: {:?}",
+                        if watching {
+                            "Watching"
+                        } else {
+                            "Stopped watching"
+                        },
+                        watch_folder
+                    );
+                }
+                Err(e) => eprintln!(
+                    "[WATCHER] Failed to update watch of {:?}: {}",
+                    watch_folder, e
+                ),
+            }
+        }
 
         println!("[WATCHER] File watcher stopped");
     });
@@ -62,13 +123,198 @@ pub async fn start_file_watcher(folder: PathBuf, event_queue: EventQueue) -> Not
     Ok(())
 }
 
+/// How long the source half of a rename waits for its destination before it
+/// is treated as a removal, i.e. the file was moved out of the watched tree.
+const RENAME_WINDOW: Duration = Duration::from_millis(200);
+
+/// Turns notify events into queue events: pairs the two halves of a rename
+/// and holds events back while their path settles.
+struct EventBuffer {
+    debounce: Duration,
+    /// Events waiting for their path to be quiet for `debounce`.
+    settling: HashMap<PathBuf, (QueueEvent, Instant)>,
+    /// Sources of renames waiting for their destination, keyed by tracker.
+    rename_sources: HashMap<usize, (PathBuf, Instant)>,
+}
+
+impl EventBuffer {
+    fn new(debounce: Duration) -> Self {
+        Self {
+            debounce,
+            settling: HashMap::new(),
+            rename_sources: HashMap::new(),
+        }
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        let settling = self.settling.values().map(|(_, due)| *due);
+        let renames = self.rename_sources.values().map(|(_, due)| *due);
+        settling.chain(renames).min()
+    }
+
+    /// Takes a notify event and returns the queue events that are ready now.
+    fn push(&mut self, event: Event) -> Vec<QueueEvent> {
+        let mut ready = Vec::new();
+        let tracker = event.tracker();
+
+        match (&event.kind, tracker, event.paths.as_slice()) {
+            // Wait for the matching destination to report a single rename.
+            (EventKind::Modify(ModifyKind::Name(RenameMode::From)), Some(tracker), [path]) => {
+                let due = Instant::now() + RENAME_WINDOW;
+                self.rename_sources.insert(tracker, (path.clone(), due));
+            }
+            // Reported again with both paths right after.
+            (EventKind::Modify(ModifyKind::Name(RenameMode::To)), Some(tracker), _)
+                if self.rename_sources.contains_key(&tracker) => {}
+            (EventKind::Modify(ModifyKind::Name(RenameMode::Both)), _, [from, to]) => {
+                if let Some(tracker) = tracker {
+                    self.rename_sources.remove(&tracker);
+                }
+                for (path, q_event) in map_rename(from.clone(), to.clone()) {
+                    self.submit(path, q_event, &mut ready);
+                }
+            }
+            _ => {
+                for path in event.paths {
+                    if let Some(q_event) = map_notify_event(path.clone(), &event.kind) {
+                        self.submit(path, q_event, &mut ready);
+                    }
+                }
+            }
+        }
+        ready
+    }
+
+    /// Returns the events whose wait is over at `now`.
+    fn take_due(&mut self, now: Instant) -> Vec<QueueEvent> {
+        let mut ready = Vec::new();
+
+        let unmatched: Vec<usize> = self
+            .rename_sources
+            .iter()
+            .filter(|(_, (_, due))| *due <= now)
+            .map(|(tracker, _)| *tracker)
+            .collect();
+        for tracker in unmatched {
+            if let Some((path, _)) = self.rename_sources.remove(&tracker)
+                && !ignore::is_internal_path(&path)
+            {
+                let q_event = QueueEvent::FileChanged {
+                    path: path.clone(),
+                    kind: FsEventKind::Remove,
+                    trace: TraceId::new(),
+                };
+                self.submit(path, q_event, &mut ready);
+            }
+        }
+
+        let settled: Vec<PathBuf> = self
+            .settling
+            .iter()
+            .filter(|(_, (_, due))| *due <= now)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in settled {
+            if let Some((q_event, _)) = self.settling.remove(&path) {
+                ready.push(q_event);
+            }
+        }
+        ready
+    }
+
+    fn submit(&mut self, path: PathBuf, q_event: QueueEvent, ready: &mut Vec<QueueEvent>) {
+        if self.debounce.is_zero() {
+            ready.push(q_event);
+        } else {
+            let due = Instant::now() + self.debounce;
+            // A metadata change must not replace a pending content change,
+            // or the content would never be hashed.
+            let q_event = match self.settling.remove(&path) {
+                Some((pending, _)) if is_metadata_only(&q_event) && changes_content(&pending) => {
+                    pending
+                }
+                _ => q_event,
+            };
+            self.settling.insert(path, (q_event, due));
+        }
+    }
+}
+
+fn is_metadata_only(q_event: &QueueEvent) -> bool {
+    matches!(
+        q_event,
+        QueueEvent::FileChanged {
+            kind: FsEventKind::Metadata,
+            ..
+        }
+    )
+}
+
+fn changes_content(q_event: &QueueEvent) -> bool {
+    matches!(
+        q_event,
+        QueueEvent::FileChanged {
+            kind: FsEventKind::Create | FsEventKind::Modify | FsEventKind::Rename { .. },
+            ..
+        }
+    )
+}
+
+/// Maps a rename with both paths known. Renamed files keep their index
+/// entry; moves into or out of sync_rs' own directories are a create or a
+/// removal, and renamed directories are rescanned.
+fn map_rename(from: PathBuf, to: PathBuf) -> Vec<(PathBuf, QueueEvent)> {
+    let removal = |path: PathBuf| {
+        let q_event = QueueEvent::FileChanged {
+            path: path.clone(),
+            kind: FsEventKind::Remove,
+            trace: TraceId::new(),
+        };
+        (path, q_event)
+    };
+
+    match (
+        ignore::is_internal_path(&from),
+        ignore::is_internal_path(&to),
+    ) {
+        (true, true) => Vec::new(),
+        (false, true) => vec![removal(from)],
+        (true, false) => map_notify_event(to.clone(), &EventKind::Create(CreateKind::Any))
+            .map(|q_event| vec![(to, q_event)])
+            .unwrap_or_default(),
+        (false, false) if to.is_dir() => {
+            vec![
+                removal(from),
+                (to.clone(), QueueEvent::FolderAdded { path: to }),
+            ]
+        }
+        (false, false) => {
+            let q_event = QueueEvent::FileChanged {
+                path: to.clone(),
+                kind: FsEventKind::Rename {
+                    old_path: from,
+                    new_path: to.clone(),
+                },
+                trace: TraceId::new(),
+            };
+            vec![(to, q_event)]
+        }
+    }
+}
+
 fn map_notify_event(path: PathBuf, kind: &EventKind) -> Option<QueueEvent> {
+    // Changes to sync_rs' own files would otherwise feed back into the queue.
+    if ignore::is_internal_path(&path) {
+        return None;
+    }
+
     match kind {
         EventKind::Modify(modify_kind) => match modify_kind {
             ModifyKind::Name(name_kind) => match name_kind {
                 RenameMode::From => Some(QueueEvent::FileChanged {
                     path,
                     kind: FsEventKind::Remove,
+                    trace: TraceId::new(),
                 }),
 
                 RenameMode::To => {
@@ -78,6 +324,7 @@ fn map_notify_event(path: PathBuf, kind: &EventKind) -> Option<QueueEvent> {
                         Some(QueueEvent::FileChanged {
                             path,
                             kind: FsEventKind::Create,
+                            trace: TraceId::new(),
                         })
                     }
                 }
@@ -85,12 +332,20 @@ fn map_notify_event(path: PathBuf, kind: &EventKind) -> Option<QueueEvent> {
                 _ => Some(QueueEvent::FileChanged {
                     path,
                     kind: FsEventKind::Modify,
+                    trace: TraceId::new(),
                 }),
             },
 
+            ModifyKind::Metadata(_) => Some(QueueEvent::FileChanged {
+                path,
+                kind: FsEventKind::Metadata,
+                trace: TraceId::new(),
+            }),
+
             _ => Some(QueueEvent::FileChanged {
                 path,
                 kind: FsEventKind::Modify,
+                trace: TraceId::new(),
             }),
         },
         EventKind::Create(_) => {
@@ -100,12 +355,14 @@ fn map_notify_event(path: PathBuf, kind: &EventKind) -> Option<QueueEvent> {
                 Some(QueueEvent::FileChanged {
                     path,
                     kind: FsEventKind::Create,
+                    trace: TraceId::new(),
                 })
             }
         }
         EventKind::Remove(_) => Some(QueueEvent::FileChanged {
             path,
             kind: FsEventKind::Remove,
+            trace: TraceId::new(),
         }),
         _ => None,
     }
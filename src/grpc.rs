@@ -0,0 +1,572 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+use crate::config::LiveConfig;
+use crate::conflicts::{self, Resolution};
+use crate::control::SyncControl;
+use crate::database::{
+    Database, FileTags, LocalChange, PendingChange, SyncedFolder, is_valid_tag_key,
+};
+use crate::db_pool::DbPool;
+use crate::event_queue::{EventQueue, QueueEvent};
+use crate::events::{self, EventBus, SyncEventKind};
+use crate::file_watcher;
+use crate::reload::Reloader;
+
+pub mod proto {
+    tonic::include_proto!("sync_rs");
+}
+
+use proto::sync_manager_server::{SyncManager, SyncManagerServer};
+
+/// Implementation of the `SyncManager` gRPC service defined in
+/// `proto/sync_rs.proto`.
+pub struct ManagementService {
+    db: DbPool,
+    queue: EventQueue,
+    events: EventBus,
+    config: Arc<LiveConfig>,
+    control: Arc<SyncControl>,
+    reloader: Arc<Reloader>,
+}
+
+impl ManagementService {
+    pub fn new(
+        db: DbPool,
+        queue: EventQueue,
+        events: EventBus,
+        config: Arc<LiveConfig>,
+        control: Arc<SyncControl>,
+        reloader: Arc<Reloader>,
+    ) -> Self {
+        Self {
+            db,
+            queue,
+            events,
+            config,
+            control,
+            reloader,
+        }
+    }
+
+    fn control_state(&self) -> proto::ControlState {
+        proto::ControlState {
+            paused: self.control.is_paused(),
+            maintenance: self.control.in_maintenance(),
+        }
+    }
+}
+
+pub async fn serve(
+    addr: SocketAddr,
+    service: ManagementService,
+) -> Result<(), tonic::transport::Error> {
+    println!("[GRPC] Listening on {}", addr);
+    tonic::transport::Server::builder()
+        .add_service(SyncManagerServer::new(service))
+        .serve(addr)
+        .await
+}
+
+fn db_error(e: rusqlite::Error) -> Status {
+    Status::internal(format!("database error: {}", e))
+}
+
+fn list_peers(db: &Database) -> Result<Vec<proto::Peer>, rusqlite::Error> {
+    db.get_peers()?
+        .into_iter()
+        .map(|peer| {
+            let folders = db
+                .get_peer_backlog(&peer.device_id)?
+                .into_iter()
+                .map(|backlog| proto::PeerFolder {
+                    folder_id: backlog.folder_id,
+                    acked_sequence: backlog.acked_sequence,
+                    last_synced_secs: backlog.last_synced_secs.unwrap_or_default(),
+                    files_behind: backlog.files,
+                    bytes_behind: backlog.bytes,
+                })
+                .collect();
+            Ok(proto::Peer {
+                device_id: peer.device_id,
+                device_name: peer.device_name.unwrap_or_default(),
+                connected: peer.connected,
+                last_seen_secs: peer.last_seen_secs.unwrap_or_default(),
+                folders,
+            })
+        })
+        .collect()
+}
+
+fn pending_changes_to_proto(changes: Vec<PendingChange>) -> proto::PendingChanges {
+    let changes = changes
+        .into_iter()
+        .map(|pending| {
+            let (kind, from_path) = match pending.change {
+                LocalChange::Modified => (proto::LocalChangeKind::Modified, String::new()),
+                LocalChange::Removed => (proto::LocalChangeKind::Removed, String::new()),
+                LocalChange::Renamed { from } => (
+                    proto::LocalChangeKind::Renamed,
+                    from.to_string_lossy().into_owned(),
+                ),
+            };
+            let mut change = proto::PendingChange {
+                path: pending.relative_path.to_string_lossy().into_owned(),
+                from_path,
+                detected_secs: pending.detected_secs as i64,
+                ..Default::default()
+            };
+            change.set_kind(kind);
+            change
+        })
+        .collect();
+    proto::PendingChanges { changes }
+}
+
+fn manual_push_folder(db: &Database, folder_id: i64) -> Result<SyncedFolder, Status> {
+    match db.get_folder_by_id(folder_id).map_err(db_error)? {
+        Some(folder) if folder.manual_push => Ok(folder),
+        Some(folder) => Err(Status::failed_precondition(format!(
+            "{} does not use manual push",
+            folder.name
+        ))),
+        None => Err(Status::not_found(format!(
+            "no folder with ID {}",
+            folder_id
+        ))),
+    }
+}
+
+fn not_indexed(path: &str) -> Status {
+    Status::not_found(format!("{} is not an indexed file of the folder", path))
+}
+
+fn folder_to_proto(db: &Database, folder: SyncedFolder) -> Result<proto::Folder, rusqlite::Error> {
+    let (file_count, total_bytes) = db.get_folder_totals(folder.id)?;
+    let stats = db.get_folder_stats(folder.id)?;
+    Ok(proto::Folder {
+        id: folder.id,
+        folder_uuid: folder.folder_uuid,
+        name: folder.name,
+        local_path: folder.local_path.to_string_lossy().into_owned(),
+        file_count,
+        total_bytes,
+        ignore_hidden: folder.ignore_hidden,
+        profile: folder.profile.unwrap_or_default(),
+        receive_only: folder.receive_only,
+        sync_xattrs: folder.sync_xattrs,
+        quota_bytes: folder.quota_bytes.unwrap_or_default(),
+        unsynced_paths: folder
+            .unsynced_paths
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect(),
+        placeholders: folder.placeholders,
+        locked_files: db
+            .get_locked_files(folder.id)?
+            .into_iter()
+            .map(|locked| locked.relative_path.to_string_lossy().into_owned())
+            .collect(),
+        events_processed: stats.events_processed,
+        failures: stats.failures,
+        last_failure: stats.last_failure.unwrap_or_default(),
+        verification_failures: stats.verification_failures,
+        audited_files: stats.audited_files,
+        audit_mismatches: stats.audit_mismatches,
+        last_audit_mismatch: stats.last_audit_mismatch.unwrap_or_default(),
+        manual_push: folder.manual_push,
+        pending_changes: db.get_pending_changes(folder.id)?.len() as u64,
+        mirror_of: folder.mirror_of.unwrap_or_default(),
+        removable: folder.removable,
+        last_scan_secs: stats.last_scan_secs.unwrap_or_default(),
+        last_watcher_error: stats.last_watcher_error.unwrap_or_default(),
+        observer: folder.observer,
+        hash_threads: folder.hash_threads as u32,
+        settle_secs: folder.settle_secs,
+        sync_hardlinks: folder.sync_hardlinks,
+    })
+}
+
+fn list_folders(db: &Database) -> Result<Vec<proto::Folder>, rusqlite::Error> {
+    db.get_all_synced_folders()?
+        .into_iter()
+        .map(|folder| folder_to_proto(db, folder))
+        .collect()
+}
+
+fn event_to_proto(event: events::SyncEvent) -> proto::SyncEvent {
+    let timestamp_secs = event
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+
+    let mut proto_event = proto::SyncEvent {
+        timestamp_secs,
+        ..Default::default()
+    };
+
+    match event.kind {
+        SyncEventKind::FileIndexed { folder_id, path } => {
+            proto_event.set_kind(proto::SyncEventKind::FileIndexed);
+            proto_event.folder_id = folder_id;
+            proto_event.path = path.to_string_lossy().into_owned();
+        }
+        SyncEventKind::FileRemoved { folder_id, path } => {
+            proto_event.set_kind(proto::SyncEventKind::FileRemoved);
+            proto_event.folder_id = folder_id;
+            proto_event.path = path.to_string_lossy().into_owned();
+        }
+        SyncEventKind::FileRenamed {
+            folder_id,
+            from,
+            path,
+        } => {
+            proto_event.set_kind(proto::SyncEventKind::FileRenamed);
+            proto_event.folder_id = folder_id;
+            proto_event.path = path.to_string_lossy().into_owned();
+            proto_event.old_path = from.to_string_lossy().into_owned();
+        }
+        SyncEventKind::FileMoved {
+            from_folder_id,
+            from,
+            folder_id,
+            path,
+        } => {
+            proto_event.set_kind(proto::SyncEventKind::FileMoved);
+            proto_event.folder_id = folder_id;
+            proto_event.path = path.to_string_lossy().into_owned();
+            proto_event.old_folder_id = from_folder_id;
+            proto_event.old_path = from.to_string_lossy().into_owned();
+        }
+        SyncEventKind::FolderAdded { folder_id, path } => {
+            proto_event.set_kind(proto::SyncEventKind::FolderAdded);
+            proto_event.folder_id = folder_id;
+            proto_event.path = path.to_string_lossy().into_owned();
+        }
+        SyncEventKind::FolderSynced { folder_id } => {
+            proto_event.set_kind(proto::SyncEventKind::FolderSynced);
+            proto_event.folder_id = folder_id;
+        }
+        SyncEventKind::Conflict { folder_id, path } => {
+            proto_event.set_kind(proto::SyncEventKind::Conflict);
+            proto_event.folder_id = folder_id;
+            proto_event.path = path.to_string_lossy().into_owned();
+        }
+        SyncEventKind::FolderOffline { folder_id } => {
+            proto_event.set_kind(proto::SyncEventKind::FolderOffline);
+            proto_event.folder_id = folder_id;
+        }
+        SyncEventKind::FolderOnline { folder_id } => {
+            proto_event.set_kind(proto::SyncEventKind::FolderOnline);
+            proto_event.folder_id = folder_id;
+        }
+        SyncEventKind::PeerOffline {
+            device_id,
+            device_name,
+        } => {
+            proto_event.set_kind(proto::SyncEventKind::PeerOffline);
+            proto_event.message = device_name.unwrap_or_default();
+            proto_event.device_id = device_id;
+        }
+        SyncEventKind::QuotaExceeded {
+            folder_id,
+            used_bytes,
+            quota_bytes,
+        } => {
+            proto_event.set_kind(proto::SyncEventKind::QuotaExceeded);
+            proto_event.folder_id = folder_id;
+            proto_event.message = format!("{} of {} bytes used", used_bytes, quota_bytes);
+        }
+        SyncEventKind::Error { path, message } => {
+            proto_event.set_kind(proto::SyncEventKind::Error);
+            proto_event.path = path
+                .map(|p| p.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            proto_event.message = message;
+        }
+    }
+
+    proto_event
+}
+
+#[tonic::async_trait]
+impl SyncManager for ManagementService {
+    type StreamEventsStream =
+        Pin<Box<dyn Stream<Item = Result<proto::SyncEvent, Status>> + Send + 'static>>;
+
+    async fn list_folders(
+        &self,
+        _request: Request<proto::ListFoldersRequest>,
+    ) -> Result<Response<proto::ListFoldersResponse>, Status> {
+        let db = self.db.read().await;
+        let folders = list_folders(&db).map_err(db_error)?;
+        Ok(Response::new(proto::ListFoldersResponse { folders }))
+    }
+
+    async fn add_folder(
+        &self,
+        request: Request<proto::AddFolderRequest>,
+    ) -> Result<Response<proto::Folder>, Status> {
+        let request = request.into_inner();
+
+        let path = std::fs::canonicalize(&request.local_path)
+            .map_err(|e| Status::invalid_argument(format!("{}: {}", request.local_path, e)))?;
+        if !path.is_dir() {
+            return Err(Status::invalid_argument(format!(
+                "{} is not a directory",
+                request.local_path
+            )));
+        }
+        let path_str = path
+            .to_str()
+            .ok_or_else(|| Status::invalid_argument("path is not valid UTF-8"))?;
+
+        let name = match request.name.as_str() {
+            "" => path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .ok_or_else(|| Status::invalid_argument("cannot derive a folder name"))?
+                .to_string(),
+            name => name.to_string(),
+        };
+
+        let config = self.config.get();
+        let profile = match request.profile.as_str() {
+            "" => config.profile(None),
+            profile => config.profile(Some(profile)),
+        }
+        .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+        let (folder, debounce) = {
+            let db = self.db.write().await;
+            if db.get_folder_by_path(path_str).map_err(db_error)?.is_some() {
+                return Err(Status::already_exists(format!(
+                    "{} is already synced",
+                    path_str
+                )));
+            }
+
+            let folder_id = match request.folder_uuid.as_str() {
+                "" => db.add_folder(&name, path_str),
+                folder_uuid => db.add_shared_folder(folder_uuid, &name, path_str),
+            }
+            .map_err(db_error)?;
+            if let Some((profile_name, profile)) = profile {
+                db.apply_folder_profile(folder_id, profile_name, profile)
+                    .map_err(db_error)?;
+            }
+
+            let folder = db
+                .get_folder_by_id(folder_id)
+                .map_err(db_error)?
+                .ok_or_else(|| Status::internal("folder vanished after insert"))?;
+            let debounce = folder.debounce();
+            (folder_to_proto(&db, folder).map_err(db_error)?, debounce)
+        };
+
+        file_watcher::start_file_watcher(
+            path.clone(),
+            self.queue.clone(),
+            debounce,
+            self.control.subscribe_maintenance(),
+        )
+        .await
+        .map_err(|e| Status::internal(format!("failed to watch folder: {}", e)))?;
+        self.queue.send(QueueEvent::FolderAdded { path }).await;
+
+        Ok(Response::new(folder))
+    }
+
+    async fn get_status(
+        &self,
+        _request: Request<proto::GetStatusRequest>,
+    ) -> Result<Response<proto::StatusResponse>, Status> {
+        let db = self.db.read().await;
+        Ok(Response::new(proto::StatusResponse {
+            device_id: db.get_or_create_device_id().map_err(db_error)?,
+            device_name: db.get_device_name().map_err(db_error)?.unwrap_or_default(),
+            folders: list_folders(&db).map_err(db_error)?,
+            queue_depth: self.queue.depth() as u64,
+            queue_capacity: self.queue.capacity() as u64,
+            peers: list_peers(&db).map_err(db_error)?,
+            current_event: self.queue.current().map(|event| proto::CurrentEvent {
+                path: event.path.to_string_lossy().into_owned(),
+                stage: event.stage.to_string(),
+                trace: event.trace.to_string(),
+                elapsed_secs: event.started.elapsed().as_secs(),
+            }),
+            paused: self.control.is_paused(),
+            maintenance: self.control.in_maintenance(),
+        }))
+    }
+
+    async fn stream_events(
+        &self,
+        _request: Request<proto::StreamEventsRequest>,
+    ) -> Result<Response<Self::StreamEventsStream>, Status> {
+        // Events missed by a lagging client are skipped rather than ending the stream.
+        let stream = BroadcastStream::new(self.events.subscribe())
+            .filter_map(|event| event.ok().map(|event| Ok(event_to_proto(event))));
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn get_file_tags(
+        &self,
+        request: Request<proto::GetFileTagsRequest>,
+    ) -> Result<Response<proto::FileTags>, Status> {
+        let request = request.into_inner();
+        let db = self.db.read().await;
+        let path = Path::new(&request.path);
+        if db
+            .get_file_version(request.folder_id, path)
+            .map_err(db_error)?
+            .is_none()
+        {
+            return Err(not_indexed(&request.path));
+        }
+        let tags = db
+            .get_file_tags(request.folder_id, path)
+            .map_err(db_error)?;
+        Ok(Response::new(proto::FileTags {
+            tags: tags.into_iter().collect(),
+        }))
+    }
+
+    async fn set_file_tags(
+        &self,
+        request: Request<proto::SetFileTagsRequest>,
+    ) -> Result<Response<proto::FileTags>, Status> {
+        let request = request.into_inner();
+        if let Some(key) = request.set.keys().find(|key| !is_valid_tag_key(key)) {
+            return Err(Status::invalid_argument(format!(
+                "invalid tag key {:?}",
+                key
+            )));
+        }
+        let set: FileTags = request.set.into_iter().collect();
+
+        let db = self.db.write().await;
+        let path = Path::new(&request.path);
+        if !db
+            .update_file_tags(request.folder_id, path, &set, &request.remove)
+            .map_err(db_error)?
+        {
+            return Err(not_indexed(&request.path));
+        }
+        let tags = db
+            .get_file_tags(request.folder_id, path)
+            .map_err(db_error)?;
+        Ok(Response::new(proto::FileTags {
+            tags: tags.into_iter().collect(),
+        }))
+    }
+
+    async fn list_pending_changes(
+        &self,
+        request: Request<proto::ListPendingChangesRequest>,
+    ) -> Result<Response<proto::PendingChanges>, Status> {
+        let request = request.into_inner();
+        let db = self.db.read().await;
+        let folder = manual_push_folder(&db, request.folder_id)?;
+        let pending = db.get_pending_changes(folder.id).map_err(db_error)?;
+        Ok(Response::new(pending_changes_to_proto(pending)))
+    }
+
+    /// Approves pending changes and returns them.
+    async fn push_changes(
+        &self,
+        request: Request<proto::PushChangesRequest>,
+    ) -> Result<Response<proto::PendingChanges>, Status> {
+        let request = request.into_inner();
+        let db = self.db.write().await;
+        let folder = manual_push_folder(&db, request.folder_id)?;
+        let paths: Vec<PathBuf> = request.paths.iter().map(PathBuf::from).collect();
+        let approved = db
+            .approve_pending_changes(folder.id, &paths)
+            .map_err(db_error)?;
+        println!(
+            "[GRPC] Approved {} pending change(s) in {}",
+            approved.len(),
+            folder.name
+        );
+        Ok(Response::new(pending_changes_to_proto(approved)))
+    }
+
+    async fn resolve_conflict(
+        &self,
+        request: Request<proto::ResolveConflictRequest>,
+    ) -> Result<Response<proto::ResolveConflictResponse>, Status> {
+        let request = request.into_inner();
+        let resolution = match request.keep() {
+            proto::Resolution::KeepLocal => Resolution::KeepLocal,
+            proto::Resolution::KeepRemote => Resolution::KeepRemote,
+            proto::Resolution::KeepBoth => Resolution::KeepBoth,
+            proto::Resolution::Unspecified => {
+                return Err(Status::invalid_argument("keep must be set"));
+            }
+        };
+
+        let db = self.db.write().await;
+        let conflict = db
+            .get_conflict(request.conflict_id)
+            .map_err(db_error)?
+            .ok_or_else(|| {
+                Status::not_found(format!("no conflict with ID {}", request.conflict_id))
+            })?;
+        conflicts::resolve(&db, &conflict, resolution)
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(proto::ResolveConflictResponse {}))
+    }
+
+    async fn pause(
+        &self,
+        _request: Request<proto::PauseRequest>,
+    ) -> Result<Response<proto::ControlState>, Status> {
+        self.control
+            .pause(&*self.db.write().await)
+            .map_err(db_error)?;
+        Ok(Response::new(self.control_state()))
+    }
+
+    async fn resume(
+        &self,
+        _request: Request<proto::ResumeRequest>,
+    ) -> Result<Response<proto::ControlState>, Status> {
+        self.control
+            .resume(&self.db, &self.queue)
+            .await
+            .map_err(db_error)?;
+        Ok(Response::new(self.control_state()))
+    }
+
+    async fn set_maintenance(
+        &self,
+        request: Request<proto::SetMaintenanceRequest>,
+    ) -> Result<Response<proto::ControlState>, Status> {
+        self.control
+            .set_maintenance(&self.db, &self.queue, request.into_inner().enabled)
+            .await
+            .map_err(db_error)?;
+        Ok(Response::new(self.control_state()))
+    }
+
+    async fn reload(
+        &self,
+        _request: Request<proto::ReloadRequest>,
+    ) -> Result<Response<proto::ReloadResponse>, Status> {
+        let changes = self
+            .reloader
+            .reload()
+            .await
+            .map_err(|e| Status::failed_precondition(e.to_string()))?;
+        Ok(Response::new(proto::ReloadResponse { changes }))
+    }
+}
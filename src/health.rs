@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::database::{Database, FolderStats};
+use crate::db_pool::DbPool;
+use crate::error::SyncError;
+use crate::transfers;
+
+/// How often changed counters are written to the database, where
+/// `sync_rs status` and the APIs read them.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Per-folder counters kept in memory by the daemon, so the event loop does
+/// not write to the database for every event it counts.
+#[derive(Debug, Default)]
+pub struct FolderHealth {
+    /// Counters by folder ID, with whether they changed since the last flush.
+    folders: Mutex<HashMap<i64, (FolderStats, bool)>>,
+    /// Bytes sent and received per peer and folder since the last flush.
+    transfers: Mutex<HashMap<(String, i64), (u64, u64)>>,
+}
+
+impl FolderHealth {
+    /// Continues from the counters stored by a previous run.
+    pub fn load(db: &Database) -> Result<Self, rusqlite::Error> {
+        let folders = db
+            .get_all_folder_stats()?
+            .into_iter()
+            .map(|(folder_id, stats)| (folder_id, (stats, false)))
+            .collect();
+        Ok(Self {
+            folders: Mutex::new(folders),
+            transfers: Mutex::default(),
+        })
+    }
+
+    /// Counts a processed event and, if it failed, the failure. Files that
+    /// vanished before they were read are not failures.
+    pub fn record_event(&self, folder_id: i64, error: Option<&SyncError>) {
+        self.update(folder_id, |stats| {
+            stats.events_processed += 1;
+            if let Some(error) = error.filter(|error| !error.is_not_found()) {
+                stats.failures += 1;
+                stats.last_failure = Some(error.to_string());
+            }
+        });
+    }
+
+    /// Counts a received file that was discarded because it failed
+    /// verification; it also counts as a failure.
+    pub fn record_verification_failure(&self, folder_id: i64, error: &SyncError) {
+        self.update(folder_id, |stats| {
+            stats.verification_failures += 1;
+            stats.failures += 1;
+            stats.last_failure = Some(error.to_string());
+        });
+    }
+
+    /// Counts file content sent to or received from a peer, for the
+    /// folder and for the peer's daily transfers (see [`crate::transfers`]).
+    pub fn record_transfer(&self, folder_id: i64, device_id: &str, sent: u64, received: u64) {
+        self.update(folder_id, |stats| {
+            stats.bytes_sent += sent;
+            stats.bytes_received += received;
+        });
+        let mut transfers = self.transfers.lock().unwrap();
+        let total = transfers
+            .entry((device_id.to_string(), folder_id))
+            .or_default();
+        total.0 += sent;
+        total.1 += received;
+    }
+
+    pub fn record_scan(&self, folder_id: i64) {
+        self.update(folder_id, |stats| stats.last_scan_secs = Some(unix_now()));
+    }
+
+    pub fn record_watcher_error(&self, folder_id: i64, message: String) {
+        self.update(folder_id, |stats| {
+            stats.last_watcher_error = Some(message);
+            stats.last_watcher_error_secs = Some(unix_now());
+        });
+    }
+
+    /// Counts a file re-verified by the spot audit and, if it no longer
+    /// matched the index, the mismatch.
+    pub fn record_audit(&self, folder_id: i64, mismatch: Option<String>) {
+        self.update(folder_id, |stats| {
+            stats.audited_files += 1;
+            if let Some(mismatch) = mismatch {
+                stats.audit_mismatches += 1;
+                stats.last_audit_mismatch = Some(mismatch);
+            }
+        });
+    }
+
+    fn update(&self, folder_id: i64, change: impl FnOnce(&mut FolderStats)) {
+        let mut folders = self.folders.lock().unwrap();
+        let (stats, dirty) = folders.entry(folder_id).or_default();
+        change(stats);
+        *dirty = true;
+    }
+
+    /// Writes the counters that changed since the last flush.
+    pub fn flush(&self, db: &Database) -> Result<(), rusqlite::Error> {
+        let changed: Vec<(i64, FolderStats)> = self
+            .folders
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .filter(|(_, (_, dirty))| *dirty)
+            .map(|(folder_id, (stats, dirty))| {
+                *dirty = false;
+                (*folder_id, stats.clone())
+            })
+            .collect();
+        for (folder_id, stats) in changed {
+            db.save_folder_stats(folder_id, &stats)?;
+        }
+        let transfers = std::mem::take(&mut *self.transfers.lock().unwrap());
+        if !transfers.is_empty() {
+            let today = transfers::today();
+            for ((device_id, folder_id), totals) in transfers {
+                db.add_transfer(&today, &device_id, folder_id, totals)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Flushes the counters every [`FLUSH_INTERVAL`].
+pub async fn run_flusher(health: Arc<FolderHealth>, db: DbPool) {
+    let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = health.flush(&*db.write().await) {
+            eprintln!("[HEALTH] Failed to save folder counters: {}", e);
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
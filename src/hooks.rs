@@ -0,0 +1,109 @@
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use crate::database::SyncedFolder;
+use crate::error::SyncError;
+use crate::event_queue::QueueEvent;
+
+/// Whether the event loop goes on with an event or file a hook was shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Continue,
+    /// Drop the event, or leave the file out of the index this time.
+    Veto,
+}
+
+/// A file the event loop has hashed and is about to index, or has indexed.
+#[derive(Debug, Clone, Copy)]
+pub struct IndexChange<'a> {
+    pub folder: &'a SyncedFolder,
+    pub path: &'a Path,
+    pub relative_path: &'a Path,
+    pub size: u64,
+    pub hash: &'a str,
+}
+
+/// Lets an application embedding the crate filter, rewrite or observe what
+/// the event loop does, e.g. for custom ignore rules or telemetry. Every
+/// method does nothing by default.
+///
+/// Hooks run on the event loop, the index ones while it holds the database
+/// writer, so they must return quickly; hand slow work to a task of your
+/// own through a channel.
+pub trait EventHook: Send + Sync {
+    /// Sees each file change and folder scan before it is handled, and may
+    /// change it in place. Vetoed events are dropped.
+    fn on_event(&self, _event: &mut QueueEvent) -> Verdict {
+        Verdict::Continue
+    }
+
+    /// Sees a changed file once it is hashed. A vetoed file keeps its
+    /// previous index entry, if any, until its next change.
+    fn before_index(&self, _file: &IndexChange<'_>) -> Verdict {
+        Verdict::Continue
+    }
+
+    /// Sees a file once it is indexed; `changed` is false when only its
+    /// metadata differed from the index.
+    fn after_index(&self, _file: &IndexChange<'_>, _changed: bool) {}
+
+    /// Sees every error the event loop reports, except files that vanished
+    /// before they were read.
+    fn on_error(&self, _error: &SyncError) {}
+}
+
+/// The hooks of an event queue, run in the order they were added. Shared
+/// by every clone of the queue (see [`crate::event_queue::EventQueue::hooks`]).
+#[derive(Clone, Default)]
+pub struct Hooks {
+    hooks: Arc<RwLock<Vec<Arc<dyn EventHook>>>>,
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Hooks({})", self.hooks.read().unwrap().len())
+    }
+}
+
+impl Hooks {
+    pub fn add(&self, hook: Arc<dyn EventHook>) {
+        self.hooks.write().unwrap().push(hook);
+    }
+
+    /// Runs [`EventHook::on_event`] until a hook vetoes the event.
+    pub fn on_event(&self, event: &mut QueueEvent) -> Verdict {
+        self.run(|hook| hook.on_event(event))
+    }
+
+    /// Runs [`EventHook::before_index`] until a hook vetoes the file.
+    pub fn before_index(&self, file: &IndexChange<'_>) -> Verdict {
+        self.run(|hook| hook.before_index(file))
+    }
+
+    pub fn after_index(&self, file: &IndexChange<'_>, changed: bool) {
+        for hook in self.snapshot() {
+            hook.after_index(file, changed);
+        }
+    }
+
+    pub fn on_error(&self, error: &SyncError) {
+        for hook in self.snapshot() {
+            hook.on_error(error);
+        }
+    }
+
+    fn run(&self, mut call: impl FnMut(&dyn EventHook) -> Verdict) -> Verdict {
+        for hook in self.snapshot() {
+            if call(hook.as_ref()) == Verdict::Veto {
+                return Verdict::Veto;
+            }
+        }
+        Verdict::Continue
+    }
+
+    /// The hooks as of now, so one may add another without a deadlock.
+    fn snapshot(&self) -> Vec<Arc<dyn EventHook>> {
+        self.hooks.read().unwrap().clone()
+    }
+}
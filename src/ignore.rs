@@ -0,0 +1,89 @@
+use std::path::{Component, Path};
+
+use crate::database::DB_PATH;
+use crate::instance_lock::LOCK_PATH;
+use crate::placeholder::PLACEHOLDER_SUFFIX;
+use crate::removable::MARKER_FILE;
+use crate::staging::STAGING_DIR;
+
+/// Directory holding previous versions of synced files (see
+/// [`crate::versions`]).
+pub const VERSIONS_DIR: &str = ".sync_versions";
+
+/// Directories owned by sync_rs that may live inside a synced folder.
+const INTERNAL_DIRS: &[&str] = &[STAGING_DIR, VERSIONS_DIR];
+
+/// Suffixes SQLite appends to the database path for its side files.
+const DB_SUFFIXES: &[&str] = &["", "-wal", "-shm", "-journal"];
+
+/// Returns true for files and directories created by sync_rs itself, which must
+/// never be watched or indexed to avoid feedback loops.
+pub fn is_internal_path(path: &Path) -> bool {
+    let in_internal_dir = path.components().any(
+        |c| matches!(c, Component::Normal(name) if INTERNAL_DIRS.iter().any(|dir| name == *dir)),
+    );
+    if in_internal_dir {
+        return true;
+    }
+
+    let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+    file_name == LOCK_PATH
+        || file_name == MARKER_FILE
+        || file_name.ends_with(PLACEHOLDER_SUFFIX)
+        || DB_SUFFIXES
+            .iter()
+            .any(|suffix| file_name.strip_suffix(suffix) == Some(DB_PATH))
+}
+
+/// Returns true if any file or directory between `folder_root` and `path` is
+/// hidden, so files inside hidden directories count as hidden too.
+pub fn is_hidden(folder_root: &Path, path: &Path) -> bool {
+    let Ok(relative) = path.strip_prefix(folder_root) else {
+        return false;
+    };
+    let mut current = folder_root.to_path_buf();
+    relative.components().any(|component| {
+        current.push(component);
+        is_hidden_entry(&current)
+    })
+}
+
+/// Returns true if the file or directory itself is hidden: its name starts
+/// with a dot on Unix, or it has the hidden attribute on Windows.
+#[cfg(not(windows))]
+pub fn is_hidden_entry(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.as_encoded_bytes().starts_with(b"."))
+}
+
+#[cfg(windows)]
+pub fn is_hidden_entry(path: &Path) -> bool {
+    use std::os::windows::fs::MetadataExt;
+
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    std::fs::symlink_metadata(path)
+        .is_ok_and(|meta| meta.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0)
+}
+
+/// Returns true if `relative_path` or one of its parent directories matches
+/// a folder's ignore patterns. Patterns without a `/` are matched against
+/// each path component (`node_modules`, `*.xmp`); patterns with one are
+/// matched against the path from the folder root (`build/*.o`).
+pub fn matches_patterns(patterns: &[String], relative_path: &Path) -> bool {
+    patterns.iter().any(|pattern| {
+        let Ok(glob) = glob::Pattern::new(pattern) else {
+            return false;
+        };
+        if pattern.contains('/') {
+            relative_path
+                .ancestors()
+                .any(|ancestor| glob.matches_path(ancestor))
+        } else {
+            relative_path.components().any(
+                |c| matches!(c, Component::Normal(name) if name.to_str().is_some_and(|name| glob.matches(name))),
+            )
+        }
+    })
+}
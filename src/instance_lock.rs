@@ -0,0 +1,149 @@
+use std::fmt;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{self, Read, Seek, Write};
+use std::time::{Duration, Instant};
+
+/// Lock file guarding the data directory, created next to the database.
+pub const LOCK_PATH: &str = "sync_rs.lock";
+
+/// How long `--takeover` waits for the previous instance to exit.
+const TAKEOVER_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum LockError {
+    AlreadyRunning { pid: Option<u32> },
+    Io(io::Error),
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockError::AlreadyRunning { pid: Some(pid) } => write!(
+                f,
+                "another sync_rs instance (pid {}) is already running in this data directory; \
+                 stop it or restart with --takeover",
+                pid
+            ),
+            LockError::AlreadyRunning { pid: None } => write!(
+                f,
+                "another sync_rs instance is already running in this data directory; \
+                 stop it or restart with --takeover"
+            ),
+            LockError::Io(e) => write!(f, "failed to acquire instance lock: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for LockError {}
+
+impl From<io::Error> for LockError {
+    fn from(e: io::Error) -> Self {
+        LockError::Io(e)
+    }
+}
+
+/// Exclusive lock held by the running daemon. Released when dropped or when
+/// the process exits.
+#[derive(Debug)]
+pub struct InstanceLock {
+    _file: File,
+}
+
+impl InstanceLock {
+    pub fn acquire() -> Result<Self, LockError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(LOCK_PATH)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                return Err(LockError::AlreadyRunning {
+                    pid: read_pid(&mut file),
+                });
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", std::process::id())?;
+        file.sync_all()?;
+
+        Ok(Self { _file: file })
+    }
+
+    /// Checks whether an instance holds the lock without taking it over or
+    /// recording a PID: `Err(AlreadyRunning)` while one does. A missing lock
+    /// file means no instance ever ran in this data directory.
+    pub fn probe() -> Result<(), LockError> {
+        let mut file = match File::open(LOCK_PATH) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        // Shared and released on return, so it never blocks the daemon for long.
+        match file.try_lock_shared() {
+            Ok(()) => Ok(()),
+            Err(TryLockError::WouldBlock) => Err(LockError::AlreadyRunning {
+                pid: read_pid(&mut file),
+            }),
+            Err(TryLockError::Error(e)) => Err(e.into()),
+        }
+    }
+
+    /// Asks the instance currently holding the lock to exit and takes the lock
+    /// over once it is released.
+    pub fn takeover() -> Result<Self, LockError> {
+        let pid = match Self::acquire() {
+            Err(LockError::AlreadyRunning { pid: Some(pid) }) => pid,
+            result => return result,
+        };
+
+        println!("[LOCK] Asking running instance (pid {}) to exit...", pid);
+        terminate_process(pid)?;
+
+        let deadline = Instant::now() + TAKEOVER_TIMEOUT;
+        loop {
+            match Self::acquire() {
+                Err(LockError::AlreadyRunning { .. }) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(200));
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Reads the PID recorded by the lock holder, if any.
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
+
+#[cfg(unix)]
+fn terminate_process(pid: u32) -> io::Result<()> {
+    // SAFETY: kill has no memory-safety preconditions.
+    let result = unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(windows)]
+fn terminate_process(pid: u32) -> io::Result<()> {
+    let status = std::process::Command::new("taskkill")
+        .args(["/F", "/PID", &pid.to_string()])
+        .status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("taskkill exited with {}", status)))
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::database::Database;
+use crate::error::{self, SyncError};
+use crate::sync_engine::calculate_hash;
+use crate::version_vector::VersionVector;
+
+/// A change received from a peer, journaled before it touches the disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Intent {
+    /// Create or overwrite a file with the given content and version.
+    Write {
+        relative_path: PathBuf,
+        hash: String,
+        version_vector: VersionVector,
+    },
+    Remove {
+        relative_path: PathBuf,
+    },
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+        hash: String,
+    },
+}
+
+/// Remote changes being applied to one folder. Every change is journaled
+/// before the first is applied; after applying one on disk (see
+/// [`crate::apply`]), [`Batch::complete`] updates the index to match. If
+/// the process dies in between, [`recover`] finds the journal on the next
+/// start and brings the index in line with what reached the disk.
+#[derive(Debug)]
+pub struct Batch {
+    id: i64,
+    folder_id: i64,
+    folder_root: PathBuf,
+    entries: Vec<(i64, Intent)>,
+}
+
+impl Batch {
+    pub fn begin(
+        db: &Database,
+        folder_id: i64,
+        folder_root: &Path,
+        intents: Vec<Intent>,
+    ) -> error::Result<Self> {
+        let (id, entry_ids) = db.begin_journal_batch(folder_id, &intents)?;
+        Ok(Self {
+            id,
+            folder_id,
+            folder_root: folder_root.to_path_buf(),
+            entries: entry_ids.into_iter().zip(intents).collect(),
+        })
+    }
+
+    pub fn intents(&self) -> impl Iterator<Item = &Intent> {
+        self.entries.iter().map(|(_, intent)| intent)
+    }
+
+    /// Updates the index for the change at `index` once it is on disk.
+    pub fn complete(&self, db: &Database, index: usize) -> error::Result<()> {
+        let (entry_id, intent) = &self.entries[index];
+        record_in_index(db, self.folder_id, &self.folder_root, intent)?;
+        db.complete_journal_entry(*entry_id)?;
+        Ok(())
+    }
+
+    /// Drops the batch from the journal, whether every change was applied
+    /// or the rest was abandoned.
+    pub fn finish(self, db: &Database) -> error::Result<()> {
+        db.finish_journal_batch(self.id)?;
+        Ok(())
+    }
+}
+
+/// Completes the batches a previous run left unfinished: changes that
+/// reached the disk are recorded in the index, the others are rolled back,
+/// which leaves the index untouched since it is only updated afterwards.
+/// Partial downloads are removed with the staging directories. Returns the
+/// number of changes completed.
+pub fn recover(db: &Database) -> error::Result<usize> {
+    let entries = db.get_pending_journal_entries()?;
+    let batches: BTreeSet<i64> = entries.iter().map(|(batch, ..)| *batch).collect();
+    let mut completed = 0;
+    for (_, folder_id, _, intent) in &entries {
+        let Some(folder) = db.get_folder_by_id(*folder_id)? else {
+            continue;
+        };
+        if applied_on_disk(&folder.local_path, intent) {
+            record_in_index(db, folder.id, &folder.local_path, intent)?;
+            completed += 1;
+        }
+    }
+    db.clear_journal()?;
+    if !batches.is_empty() {
+        println!(
+            "[JOURNAL] Recovered {} interrupted batch(es): {} change(s) completed, {} rolled back",
+            batches.len(),
+            completed,
+            entries.len() - completed
+        );
+    }
+    Ok(completed)
+}
+
+fn applied_on_disk(folder_root: &Path, intent: &Intent) -> bool {
+    let has_content = |path: &Path, hash: &str| {
+        calculate_hash(&folder_root.join(path)).is_ok_and(|actual| actual == hash)
+    };
+    match intent {
+        Intent::Write {
+            relative_path,
+            hash,
+            ..
+        } => has_content(relative_path, hash),
+        Intent::Remove { relative_path } => !folder_root.join(relative_path).exists(),
+        Intent::Rename { from, to, hash } => {
+            !folder_root.join(from).exists() && has_content(to, hash)
+        }
+    }
+}
+
+/// Brings the index in line with an applied change. Safe to repeat.
+fn record_in_index(
+    db: &Database,
+    folder_id: i64,
+    folder_root: &Path,
+    intent: &Intent,
+) -> error::Result<()> {
+    match intent {
+        Intent::Write {
+            relative_path,
+            hash,
+            version_vector,
+        } => {
+            let path = folder_root.join(relative_path);
+            let metadata = path
+                .metadata()
+                .map_err(|e| SyncError::io("failed to read metadata of", &path, e))?;
+            let modified_secs = metadata
+                .modified()
+                .unwrap_or_else(|_| SystemTime::now())
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            db.record_remote_file(
+                folder_id,
+                relative_path,
+                metadata.len(),
+                hash,
+                modified_secs,
+                version_vector,
+            )?;
+        }
+        Intent::Remove { relative_path } => db.remove_file_entry(folder_id, relative_path)?,
+        Intent::Rename { from, to, .. } => db.rename_file_entry(folder_id, from, to)?,
+    }
+    Ok(())
+}
@@ -0,0 +1,51 @@
+pub mod apply;
+pub mod audit;
+pub mod backup;
+pub mod bench;
+pub mod bep;
+pub mod compression;
+pub mod config;
+pub mod conflicts;
+pub mod control;
+pub mod database;
+pub mod db_pool;
+#[cfg(all(target_os = "linux", feature = "dbus"))]
+pub mod dbus;
+pub mod dedup;
+pub mod error;
+pub mod event_queue;
+pub mod events;
+pub mod file_watcher;
+pub mod grpc;
+pub mod health;
+pub mod hooks;
+pub mod ignore;
+pub mod instance_lock;
+pub mod journal;
+pub mod mirror;
+pub mod mqtt;
+pub mod output;
+pub mod placeholder;
+pub mod protocol;
+pub mod reload;
+pub mod relocate;
+pub mod removable;
+pub mod replay;
+pub mod sendfile;
+pub mod service;
+pub mod snapshot;
+pub mod sparse;
+pub mod staging;
+pub mod stats_history;
+pub mod suppression;
+pub mod sync_engine;
+pub mod syncthing;
+pub mod trace;
+pub mod transfers;
+pub mod verify;
+pub mod version_vector;
+pub mod versions;
+pub mod watchdog;
+pub mod web;
+pub mod webhooks;
+pub mod xattrs;
@@ -1,45 +1,263 @@
-mod database;
-use database::Database;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
-mod event_queue;
-use event_queue::EventQueue;
-use notify::Watcher;
+use clap::Parser;
 
-use std::{path::PathBuf, sync::Arc};
+use sync_rs::audit;
+use sync_rs::config::{self, Config, LiveConfig};
+use sync_rs::control::SyncControl;
+use sync_rs::database::Database;
+use sync_rs::db_pool::{self, DbPool};
+use sync_rs::error::{self, SyncError};
+use sync_rs::event_queue::{self, EventLoopContext, EventQueue, QueueEvent};
+use sync_rs::events::EventBus;
+use sync_rs::file_watcher;
+use sync_rs::grpc::{self, ManagementService};
+use sync_rs::health::{self, FolderHealth};
+use sync_rs::instance_lock::InstanceLock;
+use sync_rs::journal;
+use sync_rs::mirror;
+use sync_rs::mqtt::MqttSettings;
+use sync_rs::reload::Reloader;
+use sync_rs::removable;
+use sync_rs::replay;
+use sync_rs::staging;
+use sync_rs::stats_history;
+use sync_rs::suppression::ExpectedChanges;
+use sync_rs::versions;
+use sync_rs::watchdog;
+use sync_rs::web::{self, WebState};
+use sync_rs::webhooks;
 
-use tokio::sync::Mutex as TokioMutex;
+mod cli;
+mod commands;
 
-pub mod sync_engine;
-
-mod file_watcher;
+use cli::{Cli, Command, RunArgs};
 
 #[tokio::main]
 async fn main() {
-    let db = Arc::new(TokioMutex::new(
-        Database::new().expect("Failed to initialize database"),
-    ));
+    let cli = Cli::parse();
+
+    let result = match cli.command.unwrap_or(Command::Run(RunArgs::default())) {
+        Command::Run(args) => run_daemon(args).await,
+        Command::Config { action } => with_database(|db| commands::config::run(db, action)),
+        Command::Status { format } => with_database(|db| commands::status::run(db, format)),
+        Command::Webhooks { action } => with_database(|db| commands::webhooks::run(db, action)),
+        Command::Folders { action } => with_database(|db| commands::folders::run(db, action)),
+        Command::DedupReport { consolidate } => {
+            with_database(|db| commands::dedup_report::run(db, consolidate.map(Into::into)))
+        }
+        Command::MoveFolder(args) => with_database(|db| commands::move_folder::run(db, args)),
+        Command::Snapshot(args) => commands::snapshot::run(args),
+        Command::Replay(args) => commands::replay::run(args).await,
+        Command::Bench(args) => commands::bench::run(args).await,
+        Command::Backup {
+            folder,
+            target,
+            keep,
+        } => with_database(|db| commands::backup::run(db, &folder, &target, keep)),
+        Command::Versions { action } => with_database(|db| commands::versions::run(db, action)),
+        Command::Stats { action } => with_database(|db| commands::stats::run(db, action)),
+        Command::Conflicts { action } => with_database(|db| commands::conflicts::run(db, action)),
+        Command::Find(args) => with_database(|db| commands::find::run(db, args)),
+        Command::Tags { action } => with_database(|db| commands::tags::run(db, action)),
+        Command::Fetch { path } => with_database(|db| commands::fetch::run(db, &path)),
+        Command::Push(args) => with_database(|db| commands::push::run(db, args)),
+        Command::Pause(args) => commands::control::pause(args).await,
+        Command::Resume(args) => commands::control::resume(args).await,
+        Command::Maintenance(args) => commands::control::maintenance(args).await,
+        Command::Reload(args) => commands::control::reload(args).await,
+        Command::Service { action } => commands::service::run(action),
+        Command::ImportSyncthing(args) => {
+            with_database(|db| commands::import_syncthing::run(db, &args))
+        }
+        Command::Doctor(args) => {
+            if !commands::doctor::run(&args) {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Command::Completions { shell } => {
+            commands::completions::run(shell);
+            Ok(())
+        }
+        Command::Manpages { dir } => commands::manpages::run(&dir)
+            .map_err(|e| SyncError::io("failed to write man pages to", dir, e)),
+    };
+
+    if let Err(e) = result {
+        eprintln!("[MAIN] Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Opens the database for a one-shot command.
+fn with_database<E>(command: impl FnOnce(&Database) -> Result<(), E>) -> error::Result<()>
+where
+    SyncError: From<E>,
+{
+    let db = Database::new()?;
+    Ok(command(&db)?)
+}
+
+async fn run_daemon(args: RunArgs) -> error::Result<()> {
+    // Services start in the system directory, away from the database.
+    #[cfg(windows)]
+    if let Some(dir) = &args.service_dir {
+        std::env::set_current_dir(dir)
+            .map_err(|e| SyncError::io("failed to change to data directory", dir, e))?;
+        sync_rs::service::connect_to_service_manager().map_err(|source| SyncError::Service {
+            action: "start",
+            source,
+        })?;
+    }
+
+    let _lock = if args.takeover {
+        InstanceLock::takeover()?
+    } else {
+        InstanceLock::acquire()?
+    };
+
+    let db = DbPool::open(db_pool::DEFAULT_READERS)?;
     println!("[MAIN] Database initialized successfully.");
 
-    let device_id = db
-        .lock()
-        .await
-        .get_or_create_device_id()
-        .expect("[MAIN] Failed to get or create device ID");
+    let (device_id, device_name, control) = {
+        let db_guard = db.write().await;
+        let device_id = db_guard.get_or_create_device_id()?;
+        let device_name = db_guard.get_device_name()?;
+        let control = SyncControl::load(&db_guard)?;
+        // Connections recorded as open belong to a previous run.
+        db_guard.disconnect_all_peers()?;
+        watchdog::clear_slow_event(&db_guard)?;
+        (device_id, device_name, Arc::new(control))
+    };
 
-    println!("[MAIN] Device ID: {}", device_id);
+    match &device_name {
+        Some(name) => println!("[MAIN] Device: {} ({})", name, device_id),
+        None => println!("[MAIN] Device ID: {}", device_id),
+    }
+    let device_label = device_name.unwrap_or_else(|| device_id.clone());
+    if control.is_paused() {
+        println!("[MAIN] Sync is paused; resume it with `sync_rs resume --all`.");
+    }
 
-    let (queue, receiver) = EventQueue::new(100);
+    cleanup_partial_transfers(&db).await;
+    journal::recover(&*db.write().await)?;
+
+    let config = Arc::new(LiveConfig::new(Config::load()?));
+    let config_now = config.get();
+    if !config_now.profiles.is_empty() {
+        println!(
+            "[MAIN] Loaded {} folder profile(s) from {}",
+            config_now.profiles.len(),
+            config::CONFIG_PATH
+        );
+    }
+
+    let (queue, receiver) = EventQueue::new(config_now.queues.event_queue);
+    if let Err(e) = watchdog::spawn(queue.clone(), config.clone()) {
+        eprintln!("[MAIN] Failed to start the watchdog: {}", e);
+    }
+    let expected_changes = Arc::new(ExpectedChanges::default());
+    let events = EventBus::new(config_now.queues.event_bus);
+
+    let health = Arc::new(FolderHealth::load(&*db.read().await)?);
+    tokio::spawn(health::run_flusher(health.clone(), db.clone()));
+    tokio::spawn(stats_history::run(db.clone()));
+    tokio::spawn(replay::run(queue.recorder().clone(), db.clone()));
 
     let event_loop_handle = tokio::spawn(event_queue::start_event_loop(
         receiver,
+        EventLoopContext {
+            db: db.clone(),
+            queue: queue.clone(),
+            expected_changes: expected_changes.clone(),
+            events: events.clone(),
+            control: control.clone(),
+            config: config.clone(),
+            health: health.clone(),
+        },
+    ));
+
+    let test_folder = start_test_folder()?;
+    file_watcher::start_file_watcher(
+        test_folder.clone(),
+        queue.clone(),
+        Duration::ZERO,
+        control.subscribe_maintenance(),
+    )
+    .await?;
+    watch_registered_folders(&db, &queue, &control, &test_folder).await;
+    if control.in_maintenance() {
+        println!("[MAIN] In maintenance mode; leave it with `sync_rs maintenance off`.");
+    } else {
+        resume_interrupted_scans(&db, &queue).await;
+    }
+
+    let reloader =
+        Arc::new(Reloader::new(db.clone(), queue.clone(), control.clone(), config.clone()).await?);
+    #[cfg(unix)]
+    tokio::spawn(sync_rs::reload::run_on_sighup(reloader.clone()));
+
+    let management = ManagementService::new(
         db.clone(),
         queue.clone(),
+        events.clone(),
+        config.clone(),
+        control.clone(),
+        reloader,
+    );
+    tokio::spawn(async move {
+        if let Err(e) = grpc::serve(args.grpc_listen, management).await {
+            eprintln!("[MAIN] gRPC server error: {}", e);
+        }
+    });
+
+    tokio::spawn(webhooks::run_dispatcher(db.clone(), events.clone()));
+    tokio::spawn(mirror::run(db.clone(), events.clone(), expected_changes));
+    tokio::spawn(removable::run(
+        db.clone(),
+        queue.clone(),
+        events.clone(),
+        control.clone(),
+    ));
+    tokio::spawn(versions::run(db.clone(), config.clone()));
+    tokio::spawn(audit::run(
+        db.clone(),
+        queue.clone(),
+        control.clone(),
+        config.clone(),
+        health,
     ));
 
-    let test_folder = start_test_folder();
-    file_watcher::start_file_watcher(test_folder, queue.clone())
-        .await
-        .expect("[MAIN] Failed to start file watcher");
+    let mqtt_settings = MqttSettings::load(&*db.read().await);
+    match mqtt_settings {
+        Ok(Some(settings)) => start_mqtt(&db, &events, settings, &device_id, &device_label),
+        Ok(None) => {}
+        Err(e) => eprintln!("[MAIN] Failed to load MQTT settings: {}", e),
+    }
+
+    #[cfg(all(target_os = "linux", feature = "dbus"))]
+    {
+        let interface =
+            sync_rs::dbus::DaemonInterface::new(db.clone(), queue.clone(), control.clone());
+        let events = events.clone();
+        tokio::spawn(async move {
+            if let Err(e) = sync_rs::dbus::serve(interface, events).await {
+                eprintln!("[MAIN] D-Bus interface unavailable: {}", e);
+            }
+        });
+    }
+
+    let web_state = WebState::new(db.clone(), queue.clone(), events, control);
+    tokio::spawn(async move {
+        if let Err(e) = web::serve(args.http_listen, web_state).await {
+            eprintln!("[MAIN] Web server error: {}", e);
+        }
+    });
 
     println!("[MAIN] File watcher started. Waiting for events... (Press Ctrl+C to exit)");
 
@@ -48,15 +266,137 @@ async fn main() {
     if let Err(e) = event_loop_handle.await {
         eprintln!("[MAIN] Event loop error: {:?}", e);
     }
+    Ok(())
+}
+
+/// Removes partial files left in staging directories by a previous run.
+async fn cleanup_partial_transfers(db: &DbPool) {
+    let folders = match db.read().await.get_all_synced_folders() {
+        Ok(folders) => folders,
+        Err(e) => {
+            eprintln!("[MAIN] Failed to list folders for staging cleanup: {}", e);
+            return;
+        }
+    };
+
+    for folder in folders {
+        match staging::cleanup_staging_dir(&folder.local_path) {
+            Ok(0) => {}
+            Ok(removed) => println!(
+                "[MAIN] Removed {} partial file(s) from {:?}",
+                removed,
+                staging::staging_dir(&folder.local_path)
+            ),
+            Err(e) => eprintln!(
+                "[MAIN] Failed to clean staging directory of {:?}: {}",
+                folder.local_path, e
+            ),
+        }
+    }
+}
+
+/// Starts watchers for folders registered in earlier runs. Folders inside the
+/// test folder are already covered by its recursive watcher.
+async fn watch_registered_folders(
+    db: &DbPool,
+    queue: &EventQueue,
+    control: &SyncControl,
+    test_folder: &Path,
+) {
+    let folders = match db.read().await.get_all_synced_folders() {
+        Ok(folders) => folders,
+        Err(e) => {
+            eprintln!("[MAIN] Failed to list synced folders: {}", e);
+            return;
+        }
+    };
+
+    for folder in folders {
+        // Removable folders are watched once their drive is mounted.
+        if folder.local_path.starts_with(test_folder)
+            || !folder.local_path.is_dir()
+            || !removable::is_mounted(&folder)
+        {
+            continue;
+        }
+        let watched = file_watcher::start_file_watcher(
+            folder.local_path.clone(),
+            queue.clone(),
+            folder.debounce(),
+            control.subscribe_maintenance(),
+        );
+        if let Err(e) = watched.await {
+            eprintln!("[MAIN] Failed to watch {:?}: {}", folder.local_path, e);
+        }
+    }
+}
+
+/// Restarts scans that were cut short by the previous run; they continue
+/// from their last checkpoint.
+async fn resume_interrupted_scans(db: &DbPool, queue: &EventQueue) {
+    let folders = match db.read().await.get_interrupted_scans() {
+        Ok(folders) => folders,
+        Err(e) => {
+            eprintln!("[MAIN] Failed to list interrupted scans: {}", e);
+            return;
+        }
+    };
+
+    for folder in folders {
+        if !folder.local_path.is_dir() || !removable::is_mounted(&folder) {
+            continue;
+        }
+        println!(
+            "[MAIN] Resuming interrupted scan of {:?}",
+            folder.local_path
+        );
+        queue
+            .send(QueueEvent::FolderAdded {
+                path: folder.local_path,
+            })
+            .await;
+    }
+}
+
+#[cfg(feature = "mqtt")]
+fn start_mqtt(
+    db: &DbPool,
+    events: &EventBus,
+    settings: MqttSettings,
+    device_id: &str,
+    device_label: &str,
+) {
+    tokio::spawn(sync_rs::mqtt::run_publisher(
+        db.clone(),
+        events.clone(),
+        settings,
+        device_id.to_string(),
+        device_label.to_string(),
+    ));
+}
+
+#[cfg(not(feature = "mqtt"))]
+fn start_mqtt(
+    _db: &DbPool,
+    _events: &EventBus,
+    settings: MqttSettings,
+    _device_id: &str,
+    _device_label: &str,
+) {
+    eprintln!(
+        "[MAIN] mqtt-url is set to {} but this build lacks the `mqtt` feature; not publishing.",
+        settings.url
+    );
 }
 
-fn start_test_folder() -> PathBuf {
+fn start_test_folder() -> error::Result<PathBuf> {
     let test_folder = PathBuf::from("test");
 
     if !test_folder.exists() {
-        std::fs::create_dir_all(&test_folder).expect("[MAIN] Failed to create test folder");
+        std::fs::create_dir_all(&test_folder)
+            .map_err(|e| SyncError::io("failed to create test folder", &test_folder, e))?;
         println!("[MAIN] Test folder created at: {:?}", test_folder);
     }
 
-    test_folder
+    Ok(test_folder)
 }
@@ -0,0 +1,303 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::apply::{self, IncomingFile};
+use crate::database::{Database, SyncedFolder};
+use crate::db_pool::DbPool;
+use crate::error;
+use crate::events::{EventBus, SyncEventKind};
+use crate::journal::{Batch, Intent};
+use crate::protocol::{INDEX_PAGE_SIZE, IndexEntry};
+use crate::removable;
+use crate::suppression::ExpectedChanges;
+use crate::xattrs;
+
+/// How long the mirror task waits for a burst of changes to end before
+/// copying them.
+const SETTLE_DELAY: Duration = Duration::from_secs(2);
+
+/// Keeps every mirror folder a copy of its source folder, without any
+/// networking: e.g. a folder on an external drive mirroring one on the
+/// internal disk. Both folders are indexed as usual; whenever either
+/// changes, the indexes are compared and the differences applied to the
+/// mirror like changes from a peer, through the journal. Changes made in
+/// the mirror itself are overwritten, since the source always wins.
+pub async fn run(db: DbPool, events: EventBus, expected_changes: Arc<ExpectedChanges>) {
+    let mut receiver = events.subscribe();
+    // Folders changed since the last pass; `None` for all of them, as at
+    // startup or after missing events.
+    let mut changed: Option<HashSet<i64>> = None;
+    let mut due = true;
+    loop {
+        if !due {
+            match receiver.recv().await {
+                Ok(event) => note_change(&mut changed, &event.kind),
+                Err(RecvError::Lagged(_)) => changed = None,
+                Err(RecvError::Closed) => return,
+            }
+            due = true;
+            continue;
+        }
+        match tokio::time::timeout(SETTLE_DELAY, receiver.recv()).await {
+            Ok(Ok(event)) => {
+                note_change(&mut changed, &event.kind);
+                continue;
+            }
+            Ok(Err(RecvError::Lagged(_))) => {
+                changed = None;
+                continue;
+            }
+            Ok(Err(RecvError::Closed)) => return,
+            Err(_) => {}
+        }
+
+        if let Err(e) = mirror_changed(&db, changed.as_ref(), &expected_changes).await {
+            eprintln!("[MIRROR] {}", e);
+        }
+        changed = Some(HashSet::new());
+        due = false;
+    }
+}
+
+fn note_change(changed: &mut Option<HashSet<i64>>, kind: &SyncEventKind) {
+    if let (Some(changed), Some(folder_id)) = (changed.as_mut(), changed_folder(kind)) {
+        changed.insert(folder_id);
+    }
+}
+
+fn changed_folder(kind: &SyncEventKind) -> Option<i64> {
+    match kind {
+        SyncEventKind::FileIndexed { folder_id, .. }
+        | SyncEventKind::FileRemoved { folder_id, .. }
+        | SyncEventKind::FileRenamed { folder_id, .. }
+        | SyncEventKind::FolderSynced { folder_id }
+        | SyncEventKind::FolderOnline { folder_id } => Some(*folder_id),
+        // Counts for the folder it left as well.
+        SyncEventKind::FileMoved { from_folder_id, .. } => Some(*from_folder_id),
+        _ => None,
+    }
+}
+
+/// Updates the mirrors whose source or mirror folder is in `changed`, or
+/// all of them when `changed` is `None`.
+async fn mirror_changed(
+    db: &DbPool,
+    changed: Option<&HashSet<i64>>,
+    expected_changes: &Arc<ExpectedChanges>,
+) -> error::Result<()> {
+    let pairs = {
+        let db = db.read().await;
+        let folders = db.get_all_synced_folders()?;
+        let mut pairs = Vec::new();
+        for target in &folders {
+            let Some(source_id) = target.mirror_of else {
+                continue;
+            };
+            if changed.is_some_and(|ids| !ids.contains(&source_id) && !ids.contains(&target.id)) {
+                continue;
+            }
+            // Nothing is copied to or from a drive that is not plugged in.
+            if let Some(source) = folders.iter().find(|folder| folder.id == source_id)
+                && removable::is_mounted(source)
+                && removable::is_mounted(target)
+            {
+                pairs.push((source.clone(), target.clone()));
+            }
+        }
+        pairs
+    };
+    for (source, target) in pairs {
+        mirror(db, &source, &target, expected_changes).await?;
+    }
+    Ok(())
+}
+
+/// Brings `target` in line with `source` in one pass. Returns the number
+/// of changes applied; changes that fail are logged and retried by the
+/// next pass.
+pub async fn mirror(
+    db: &DbPool,
+    source: &SyncedFolder,
+    target: &SyncedFolder,
+    expected_changes: &Arc<ExpectedChanges>,
+) -> error::Result<usize> {
+    let (source_index, intents) = {
+        let db = db.read().await;
+        let source_index = load_index(&db, source.id)?;
+        let intents = plan(source, &source_index, &load_index(&db, target.id)?);
+        (source_index, intents)
+    };
+    if intents.is_empty() {
+        return Ok(0);
+    }
+
+    let batch = Batch::begin(&*db.write().await, target.id, &target.local_path, intents)?;
+    let mut applied = 0;
+    for (index, intent) in batch.intents().enumerate() {
+        let modified_secs = match intent {
+            Intent::Write { relative_path, .. } => source_index
+                .get(relative_path)
+                .map_or(0, |entry| entry.modified_secs),
+            _ => 0,
+        };
+        let result = tokio::task::spawn_blocking({
+            let (source, target) = (source.clone(), target.clone());
+            let (intent, expected_changes) = (intent.clone(), expected_changes.clone());
+            move || apply_intent(&source, &target, &intent, modified_secs, &expected_changes)
+        })
+        .await
+        .unwrap_or_else(|e| Err(io::Error::other(e)));
+        match result {
+            Ok(()) => {
+                batch.complete(&*db.write().await, index)?;
+                applied += 1;
+            }
+            // The source file is gone and its index entry will follow.
+            Err(e)
+                if e.kind() == io::ErrorKind::NotFound
+                    && matches!(intent, Intent::Write { .. }) => {}
+            Err(e) => eprintln!(
+                "[MIRROR] Failed to apply {:?} to {}: {}",
+                intent, target.name, e
+            ),
+        }
+    }
+    batch.finish(&*db.write().await)?;
+    println!(
+        "[MIRROR] {} -> {}: {} change(s) applied",
+        source.name, target.name, applied
+    );
+    Ok(applied)
+}
+
+/// The folder's index by path, without entries of removed files.
+fn load_index(db: &Database, folder_id: i64) -> error::Result<HashMap<PathBuf, IndexEntry>> {
+    let mut index = HashMap::new();
+    let mut after_sequence = 0;
+    loop {
+        let page = db.get_index_page(folder_id, after_sequence, INDEX_PAGE_SIZE)?;
+        let Some(last) = page.last() else {
+            return Ok(index);
+        };
+        after_sequence = last.sequence;
+        for entry in page.into_iter().filter(|entry| !entry.deleted) {
+            index.insert(PathBuf::from(&entry.path), entry);
+        }
+    }
+}
+
+/// The changes that turn the mirror's index into the source's: renames for
+/// content that only moved, then writes, then removals. Files the source
+/// only knows from peers are left alone.
+fn plan(
+    source: &SyncedFolder,
+    source_index: &HashMap<PathBuf, IndexEntry>,
+    target_index: &HashMap<PathBuf, IndexEntry>,
+) -> Vec<Intent> {
+    let mut removed: HashMap<&str, Vec<&Path>> = HashMap::new();
+    for (path, entry) in target_index {
+        if !source_index.contains_key(path)
+            && let Some(hash) = &entry.sha256_hash
+        {
+            removed.entry(hash).or_default().push(path);
+        }
+    }
+
+    let mut renames = Vec::new();
+    let mut renamed = HashSet::new();
+    let mut writes = Vec::new();
+    for (path, entry) in source_index {
+        let Some(hash) = &entry.sha256_hash else {
+            continue;
+        };
+        if source.is_unsynced(path)
+            || target_index.get(path).and_then(|e| e.sha256_hash.as_ref()) == Some(hash)
+        {
+            continue;
+        }
+        let moved_from = (!target_index.contains_key(path))
+            .then(|| removed.get_mut(hash.as_str()).and_then(Vec::pop))
+            .flatten();
+        match moved_from {
+            Some(from) => {
+                renamed.insert(from);
+                renames.push(Intent::Rename {
+                    from: from.to_path_buf(),
+                    to: path.clone(),
+                    hash: hash.clone(),
+                });
+            }
+            None => writes.push(Intent::Write {
+                relative_path: path.clone(),
+                hash: hash.clone(),
+                version_vector: entry.version_vector.clone(),
+            }),
+        }
+    }
+    let removals = target_index
+        .keys()
+        .filter(|path| !source_index.contains_key(*path) && !renamed.contains(path.as_path()))
+        .map(|path| Intent::Remove {
+            relative_path: path.clone(),
+        });
+
+    renames.into_iter().chain(writes).chain(removals).collect()
+}
+
+fn apply_intent(
+    source: &SyncedFolder,
+    target: &SyncedFolder,
+    intent: &Intent,
+    modified_secs: u64,
+    expected_changes: &ExpectedChanges,
+) -> io::Result<()> {
+    match intent {
+        Intent::Write {
+            relative_path,
+            hash,
+            ..
+        } => {
+            let source_path = source.local_path.join(relative_path);
+            let attrs = if target.sync_xattrs {
+                xattrs::read(&source_path).ok()
+            } else {
+                None
+            };
+            let file = IncomingFile {
+                relative_path,
+                hash,
+                modified: UNIX_EPOCH + Duration::from_secs(modified_secs),
+                xattrs: attrs.as_ref(),
+            };
+            apply::copy_local_file(&target.local_path, file, &source_path, expected_changes)
+        }
+        Intent::Remove { relative_path } => {
+            apply::remove_local_file(&target.local_path, relative_path, expected_changes)
+        }
+        Intent::Rename { from, to, hash } => {
+            apply::rename_local_file(&target.local_path, from, to, hash, expected_changes)
+        }
+    }
+}
+
+/// Why `target` cannot mirror `source`, if it cannot: it must be another
+/// folder that neither contains nor is contained in it, and that `source`
+/// does not mirror already.
+pub fn mirror_conflict(source: &SyncedFolder, target: &SyncedFolder) -> Option<&'static str> {
+    if source.id == target.id {
+        Some("a folder cannot mirror itself")
+    } else if source.local_path.starts_with(&target.local_path)
+        || target.local_path.starts_with(&source.local_path)
+    {
+        Some("the folders are nested")
+    } else if source.mirror_of == Some(target.id) {
+        Some("the source mirrors this folder already")
+    } else {
+        None
+    }
+}
@@ -0,0 +1,169 @@
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::control::SyncControl;
+use crate::database::{
+    Conflict, Database, FolderStats, Peer, PeerBacklog, StatsHistoryEntry, TransferRecord,
+};
+use crate::transfers::PeerUsage;
+use crate::watchdog::{self, SlowEvent};
+
+// The JSON printed by `--format json` and served by the web API. Scripts
+// depend on it, so fields are only ever added, never renamed or removed.
+
+/// A synced folder with its options, size and health.
+#[derive(Debug, Clone, Serialize)]
+pub struct FolderOutput {
+    pub id: i64,
+    pub folder_uuid: String,
+    pub name: String,
+    pub local_path: String,
+    pub file_count: u64,
+    pub total_bytes: u64,
+    pub ignore_hidden: bool,
+    pub profile: Option<String>,
+    pub receive_only: bool,
+    pub sync_xattrs: bool,
+    pub quota_bytes: Option<u64>,
+    pub unsynced_paths: Vec<PathBuf>,
+    pub placeholders: bool,
+    pub observer: bool,
+    pub hash_threads: usize,
+    /// Seconds a file must go unmodified before it is indexed.
+    pub settle_secs: u64,
+    pub sync_hardlinks: bool,
+    pub manual_push: bool,
+    /// Local changes waiting for approval (see `/api/folders/{id}/pending`).
+    pub pending_changes: usize,
+    /// ID of the folder this one is a local mirror of.
+    pub mirror_of: Option<i64>,
+    pub removable: bool,
+    /// Files skipped because another process has them locked.
+    pub locked_files: Vec<PathBuf>,
+    pub health: FolderStats,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerOutput {
+    #[serde(flatten)]
+    pub peer: Peer,
+    /// How far behind the peer is on each folder it syncs.
+    pub folders: Vec<PeerBacklog>,
+}
+
+/// Output of `sync_rs status`.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusOutput {
+    pub device_id: String,
+    pub device_name: Option<String>,
+    pub paused: bool,
+    /// Watchers and transfers are stopped; see `sync_rs maintenance`.
+    pub maintenance: bool,
+    /// The event a running daemon has been stuck on, if any.
+    pub slow_event: Option<SlowEvent>,
+    pub folders: Vec<FolderOutput>,
+    pub peers: Vec<PeerOutput>,
+}
+
+/// An unresolved conflict, with the name of its folder.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConflictOutput {
+    #[serde(flatten)]
+    pub conflict: Conflict,
+    pub folder_name: Option<String>,
+}
+
+/// Output of `sync_rs stats history`, oldest day first.
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryOutput {
+    pub folder_id: i64,
+    pub folder_name: String,
+    pub days: Vec<StatsHistoryEntry>,
+}
+
+/// Output of `sync_rs stats transfers`.
+#[derive(Debug, Clone, Serialize)]
+pub struct TransfersOutput {
+    /// First day listed.
+    pub since: String,
+    pub transfers: Vec<TransferRecord>,
+    /// Each peer's usage since the start of the month.
+    pub month: Vec<PeerUsage>,
+}
+
+pub fn list_folders(db: &Database) -> Result<Vec<FolderOutput>, rusqlite::Error> {
+    db.get_all_synced_folders()?
+        .into_iter()
+        .map(|folder| {
+            let (file_count, total_bytes) = db.get_folder_totals(folder.id)?;
+            Ok(FolderOutput {
+                id: folder.id,
+                folder_uuid: folder.folder_uuid,
+                name: folder.name,
+                local_path: folder.local_path.to_string_lossy().into_owned(),
+                file_count,
+                total_bytes,
+                ignore_hidden: folder.ignore_hidden,
+                profile: folder.profile,
+                receive_only: folder.receive_only,
+                sync_xattrs: folder.sync_xattrs,
+                quota_bytes: folder.quota_bytes,
+                unsynced_paths: folder.unsynced_paths,
+                placeholders: folder.placeholders,
+                observer: folder.observer,
+                hash_threads: folder.hash_threads,
+                settle_secs: folder.settle_secs,
+                sync_hardlinks: folder.sync_hardlinks,
+                manual_push: folder.manual_push,
+                pending_changes: db.get_pending_changes(folder.id)?.len(),
+                mirror_of: folder.mirror_of,
+                removable: folder.removable,
+                locked_files: db
+                    .get_locked_files(folder.id)?
+                    .into_iter()
+                    .map(|locked| locked.relative_path)
+                    .collect(),
+                health: db.get_folder_stats(folder.id)?,
+            })
+        })
+        .collect()
+}
+
+pub fn list_peers(db: &Database) -> Result<Vec<PeerOutput>, rusqlite::Error> {
+    db.get_peers()?
+        .into_iter()
+        .map(|peer| {
+            let folders = db.get_peer_backlog(&peer.device_id)?;
+            Ok(PeerOutput { peer, folders })
+        })
+        .collect()
+}
+
+pub fn status(db: &Database) -> Result<StatusOutput, rusqlite::Error> {
+    let control = SyncControl::load(db)?;
+    Ok(StatusOutput {
+        device_id: db.get_or_create_device_id()?,
+        device_name: db.get_device_name()?,
+        paused: control.is_paused(),
+        maintenance: control.in_maintenance(),
+        slow_event: watchdog::load_slow_event(db)?,
+        folders: list_folders(db)?,
+        peers: list_peers(db)?,
+    })
+}
+
+pub fn list_conflicts(db: &Database) -> Result<Vec<ConflictOutput>, rusqlite::Error> {
+    db.get_conflicts()?
+        .into_iter()
+        .map(|conflict| {
+            let folder_name = db
+                .get_folder_by_id(conflict.folder_id)?
+                .map(|folder| folder.name);
+            Ok(ConflictOutput {
+                conflict,
+                folder_name,
+            })
+        })
+        .collect()
+}
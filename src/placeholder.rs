@@ -0,0 +1,97 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use filetime::FileTime;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::staging::StagedFile;
+
+/// Suffix that marks a placeholder stub next to the name of the file it
+/// stands in for, e.g. `video.mp4.syncrs-placeholder`.
+pub const PLACEHOLDER_SUFFIX: &str = ".syncrs-placeholder";
+
+/// Stands in for a file whose content is not downloaded. The stub gets the
+/// file's modification time, so listings still sort and show it sensibly.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Placeholder {
+    pub size: u64,
+    pub hash: String,
+    pub modified_secs: u64,
+}
+
+pub fn is_placeholder_path(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.ends_with(PLACEHOLDER_SUFFIX))
+}
+
+/// Path of the placeholder for the file at `path`.
+pub fn placeholder_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(PLACEHOLDER_SUFFIX);
+    PathBuf::from(name)
+}
+
+/// Path of the file a placeholder stands in for.
+pub fn target_path(placeholder: &Path) -> Option<PathBuf> {
+    let name = placeholder.file_name()?.to_str()?;
+    let target = name.strip_suffix(PLACEHOLDER_SUFFIX)?;
+    Some(placeholder.with_file_name(target))
+}
+
+/// Writes a placeholder for `relative_path`. Placeholders are internal
+/// files, so the watcher does not report them and nothing needs to be
+/// registered as an expected change.
+pub fn write(
+    folder_root: &Path,
+    relative_path: &Path,
+    placeholder: &Placeholder,
+) -> io::Result<()> {
+    let mut staged = StagedFile::create(folder_root)?;
+    serde_json::to_writer(&mut staged, placeholder)?;
+    let modified = UNIX_EPOCH + Duration::from_secs(placeholder.modified_secs);
+    filetime::set_file_mtime(staged.path(), FileTime::from_system_time(modified))?;
+    staged.persist(&placeholder_path(&folder_root.join(relative_path)))
+}
+
+/// Reads the placeholder at `path`, which may be given with or without the
+/// suffix. Returns `None` when there is no placeholder.
+pub fn read(path: &Path) -> io::Result<Option<Placeholder>> {
+    let path = if is_placeholder_path(path) {
+        path.to_path_buf()
+    } else {
+        placeholder_path(path)
+    };
+    match fs::read(&path) {
+        Ok(data) => serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Removes every placeholder under `folder_root` and returns how many.
+pub fn remove_all(folder_root: &Path) -> io::Result<usize> {
+    let mut removed = 0;
+    for entry in WalkDir::new(folder_root) {
+        let entry = entry?;
+        if entry.file_type().is_file() && is_placeholder_path(entry.path()) {
+            fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Removes the placeholder of a file whose content has just arrived.
+pub fn remove_for(path: &Path) -> io::Result<()> {
+    match fs::remove_file(placeholder_path(path)) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
@@ -0,0 +1,589 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::apply;
+use crate::compression::Encoding;
+use crate::database::{Database, SyncedFolder};
+use crate::db_pool::DbPool;
+use crate::error::SyncError;
+use crate::version_vector::VersionVector;
+
+/// Upper bound for a single framed message, to avoid allocating on garbage input.
+const MAX_MESSAGE_SIZE: u32 = 16 * 1024 * 1024;
+
+/// Clock differences above this are logged as a warning during the handshake.
+pub const CLOCK_SKEW_WARN_MS: i64 = 30_000;
+
+/// First message sent by both sides of a peer connection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hello {
+    pub device_id: String,
+    pub device_name: Option<String>,
+    pub client_version: String,
+    /// Sender's wall clock when the message was sent, in Unix milliseconds;
+    /// 0 from peers that predate it.
+    #[serde(default)]
+    pub sent_at_ms: i64,
+}
+
+impl Hello {
+    pub fn local(db: &Database) -> Result<Self, rusqlite::Error> {
+        Ok(Self {
+            device_id: db.get_or_create_device_id()?,
+            device_name: db.get_device_name()?,
+            client_version: env!("CARGO_PKG_VERSION").to_string(),
+            sent_at_ms: 0,
+        })
+    }
+
+    /// Name to show for the remote device in logs and status output.
+    pub fn display_name(&self) -> &str {
+        self.device_name.as_deref().unwrap_or(&self.device_id)
+    }
+}
+
+/// A folder offered to a peer, identified by its UUID rather than the local row ID.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SharedFolder {
+    pub folder_uuid: String,
+    pub name: String,
+    /// This device will not send changes for the folder, only receive them.
+    #[serde(default)]
+    pub receive_only: bool,
+    /// This device only wants the index of the folder, never file content.
+    #[serde(default)]
+    pub observer: bool,
+}
+
+/// Sent after the handshake to announce which folders this device shares.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FolderList {
+    pub folders: Vec<SharedFolder>,
+}
+
+impl FolderList {
+    pub fn local(db: &Database) -> Result<Self, rusqlite::Error> {
+        let folders = db
+            .get_all_synced_folders()?
+            .into_iter()
+            .map(|folder| SharedFolder {
+                folder_uuid: folder.folder_uuid,
+                name: folder.name,
+                receive_only: folder.receive_only || folder.observer,
+                observer: folder.observer,
+            })
+            .collect();
+        Ok(Self { folders })
+    }
+}
+
+/// Announces that a file moved from one shared folder to another. It is sent
+/// before the removal from the source folder, so a peer sharing both folders
+/// moves its own copy (see [`crate::apply::move_local_file`]) instead of
+/// deleting it and downloading it again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileMoved {
+    pub from_folder_uuid: String,
+    pub from_path: String,
+    pub folder_uuid: String,
+    pub path: String,
+    pub sha256_hash: String,
+}
+
+/// Index entries per [`IndexPage`]. Pages stay far below
+/// [`MAX_MESSAGE_SIZE`] even with long paths and large version vectors, and
+/// a lost connection only costs the page in flight.
+pub const INDEX_PAGE_SIZE: usize = 1000;
+
+/// One file of a folder's index as exchanged between peers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub path: String,
+    pub size_bytes: u64,
+    pub modified_secs: u64,
+    pub sha256_hash: Option<String>,
+    pub version_vector: VersionVector,
+    /// The sender's sequence number of the entry's last change.
+    pub sequence: u64,
+    /// The file was removed, or renamed away, at `sequence`.
+    #[serde(default)]
+    pub deleted: bool,
+}
+
+/// Asks a peer for its index of a shared folder, starting after
+/// `after_sequence`; 0 requests the whole index. Once the initial exchange
+/// is done, reconnecting only brings the changes made since.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexRequest {
+    pub folder_uuid: String,
+    /// Highest sequence number already received from the peer, which also
+    /// acknowledges every change up to it.
+    pub after_sequence: u64,
+}
+
+/// A page of a folder's index, in sequence order. Entries changed while the
+/// index is sent get a new sequence number and so arrive in a later page.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexPage {
+    pub folder_uuid: String,
+    pub entries: Vec<IndexEntry>,
+    /// Sequence number to resume after once this page is applied.
+    pub last_sequence: u64,
+    /// No more pages follow.
+    pub complete: bool,
+}
+
+/// Answers `device_id`'s [`IndexRequest`] for the folder with ID
+/// `folder_id` with [`IndexPage`]s of up to [`INDEX_PAGE_SIZE`] entries,
+/// after recording the request's sequence number as acknowledged. A
+/// database reader is only borrowed while a page is read, never while it is
+/// sent. Receive-only and observer folders announce nothing, so their
+/// answer is a single empty, complete page. Returns the number of entries
+/// sent.
+pub async fn send_index<W>(
+    writer: &mut W,
+    db: &DbPool,
+    device_id: &str,
+    folder_id: i64,
+    request: &IndexRequest,
+) -> io::Result<u64>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut after_sequence = request.after_sequence;
+    let announces = {
+        let db = db.write().await;
+        let folder = db
+            .get_folder_by_id(folder_id)
+            .map_err(io::Error::other)?
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("folder {} is not synced", folder_id),
+                )
+            })?;
+        if after_sequence
+            > db.get_folder_sequence(folder_id)
+                .map_err(io::Error::other)?
+        {
+            // Our index was rebuilt since the peer last synced, so its
+            // cursor means nothing here any more.
+            println!(
+                "[PROTOCOL] {} is ahead of our index of folder {}, sending all of it",
+                device_id, folder_id
+            );
+            after_sequence = 0;
+        } else {
+            db.ack_sequence(device_id, folder_id, after_sequence)
+                .map_err(io::Error::other)?;
+        }
+        !folder.receive_only && !folder.observer
+    };
+    if !announces {
+        let page = IndexPage {
+            folder_uuid: request.folder_uuid.clone(),
+            entries: Vec::new(),
+            last_sequence: after_sequence,
+            complete: true,
+        };
+        write_message(writer, &page).await?;
+        return Ok(0);
+    }
+    let mut sent = 0;
+    loop {
+        let entries = db
+            .read()
+            .await
+            .get_index_page(folder_id, after_sequence, INDEX_PAGE_SIZE)
+            .map_err(io::Error::other)?;
+        let complete = entries.len() < INDEX_PAGE_SIZE;
+        after_sequence = entries
+            .last()
+            .map_or(after_sequence, |entry| entry.sequence);
+        sent += entries.len() as u64;
+        let page = IndexPage {
+            folder_uuid: request.folder_uuid.clone(),
+            entries,
+            last_sequence: after_sequence,
+            complete,
+        };
+        write_message(writer, &page).await?;
+        if complete {
+            return Ok(sent);
+        }
+    }
+}
+
+/// Requests a peer's index of `folder` and receives it page by page. Each
+/// page is handed to `apply`, then its last sequence number is stored as
+/// the resume point (see [`Database::get_received_sequence`]), so an
+/// exchange cut off by a flaky link continues with the next page when the
+/// peer reconnects. Returns the number of entries received.
+pub async fn receive_index<S, F>(
+    stream: &mut S,
+    db: &DbPool,
+    device_id: &str,
+    folder: &SyncedFolder,
+    mut apply: F,
+) -> io::Result<u64>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    F: FnMut(&Database, &IndexPage) -> Result<(), SyncError>,
+{
+    let after_sequence = db
+        .read()
+        .await
+        .get_received_sequence(device_id, folder.id)
+        .map_err(io::Error::other)?;
+    if after_sequence > 0 {
+        println!(
+            "[PROTOCOL] Resuming index of {} from {} after sequence {}",
+            folder.name, device_id, after_sequence
+        );
+    }
+    let request = IndexRequest {
+        folder_uuid: folder.folder_uuid.clone(),
+        after_sequence,
+    };
+    write_message(stream, &request).await?;
+
+    let mut received = 0;
+    loop {
+        let page: IndexPage = read_message(stream).await?;
+        if page.folder_uuid != folder.folder_uuid {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("index page for unexpected folder {}", page.folder_uuid),
+            ));
+        }
+        {
+            let db = db.write().await;
+            apply(&db, &page).map_err(io::Error::other)?;
+            db.set_received_sequence(device_id, folder.id, page.last_sequence)
+                .map_err(io::Error::other)?;
+        }
+        received += page.entries.len() as u64;
+        if page.complete {
+            return Ok(received);
+        }
+    }
+}
+
+/// Files up to this size are fetched in batches (see [`SmallFilesRequest`])
+/// rather than with a request each, since for trees of thousands of tiny
+/// files, such as `node_modules`, round trips dominate the transfer time.
+pub const SMALL_FILE_SIZE: u64 = 64 * 1024;
+
+/// Most files per [`SmallFilesRequest`].
+pub const BATCH_MAX_FILES: usize = 1000;
+
+/// Most bytes of file content, before encoding, per [`SmallFilesRequest`].
+pub const BATCH_MAX_BYTES: u64 = 8 * 1024 * 1024;
+
+/// Asks a peer for the content of many small files of a folder at once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmallFilesRequest {
+    pub folder_uuid: String,
+    pub paths: Vec<String>,
+}
+
+/// One file of a [`SmallFilesBatch`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchedFile {
+    pub path: String,
+    pub encoding: Encoding,
+    /// Bytes of encoded content following the batch for this file.
+    pub length: u64,
+    /// The file could not be sent, e.g. because it was removed or grew
+    /// beyond [`SMALL_FILE_SIZE`] since it was indexed; it has no content
+    /// in the batch and is to be requested on its own.
+    pub missing: bool,
+}
+
+/// Answers a [`SmallFilesRequest`]. The files' encoded contents follow the
+/// message back to back, in the order of `files`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmallFilesBatch {
+    pub folder_uuid: String,
+    pub files: Vec<BatchedFile>,
+}
+
+/// Splits the files of `entries` worth fetching in batches into the paths of
+/// [`SmallFilesRequest`]s of up to [`BATCH_MAX_FILES`] files and
+/// [`BATCH_MAX_BYTES`]. Removed files and files larger than
+/// [`SMALL_FILE_SIZE`] are left out, for the caller to handle one by one.
+pub fn plan_small_file_batches<'a>(
+    entries: impl IntoIterator<Item = &'a IndexEntry>,
+) -> Vec<Vec<String>> {
+    let mut batches: Vec<Vec<String>> = Vec::new();
+    let mut batch_bytes = 0;
+    for entry in entries {
+        if entry.deleted || entry.size_bytes > SMALL_FILE_SIZE {
+            continue;
+        }
+        match batches.last_mut() {
+            Some(batch)
+                if batch.len() < BATCH_MAX_FILES
+                    && batch_bytes + entry.size_bytes <= BATCH_MAX_BYTES =>
+            {
+                batch.push(entry.path.clone());
+                batch_bytes += entry.size_bytes;
+            }
+            _ => {
+                batches.push(vec![entry.path.clone()]);
+                batch_bytes = entry.size_bytes;
+            }
+        }
+    }
+    batches
+}
+
+/// Answers a [`SmallFilesRequest`] for `folder` with a [`SmallFilesBatch`]
+/// and the files' contents, each compressed when that pays off. Files that
+/// are gone, grew beyond [`SMALL_FILE_SIZE`] or whose path leaves the folder
+/// are marked missing. Returns the number of files sent.
+pub async fn send_small_files<W>(
+    writer: &mut W,
+    folder: &SyncedFolder,
+    request: &SmallFilesRequest,
+) -> io::Result<usize>
+where
+    W: AsyncWrite + Unpin,
+{
+    if request.paths.len() > BATCH_MAX_FILES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("batch of {} files is too large", request.paths.len()),
+        ));
+    }
+    let root = folder.local_path.clone();
+    let paths = request.paths.clone();
+    let (files, contents) = tokio::task::spawn_blocking(move || {
+        let mut files = Vec::with_capacity(paths.len());
+        let mut contents = Vec::with_capacity(paths.len());
+        for path in paths {
+            match read_small_file(&root, &path) {
+                Some((encoding, content)) => {
+                    files.push(BatchedFile {
+                        path,
+                        encoding,
+                        length: content.len() as u64,
+                        missing: false,
+                    });
+                    contents.push(content);
+                }
+                None => files.push(BatchedFile {
+                    path,
+                    encoding: Encoding::Raw,
+                    length: 0,
+                    missing: true,
+                }),
+            }
+        }
+        (files, contents)
+    })
+    .await
+    .map_err(io::Error::other)?;
+
+    let sent = contents.len();
+    let batch = SmallFilesBatch {
+        folder_uuid: request.folder_uuid.clone(),
+        files,
+    };
+    write_message(writer, &batch).await?;
+    for content in &contents {
+        writer.write_all(content).await?;
+    }
+    writer.flush().await?;
+    Ok(sent)
+}
+
+/// Reads and encodes the small file at `relative_path`, or `None` if it
+/// cannot be sent in a batch.
+fn read_small_file(root: &Path, relative_path: &str) -> Option<(Encoding, Vec<u8>)> {
+    let path = apply::folder_path(root, Path::new(relative_path)).ok()?;
+    let mut data = Vec::new();
+    File::open(&path)
+        .ok()?
+        .take(SMALL_FILE_SIZE + 1)
+        .read_to_end(&mut data)
+        .ok()?;
+    if data.len() as u64 > SMALL_FILE_SIZE {
+        return None;
+    }
+    let encoding = Encoding::for_file(&path).ok()?;
+    let encoded = encoding.encode(&data).ok()?;
+    if encoding == Encoding::Raw || encoded.len() >= data.len() {
+        return Some((Encoding::Raw, data));
+    }
+    Some((encoding, encoded.into_owned()))
+}
+
+/// Requests the small files at `paths` of `folder` (see
+/// [`plan_small_file_batches`]) and hands each one's path and content to
+/// `apply`, which writes it, typically with
+/// [`crate::apply::write_remote_file`]. Returns the paths that were not
+/// received, because the peer marked them missing or `apply` failed, e.g.
+/// on a hash mismatch; they are to be requested on their own.
+pub async fn receive_small_files<S, F>(
+    stream: &mut S,
+    folder: &SyncedFolder,
+    paths: Vec<String>,
+    mut apply: F,
+) -> io::Result<Vec<String>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    F: FnMut(&str, &[u8]) -> io::Result<()>,
+{
+    let requested: HashSet<String> = paths.iter().cloned().collect();
+    let request = SmallFilesRequest {
+        folder_uuid: folder.folder_uuid.clone(),
+        paths,
+    };
+    write_message(stream, &request).await?;
+    let batch: SmallFilesBatch = read_message(stream).await?;
+    if batch.folder_uuid != folder.folder_uuid {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("batch for unexpected folder {}", batch.folder_uuid),
+        ));
+    }
+
+    let mut not_received = Vec::new();
+    for file in batch.files {
+        // Content follows for files not marked missing, so nothing else
+        // in the batch can be trusted either.
+        if !requested.contains(&file.path)
+            || apply::folder_path(&folder.local_path, Path::new(&file.path)).is_err()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("batch has unrequested file {:?}", file.path),
+            ));
+        }
+        if file.missing {
+            not_received.push(file.path);
+            continue;
+        }
+        // Encoded content is never much larger than the file.
+        if file.length > 2 * SMALL_FILE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is too large for a batch", file.path),
+            ));
+        }
+        let mut content = vec![0; file.length as usize];
+        stream.read_exact(&mut content).await?;
+        let result = file
+            .encoding
+            .decode(&content, SMALL_FILE_SIZE as usize)
+            .and_then(|data| apply(&file.path, &data));
+        if let Err(e) = result {
+            eprintln!(
+                "[PROTOCOL] Failed to apply batched file {} of {}: {}",
+                file.path, folder.name, e
+            );
+            not_received.push(file.path);
+        }
+    }
+    Ok(not_received)
+}
+
+/// Writes a length-prefixed JSON message.
+pub async fn write_message<W, T>(writer: &mut W, message: &T) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let payload = serde_json::to_vec(message)?;
+    let len = u32::try_from(payload.len())
+        .ok()
+        .filter(|len| *len <= MAX_MESSAGE_SIZE)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "message too large"))?;
+
+    writer.write_u32(len).await?;
+    writer.write_all(&payload).await?;
+    writer.flush().await
+}
+
+/// Reads a length-prefixed JSON message written by [`write_message`].
+pub async fn read_message<R, T>(reader: &mut R) -> io::Result<T>
+where
+    R: AsyncRead + Unpin,
+    T: for<'de> Deserialize<'de>,
+{
+    let len = reader.read_u32().await?;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message too large",
+        ));
+    }
+
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload).await?;
+    Ok(serde_json::from_slice(&payload)?)
+}
+
+/// Result of the handshake with a peer.
+#[derive(Debug, Clone)]
+pub struct Handshake {
+    pub remote: Hello,
+    /// Estimated offset of the peer's clock from ours in milliseconds,
+    /// positive when it is ahead; `None` if the peer did not send its time.
+    pub clock_skew_ms: Option<i64>,
+}
+
+/// Sends our [`Hello`] and waits for the peer's, estimating the clock skew
+/// between both devices. Both sides send at once, so the peer's timestamp is
+/// compared with the midpoint of our send and receive times.
+pub async fn exchange_hello<S>(stream: &mut S, local: &Hello) -> io::Result<Handshake>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let sent_at_ms = unix_millis();
+    let local = Hello {
+        sent_at_ms,
+        ..local.clone()
+    };
+    write_message(stream, &local).await?;
+    let remote: Hello = read_message(stream).await?;
+    let received_at_ms = unix_millis();
+
+    println!(
+        "[PROTOCOL] Handshake with {} (version {})",
+        remote.display_name(),
+        remote.client_version
+    );
+
+    let clock_skew_ms =
+        (remote.sent_at_ms > 0).then(|| remote.sent_at_ms - (sent_at_ms + received_at_ms) / 2);
+    if let Some(skew) = clock_skew_ms
+        && skew.abs() > CLOCK_SKEW_WARN_MS
+    {
+        eprintln!(
+            "[PROTOCOL] Clock of {} is {:.1}s {} ours; conflicts are decided by version vectors, \
+             but timestamps shown for its changes will be off",
+            remote.display_name(),
+            skew.abs() as f64 / 1000.0,
+            if skew > 0 { "ahead of" } else { "behind" }
+        );
+    }
+
+    Ok(Handshake {
+        remote,
+        clock_skew_ms,
+    })
+}
+
+fn unix_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
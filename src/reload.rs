@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::config::{CONFIG_PATH, Config, LiveConfig};
+use crate::control::SyncControl;
+use crate::database::SyncedFolder;
+use crate::db_pool::DbPool;
+use crate::error;
+use crate::event_queue::{EventQueue, QueueEvent};
+use crate::{file_watcher, removable};
+
+/// Applies changes without restarting the daemon, on SIGHUP or `sync_rs
+/// reload`: a new [`CONFIG_PATH`] replaces the running configuration, and
+/// folders registered in the database meanwhile, e.g. by `sync_rs
+/// import-syncthing`, are watched and scanned. Folders whose ignore
+/// settings changed are rescanned to index the files no longer ignored.
+pub struct Reloader {
+    db: DbPool,
+    queue: EventQueue,
+    control: Arc<SyncControl>,
+    config: Arc<LiveConfig>,
+    /// Ignore settings of each folder as of the last (re)load.
+    ignores: Mutex<HashMap<i64, (bool, Vec<String>)>>,
+}
+
+impl Reloader {
+    pub async fn new(
+        db: DbPool,
+        queue: EventQueue,
+        control: Arc<SyncControl>,
+        config: Arc<LiveConfig>,
+    ) -> Result<Self, rusqlite::Error> {
+        let ignores = db
+            .read()
+            .await
+            .get_all_synced_folders()?
+            .iter()
+            .map(|folder| (folder.id, ignore_settings(folder)))
+            .collect();
+        Ok(Self {
+            db,
+            queue,
+            control,
+            config,
+            ignores: Mutex::new(ignores),
+        })
+    }
+
+    /// Reloads and returns a line per change applied. An invalid
+    /// configuration file is rejected and the running one kept.
+    pub async fn reload(&self) -> error::Result<Vec<String>> {
+        let new_config = match Config::load() {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("[RELOAD] Keeping the running configuration: {}", e);
+                return Err(e);
+            }
+        };
+        let mut changes = config_changes(&self.config.get(), &new_config);
+        self.config.replace(new_config);
+
+        let folders = self.db.read().await.get_all_synced_folders()?;
+        let mut unwatched = Vec::new();
+        let mut rescan = Vec::new();
+        {
+            let mut ignores = self.ignores.lock().unwrap();
+            for folder in &folders {
+                let settings = ignore_settings(folder);
+                if !self.queue.is_watched(&folder.local_path) {
+                    if folder.local_path.is_dir() && removable::is_mounted(folder) {
+                        unwatched.push(folder);
+                    }
+                } else if ignores
+                    .get(&folder.id)
+                    .is_some_and(|known| *known != settings)
+                {
+                    rescan.push(folder);
+                }
+                ignores.insert(folder.id, settings);
+            }
+        }
+
+        for folder in unwatched {
+            let watched = file_watcher::start_file_watcher(
+                folder.local_path.clone(),
+                self.queue.clone(),
+                folder.debounce(),
+                self.control.subscribe_maintenance(),
+            );
+            match watched.await {
+                Ok(()) => changes.push(format!("watching new folder {}", folder.name)),
+                Err(e) => {
+                    changes.push(format!("failed to watch {}: {}", folder.name, e));
+                    continue;
+                }
+            }
+            self.queue
+                .send(QueueEvent::FolderAdded {
+                    path: folder.local_path.clone(),
+                })
+                .await;
+        }
+        for folder in rescan {
+            changes.push(format!(
+                "rescanning {}: its ignore settings changed",
+                folder.name
+            ));
+            self.queue
+                .send(QueueEvent::FolderAdded {
+                    path: folder.local_path.clone(),
+                })
+                .await;
+        }
+
+        if changes.is_empty() {
+            println!("[RELOAD] Nothing changed");
+        }
+        for change in &changes {
+            println!("[RELOAD] {}", change);
+        }
+        Ok(changes)
+    }
+}
+
+fn ignore_settings(folder: &SyncedFolder) -> (bool, Vec<String>) {
+    (folder.ignore_hidden, folder.ignore_patterns.clone())
+}
+
+/// Describes what differs between two configurations. Everything but the
+/// queue sizes is read where it is used, so it applies right away.
+fn config_changes(old: &Config, new: &Config) -> Vec<String> {
+    let mut changes = Vec::new();
+    if old.profiles != new.profiles || old.default_profile != new.default_profile {
+        changes.push(format!(
+            "folder profiles updated ({} defined)",
+            new.profiles.len()
+        ));
+    }
+    if old.versions != new.versions {
+        changes.push("version retention updated".to_string());
+    }
+    if old.conflicts != new.conflicts {
+        changes.push("conflict policy updated".to_string());
+    }
+    if old.transfers != new.transfers {
+        changes.push("transfer caps updated".to_string());
+    }
+    if old.audit != new.audit {
+        changes.push(match new.audit.files_per_hour {
+            0 => "spot audit off".to_string(),
+            files => format!("spot audit at {} file(s) per hour", files),
+        });
+    }
+    if old.slow_event_threshold() != new.slow_event_threshold() {
+        changes.push(match new.slow_event_threshold() {
+            Some(threshold) => format!("slow event watchdog at {}s", threshold.as_secs()),
+            None => "slow event watchdog off".to_string(),
+        });
+    }
+    if old.queues != new.queues {
+        changes.push(format!(
+            "queue sizes changed in {}; they apply after a restart",
+            CONFIG_PATH
+        ));
+    }
+    changes
+}
+
+/// Reloads on every SIGHUP.
+#[cfg(unix)]
+pub async fn run_on_sighup(reloader: Arc<Reloader>) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            eprintln!("[RELOAD] Cannot handle SIGHUP: {}", e);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        println!("[RELOAD] SIGHUP received, reloading");
+        // Failures are logged by reload.
+        let _ = reloader.reload().await;
+    }
+}
@@ -0,0 +1,139 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::database::{Database, SyncedFolder};
+use crate::error::{Result, SyncError, path_str};
+use crate::sync_engine::calculate_hash;
+
+/// Files whose content is hashed by default when checking a new location,
+/// on top of those whose modification time changed.
+pub const DEFAULT_SPOT_CHECKS: usize = 20;
+
+/// How the files at a folder's new location compare to its index.
+#[derive(Debug, Default)]
+pub struct RelocationCheck {
+    /// Indexed files found with the indexed size and modification time.
+    pub matched: usize,
+    /// Files whose modification time changed in the copy but whose content
+    /// still matches its hash, with their new time. The index takes it over,
+    /// so the next scan does not rehash them.
+    pub retimed: Vec<(PathBuf, u64, u64)>,
+    /// Matched files whose content was hashed as a spot check.
+    pub spot_checked: usize,
+    /// Indexed files missing at the new location. After the move the next
+    /// scan would delete them on every peer.
+    pub missing: Vec<PathBuf>,
+    /// Files whose size or content differs from the index. After the move
+    /// they are synced as local changes.
+    pub changed: Vec<PathBuf>,
+}
+
+impl RelocationCheck {
+    pub fn is_intact(&self) -> bool {
+        self.missing.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compares the index of `folder` with the files under `new_root`, where its
+/// data was copied or moved to. Sizes and modification times are compared
+/// for every file; content is hashed where the time changed, and for up to
+/// `spot_checks` files spread over the rest.
+pub fn check(
+    db: &Database,
+    folder: &SyncedFolder,
+    new_root: &Path,
+    spot_checks: usize,
+) -> Result<RelocationCheck> {
+    let mut result = RelocationCheck::default();
+    // Observer folders only index what peers have; there is nothing local.
+    if folder.observer {
+        return Ok(result);
+    }
+
+    let mut entries: Vec<_> = db
+        .get_folders_and_files(folder.id, new_root)?
+        .into_values()
+        .collect();
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut unchanged = Vec::new();
+    for entry in entries {
+        let Ok(relative) = entry.path.strip_prefix(new_root) else {
+            continue;
+        };
+        let relative = relative.to_path_buf();
+        if folder.is_unsynced(&relative) {
+            continue;
+        }
+        let metadata = match entry.path.symlink_metadata() {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                result.missing.push(relative);
+                continue;
+            }
+            Err(e) => return Err(SyncError::io("failed to read", &entry.path, e)),
+        };
+        if metadata.len() != entry.size {
+            result.changed.push(relative);
+            continue;
+        }
+
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_secs());
+        let indexed_modified = entry
+            .last_modified
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let Some(hash) = entry.hash else {
+            // Never hashed, e.g. locked at the last scan: the next scan
+            // hashes it anyway.
+            result.matched += 1;
+            continue;
+        };
+        if modified == indexed_modified {
+            result.matched += 1;
+            unchanged.push((entry.path, relative, hash));
+        } else if content_matches(&entry.path, &hash)? {
+            result.retimed.push((relative, entry.size, modified));
+        } else {
+            result.changed.push(relative);
+        }
+    }
+
+    // Evenly spread, so a copy that stopped halfway through is noticed.
+    if spot_checks > 0 && !unchanged.is_empty() {
+        let step = unchanged.len().div_ceil(spot_checks);
+        for (path, relative, hash) in unchanged.into_iter().step_by(step) {
+            result.spot_checked += 1;
+            if !content_matches(&path, &hash)? {
+                result.matched -= 1;
+                result.changed.push(relative);
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Points `folder` at `new_root` and records the new modification times of
+/// the files in `checked`. The daemon must not be running, as it would keep
+/// watching and scanning the old location.
+pub fn apply(
+    db: &Database,
+    folder: &SyncedFolder,
+    new_root: &Path,
+    checked: &RelocationCheck,
+) -> Result<()> {
+    for (relative, size, modified) in &checked.retimed {
+        db.update_file_modified(folder.id, relative, *size, *modified)?;
+    }
+    db.set_folder_local_path(folder.id, path_str(new_root)?)?;
+    Ok(())
+}
+
+fn content_matches(path: &Path, hash: &str) -> Result<bool> {
+    let actual = calculate_hash(path).map_err(|e| SyncError::io("failed to hash", path, e))?;
+    Ok(actual == hash)
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::control::SyncControl;
+use crate::database::SyncedFolder;
+use crate::db_pool::DbPool;
+use crate::event_queue::{EventQueue, QueueEvent};
+use crate::events::{EventBus, SyncEventKind};
+use crate::file_watcher;
+
+/// File in the root of a removable folder naming the folder it belongs to.
+/// An empty mount point, or another drive mounted in its place, lacks it.
+pub const MARKER_FILE: &str = ".sync_rs_volume";
+
+/// How often the daemon checks whether removable folders are mounted.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Marks the folder's current location as its drive, so it is recognized
+/// when the drive comes back.
+pub fn write_marker(folder: &SyncedFolder) -> io::Result<()> {
+    std::fs::write(
+        folder.local_path.join(MARKER_FILE),
+        format!("{}\n", folder.folder_uuid),
+    )
+}
+
+pub fn remove_marker(folder: &SyncedFolder) -> io::Result<()> {
+    match std::fs::remove_file(folder.local_path.join(MARKER_FILE)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// True unless the folder is removable and its drive is not mounted, i.e.
+/// its root lacks the marker written for it. Files of an unmounted folder
+/// look deleted, so scans and removal events must leave the index alone.
+pub fn is_mounted(folder: &SyncedFolder) -> bool {
+    if !folder.removable {
+        return true;
+    }
+    std::fs::read_to_string(folder.local_path.join(MARKER_FILE))
+        .is_ok_and(|marker| marker.trim() == folder.folder_uuid)
+}
+
+/// Watches removable folders come and go. A folder whose drive disappears
+/// is paused: its watcher sees nothing, and scans and removal events leave
+/// its index alone, so peers are not told to delete everything. When the
+/// drive is back, the folder is watched again and rescanned, which picks up
+/// whatever changed on it elsewhere.
+pub async fn run(db: DbPool, queue: EventQueue, events: EventBus, control: Arc<SyncControl>) {
+    // Whether each removable folder was mounted when last checked. Folders
+    // mounted when first seen were watched when they were registered or
+    // when the daemon started.
+    let mut mounted: HashMap<i64, bool> = HashMap::new();
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let folders = match db.read().await.get_all_synced_folders() {
+            Ok(folders) => folders,
+            Err(e) => {
+                eprintln!("[REMOVABLE] Failed to list folders: {}", e);
+                continue;
+            }
+        };
+        mounted.retain(|folder_id, _| folders.iter().any(|f| f.id == *folder_id && f.removable));
+        for folder in folders.into_iter().filter(|folder| folder.removable) {
+            let now = is_mounted(&folder);
+            let before = mounted.insert(folder.id, now);
+            check(&folder, before, now, &queue, &events, &control).await;
+        }
+    }
+}
+
+async fn check(
+    folder: &SyncedFolder,
+    was_mounted: Option<bool>,
+    mounted: bool,
+    queue: &EventQueue,
+    events: &EventBus,
+    control: &SyncControl,
+) {
+    match (was_mounted, mounted) {
+        (None, false) => println!("[REMOVABLE] {} is not mounted; paused", folder.name),
+        (Some(true), false) => {
+            println!(
+                "[REMOVABLE] {} is not mounted; pausing it until its drive is back",
+                folder.name
+            );
+            events.publish(SyncEventKind::FolderOffline {
+                folder_id: folder.id,
+            });
+        }
+        (Some(false), true) => {
+            println!("[REMOVABLE] {} is mounted again; rescanning", folder.name);
+            // The kernel dropped the watches of the file system when it was
+            // unmounted.
+            let watched = file_watcher::start_file_watcher(
+                folder.local_path.clone(),
+                queue.clone(),
+                folder.debounce(),
+                control.subscribe_maintenance(),
+            );
+            if let Err(e) = watched.await {
+                eprintln!("[REMOVABLE] Failed to watch {:?}: {}", folder.local_path, e);
+            }
+            events.publish(SyncEventKind::FolderOnline {
+                folder_id: folder.id,
+            });
+            queue
+                .send(QueueEvent::FolderAdded {
+                    path: folder.local_path.clone(),
+                })
+                .await;
+        }
+        _ => {}
+    }
+}
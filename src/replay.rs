@@ -0,0 +1,221 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::{Config, LiveConfig};
+use crate::control::SyncControl;
+use crate::database::Database;
+use crate::db_pool::DbPool;
+use crate::error;
+use crate::event_queue::{self, EventLoopContext, EventQueue, QueueEvent};
+use crate::events::EventBus;
+use crate::health::FolderHealth;
+use crate::suppression::ExpectedChanges;
+use crate::sync_engine::FsEventKind;
+use crate::trace::TraceId;
+
+/// How long watcher events are kept for `sync_rs replay`.
+pub const RECORD_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often recorded events are written to the database.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long the event loop has to stay idle before a replay is done.
+pub const IDLE_TIME: Duration = Duration::from_secs(1);
+
+/// How often the event loop is checked for being idle.
+const IDLE_CHECK: Duration = Duration::from_millis(200);
+
+/// What the watcher reported about a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordedKind {
+    Create,
+    Modify,
+    Metadata,
+    Remove,
+    /// The path is the destination.
+    Rename {
+        old_path: PathBuf,
+    },
+    /// A directory appeared, e.g. moved into the folder, and was scanned.
+    FolderAdded,
+}
+
+impl RecordedKind {
+    /// Name stored in the database.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecordedKind::Create => "create",
+            RecordedKind::Modify => "modify",
+            RecordedKind::Metadata => "metadata",
+            RecordedKind::Remove => "remove",
+            RecordedKind::Rename { .. } => "rename",
+            RecordedKind::FolderAdded => "folder_added",
+        }
+    }
+
+    pub fn parse(name: &str, old_path: Option<PathBuf>) -> Option<Self> {
+        Some(match (name, old_path) {
+            ("create", _) => RecordedKind::Create,
+            ("modify", _) => RecordedKind::Modify,
+            ("metadata", _) => RecordedKind::Metadata,
+            ("remove", _) => RecordedKind::Remove,
+            ("rename", Some(old_path)) => RecordedKind::Rename { old_path },
+            ("folder_added", _) => RecordedKind::FolderAdded,
+            _ => return None,
+        })
+    }
+}
+
+/// A row of the `recorded_events` table: an event a file watcher queued.
+#[derive(Debug, Clone)]
+pub struct RecordedEvent {
+    /// Unix time in milliseconds.
+    pub recorded_ms: u64,
+    pub path: PathBuf,
+    pub kind: RecordedKind,
+}
+
+impl RecordedEvent {
+    fn from_queue_event(event: &QueueEvent) -> Option<Self> {
+        let (path, kind) = match event {
+            QueueEvent::FileChanged { path, kind, .. } => {
+                let kind = match kind {
+                    FsEventKind::Create => RecordedKind::Create,
+                    FsEventKind::Modify => RecordedKind::Modify,
+                    FsEventKind::Metadata => RecordedKind::Metadata,
+                    FsEventKind::Remove => RecordedKind::Remove,
+                    FsEventKind::Rename { old_path, .. } => RecordedKind::Rename {
+                        old_path: old_path.clone(),
+                    },
+                };
+                (path, kind)
+            }
+            QueueEvent::FolderAdded { path } => (path, RecordedKind::FolderAdded),
+            _ => return None,
+        };
+        Some(Self {
+            recorded_ms: unix_millis(SystemTime::now()),
+            path: path.clone(),
+            kind,
+        })
+    }
+
+    /// The event as the watcher queued it, under a new trace.
+    pub fn to_queue_event(&self) -> QueueEvent {
+        let kind = match &self.kind {
+            RecordedKind::Create => FsEventKind::Create,
+            RecordedKind::Modify => FsEventKind::Modify,
+            RecordedKind::Metadata => FsEventKind::Metadata,
+            RecordedKind::Remove => FsEventKind::Remove,
+            RecordedKind::Rename { old_path } => FsEventKind::Rename {
+                old_path: old_path.clone(),
+                new_path: self.path.clone(),
+            },
+            RecordedKind::FolderAdded => {
+                return QueueEvent::FolderAdded {
+                    path: self.path.clone(),
+                };
+            }
+        };
+        QueueEvent::FileChanged {
+            path: self.path.clone(),
+            kind,
+            trace: TraceId::new(),
+        }
+    }
+}
+
+/// Collects the events file watchers queue until they are written to the
+/// database, keeping a rolling window of [`RECORD_WINDOW`] that
+/// `sync_rs replay` feeds through the event loop again to reproduce bugs.
+#[derive(Debug, Clone, Default)]
+pub struct EventRecorder {
+    pending: Arc<Mutex<Vec<RecordedEvent>>>,
+}
+
+impl EventRecorder {
+    pub fn record(&self, event: &QueueEvent) {
+        if let Some(recorded) = RecordedEvent::from_queue_event(event) {
+            self.pending.lock().unwrap().push(recorded);
+        }
+    }
+
+    /// Writes the recorded events and forgets those older than
+    /// [`RECORD_WINDOW`].
+    pub fn flush(&self, db: &Database) -> Result<(), rusqlite::Error> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        if !pending.is_empty() {
+            db.record_events(&pending)?;
+        }
+        let oldest = SystemTime::now()
+            .checked_sub(RECORD_WINDOW)
+            .map_or(0, unix_millis);
+        db.prune_recorded_events(oldest)?;
+        Ok(())
+    }
+}
+
+/// Flushes the recorder every [`FLUSH_INTERVAL`].
+pub async fn run(recorder: EventRecorder, db: DbPool) {
+    let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = recorder.flush(&*db.write().await) {
+            eprintln!("[REPLAY] Failed to save watcher events: {}", e);
+        }
+    }
+}
+
+/// Feeds `recorded` through a new event loop in this process, in order, and
+/// returns once it has handled them and the work they caused, such as the
+/// scan of an added directory. Files are read as they are on disk now. The
+/// daemon must not be running.
+pub async fn replay(db: DbPool, config: Config, recorded: &[RecordedEvent]) -> error::Result<()> {
+    let (queue, receiver) = EventQueue::new(config.queues.event_queue);
+    let health = Arc::new(FolderHealth::load(&*db.read().await)?);
+    let event_loop = tokio::spawn(event_queue::start_event_loop(
+        receiver,
+        EventLoopContext {
+            db: db.clone(),
+            queue: queue.clone(),
+            expected_changes: Arc::new(ExpectedChanges::default()),
+            events: EventBus::new(config.queues.event_bus),
+            // Replayed even if sync is paused.
+            control: Arc::new(SyncControl::default()),
+            config: Arc::new(LiveConfig::new(config)),
+            health: health.clone(),
+        },
+    ));
+
+    for event in recorded {
+        queue.send(event.to_queue_event()).await;
+    }
+    wait_until_idle(&queue).await;
+    queue.send(QueueEvent::Shutdown).await;
+    if let Err(e) = event_loop.await {
+        eprintln!("[REPLAY] Event loop failed: {}", e);
+    }
+    health.flush(&*db.write().await)?;
+    Ok(())
+}
+
+/// Waits until the queue has been empty and the event loop idle for
+/// [`IDLE_TIME`], which scans started by the events get to queue their
+/// files in.
+async fn wait_until_idle(queue: &EventQueue) {
+    let mut idle = Duration::ZERO;
+    while idle < IDLE_TIME {
+        tokio::time::sleep(IDLE_CHECK).await;
+        if queue.depth() == 0 && queue.current().is_none() {
+            idle += IDLE_CHECK;
+        } else {
+            idle = Duration::ZERO;
+        }
+    }
+}
+
+pub fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
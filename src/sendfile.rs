@@ -0,0 +1,170 @@
+use std::fs::File;
+use std::io;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Most bytes handed to the kernel per `sendfile` call, so a large file
+/// does not monopolize the connection's task.
+#[cfg(target_os = "linux")]
+const MAX_CHUNK: u64 = 8 * 1024 * 1024;
+
+/// Streams the first `len` bytes of `file` to a peer over `socket`, as the
+/// raw content of a whole-file transfer. On Linux the kernel copies the data
+/// with `sendfile`, without passing it through userspace, which saves
+/// noticeable CPU on NAS-class machines. Where `sendfile` is unavailable or
+/// refuses the file, e.g. on some FUSE filesystems, the data is read and
+/// written as usual. Only for data sent as [`crate::compression::Encoding::Raw`].
+///
+/// Fails with `UnexpectedEof` if the file is shorter than `len`, which means
+/// it changed since it was announced.
+#[cfg(target_os = "linux")]
+pub async fn send_file(socket: &mut TcpStream, file: &File, len: u64) -> io::Result<u64> {
+    use std::os::fd::AsRawFd;
+    use tokio::io::Interest;
+
+    let mut offset: libc::off_t = 0;
+    let mut sent = 0;
+    while sent < len {
+        let chunk = (len - sent).min(MAX_CHUNK) as usize;
+        socket.writable().await?;
+        let result = socket.try_io(Interest::WRITABLE, || {
+            let written =
+                unsafe { libc::sendfile(socket.as_raw_fd(), file.as_raw_fd(), &mut offset, chunk) };
+            if written < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(written as u64)
+            }
+        });
+        match result {
+            Ok(0) => return Err(shrunk()),
+            Ok(written) => sent += written,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e)
+                if sent == 0 && matches!(e.raw_os_error(), Some(libc::EINVAL | libc::ENOSYS)) =>
+            {
+                return copy_file(socket, file, len).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(sent)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn send_file(socket: &mut TcpStream, file: &File, len: u64) -> io::Result<u64> {
+    copy_file(socket, file, len).await
+}
+
+/// Sends the file through a userspace buffer.
+async fn copy_file(socket: &mut TcpStream, file: &File, len: u64) -> io::Result<u64> {
+    let file = tokio::fs::File::from_std(file.try_clone()?);
+    let sent = tokio::io::copy(&mut file.take(len), socket).await?;
+    if sent < len {
+        return Err(shrunk());
+    }
+    socket.flush().await?;
+    Ok(sent)
+}
+
+fn shrunk() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "file shrank while it was being sent",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// A file of `len` bytes in the temporary directory, removed on drop.
+    struct Sample(PathBuf);
+
+    impl Sample {
+        fn new(name: &str, len: usize) -> (Self, Vec<u8>) {
+            let path = std::env::temp_dir().join(format!(
+                "sync_rs-sendfile-{}-{}",
+                std::process::id(),
+                name
+            ));
+            let content: Vec<u8> = (0..len).map(|index| (index % 251) as u8).collect();
+            std::fs::write(&path, &content).unwrap();
+            (Self(path), content)
+        }
+    }
+
+    impl Drop for Sample {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// Sends through `send` to a loopback peer and returns what the peer
+    /// received along with the result.
+    async fn transfer<F, Fut>(send: F) -> (io::Result<u64>, Vec<u8>)
+    where
+        F: FnOnce(TcpStream) -> Fut,
+        Fut: Future<Output = io::Result<u64>>,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let socket = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut peer, _) = listener.accept().await.unwrap();
+        let receiver = tokio::spawn(async move {
+            let mut received = Vec::new();
+            peer.read_to_end(&mut received).await.map(|_| received)
+        });
+        let result = send(socket).await;
+        (result, receiver.await.unwrap().unwrap())
+    }
+
+    #[tokio::test]
+    async fn sends_whole_file() {
+        // Larger than a socket buffer, so sending has to wait for the peer.
+        let (sample, content) = Sample::new("whole", 3 * 1024 * 1024 + 17);
+        let file = File::open(&sample.0).unwrap();
+        let len = content.len() as u64;
+        let (result, received) =
+            transfer(|mut socket| async move { send_file(&mut socket, &file, len).await }).await;
+        assert_eq!(result.unwrap(), content.len() as u64);
+        assert_eq!(received, content);
+    }
+
+    #[tokio::test]
+    async fn sends_only_the_requested_prefix() {
+        let (sample, content) = Sample::new("prefix", 10_000);
+        let file = File::open(&sample.0).unwrap();
+        let (result, received) =
+            transfer(|mut socket| async move { send_file(&mut socket, &file, 4096).await }).await;
+        assert_eq!(result.unwrap(), 4096);
+        assert_eq!(received, content[..4096]);
+    }
+
+    #[tokio::test]
+    async fn shorter_file_fails() {
+        let (sample, content) = Sample::new("shrunk", 1000);
+        let file = File::open(&sample.0).unwrap();
+        let (result, received) =
+            transfer(|mut socket| async move { send_file(&mut socket, &file, 2000).await }).await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(received, content);
+    }
+
+    #[tokio::test]
+    async fn fallback_copies_through_userspace() {
+        let (sample, content) = Sample::new("copy", 100_000);
+        let file = File::open(&sample.0).unwrap();
+        let len = content.len() as u64;
+        let (result, received) =
+            transfer(|mut socket| async move { copy_file(&mut socket, &file, len).await }).await;
+        assert_eq!(result.unwrap(), content.len() as u64);
+        assert_eq!(received, content);
+    }
+}
@@ -0,0 +1,462 @@
+use std::io;
+use std::path::PathBuf;
+
+/// Name of the systemd user unit and of the Windows service.
+pub const SERVICE_NAME: &str = "sync_rs";
+
+/// Label of the launchd agent on macOS.
+pub const LAUNCHD_LABEL: &str = "org.syncrs.daemon";
+
+/// Log file of the launchd agent, in the data directory.
+const LAUNCHD_LOG: &str = "sync_rs.log";
+
+/// How the service manager runs the daemon.
+#[derive(Debug, Clone)]
+pub struct ServiceSpec {
+    pub executable: PathBuf,
+    /// Working directory, which holds the database and sync_rs.toml.
+    pub data_dir: PathBuf,
+    /// Arguments after the executable, e.g. `run --grpc-listen ...`.
+    pub args: Vec<String>,
+}
+
+impl ServiceSpec {
+    /// Runs this executable with `args` in the current directory.
+    pub fn current(args: Vec<String>) -> io::Result<Self> {
+        Ok(Self {
+            executable: std::env::current_exe()?,
+            data_dir: std::env::current_dir()?,
+            args,
+        })
+    }
+
+    fn command_line(&self) -> impl Iterator<Item = String> + '_ {
+        std::iter::once(self.executable.to_string_lossy().into_owned())
+            .chain(self.args.iter().cloned())
+    }
+}
+
+/// A systemd user unit that starts the daemon with the user's session.
+/// `systemctl --user reload` sends SIGHUP (see [`crate::reload`]).
+pub fn systemd_unit(spec: &ServiceSpec) -> String {
+    let exec_start = spec
+        .command_line()
+        .map(|arg| format!("\"{}\"", systemd_escape(&arg)))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!(
+        "[Unit]\n\
+         Description=sync_rs file synchronization daemon\n\
+         After=network-online.target\n\
+         \n\
+         [Service]\n\
+         Type=simple\n\
+         WorkingDirectory={}\n\
+         ExecStart={}\n\
+         ExecReload=/bin/kill -HUP $MAINPID\n\
+         Restart=on-failure\n\
+         RestartSec=10\n\
+         \n\
+         [Install]\n\
+         WantedBy=default.target\n",
+        spec.data_dir.to_string_lossy().replace('%', "%%"),
+        exec_start
+    )
+}
+
+/// Escapes a quoted word of a unit file: specifiers, variables, quotes and
+/// backslashes.
+fn systemd_escape(arg: &str) -> String {
+    arg.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$")
+}
+
+/// A launchd agent that starts the daemon at login and restarts it when it
+/// fails. Its output goes to [`LAUNCHD_LOG`] in the data directory.
+pub fn launchd_plist(spec: &ServiceSpec) -> String {
+    let arguments: String = spec
+        .command_line()
+        .map(|arg| format!("        <string>{}</string>\n", xml_escape(&arg)))
+        .collect();
+    let data_dir = xml_escape(&spec.data_dir.to_string_lossy());
+    let log = xml_escape(&spec.data_dir.join(LAUNCHD_LOG).to_string_lossy());
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{LAUNCHD_LABEL}</string>
+    <key>ProgramArguments</key>
+    <array>
+{arguments}    </array>
+    <key>WorkingDirectory</key>
+    <string>{data_dir}</string>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+    <key>StandardOutPath</key>
+    <string>{log}</string>
+    <key>StandardErrorPath</key>
+    <string>{log}</string>
+</dict>
+</plist>
+"#
+    )
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// The command line of the Windows service. Services start in the system
+/// directory, so `--service-dir` names the data directory the daemon
+/// changes to, and tells it to report to the service control manager.
+pub fn windows_service_command(spec: &ServiceSpec) -> String {
+    spec.command_line()
+        .chain([
+            "--service-dir".to_string(),
+            spec.data_dir.to_string_lossy().into_owned(),
+        ])
+        .map(|arg| format!("\"{}\"", arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// What [`install`] registers on this platform, for `--dry-run`.
+pub fn definition(spec: &ServiceSpec) -> String {
+    if cfg!(target_os = "macos") {
+        launchd_plist(spec)
+    } else if cfg!(windows) {
+        windows_service_command(spec)
+    } else {
+        systemd_unit(spec)
+    }
+}
+
+/// Runs a service manager command, failing when it does.
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+fn run(program: &str, args: &[&str]) -> io::Result<()> {
+    let status = std::process::Command::new(program).args(args).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "`{} {}` exited with {}",
+            program,
+            args.join(" "),
+            status
+        )))
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn home_dir() -> io::Result<PathBuf> {
+    std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HOME is not set"))
+}
+
+/// Where the systemd user unit or the launchd agent is written; `None` on
+/// Windows, where the service control manager keeps the registration.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn definition_path() -> io::Result<Option<PathBuf>> {
+    definition_file().map(Some)
+}
+
+#[cfg(target_os = "linux")]
+fn definition_file() -> io::Result<PathBuf> {
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => home_dir()?.join(".config"),
+    };
+    Ok(config_dir
+        .join("systemd/user")
+        .join(format!("{}.service", SERVICE_NAME)))
+}
+
+#[cfg(target_os = "macos")]
+fn definition_file() -> io::Result<PathBuf> {
+    Ok(home_dir()?
+        .join("Library/LaunchAgents")
+        .join(format!("{}.plist", LAUNCHD_LABEL)))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn definition_path() -> io::Result<Option<PathBuf>> {
+    Ok(None)
+}
+
+/// Registers the daemon to start at login, or at boot as a Windows service,
+/// replacing an earlier registration.
+#[cfg(target_os = "linux")]
+pub fn install(spec: &ServiceSpec) -> io::Result<()> {
+    write_definition(&systemd_unit(spec))?;
+    let unit = format!("{}.service", SERVICE_NAME);
+    run("systemctl", &["--user", "daemon-reload"])?;
+    run("systemctl", &["--user", "enable", &unit])
+}
+
+#[cfg(target_os = "macos")]
+pub fn install(spec: &ServiceSpec) -> io::Result<()> {
+    let path = write_definition(&launchd_plist(spec))?;
+    // Loading a replaced agent fails while the old one is loaded.
+    let _ = run("launchctl", &["unload", &path.to_string_lossy()]);
+    run("launchctl", &["load", "-w", &path.to_string_lossy()])
+}
+
+#[cfg(windows)]
+pub fn install(spec: &ServiceSpec) -> io::Result<()> {
+    // Fails when there is no earlier registration, which is fine.
+    let _ = uninstall();
+    let command = windows_service_command(spec);
+    run(
+        "sc.exe",
+        &[
+            "create",
+            SERVICE_NAME,
+            "binPath=",
+            &command,
+            "start=",
+            "auto",
+            "DisplayName=",
+            "sync_rs file synchronization daemon",
+        ],
+    )?;
+    // Restarts it 10 seconds after it fails, like the systemd unit.
+    run(
+        "sc.exe",
+        &[
+            "failure",
+            SERVICE_NAME,
+            "reset=",
+            "86400",
+            "actions=",
+            "restart/10000",
+        ],
+    )
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn install(_spec: &ServiceSpec) -> io::Result<()> {
+    Err(unsupported())
+}
+
+/// Stops the daemon and removes its registration.
+#[cfg(target_os = "linux")]
+pub fn uninstall() -> io::Result<()> {
+    let path = existing_definition()?;
+    let unit = format!("{}.service", SERVICE_NAME);
+    run("systemctl", &["--user", "disable", "--now", &unit])?;
+    std::fs::remove_file(&path)?;
+    run("systemctl", &["--user", "daemon-reload"])
+}
+
+#[cfg(target_os = "macos")]
+pub fn uninstall() -> io::Result<()> {
+    let path = existing_definition()?;
+    run("launchctl", &["unload", "-w", &path.to_string_lossy()])?;
+    std::fs::remove_file(&path)
+}
+
+#[cfg(windows)]
+pub fn uninstall() -> io::Result<()> {
+    // Fails when the service is not running, which is fine.
+    let _ = run("sc.exe", &["stop", SERVICE_NAME]);
+    run("sc.exe", &["delete", SERVICE_NAME])
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn uninstall() -> io::Result<()> {
+    Err(unsupported())
+}
+
+/// Starts the registered daemon now instead of at the next login or boot.
+#[cfg(target_os = "linux")]
+pub fn start() -> io::Result<()> {
+    existing_definition()?;
+    let unit = format!("{}.service", SERVICE_NAME);
+    run("systemctl", &["--user", "start", &unit])
+}
+
+#[cfg(target_os = "macos")]
+pub fn start() -> io::Result<()> {
+    existing_definition()?;
+    run("launchctl", &["start", LAUNCHD_LABEL])
+}
+
+#[cfg(windows)]
+pub fn start() -> io::Result<()> {
+    run("sc.exe", &["start", SERVICE_NAME])
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn start() -> io::Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn write_definition(contents: &str) -> io::Result<PathBuf> {
+    let path = definition_file()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, contents)?;
+    Ok(path)
+}
+
+/// The installed unit or agent, or an error saying it is not installed.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn existing_definition() -> io::Result<PathBuf> {
+    let path = definition_file()?;
+    if !path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "{} does not exist; run `sync_rs service install` first",
+                path.display()
+            ),
+        ));
+    }
+    Ok(path)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "no supported service manager on this platform",
+    )
+}
+
+/// Hands the process to the Windows service control manager, which started
+/// it through the command of [`windows_service_command`], and reports the
+/// service as running. A stop request ends the process like Ctrl+C does in a
+/// console. Fails when the process was not started as a service.
+#[cfg(windows)]
+pub fn connect_to_service_manager() -> io::Result<()> {
+    scm::connect()
+}
+
+#[cfg(windows)]
+mod scm {
+    use std::ffi::c_void;
+    use std::io;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::{self, SyncSender};
+
+    use windows_sys::Win32::Foundation::{ERROR_CALL_NOT_IMPLEMENTED, NO_ERROR};
+    use windows_sys::Win32::System::Services::{
+        RegisterServiceCtrlHandlerExW, SERVICE_ACCEPT_SHUTDOWN, SERVICE_ACCEPT_STOP,
+        SERVICE_CONTROL_INTERROGATE, SERVICE_CONTROL_SHUTDOWN, SERVICE_CONTROL_STOP,
+        SERVICE_RUNNING, SERVICE_STATUS, SERVICE_STATUS_HANDLE, SERVICE_STOPPED,
+        SERVICE_TABLE_ENTRYW, SERVICE_WIN32_OWN_PROCESS, SetServiceStatus,
+        StartServiceCtrlDispatcherW,
+    };
+    use windows_sys::core::PWSTR;
+
+    use super::SERVICE_NAME;
+
+    /// Handle `service_main` registered, for reporting status changes.
+    static STATUS_HANDLE: AtomicUsize = AtomicUsize::new(0);
+
+    /// Where `service_main` reports whether the service is running.
+    static STARTED: Mutex<Option<SyncSender<io::Result<()>>>> = Mutex::new(None);
+
+    pub fn connect() -> io::Result<()> {
+        let (started, receiver) = mpsc::sync_channel(1);
+        *STARTED.lock().unwrap() = Some(started.clone());
+        // The dispatcher runs the control handler on its own thread until
+        // the service stops.
+        std::thread::spawn(move || {
+            let mut name = wide(SERVICE_NAME);
+            let table = [
+                SERVICE_TABLE_ENTRYW {
+                    lpServiceName: name.as_mut_ptr(),
+                    lpServiceProc: Some(service_main),
+                },
+                SERVICE_TABLE_ENTRYW::default(),
+            ];
+            // SAFETY: the table ends with a null entry and outlives the
+            // call, which returns once the service stopped.
+            if unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) } == 0 {
+                let _ = started.send(Err(io::Error::last_os_error()));
+            }
+        });
+        receiver
+            .recv()
+            .map_err(|_| io::Error::other("the service dispatcher exited"))?
+    }
+
+    /// Registers the control handler and reports the service as running;
+    /// the daemon itself runs on the threads of the caller of `connect`.
+    unsafe extern "system" fn service_main(_argc: u32, _argv: *mut PWSTR) {
+        let name = wide(SERVICE_NAME);
+        // SAFETY: `name` is null-terminated and `handle_control` has the
+        // signature of a HandlerEx callback.
+        let handle = unsafe {
+            RegisterServiceCtrlHandlerExW(name.as_ptr(), Some(handle_control), std::ptr::null())
+        };
+        let result = if handle.is_null() {
+            Err(io::Error::last_os_error())
+        } else {
+            STATUS_HANDLE.store(handle as usize, Ordering::SeqCst);
+            set_state(SERVICE_RUNNING)
+        };
+        if let Some(started) = STARTED.lock().unwrap().take() {
+            let _ = started.send(result);
+        }
+    }
+
+    unsafe extern "system" fn handle_control(
+        control: u32,
+        _event_type: u32,
+        _event_data: *mut c_void,
+        _context: *mut c_void,
+    ) -> u32 {
+        match control {
+            SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+                let _ = set_state(SERVICE_STOPPED);
+                std::process::exit(0);
+            }
+            SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+            _ => ERROR_CALL_NOT_IMPLEMENTED,
+        }
+    }
+
+    fn set_state(state: u32) -> io::Result<()> {
+        let status = SERVICE_STATUS {
+            dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+            dwCurrentState: state,
+            dwControlsAccepted: if state == SERVICE_RUNNING {
+                SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN
+            } else {
+                0
+            },
+            dwWin32ExitCode: NO_ERROR,
+            ..Default::default()
+        };
+        let handle = STATUS_HANDLE.load(Ordering::SeqCst) as SERVICE_STATUS_HANDLE;
+        // SAFETY: `handle` was returned by RegisterServiceCtrlHandlerExW and
+        // stays valid while the process runs.
+        if unsafe { SetServiceStatus(handle, &status) } == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// A null-terminated UTF-16 copy of `text`.
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().chain(std::iter::once(0)).collect()
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::database::{Database, SyncedFolder};
+use crate::error::{Result, SyncError};
+use crate::sync_engine::calculate_hash;
+
+/// Name of the manifest stored as the first entry of every snapshot.
+pub const MANIFEST_NAME: &str = ".sync_rs_snapshot.json";
+
+const ZSTD_LEVEL: i32 = 3;
+
+/// Describes the folder and the files a snapshot holds.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub folder_uuid: String,
+    pub folder_name: String,
+    pub device_id: String,
+    pub created_secs: u64,
+    pub files: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn new(db: &Database, folder: &SyncedFolder, files: Vec<ManifestEntry>) -> Result<Self> {
+        Ok(Self {
+            folder_uuid: folder.folder_uuid.clone(),
+            folder_name: folder.name.clone(),
+            device_id: db.get_or_create_device_id()?,
+            created_secs: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            files,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Path relative to the folder root.
+    pub path: PathBuf,
+    pub size: u64,
+    pub hash: String,
+}
+
+/// Files left out of a snapshot, with the reason.
+pub type Skipped = Vec<(PathBuf, String)>;
+
+#[derive(Debug)]
+pub struct ExportSummary {
+    pub archived: usize,
+    pub total_bytes: u64,
+    /// Indexed files that are missing or no longer match their indexed hash.
+    pub skipped: Skipped,
+}
+
+/// Writes the indexed files of `folder` that still match their indexed hash
+/// into a zstd-compressed tar archive at `output`. The archive is written
+/// next to `output` first and renamed once complete.
+pub fn export(db: &Database, folder: &SyncedFolder, output: &Path) -> Result<ExportSummary> {
+    let (files, skipped) = verified_files(db, folder)?;
+    let manifest = Manifest::new(db, folder, files)?;
+
+    let mut partial = output.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let written = write_archive(&folder.local_path, &manifest, &partial)
+        .and_then(|()| fs::rename(&partial, output));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&partial);
+        return Err(SyncError::io("failed to write snapshot", output, e));
+    }
+
+    Ok(ExportSummary {
+        archived: manifest.files.len(),
+        total_bytes: manifest.files.iter().map(|f| f.size).sum(),
+        skipped,
+    })
+}
+
+/// Returns the indexed files of `folder` whose content on disk still matches
+/// the index, sorted by path, and the skipped files with the reason.
+pub fn verified_files(
+    db: &Database,
+    folder: &SyncedFolder,
+) -> Result<(Vec<ManifestEntry>, Skipped)> {
+    let mut indexed: Vec<_> = db
+        .get_folders_and_files(folder.id, &folder.local_path)?
+        .into_values()
+        .collect();
+    indexed.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut files = Vec::new();
+    let mut skipped = Vec::new();
+    for entry in indexed {
+        let Ok(relative) = entry.path.strip_prefix(&folder.local_path) else {
+            continue;
+        };
+        let relative = relative.to_path_buf();
+        let Some(expected) = entry.hash else {
+            skipped.push((relative, "not hashed yet".to_string()));
+            continue;
+        };
+        match calculate_hash(&entry.path) {
+            Ok(actual) if actual == expected => files.push(ManifestEntry {
+                path: relative,
+                size: entry.size,
+                hash: expected,
+            }),
+            Ok(_) => skipped.push((relative, "changed since it was indexed".to_string())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                skipped.push((relative, "missing".to_string()))
+            }
+            Err(e) => skipped.push((relative, e.to_string())),
+        }
+    }
+    Ok((files, skipped))
+}
+
+fn write_archive(root: &Path, manifest: &Manifest, path: &Path) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let encoder = zstd::Encoder::new(file, ZSTD_LEVEL)?;
+    let mut builder = tar::Builder::new(encoder);
+
+    let manifest_json = serde_json::to_vec_pretty(manifest)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest_json.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(manifest.created_secs);
+    header.set_cksum();
+    builder.append_data(&mut header, MANIFEST_NAME, manifest_json.as_slice())?;
+
+    for entry in &manifest.files {
+        builder.append_path_with_name(root.join(&entry.path), &entry.path)?;
+    }
+
+    let mut file = builder.into_inner()?.finish()?;
+    file.flush()?;
+    file.get_ref().sync_all()
+}
+
+#[derive(Debug)]
+pub struct RestoreSummary {
+    pub manifest: Manifest,
+    pub restored: usize,
+    pub total_bytes: u64,
+}
+
+/// Extracts a snapshot written by [`export`] into `target`, which must not
+/// exist yet or be empty, and checks every file against the manifest.
+pub fn restore(archive_path: &Path, target: &Path) -> Result<RestoreSummary> {
+    let not_empty = fs::read_dir(target).is_ok_and(|mut entries| entries.next().is_some());
+    if not_empty {
+        return Err(invalid(target, "target directory is not empty"));
+    }
+    fs::create_dir_all(target)
+        .map_err(|e| SyncError::io("failed to create restore target", target, e))?;
+
+    let file = File::open(archive_path)
+        .map_err(|e| SyncError::io("failed to open snapshot", archive_path, e))?;
+    let decoder = zstd::Decoder::new(file)
+        .map_err(|e| SyncError::io("failed to read snapshot", archive_path, e))?;
+    let mut archive = tar::Archive::new(decoder);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(true);
+
+    let read_error = |e| SyncError::io("failed to read snapshot", archive_path, e);
+    let mut entries = archive.entries().map_err(read_error)?;
+
+    let manifest: Manifest = match entries.next() {
+        Some(Ok(entry)) if entry.path().is_ok_and(|p| p == Path::new(MANIFEST_NAME)) => {
+            serde_json::from_reader(entry)
+                .map_err(|e| invalid(archive_path, &format!("bad manifest: {}", e)))?
+        }
+        Some(Err(e)) => return Err(read_error(e)),
+        _ => return Err(invalid(archive_path, "manifest missing")),
+    };
+    let mut pending: HashMap<&Path, &ManifestEntry> = manifest
+        .files
+        .iter()
+        .map(|entry| (entry.path.as_path(), entry))
+        .collect();
+
+    for entry in entries {
+        let mut entry = entry.map_err(read_error)?;
+        let path = entry.path().map_err(read_error)?.into_owned();
+        let Some(expected) = pending.remove(path.as_path()) else {
+            return Err(invalid(
+                archive_path,
+                &format!("{:?} is not in the manifest", path),
+            ));
+        };
+        // unpack_in refuses paths that would escape the target directory.
+        if !entry.unpack_in(target).map_err(read_error)? {
+            return Err(invalid(archive_path, &format!("unsafe path {:?}", path)));
+        }
+
+        let restored = target.join(&path);
+        let hash = calculate_hash(&restored)
+            .map_err(|e| SyncError::io("failed to verify restored file", &restored, e))?;
+        if hash != expected.hash {
+            return Err(invalid(
+                archive_path,
+                &format!("{:?} does not match its recorded hash", path),
+            ));
+        }
+    }
+
+    if let Some(missing) = pending.keys().next() {
+        return Err(invalid(
+            archive_path,
+            &format!("{:?} is listed in the manifest but missing", missing),
+        ));
+    }
+
+    Ok(RestoreSummary {
+        restored: manifest.files.len(),
+        total_bytes: manifest.files.iter().map(|f| f.size).sum(),
+        manifest,
+    })
+}
+
+fn invalid(path: &Path, message: &str) -> SyncError {
+    SyncError::InvalidSnapshot {
+        path: path.to_path_buf(),
+        message: message.to_string(),
+    }
+}
@@ -0,0 +1,152 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+
+/// Granularity at which zeros in received data become holes. Filesystems
+/// allocate whole blocks, so shorter runs of zeros would be written anyway.
+const BLOCK_SIZE: usize = 4096;
+
+/// Data read at a time while writing a file sparsely.
+const CHUNK_SIZE: usize = 32 * BLOCK_SIZE;
+
+/// Returns the byte ranges of `file` that hold data, in order; the gaps
+/// between them are holes, which read as zeros but take no space on disk.
+/// A file on a filesystem that cannot report holes is one data region.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "freebsd"
+))]
+pub fn data_regions(file: &File) -> io::Result<Vec<Range<u64>>> {
+    use std::os::fd::AsRawFd;
+
+    let len = file.metadata()?.len();
+    let fd = file.as_raw_fd();
+    let mut regions = Vec::new();
+    let mut offset = 0;
+    while offset < len {
+        let start = match seek(fd, offset, libc::SEEK_DATA) {
+            Ok(start) => start,
+            // Only a hole is left up to the end of the file.
+            Err(e) if e.raw_os_error() == Some(libc::ENXIO) => break,
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) && offset == 0 => {
+                regions.push(0..len);
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        let end = seek(fd, start, libc::SEEK_HOLE)?.min(len);
+        regions.push(start..end);
+        offset = end;
+    }
+    Ok(regions)
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "freebsd"
+))]
+fn seek(fd: std::os::fd::RawFd, offset: u64, whence: libc::c_int) -> io::Result<u64> {
+    let pos = unsafe { libc::lseek(fd, offset as libc::off_t, whence) };
+    if pos < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(pos as u64)
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "freebsd"
+)))]
+pub fn data_regions(file: &File) -> io::Result<Vec<Range<u64>>> {
+    let len = file.metadata()?.len();
+    let mut regions = Vec::new();
+    if len > 0 {
+        regions.push(0..len);
+    }
+    Ok(regions)
+}
+
+/// Writes `data` to the start of `file`, seeking over blocks of zeros
+/// instead of writing them, so they end up as holes where the filesystem
+/// supports them. Returns the file's length.
+pub fn write_sparse<R: Read>(mut data: R, file: &mut File) -> io::Result<u64> {
+    let mut chunk = vec![0; CHUNK_SIZE];
+    let mut len = 0;
+    loop {
+        let filled = read_full(&mut data, &mut chunk)?;
+        if filled == 0 {
+            break;
+        }
+        let block_end = |pos: usize| (pos + BLOCK_SIZE).min(filled);
+        let mut pos = 0;
+        while pos < filled {
+            // Runs of blocks of the same kind are written or skipped at once.
+            let is_hole = is_zero(&chunk[pos..block_end(pos)]);
+            let mut end = block_end(pos);
+            while end < filled && is_zero(&chunk[end..block_end(end)]) == is_hole {
+                end = block_end(end);
+            }
+            if is_hole {
+                file.seek(SeekFrom::Current((end - pos) as i64))?;
+            } else {
+                file.write_all(&chunk[pos..end])?;
+            }
+            pos = end;
+        }
+        len += filled as u64;
+    }
+    // Extends the file over a trailing hole, which seeking alone does not.
+    file.set_len(len)?;
+    Ok(len)
+}
+
+/// Copies `source` to `target`, writing only the source's data regions so
+/// its holes are kept. Returns the number of bytes written.
+pub fn copy(source: &Path, target: &Path) -> io::Result<u64> {
+    let mut from = File::open(source)?;
+    let mut to = File::create(target)?;
+    let mut written = 0;
+    for region in data_regions(&from)? {
+        from.seek(SeekFrom::Start(region.start))?;
+        to.seek(SeekFrom::Start(region.start))?;
+        written += io::copy(&mut (&mut from).take(region.end - region.start), &mut to)?;
+    }
+    to.set_len(from.metadata()?.len())?;
+    Ok(written)
+}
+
+/// Clones `source` to `target` where the filesystem supports reflinks and
+/// falls back to [`copy`] otherwise. Like [`reflink_copy::reflink_or_copy`],
+/// returns `None` for a reflink and the number of bytes written for a copy.
+pub fn reflink_or_copy(source: &Path, target: &Path) -> io::Result<Option<u64>> {
+    match reflink_copy::reflink(source, target) {
+        Ok(()) => Ok(None),
+        Err(_) => copy(source, target).map(Some),
+    }
+}
+
+fn is_zero(block: &[u8]) -> bool {
+    block.iter().all(|&b| b == 0)
+}
+
+/// Reads until `buf` is full or the data ends, so chunks stay block-aligned.
+fn read_full<R: Read>(data: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match data.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
@@ -0,0 +1,138 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use filetime::FileTime;
+
+use crate::sparse;
+
+/// Per-folder directory where incoming file data is written before it is
+/// moved into place.
+pub const STAGING_DIR: &str = ".sync_tmp";
+
+const PARTIAL_EXTENSION: &str = "part";
+
+pub fn staging_dir(folder_root: &Path) -> PathBuf {
+    folder_root.join(STAGING_DIR)
+}
+
+/// Returns an unused path inside the staging directory, creating the
+/// directory if needed. The caller creates the file.
+pub fn staging_path(folder_root: &Path) -> io::Result<PathBuf> {
+    let dir = staging_dir(folder_root);
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(format!("{}.{}", uuid::Uuid::new_v4(), PARTIAL_EXTENSION)))
+}
+
+/// A partially received file. The data only becomes visible at its final
+/// location once [`StagedFile::persist`] succeeds; dropping it removes the
+/// partial file.
+#[derive(Debug)]
+pub struct StagedFile {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl StagedFile {
+    pub fn create(folder_root: &Path) -> io::Result<Self> {
+        let path = staging_path(folder_root)?;
+        let file = File::create(&path)?;
+        Ok(Self {
+            path,
+            file: Some(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes all of `data` with [`sparse::write_sparse`], so blocks of zeros
+    /// become holes. Returns the number of bytes written.
+    pub fn write_sparse<R: Read>(&mut self, data: R) -> io::Result<u64> {
+        let file = self.file.as_mut().expect("staged file already persisted");
+        sparse::write_sparse(data, file)
+    }
+
+    /// Sets the modification time the file will have once persisted.
+    pub fn set_modified(&self, modified: SystemTime) -> io::Result<()> {
+        let file = self.file.as_ref().expect("staged file already persisted");
+        filetime::set_file_handle_times(file, None, Some(FileTime::from_system_time(modified)))
+    }
+
+    /// Flushes the data to disk and atomically renames it to `target`.
+    pub fn persist(mut self, target: &Path) -> io::Result<()> {
+        let file = self.file.take().expect("staged file already persisted");
+        file.sync_all()?;
+        drop(file);
+
+        move_into_place(&self.path, target)
+    }
+}
+
+/// Atomically renames a fully written staging file to `target`.
+pub fn move_into_place(staged: &Path, target: &Path) -> io::Result<()> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(staged, target)?;
+    sync_parent_dir(target)
+}
+
+impl Write for StagedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file
+            .as_mut()
+            .expect("staged file already persisted")
+            .write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file
+            .as_mut()
+            .expect("staged file already persisted")
+            .flush()
+    }
+}
+
+impl Drop for StagedFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Makes the rename durable by syncing the directory entry as well.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) => File::open(parent)?.sync_all(),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Removes partial files left behind by an interrupted transfer. Only call this
+/// while no transfer into the folder is in progress, e.g. on startup.
+pub fn cleanup_staging_dir(folder_root: &Path) -> io::Result<usize> {
+    let dir = staging_dir(folder_root);
+    if !dir.is_dir() {
+        return Ok(0);
+    }
+
+    let mut removed = 0;
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.is_file() {
+            fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
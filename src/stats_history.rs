@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+use chrono::{Days, Local};
+
+use crate::database::Database;
+use crate::db_pool::DbPool;
+use crate::transfers;
+
+/// How often the daemon records the statistics of every folder. Each
+/// snapshot replaces the earlier one of the same day.
+pub const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Days of statistics kept.
+pub const KEEP_DAYS: u32 = 400;
+
+/// Records today's statistics of every folder: its file count and size
+/// from the index, and its transfer counters (see
+/// [`crate::health::FolderHealth::record_transfer`]).
+pub fn snapshot(db: &Database) -> Result<(), rusqlite::Error> {
+    let today = Local::now().date_naive();
+    let day = transfers::day(today);
+    for folder in db.get_all_synced_folders()? {
+        let totals = db.get_folder_totals(folder.id)?;
+        let stats = db.get_folder_stats(folder.id)?;
+        db.record_stats_snapshot(folder.id, &day, totals, &stats)?;
+    }
+    if let Some(oldest) = today.checked_sub_days(Days::new(KEEP_DAYS.into())) {
+        db.prune_stats_history(&transfers::day(oldest))?;
+    }
+    Ok(())
+}
+
+/// Takes a [`snapshot`] every [`SNAPSHOT_INTERVAL`].
+pub async fn run(db: DbPool) {
+    let mut interval = tokio::time::interval(SNAPSHOT_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = snapshot(&*db.write().await) {
+            eprintln!("[STATS] Failed to record folder statistics: {}", e);
+        }
+    }
+}
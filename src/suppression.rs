@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a change made by sync_rs is remembered while waiting for the
+/// watcher to report it.
+pub const EXPECTED_CHANGE_TTL: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+struct ExpectedChange {
+    /// Content hash the file will have, or `None` for an expected removal.
+    hash: Option<String>,
+    expires_at: Instant,
+}
+
+/// Registry of filesystem changes made by the sync engine itself, so the
+/// watcher events they cause are not re-indexed and re-announced.
+///
+/// Entries are keyed by path and hash and are not consumed on match: a single
+/// write usually produces several events. A later user edit has a different
+/// hash and is therefore never suppressed.
+#[derive(Debug)]
+pub struct ExpectedChanges {
+    ttl: Duration,
+    entries: Mutex<HashMap<PathBuf, ExpectedChange>>,
+}
+
+impl Default for ExpectedChanges {
+    fn default() -> Self {
+        Self::new(EXPECTED_CHANGE_TTL)
+    }
+}
+
+impl ExpectedChanges {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records that `path` is about to be written with content `hash`, or
+    /// removed when `hash` is `None`.
+    pub fn expect(&self, path: PathBuf, hash: Option<String>) {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        entries.retain(|_, change| change.expires_at > now);
+        entries.insert(
+            path,
+            ExpectedChange {
+                hash,
+                expires_at: now + self.ttl,
+            },
+        );
+    }
+
+    /// Returns true if the observed state of `path` was caused by sync_rs.
+    pub fn is_expected(&self, path: &Path, hash: Option<&str>) -> bool {
+        let entries = self.entries.lock().unwrap();
+        entries.get(path).is_some_and(|change| {
+            change.expires_at > Instant::now() && change.hash.as_deref() == hash
+        })
+    }
+}
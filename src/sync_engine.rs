@@ -7,10 +7,16 @@ use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 use walkdir::WalkDir;
 
+use crate::ignore;
+use crate::version_vector::{Causality, VersionVector};
+
 #[derive(Debug, Clone)]
 pub enum FsEventKind {
     Create,
     Modify,
+    /// Only timestamps, permissions or extended attributes changed, so the
+    /// content does not need to be hashed again.
+    Metadata,
     Remove,
     Rename {
         old_path: PathBuf,
@@ -53,6 +59,12 @@ pub struct SyncEngine {
     pub folders: Vec<SyncFolder>,
 }
 
+impl Default for SyncEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SyncEngine {
     pub fn new() -> Self {
         Self {
@@ -73,6 +85,7 @@ impl SyncEngine {
 
         for entry in WalkDir::new(&folder.path)
             .into_iter()
+            .filter_entry(|e| !ignore::is_internal_path(e.path()))
             .filter_map(Result::ok)
             .filter(|e| e.file_type().is_file())
         {
@@ -123,6 +136,12 @@ impl SyncEngine {
                     folder.files.insert(event_path.to_path_buf(), file_entry);
                 }
             }
+            FsEventKind::Metadata => {
+                if let Some(entry) = folder.files.get_mut(event_path) {
+                    entry.last_modified =
+                        fs::metadata(event_path)?.modified().unwrap_or(UNIX_EPOCH);
+                }
+            }
             FsEventKind::Remove => {
                 folder.files.remove(event_path);
             }
@@ -138,6 +157,55 @@ impl SyncEngine {
     }
 }
 
+/// True when two paths differ only in letter case, like `photo.JPG` and
+/// `photo.jpg`. On case-insensitive filesystems both name the same file, so
+/// such a rename must never be applied as a removal plus a create.
+pub fn is_case_only_rename(from: &Path, to: &Path) -> bool {
+    from != to && from.to_string_lossy().to_lowercase() == to.to_string_lossy().to_lowercase()
+}
+
+/// Identifies the inode behind a file that has more than one hard link, as
+/// `device:inode`, so the index can group its links. `None` for a file with a
+/// single link, and on platforms where std does not report link counts.
+#[cfg(unix)]
+pub fn link_group(metadata: &fs::Metadata) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.nlink() > 1).then(|| format!("{}:{}", metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn link_group(_metadata: &fs::Metadata) -> Option<String> {
+    None
+}
+
+/// Builds the name of the copy kept when a file conflicts with a remote change,
+/// e.g. `report.sync-conflict-20260101-120000-laptop.txt`.
+pub fn conflict_file_name(path: &Path, device_name: &str, time: SystemTime) -> PathBuf {
+    let timestamp = chrono::DateTime::<chrono::Local>::from(time).format("%Y%m%d-%H%M%S");
+    let device: String = device_name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+    let mut file_name = format!("{}.sync-conflict-{}-{}", stem, timestamp, device);
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        file_name.push('.');
+        file_name.push_str(ext);
+    }
+
+    path.with_file_name(file_name)
+}
+
 pub fn calculate_hash(file_path: &Path) -> io::Result<String> {
     let mut file = File::open(file_path)?;
     let mut hasher = Sha256::new();
@@ -153,3 +221,38 @@ pub fn calculate_hash(file_path: &Path) -> io::Result<String> {
 
     Ok(format!("{:x}", hasher.finalize()))
 }
+
+/// What to do when a peer announces a version of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncDecision {
+    /// Both sides hold the same content; only merge the version vectors.
+    UpToDate,
+    /// The remote version descends from ours: download it.
+    TakeRemote,
+    /// Ours descends from the remote version: the peer will fetch it.
+    KeepLocal,
+    /// Both changed independently: keep ours and store theirs as a conflict copy.
+    Conflict,
+}
+
+/// Decides between the local and a remote version of a file using content
+/// hashes and version vectors only. Modification times are deliberately not
+/// consulted, since device clocks may disagree by minutes or more.
+pub fn decide(
+    local: Option<(&str, &VersionVector)>,
+    remote_hash: &str,
+    remote_vector: &VersionVector,
+) -> SyncDecision {
+    let Some((local_hash, local_vector)) = local else {
+        return SyncDecision::TakeRemote;
+    };
+    if local_hash == remote_hash {
+        return SyncDecision::UpToDate;
+    }
+
+    match local_vector.compare(remote_vector) {
+        Causality::Before => SyncDecision::TakeRemote,
+        Causality::After => SyncDecision::KeepLocal,
+        Causality::Equal | Causality::Concurrent => SyncDecision::Conflict,
+    }
+}
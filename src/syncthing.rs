@@ -0,0 +1,179 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Result, SyncError};
+
+/// Syncthing's per-folder ignore file, in the folder root.
+pub const STIGNORE_FILE: &str = ".stignore";
+
+/// The parts of a Syncthing `config.xml` that have a sync_rs equivalent.
+#[derive(Debug, Default)]
+pub struct SyncthingConfig {
+    pub folders: Vec<SyncthingFolder>,
+    pub devices: Vec<SyncthingDevice>,
+}
+
+#[derive(Debug)]
+pub struct SyncthingFolder {
+    /// Folder ID shared by all devices syncing the folder.
+    pub id: String,
+    pub label: Option<String>,
+    pub path: PathBuf,
+    /// `sendreceive`, `sendonly`, `receiveonly` or `receiveencrypted`.
+    pub folder_type: String,
+    pub paused: bool,
+    pub sync_xattrs: bool,
+    /// Devices the folder is shared with, including this one.
+    pub device_ids: Vec<String>,
+}
+
+impl SyncthingFolder {
+    /// Label if set, otherwise the ID, like the Syncthing UI.
+    pub fn name(&self) -> &str {
+        self.label
+            .as_deref()
+            .filter(|label| !label.is_empty())
+            .unwrap_or(&self.id)
+    }
+}
+
+#[derive(Debug)]
+pub struct SyncthingDevice {
+    pub id: String,
+    pub name: Option<String>,
+}
+
+/// Reads a Syncthing `config.xml`, usually found in
+/// `~/.local/state/syncthing` or `~/.config/syncthing`.
+pub fn read_config(path: &Path) -> Result<SyncthingConfig> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| SyncError::io("failed to read Syncthing config", path, e))?;
+    parse_config(&text).map_err(|message| SyncError::Config {
+        path: path.to_path_buf(),
+        message,
+    })
+}
+
+fn parse_config(text: &str) -> Result<SyncthingConfig, String> {
+    let document = roxmltree::Document::parse(text).map_err(|e| e.to_string())?;
+    let root = document.root_element();
+    if !root.has_tag_name("configuration") {
+        return Err(format!(
+            "expected a <configuration> element, found <{}>",
+            root.tag_name().name()
+        ));
+    }
+
+    let mut config = SyncthingConfig::default();
+    for node in root.children().filter(|node| node.is_element()) {
+        match node.tag_name().name() {
+            "folder" => {
+                let id = node.attribute("id").ok_or("a <folder> has no id")?;
+                let path = node
+                    .attribute("path")
+                    .ok_or_else(|| format!("folder {:?} has no path", id))?;
+                let child_text = |name: &str| {
+                    node.children()
+                        .find(|child| child.has_tag_name(name))
+                        .and_then(|child| child.text())
+                        .map(str::trim)
+                };
+                config.folders.push(SyncthingFolder {
+                    id: id.to_string(),
+                    label: node.attribute("label").map(str::to_string),
+                    path: expand_home(path),
+                    folder_type: node.attribute("type").unwrap_or("sendreceive").to_string(),
+                    paused: child_text("paused") == Some("true"),
+                    sync_xattrs: child_text("syncXattrs") == Some("true"),
+                    device_ids: node
+                        .children()
+                        .filter(|child| child.has_tag_name("device"))
+                        .filter_map(|child| child.attribute("id"))
+                        .map(str::to_string)
+                        .collect(),
+                });
+            }
+            "device" => {
+                let id = node.attribute("id").ok_or("a <device> has no id")?;
+                config.devices.push(SyncthingDevice {
+                    id: id.to_string(),
+                    name: node
+                        .attribute("name")
+                        .filter(|name| !name.is_empty())
+                        .map(str::to_string),
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(config)
+}
+
+/// Syncthing writes `~` for the home directory in folder paths.
+fn expand_home(path: &str) -> PathBuf {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    match (path.strip_prefix("~"), home) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            home.join(rest.trim_start_matches('/'))
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Ignore patterns converted from a folder's `.stignore`, with the lines
+/// that have no equivalent and why.
+#[derive(Debug, Default)]
+pub struct ConvertedIgnores {
+    pub patterns: Vec<String>,
+    pub skipped: Vec<(String, &'static str)>,
+}
+
+/// Converts the `.stignore` in `folder_root`, if any, into sync_rs ignore
+/// patterns (see [`crate::ignore::matches_patterns`]). Plain globs carry
+/// over; negations and includes are skipped, since sync_rs patterns can
+/// only exclude. A pattern anchored with a leading `/` is kept when it
+/// names a path below the root and otherwise matches at any depth.
+pub fn convert_stignore(folder_root: &Path) -> Result<ConvertedIgnores> {
+    let path = folder_root.join(STIGNORE_FILE);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(ConvertedIgnores::default()),
+        Err(e) => return Err(SyncError::io("failed to read", path, e)),
+    };
+
+    let mut converted = ConvertedIgnores::default();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+        let skip = if line.starts_with("#include") {
+            Some("includes are not supported")
+        } else if line.starts_with('!') {
+            Some("negated patterns are not supported")
+        } else if line.starts_with("(?i)") {
+            Some("case-insensitive patterns are not supported")
+        } else {
+            None
+        };
+        if let Some(reason) = skip {
+            converted.skipped.push((line.to_string(), reason));
+            continue;
+        }
+
+        // `(?d)` lets Syncthing delete ignored files blocking a directory
+        // removal; sync_rs never deletes ignored files.
+        let pattern = line.trim_start_matches("(?d)");
+        let pattern = pattern.trim_start_matches('/').trim_end_matches('/');
+        if pattern.is_empty() {
+            continue;
+        }
+        match glob::Pattern::new(pattern) {
+            Ok(_) => converted.patterns.push(pattern.to_string()),
+            Err(_) => converted
+                .skipped
+                .push((line.to_string(), "not a valid glob pattern")),
+        }
+    }
+    Ok(converted)
+}
@@ -0,0 +1,53 @@
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+tokio::task_local! {
+    static CURRENT: TraceId;
+}
+
+/// Identifies one filesystem event on its way from the watcher through
+/// hashing to the index, so the log lines of a single file's journey can be
+/// found with `grep`. IDs are unique for the lifetime of the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceId(u64);
+
+impl TraceId {
+    pub fn new() -> Self {
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl Default for TraceId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "t{:06x}", self.0)
+    }
+}
+
+/// Runs `future` as the handling of the event traced by `trace`: log lines
+/// written with [`prefix`] inside it carry the trace ID, however deep in
+/// the call stack they are.
+pub async fn scope<F: Future>(trace: TraceId, future: F) -> F::Output {
+    CURRENT.scope(trace, future).await
+}
+
+/// The trace of the event being handled, if any.
+pub fn current() -> Option<TraceId> {
+    CURRENT.try_with(|trace| *trace).ok()
+}
+
+/// Log line prefix naming the current trace, e.g. `[t00002a] `; empty
+/// outside of [`scope`].
+pub fn prefix() -> String {
+    current()
+        .map(|trace| format!("[{}] ", trace))
+        .unwrap_or_default()
+}
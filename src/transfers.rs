@@ -0,0 +1,69 @@
+use chrono::{Datelike, Local, NaiveDate};
+use serde::Serialize;
+
+use crate::config::TransferCaps;
+use crate::database::Database;
+
+/// Format of the days transfers and folder statistics are recorded under.
+pub const DAY_FORMAT: &str = "%Y-%m-%d";
+
+pub fn day(date: NaiveDate) -> String {
+    date.format(DAY_FORMAT).to_string()
+}
+
+pub fn today() -> String {
+    day(Local::now().date_naive())
+}
+
+/// First day of the current month, from which monthly caps count.
+pub fn month_start() -> String {
+    let today = Local::now().date_naive();
+    day(today.with_day(1).unwrap_or(today))
+}
+
+/// Data exchanged with a peer this month, against its cap.
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerUsage {
+    pub device_id: String,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub cap_bytes: Option<u64>,
+}
+
+impl PeerUsage {
+    pub fn used_bytes(&self) -> u64 {
+        self.bytes_sent + self.bytes_received
+    }
+
+    pub fn is_over_cap(&self) -> bool {
+        self.cap_bytes.is_some_and(|cap| self.used_bytes() >= cap)
+    }
+}
+
+pub fn month_usage(
+    db: &Database,
+    caps: &TransferCaps,
+    device_id: &str,
+) -> Result<PeerUsage, rusqlite::Error> {
+    let (bytes_sent, bytes_received) = db.get_peer_transfer_total(device_id, &month_start())?;
+    Ok(PeerUsage {
+        device_id: device_id.to_string(),
+        bytes_sent,
+        bytes_received,
+        cap_bytes: caps.cap_for(device_id),
+    })
+}
+
+/// Whether file content may be exchanged with `device_id`: not once its
+/// monthly cap is used up, until the month ends or the cap is raised. Index
+/// exchanges go on, so both sides still know what is pending.
+pub fn transfers_allowed(
+    db: &Database,
+    caps: &TransferCaps,
+    device_id: &str,
+) -> Result<bool, rusqlite::Error> {
+    if caps.cap_for(device_id).is_none() {
+        return Ok(true);
+    }
+    Ok(!month_usage(db, caps, device_id)?.is_over_cap())
+}
@@ -0,0 +1,63 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use crate::sync_engine::calculate_hash;
+
+/// Size of the blocks a file's content is checked in, so a transfer that
+/// fails verification only needs to request the corrupted blocks again.
+pub const BLOCK_SIZE: u64 = 128 * 1024;
+
+/// Checks that the file at `path` has the content announced as `hash`.
+/// A mismatch is an `InvalidData` error; the caller discards the file
+/// rather than indexing it.
+pub fn verify_file(path: &Path, hash: &str) -> io::Result<()> {
+    let actual = calculate_hash(path)?;
+    if actual == hash {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "{:?} failed verification: expected hash {}, got {}",
+            path, hash, actual
+        ),
+    ))
+}
+
+/// SHA-256 of every [`BLOCK_SIZE`] block of the file, in order, as a peer
+/// would announce them along with the full-file hash.
+pub fn block_hashes(path: &Path) -> io::Result<Vec<String>> {
+    block_hashes_of_size(path, BLOCK_SIZE)
+}
+
+/// SHA-256 of every `block_size` block of the file, in order. The last
+/// block is shorter unless the file size is a multiple of `block_size`.
+pub fn block_hashes_of_size(path: &Path, block_size: u64) -> io::Result<Vec<String>> {
+    let mut file = File::open(path)?;
+    let mut hashes = Vec::new();
+    loop {
+        let mut hasher = Sha256::new();
+        let read = io::copy(&mut (&mut file).take(block_size), &mut hasher)?;
+        if read == 0 {
+            break;
+        }
+        hashes.push(format!("{:x}", hasher.finalize()));
+    }
+    Ok(hashes)
+}
+
+/// Indices of the blocks of the file at `path` that do not match `expected`,
+/// including blocks missing at its end. These are the blocks to request
+/// again after a failed verification.
+pub fn corrupted_blocks(path: &Path, expected: &[String]) -> io::Result<Vec<u64>> {
+    let actual = block_hashes(path)?;
+    Ok(expected
+        .iter()
+        .enumerate()
+        .filter(|(index, hash)| actual.get(*index) != Some(*hash))
+        .map(|(index, _)| index as u64)
+        .collect())
+}
@@ -0,0 +1,85 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Per-device change counters of a file. Unlike modification times they do
+/// not depend on device clocks, so they tell reliably whether one version
+/// was derived from another or both changed independently.
+///
+/// Stored as a JSON object mapping device IDs to counters.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct VersionVector(BTreeMap<String, u64>);
+
+/// How two versions of a file relate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Causality {
+    Equal,
+    /// `self` is an ancestor of the other version.
+    Before,
+    /// `self` descends from the other version.
+    After,
+    /// Both changed independently since their common ancestor.
+    Concurrent,
+}
+
+impl VersionVector {
+    /// Parses the stored form; empty or malformed text gives an empty vector.
+    pub fn parse(text: &str) -> Self {
+        serde_json::from_str(text).unwrap_or_default()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    /// Device IDs with their change counters, ordered by device ID.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.0
+            .iter()
+            .map(|(device, &counter)| (device.as_str(), counter))
+    }
+
+    /// Records a change made on `device_id`.
+    pub fn increment(&mut self, device_id: &str) {
+        *self.0.entry(device_id.to_string()).or_default() += 1;
+    }
+
+    /// Takes the larger counter of each device, e.g. after both sides turned
+    /// out to hold the same content.
+    pub fn merge(&mut self, other: &Self) {
+        for (device, &counter) in &other.0 {
+            let entry = self.0.entry(device.clone()).or_default();
+            *entry = (*entry).max(counter);
+        }
+    }
+
+    pub fn compare(&self, other: &Self) -> Causality {
+        let mut ordering = Ordering::Equal;
+        for device in self.0.keys().chain(other.0.keys()) {
+            let ours = self.0.get(device).copied().unwrap_or(0);
+            let theirs = other.0.get(device).copied().unwrap_or(0);
+            match (ordering, ours.cmp(&theirs)) {
+                (_, Ordering::Equal) => {}
+                (Ordering::Equal, device_ordering) => ordering = device_ordering,
+                (current, device_ordering) if current != device_ordering => {
+                    return Causality::Concurrent;
+                }
+                _ => {}
+            }
+        }
+
+        match ordering {
+            Ordering::Equal => Causality::Equal,
+            Ordering::Less => Causality::Before,
+            Ordering::Greater => Causality::After,
+        }
+    }
+}
+
+impl FromIterator<(String, u64)> for VersionVector {
+    fn from_iter<I: IntoIterator<Item = (String, u64)>>(counters: I) -> Self {
+        Self(counters.into_iter().collect())
+    }
+}
@@ -0,0 +1,284 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Local, NaiveDateTime};
+use walkdir::WalkDir;
+
+use crate::config::{LiveConfig, VersionRetention};
+use crate::database::SyncedFolder;
+use crate::db_pool::DbPool;
+use crate::error::{Result, SyncError};
+use crate::ignore::VERSIONS_DIR;
+use crate::removable;
+
+/// How often the daemon applies the retention policy.
+pub const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Format of the time a version was kept, appended to the file stem.
+const STAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+/// Separates the file stem from the stamp, e.g. `report~20260101-120000.txt`.
+const STAMP_SEPARATOR: char = '~';
+
+pub fn versions_dir(folder_root: &Path) -> PathBuf {
+    folder_root.join(VERSIONS_DIR)
+}
+
+/// Where the version of `relative_path` replaced or removed at `time` is
+/// kept: the same path under [`VERSIONS_DIR`], with the time added to its
+/// name, e.g. `.sync_versions/docs/report~20260101-120000.txt`.
+pub fn version_path(folder_root: &Path, relative_path: &Path, time: SystemTime) -> PathBuf {
+    let stamp = DateTime::<Local>::from(time).format(STAMP_FORMAT);
+    let stem = relative_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    let mut name = format!("{}{}{}", stem, STAMP_SEPARATOR, stamp);
+    if let Some(extension) = relative_path.extension() {
+        name.push('.');
+        name.push_str(&extension.to_string_lossy());
+    }
+    versions_dir(folder_root).join(relative_path.with_file_name(name))
+}
+
+/// Keeps a copy of the file at `relative_path` as it is now, right after a
+/// peer's version of it was applied, for files matching
+/// [`crate::config::ConflictPolicy::text_merge`]. Both sides then have this
+/// version, so later conflicting changes are merged against it (see
+/// [`crate::conflicts::handle_conflict`]). Copies are pruned like other
+/// versions.
+pub fn keep_merge_base(folder_root: &Path, relative_path: &Path) -> io::Result<PathBuf> {
+    let target = version_path(folder_root, relative_path, SystemTime::now());
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(folder_root.join(relative_path), &target)?;
+    Ok(target)
+}
+
+/// The newest version of `relative_path` kept under [`VERSIONS_DIR`].
+pub fn latest_version(folder_root: &Path, relative_path: &Path) -> Result<Option<PathBuf>> {
+    let dir = versions_dir(folder_root).join(relative_path.parent().unwrap_or(Path::new("")));
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(SyncError::io("failed to list versions in", dir, e)),
+    };
+    let mut latest: Option<(SystemTime, PathBuf)> = None;
+    for entry in entries {
+        let entry = entry.map_err(|e| SyncError::io("failed to list versions in", &dir, e))?;
+        let Some((original, kept)) = parse_version_name(Path::new(&entry.file_name())) else {
+            continue;
+        };
+        if Some(original.as_os_str()) == relative_path.file_name()
+            && latest.as_ref().is_none_or(|(newest, _)| kept > *newest)
+        {
+            latest = Some((kept, entry.path()));
+        }
+    }
+    Ok(latest.map(|(_, path)| path))
+}
+
+/// A file in a versions directory.
+#[derive(Debug)]
+struct StoredVersion {
+    path: PathBuf,
+    /// Path of the file it is a version of, relative to the folder root.
+    original: PathBuf,
+    /// When it was kept; its modification time for files not named by
+    /// [`version_path`].
+    kept: SystemTime,
+    size: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct PruneSummary {
+    pub removed: Vec<PathBuf>,
+    pub removed_bytes: u64,
+    pub kept: usize,
+    pub kept_bytes: u64,
+}
+
+/// Applies `retention` to the versions directory of `folder`: versions
+/// older than its maximum age go first, then all but the newest versions of
+/// each file, then the oldest versions until the directory fits its maximum
+/// size. With `dry_run`, only reports what would be removed.
+pub fn prune(
+    folder: &SyncedFolder,
+    retention: &VersionRetention,
+    dry_run: bool,
+) -> Result<PruneSummary> {
+    let dir = versions_dir(&folder.local_path);
+    let mut versions = list_versions(&dir)?;
+    // Newest first, so whatever is cut off the end goes.
+    versions.sort_by_key(|version| Reverse(version.kept));
+
+    let cutoff = retention
+        .max_age()
+        .and_then(|max_age| SystemTime::now().checked_sub(max_age));
+    let mut per_file: HashMap<PathBuf, usize> = HashMap::new();
+    let mut kept_bytes = 0;
+    let mut expired = Vec::new();
+    let mut kept = Vec::new();
+    for version in versions {
+        let count = per_file.entry(version.original.clone()).or_default();
+        *count += 1;
+        let too_old = cutoff.is_some_and(|cutoff| version.kept < cutoff);
+        let too_many = retention.keep_versions.is_some_and(|keep| *count > keep);
+        if too_old || too_many {
+            expired.push(version);
+        } else {
+            kept_bytes += version.size;
+            kept.push(version);
+        }
+    }
+    if let Some(max_bytes) = retention.max_bytes {
+        while kept_bytes > max_bytes
+            && let Some(oldest) = kept.pop()
+        {
+            kept_bytes -= oldest.size;
+            expired.push(oldest);
+        }
+    }
+
+    let mut summary = PruneSummary {
+        kept: kept.len(),
+        kept_bytes,
+        ..PruneSummary::default()
+    };
+    for version in expired {
+        if !dry_run {
+            match fs::remove_file(&version.path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(SyncError::io(
+                        "failed to remove old version",
+                        &version.path,
+                        e,
+                    ));
+                }
+                _ => {}
+            }
+        }
+        summary.removed_bytes += version.size;
+        summary.removed.push(version.path);
+    }
+    if !dry_run && !summary.removed.is_empty() {
+        remove_empty_dirs(&dir);
+    }
+    Ok(summary)
+}
+
+fn list_versions(dir: &Path) -> Result<Vec<StoredVersion>> {
+    let mut versions = Vec::new();
+    if !dir.is_dir() {
+        return Ok(versions);
+    }
+    for entry in WalkDir::new(dir) {
+        let entry = entry.map_err(|e| {
+            let path = e.path().unwrap_or(dir).to_path_buf();
+            SyncError::io("failed to list versions in", path, e.into())
+        })?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let metadata = entry
+            .metadata()
+            .map_err(|e| SyncError::io("failed to read version", entry.path(), e.into()))?;
+        let Ok(relative) = entry.path().strip_prefix(dir) else {
+            continue;
+        };
+        let (original, kept) = match parse_version_name(relative) {
+            Some((original, kept)) => (original, kept),
+            None => (
+                relative.to_path_buf(),
+                metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            ),
+        };
+        versions.push(StoredVersion {
+            path: entry.into_path(),
+            original,
+            kept,
+            size: metadata.len(),
+        });
+    }
+    Ok(versions)
+}
+
+/// Splits a name written by [`version_path`] into the original path and
+/// the time the version was kept.
+fn parse_version_name(relative: &Path) -> Option<(PathBuf, SystemTime)> {
+    let name = relative.file_name()?.to_str()?;
+    let (stem, rest) = name.rsplit_once(STAMP_SEPARATOR)?;
+    let (stamp, extension) = match rest.split_once('.') {
+        Some((stamp, extension)) => (stamp, Some(extension)),
+        None => (rest, None),
+    };
+    let time = NaiveDateTime::parse_from_str(stamp, STAMP_FORMAT)
+        .ok()?
+        .and_local_timezone(Local)
+        .earliest()?;
+    let original = match extension {
+        Some(extension) => format!("{}.{}", stem, extension),
+        None => stem.to_string(),
+    };
+    Some((relative.with_file_name(original), time.into()))
+}
+
+/// Removes directories emptied by pruning, keeping the versions directory.
+fn remove_empty_dirs(dir: &Path) {
+    for entry in WalkDir::new(dir)
+        .min_depth(1)
+        .contents_first(true)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_dir())
+    {
+        // Fails for directories that still hold something.
+        let _ = fs::remove_dir(entry.path());
+    }
+}
+
+/// Applies the configured retention to every folder every
+/// [`PRUNE_INTERVAL`], as long as any limit is set.
+pub async fn run(db: DbPool, config: Arc<LiveConfig>) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let retention = config.get().versions.clone();
+        if !retention.is_set() {
+            continue;
+        }
+        let folders = match db.read().await.get_all_synced_folders() {
+            Ok(folders) => folders,
+            Err(e) => {
+                eprintln!("[VERSIONS] Failed to list folders: {}", e);
+                continue;
+            }
+        };
+        for folder in folders {
+            if !removable::is_mounted(&folder) {
+                continue;
+            }
+            let retention = retention.clone();
+            let result =
+                tokio::task::spawn_blocking(move || (prune(&folder, &retention, false), folder))
+                    .await;
+            match result {
+                Ok((Ok(summary), folder)) if !summary.removed.is_empty() => println!(
+                    "[VERSIONS] {}: removed {} old version(s) ({} bytes)",
+                    folder.name,
+                    summary.removed.len(),
+                    summary.removed_bytes
+                ),
+                Ok((Ok(_), _)) => {}
+                Ok((Err(e), folder)) => eprintln!("[VERSIONS] {}: {}", folder.name, e),
+                Err(e) => eprintln!("[VERSIONS] Pruning failed: {}", e),
+            }
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::LiveConfig;
+use crate::database::Database;
+use crate::event_queue::{CurrentEvent, EventQueue};
+
+/// How often the watchdog looks at the event being handled.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Setting holding the event that is taking too long, as JSON, for
+/// `sync_rs status`.
+const SLOW_EVENT_SETTING: &str = "slow_event";
+
+/// An event the event loop has been handling for longer than the watchdog
+/// threshold, as stored for `sync_rs status`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlowEvent {
+    pub path: PathBuf,
+    pub stage: String,
+    pub trace: String,
+    pub started_secs: u64,
+}
+
+/// Starts a thread that warns about events the event loop has been handling
+/// for longer than the configured threshold, such as a huge file being hashed or a
+/// handler waiting for a database lock, and again whenever the time they
+/// have been running doubles. The event is recorded in the database until
+/// it is done.
+///
+/// The watchdog has a thread and a database connection of its own, so
+/// neither a handler blocking the async runtime nor one holding the writer
+/// connection keeps it from reporting.
+pub fn spawn(queue: EventQueue, config: Arc<LiveConfig>) -> std::io::Result<()> {
+    std::thread::Builder::new()
+        .name("watchdog".to_string())
+        .spawn(move || run(queue, config))?;
+    Ok(())
+}
+
+fn run(queue: EventQueue, config: Arc<LiveConfig>) {
+    let db = match Database::new() {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("[WATCHDOG] Failed to open the database: {}", e);
+            return;
+        }
+    };
+    // The slow event last warned about, and how long it had run by then.
+    let mut reported: Option<(CurrentEvent, Duration)> = None;
+    loop {
+        // Read every time, as a reload may change or turn it off.
+        let threshold = config.get().slow_event_threshold();
+        std::thread::sleep(threshold.map_or(CHECK_INTERVAL, |t| CHECK_INTERVAL.min(t)));
+        let Some(threshold) = threshold else {
+            if reported.take().is_some() {
+                record(&db, None);
+            }
+            continue;
+        };
+        let current = queue.current();
+        if let Some((slow, _)) = &reported
+            && current
+                .as_ref()
+                .is_none_or(|event| event.started != slow.started)
+        {
+            println!(
+                "[WATCHDOG] [{}] Done with {:?} after about {}s",
+                slow.trace,
+                slow.path,
+                slow.started.elapsed().as_secs()
+            );
+            record(&db, None);
+            reported = None;
+        }
+        let Some(event) = current.filter(|event| event.started.elapsed() >= threshold) else {
+            continue;
+        };
+
+        let elapsed = event.started.elapsed();
+        if reported
+            .as_ref()
+            .is_some_and(|(_, warned_at)| elapsed < *warned_at * 2)
+        {
+            continue;
+        }
+        eprintln!(
+            "[WATCHDOG] [{}] Still handling {:?} after {}s ({})",
+            event.trace,
+            event.path,
+            elapsed.as_secs(),
+            event.stage
+        );
+        record(&db, Some(&event));
+        reported = Some((event, elapsed));
+    }
+}
+
+/// Stores the slow event for `sync_rs status`, or clears it once done.
+fn record(db: &Database, event: Option<&CurrentEvent>) {
+    let result = match event {
+        Some(event) => {
+            let started_secs = (SystemTime::now() - event.started.elapsed())
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let slow = SlowEvent {
+                path: event.path.clone(),
+                stage: event.stage.to_string(),
+                trace: event.trace.to_string(),
+                started_secs,
+            };
+            serde_json::to_string(&slow)
+                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
+                .and_then(|json| db.set_setting(SLOW_EVENT_SETTING, &json))
+        }
+        None => db.delete_setting(SLOW_EVENT_SETTING),
+    };
+    if let Err(e) = result {
+        eprintln!("[WATCHDOG] Failed to record slow event: {}", e);
+    }
+}
+
+/// The event the daemon has been stuck on, if any.
+pub fn load_slow_event(db: &Database) -> Result<Option<SlowEvent>, rusqlite::Error> {
+    Ok(db
+        .get_setting(SLOW_EVENT_SETTING)?
+        .and_then(|json| serde_json::from_str(&json).ok()))
+}
+
+/// Forgets a slow event recorded by a previous run.
+pub fn clear_slow_event(db: &Database) -> Result<(), rusqlite::Error> {
+    db.delete_setting(SLOW_EVENT_SETTING)
+}
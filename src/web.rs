@@ -0,0 +1,320 @@
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::extract::connect_info::{ConnectInfo, Connected};
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{StatusCode, Uri, header};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::serve::IncomingStream;
+use axum::{Json, Router};
+use include_dir::{Dir, include_dir};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+
+use crate::control::SyncControl;
+use crate::database::{Conflict, Database, PendingChange, StatsHistoryEntry};
+use crate::db_pool::DbPool;
+use crate::event_queue::EventQueue;
+use crate::events::EventBus;
+use crate::output::{self, FolderOutput, PeerOutput};
+use crate::stats_history::KEEP_DAYS;
+
+/// Static files of the web UI, compiled into the binary.
+static UI_DIR: Dir = include_dir!("$CARGO_MANIFEST_DIR/ui");
+
+#[derive(Clone)]
+pub struct WebState {
+    db: DbPool,
+    queue: EventQueue,
+    events: EventBus,
+    control: Arc<SyncControl>,
+}
+
+impl WebState {
+    pub fn new(db: DbPool, queue: EventQueue, events: EventBus, control: Arc<SyncControl>) -> Self {
+        Self {
+            db,
+            queue,
+            events,
+            control,
+        }
+    }
+}
+
+/// Serves the web UI and its JSON API.
+pub async fn serve(addr: SocketAddr, state: WebState) -> std::io::Result<()> {
+    let app = Router::new()
+        .route("/api/status", get(get_status))
+        .route("/api/folders", get(get_folders))
+        .route("/api/peers", get(get_peers))
+        .route("/api/conflicts", get(get_conflicts))
+        .route("/api/folders/{id}/pending", get(get_pending_changes))
+        .route("/api/folders/{id}/push", post(push_changes))
+        .route("/api/folders/{id}/history", get(get_stats_history))
+        .route("/api/pause", post(pause))
+        .route("/api/resume", post(resume))
+        .route("/api/maintenance", post(set_maintenance))
+        .route("/api/events", get(stream_events))
+        .fallback(static_file)
+        .with_state(state)
+        .layer(middleware::from_fn(same_origin));
+
+    let listener = TcpListener::bind(addr).await?;
+    println!("[WEB] Serving UI on http://{}", addr);
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<LocalAddr>(),
+    )
+    .await
+}
+
+/// The address of this machine a connection arrived on, which is the
+/// interface's own address even when the UI is bound to 0.0.0.0.
+#[derive(Clone, Copy)]
+struct LocalAddr(Option<SocketAddr>);
+
+impl Connected<IncomingStream<'_, TcpListener>> for LocalAddr {
+    fn connect_info(stream: IncomingStream<'_, TcpListener>) -> Self {
+        Self(stream.io().local_addr().ok())
+    }
+}
+
+/// Rejects requests a web page of another site could make through the
+/// user's browser, as the API has no login: the Host must be the address the
+/// request arrived on, which defeats DNS rebinding, and the Origin that
+/// browsers send with every POST must be that same host.
+async fn same_origin(
+    ConnectInfo(LocalAddr(local)): ConnectInfo<LocalAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let headers = request.headers();
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .filter(|host| local.is_some_and(|local| is_local_host(local, host)));
+    let Some(host) = host else {
+        return (StatusCode::FORBIDDEN, "unexpected Host header").into_response();
+    };
+    if let Some(origin) = headers.get(header::ORIGIN)
+        && origin.to_str().ok() != Some(&format!("http://{}", host))
+    {
+        return (
+            StatusCode::FORBIDDEN,
+            "cross-origin requests are not allowed",
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+/// True when `host`, e.g. `127.0.0.1:8080` or `localhost:8080`, names
+/// `local`, the address of this machine the request arrived on. Checking
+/// that address rather than the bound one keeps a UI listening on all
+/// interfaces from accepting arbitrary IPs a page could rebind its name to.
+fn is_local_host(local: SocketAddr, host: &str) -> bool {
+    let Some((name, port)) = host.rsplit_once(':') else {
+        return false;
+    };
+    if port.parse() != Ok(local.port()) {
+        return false;
+    }
+    let local_ip = local.ip().to_canonical();
+    if name == "localhost" {
+        return local_ip.is_loopback();
+    }
+    let name = name.trim_start_matches('[').trim_end_matches(']');
+    name.parse::<IpAddr>()
+        .is_ok_and(|ip| ip.to_canonical() == local_ip)
+}
+
+#[derive(Serialize)]
+struct StatusView {
+    device_id: String,
+    device_name: Option<String>,
+    paused: bool,
+    /// Watchers and transfers are stopped; see `sync_rs maintenance`.
+    maintenance: bool,
+    /// File changes waiting for the event loop, and how many fit.
+    queue_depth: usize,
+    queue_capacity: usize,
+    /// The event the event loop is handling, if any.
+    current_event: Option<CurrentEventView>,
+    folders: Vec<FolderOutput>,
+}
+
+#[derive(Serialize)]
+struct CurrentEventView {
+    path: PathBuf,
+    stage: &'static str,
+    trace: String,
+    elapsed_secs: u64,
+}
+
+struct ApiError(rusqlite::Error);
+
+impl From<rusqlite::Error> for ApiError {
+    fn from(e: rusqlite::Error) -> Self {
+        ApiError(e)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        eprintln!("[WEB] Request failed: {}", self.0);
+        (StatusCode::INTERNAL_SERVER_ERROR, self.0.to_string()).into_response()
+    }
+}
+
+fn status_view(db: &Database, state: &WebState) -> Result<StatusView, rusqlite::Error> {
+    Ok(StatusView {
+        device_id: db.get_or_create_device_id()?,
+        device_name: db.get_device_name()?,
+        paused: state.control.is_paused(),
+        maintenance: state.control.in_maintenance(),
+        queue_depth: state.queue.depth(),
+        queue_capacity: state.queue.capacity(),
+        current_event: state.queue.current().map(|event| CurrentEventView {
+            path: event.path,
+            stage: event.stage,
+            trace: event.trace.to_string(),
+            elapsed_secs: event.started.elapsed().as_secs(),
+        }),
+        folders: output::list_folders(db)?,
+    })
+}
+
+async fn get_status(State(state): State<WebState>) -> Result<Json<StatusView>, ApiError> {
+    let db = state.db.read().await;
+    Ok(Json(status_view(&db, &state)?))
+}
+
+async fn get_folders(State(state): State<WebState>) -> Result<Json<Vec<FolderOutput>>, ApiError> {
+    let db = state.db.read().await;
+    Ok(Json(output::list_folders(&db)?))
+}
+
+async fn get_peers(State(state): State<WebState>) -> Result<Json<Vec<PeerOutput>>, ApiError> {
+    let db = state.db.read().await;
+    Ok(Json(output::list_peers(&db)?))
+}
+
+async fn get_conflicts(State(state): State<WebState>) -> Result<Json<Vec<Conflict>>, ApiError> {
+    let db = state.db.read().await;
+    Ok(Json(db.get_conflicts()?))
+}
+
+async fn get_pending_changes(
+    State(state): State<WebState>,
+    Path(folder_id): Path<i64>,
+) -> Result<Json<Vec<PendingChange>>, ApiError> {
+    let db = state.db.read().await;
+    Ok(Json(db.get_pending_changes(folder_id)?))
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    /// Most recent days to return, 30 by default.
+    days: Option<u32>,
+}
+
+/// Daily statistics of a folder, oldest first, for trend graphs.
+async fn get_stats_history(
+    State(state): State<WebState>,
+    Path(folder_id): Path<i64>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<StatsHistoryEntry>>, ApiError> {
+    let days = query.days.unwrap_or(30).min(KEEP_DAYS);
+    let db = state.db.read().await;
+    Ok(Json(db.get_stats_history(folder_id, days)?))
+}
+
+/// Approves every pending change of a manual-push folder and returns them.
+async fn push_changes(
+    State(state): State<WebState>,
+    Path(folder_id): Path<i64>,
+) -> Result<Json<Vec<PendingChange>>, ApiError> {
+    let db = state.db.write().await;
+    let approved = db.approve_pending_changes(folder_id, &[])?;
+    if !approved.is_empty() {
+        println!(
+            "[WEB] Approved {} pending change(s) in folder {}",
+            approved.len(),
+            folder_id
+        );
+    }
+    Ok(Json(approved))
+}
+
+async fn pause(State(state): State<WebState>) -> Result<Json<StatusView>, ApiError> {
+    let db = state.db.write().await;
+    state.control.pause(&db)?;
+    Ok(Json(status_view(&db, &state)?))
+}
+
+async fn resume(State(state): State<WebState>) -> Result<Json<StatusView>, ApiError> {
+    state.control.resume(&state.db, &state.queue).await?;
+    let db = state.db.read().await;
+    Ok(Json(status_view(&db, &state)?))
+}
+
+#[derive(Deserialize)]
+struct MaintenanceRequest {
+    enabled: bool,
+}
+
+async fn set_maintenance(
+    State(state): State<WebState>,
+    Json(request): Json<MaintenanceRequest>,
+) -> Result<Json<StatusView>, ApiError> {
+    state
+        .control
+        .set_maintenance(&state.db, &state.queue, request.enabled)
+        .await?;
+    let db = state.db.read().await;
+    Ok(Json(status_view(&db, &state)?))
+}
+
+async fn stream_events(
+    State(state): State<WebState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = BroadcastStream::new(state.events.subscribe()).filter_map(|event| {
+        let event = event.ok()?;
+        Event::default().json_data(event).ok().map(Ok)
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+async fn static_file(uri: Uri) -> Response {
+    let path = match uri.path().trim_start_matches('/') {
+        "" => "index.html",
+        path => path,
+    };
+
+    match UI_DIR.get_file(path) {
+        Some(file) => (
+            [(header::CONTENT_TYPE, content_type(path))],
+            file.contents(),
+        )
+            .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+fn content_type(path: &str) -> &'static str {
+    match path.rsplit('.').next() {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("json") => "application/json",
+        _ => "application/octet-stream",
+    }
+}
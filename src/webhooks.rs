@@ -0,0 +1,236 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::database::Webhook;
+use crate::db_pool::DbPool;
+use crate::events::{EventBus, SyncEvent, SyncEventKind};
+
+/// Event names a webhook can subscribe to.
+pub const EVENT_NAMES: &[&str] = &[
+    "folder-synced",
+    "conflict",
+    "quota-exceeded",
+    "folder-offline",
+    "folder-online",
+    "peer-offline",
+    "error",
+];
+
+/// Header carrying `sha256=<hex HMAC of the body>` when the hook has a secret.
+pub const SIGNATURE_HEADER: &str = "X-SyncRs-Signature";
+pub const EVENT_HEADER: &str = "X-SyncRs-Event";
+
+const MAX_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Forwards sync events to the configured webhooks until the event bus closes.
+/// Hooks are re-read for every event, so changes apply without a restart.
+pub async fn run_dispatcher(db: DbPool, events: EventBus) {
+    let mut receiver = events.subscribe();
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(REQUEST_TIMEOUT))
+        .http_status_as_error(false)
+        .build()
+        .into();
+
+    loop {
+        let event = match receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                eprintln!("[WEBHOOK] Fell behind, {} event(s) not delivered", missed);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+
+        let Some(name) = event_name(&event.kind) else {
+            continue;
+        };
+
+        let (hooks, device_id, device_name) = {
+            let db = db.read().await;
+            let loaded = db.get_webhooks().and_then(|hooks| {
+                Ok((hooks, db.get_or_create_device_id()?, db.get_device_name()?))
+            });
+            match loaded {
+                Ok(loaded) => loaded,
+                Err(e) => {
+                    eprintln!("[WEBHOOK] Failed to load webhooks: {}", e);
+                    continue;
+                }
+            }
+        };
+
+        let hooks: Vec<Webhook> = hooks
+            .into_iter()
+            .filter(|hook| hook.events.is_empty() || hook.events.iter().any(|e| e == name))
+            .collect();
+        if hooks.is_empty() {
+            continue;
+        }
+
+        let body = payload(name, &event, &device_id, device_name.as_deref()).to_string();
+        for hook in hooks {
+            tokio::spawn(deliver(agent.clone(), hook, name, body.clone()));
+        }
+    }
+}
+
+fn event_name(kind: &SyncEventKind) -> Option<&'static str> {
+    match kind {
+        SyncEventKind::FolderSynced { .. } => Some("folder-synced"),
+        SyncEventKind::Conflict { .. } => Some("conflict"),
+        SyncEventKind::QuotaExceeded { .. } => Some("quota-exceeded"),
+        SyncEventKind::FolderOffline { .. } => Some("folder-offline"),
+        SyncEventKind::FolderOnline { .. } => Some("folder-online"),
+        SyncEventKind::PeerOffline { .. } => Some("peer-offline"),
+        SyncEventKind::Error { .. } => Some("error"),
+        SyncEventKind::FileIndexed { .. }
+        | SyncEventKind::FileRemoved { .. }
+        | SyncEventKind::FileRenamed { .. }
+        | SyncEventKind::FileMoved { .. }
+        | SyncEventKind::FolderAdded { .. } => None,
+    }
+}
+
+fn payload(
+    name: &str,
+    event: &SyncEvent,
+    device_id: &str,
+    device_name: Option<&str>,
+) -> serde_json::Value {
+    let timestamp = event
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let data = match &event.kind {
+        SyncEventKind::FolderSynced { folder_id }
+        | SyncEventKind::FolderOffline { folder_id }
+        | SyncEventKind::FolderOnline { folder_id } => json!({ "folder_id": folder_id }),
+        SyncEventKind::Conflict { folder_id, path } => {
+            json!({ "folder_id": folder_id, "path": path })
+        }
+        SyncEventKind::PeerOffline {
+            device_id,
+            device_name,
+        } => json!({ "device_id": device_id, "device_name": device_name }),
+        SyncEventKind::QuotaExceeded {
+            folder_id,
+            used_bytes,
+            quota_bytes,
+        } => json!({
+            "folder_id": folder_id,
+            "used_bytes": used_bytes,
+            "quota_bytes": quota_bytes,
+        }),
+        SyncEventKind::Error { path, message } => json!({ "path": path, "message": message }),
+        _ => json!({}),
+    };
+
+    json!({
+        "event": name,
+        "timestamp": timestamp,
+        "device": { "id": device_id, "name": device_name },
+        "data": data,
+    })
+}
+
+/// Returns the value of [`SIGNATURE_HEADER`] for a body signed with `secret`.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    let digest: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("sha256={}", digest)
+}
+
+enum DeliveryError {
+    Retryable(String),
+    Permanent(String),
+}
+
+async fn deliver(agent: ureq::Agent, hook: Webhook, event: &'static str, body: String) {
+    let signature = hook
+        .secret
+        .as_deref()
+        .map(|secret| sign(secret, body.as_bytes()));
+    let mut backoff = INITIAL_BACKOFF;
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        let request = {
+            let agent = agent.clone();
+            let url = hook.url.clone();
+            let body = body.clone();
+            let signature = signature.clone();
+            tokio::task::spawn_blocking(move || post(&agent, &url, event, &body, signature))
+        };
+
+        let error = match request.await {
+            Ok(Ok(())) => return,
+            Ok(Err(DeliveryError::Permanent(e))) => {
+                eprintln!("[WEBHOOK] {} rejected {} event: {}", hook.url, event, e);
+                return;
+            }
+            Ok(Err(DeliveryError::Retryable(e))) => e,
+            Err(e) => e.to_string(),
+        };
+
+        if attempt == MAX_ATTEMPTS {
+            eprintln!(
+                "[WEBHOOK] Giving up on {} event for {} after {} attempts: {}",
+                event, hook.url, attempt, error
+            );
+            return;
+        }
+
+        eprintln!(
+            "[WEBHOOK] Delivery of {} event to {} failed (attempt {}): {}; retrying in {:?}",
+            event, hook.url, attempt, error, backoff
+        );
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+}
+
+fn post(
+    agent: &ureq::Agent,
+    url: &str,
+    event: &str,
+    body: &str,
+    signature: Option<String>,
+) -> Result<(), DeliveryError> {
+    let mut request = agent
+        .post(url)
+        .header("Content-Type", "application/json")
+        .header(EVENT_HEADER, event);
+    if let Some(signature) = signature {
+        request = request.header(SIGNATURE_HEADER, signature);
+    }
+
+    let response = request
+        .send(body)
+        .map_err(|e| DeliveryError::Retryable(e.to_string()))?;
+
+    let status = response.status();
+    if status.is_success() {
+        Ok(())
+    } else if status.is_server_error() || status.as_u16() == 429 {
+        Err(DeliveryError::Retryable(format!("HTTP {}", status)))
+    } else {
+        Err(DeliveryError::Permanent(format!("HTTP {}", status)))
+    }
+}
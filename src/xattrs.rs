@@ -0,0 +1,63 @@
+use std::io;
+use std::path::Path;
+
+/// Extended attributes of a file as `(name, value)` pairs, sorted by name.
+pub type Xattrs = Vec<(String, Vec<u8>)>;
+
+/// Attribute namespaces that are synced. Others (security, trusted, system
+/// ACLs) are tied to the local machine or need privileges to set.
+#[cfg(target_os = "macos")]
+const SYNCED_PREFIXES: &[&str] = &["com.apple."];
+#[cfg(all(unix, not(target_os = "macos")))]
+const SYNCED_PREFIXES: &[&str] = &["user."];
+
+#[cfg(unix)]
+fn is_synced(name: &str) -> bool {
+    SYNCED_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
+/// Reads the synced extended attributes of `path`, including the resource
+/// fork on macOS (`com.apple.ResourceFork`).
+#[cfg(unix)]
+pub fn read(path: &Path) -> io::Result<Xattrs> {
+    let mut attrs = Vec::new();
+    for name in xattr::list(path)? {
+        let Some(name) = name.to_str().filter(|name| is_synced(name)) else {
+            continue;
+        };
+        if let Some(value) = xattr::get(path, name)? {
+            attrs.push((name.to_string(), value));
+        }
+    }
+    attrs.sort();
+    Ok(attrs)
+}
+
+/// Makes the synced extended attributes of `path` match `attrs`, removing
+/// synced attributes that are not listed.
+#[cfg(unix)]
+pub fn write(path: &Path, attrs: &Xattrs) -> io::Result<()> {
+    for name in xattr::list(path)? {
+        if let Some(name) = name.to_str().filter(|name| is_synced(name))
+            && !attrs.iter().any(|(wanted, _)| wanted == name)
+        {
+            xattr::remove(path, name)?;
+        }
+    }
+    for (name, value) in attrs.iter().filter(|(name, _)| is_synced(name)) {
+        xattr::set(path, name, value)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn read(_path: &Path) -> io::Result<Xattrs> {
+    Ok(Vec::new())
+}
+
+#[cfg(not(unix))]
+pub fn write(_path: &Path, _attrs: &Xattrs) -> io::Result<()> {
+    Ok(())
+}
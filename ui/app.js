@@ -0,0 +1,161 @@
+"use strict";
+
+const MAX_ACTIVITY = 200;
+
+let refreshTimer = null;
+
+function formatBytes(bytes) {
+  const units = ["B", "KiB", "MiB", "GiB", "TiB"];
+  let value = bytes;
+  let unit = 0;
+  while (value >= 1024 && unit < units.length - 1) {
+    value /= 1024;
+    unit += 1;
+  }
+  return `${value.toFixed(unit === 0 ? 0 : 1)} ${units[unit]}`;
+}
+
+function cell(text, className) {
+  const td = document.createElement("td");
+  td.textContent = text;
+  if (className) {
+    td.className = className;
+  }
+  return td;
+}
+
+function renderList(element, items, emptyText, render) {
+  element.replaceChildren();
+  if (items.length === 0) {
+    const li = document.createElement("li");
+    li.className = "empty";
+    li.textContent = emptyText;
+    element.appendChild(li);
+    return;
+  }
+  for (const item of items) {
+    const li = document.createElement("li");
+    li.textContent = render(item);
+    element.appendChild(li);
+  }
+}
+
+function renderStatus(status) {
+  document.getElementById("device").textContent = status.device_name
+    ? `${status.device_name} (${status.device_id})`
+    : status.device_id;
+
+  const state = document.getElementById("state");
+  state.textContent = status.maintenance ? "Maintenance" : status.paused ? "Paused" : "Syncing";
+  state.classList.toggle("paused", status.paused || status.maintenance);
+  document.getElementById("pause").disabled = status.paused;
+  document.getElementById("resume").disabled = !status.paused;
+
+  const rows = document.getElementById("folders");
+  rows.replaceChildren();
+  for (const folder of status.folders) {
+    const tr = document.createElement("tr");
+    tr.append(
+      cell(folder.observer ? `${folder.name} (observer)` : folder.name),
+      cell(folder.local_path),
+      cell(folder.file_count),
+      cell(
+        folder.quota_bytes === null
+          ? formatBytes(folder.total_bytes)
+          : `${formatBytes(folder.total_bytes)} / ${formatBytes(folder.quota_bytes)}`,
+        folder.quota_bytes !== null && folder.total_bytes > folder.quota_bytes
+          ? "over-quota"
+          : undefined,
+      ),
+      cell(folder.folder_uuid, "uuid"),
+    );
+    rows.appendChild(tr);
+  }
+}
+
+async function fetchJson(url, options) {
+  const response = await fetch(url, options);
+  if (!response.ok) {
+    throw new Error(`${url}: ${response.status} ${await response.text()}`);
+  }
+  return response.json();
+}
+
+function describePeer(peer) {
+  const name = peer.device_name ?? peer.device_id;
+  const behind = peer.folders
+    .filter((folder) => folder.files > 0)
+    .map((folder) => `${folder.files} files / ${formatBytes(folder.bytes)} behind`);
+  const state = peer.connected ? "online" : "offline";
+  return behind.length > 0 ? `${name} (${state}): ${behind.join(", ")}` : `${name} (${state})`;
+}
+
+async function refresh() {
+  try {
+    const [status, peers, conflicts] = await Promise.all([
+      fetchJson("/api/status"),
+      fetchJson("/api/peers"),
+      fetchJson("/api/conflicts"),
+    ]);
+    renderStatus(status);
+    renderList(document.getElementById("peers"), peers, "No known peers", describePeer);
+    renderList(
+      document.getElementById("conflicts"),
+      conflicts,
+      "No conflicts",
+      (conflict) =>
+        `${conflict.id}: ${conflict.relative_path} (their version: ${conflict.conflict_path})`,
+    );
+  } catch (error) {
+    addActivity({ kind: "error", message: error.message, timestamp_secs: Date.now() / 1000 });
+  }
+}
+
+function addActivity(event) {
+  const list = document.getElementById("activity");
+  const li = document.createElement("li");
+  const time = new Date(event.timestamp_secs * 1000).toLocaleTimeString();
+  const path = event.from ? `${event.from} -> ${event.path}` : event.path;
+  const moved =
+    event.from_folder_id !== undefined
+      ? `folder ${event.from_folder_id} -> ${event.folder_id}`
+      : null;
+  const usage =
+    event.quota_bytes !== undefined ? `${event.used_bytes} of ${event.quota_bytes} bytes` : null;
+  const detail = [moved, path, event.message, usage].filter(Boolean).join(": ");
+  li.textContent = `${time} ${event.kind.replace("_", " ")} ${detail}`;
+  if (event.kind === "error") {
+    li.className = "error";
+  }
+  list.prepend(li);
+  while (list.children.length > MAX_ACTIVITY) {
+    list.lastChild.remove();
+  }
+}
+
+// Bursts of events (e.g. an initial scan) only trigger one refresh.
+function scheduleRefresh() {
+  if (refreshTimer === null) {
+    refreshTimer = setTimeout(() => {
+      refreshTimer = null;
+      refresh();
+    }, 1000);
+  }
+}
+
+async function setPaused(paused) {
+  const status = await fetchJson(paused ? "/api/pause" : "/api/resume", { method: "POST" });
+  renderStatus(status);
+}
+
+document.getElementById("pause").addEventListener("click", () => setPaused(true));
+document.getElementById("resume").addEventListener("click", () => setPaused(false));
+
+const events = new EventSource("/api/events");
+events.onmessage = (message) => {
+  addActivity(JSON.parse(message.data));
+  scheduleRefresh();
+};
+
+refresh();
+setInterval(refresh, 10000);